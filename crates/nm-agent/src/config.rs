@@ -1,41 +1,30 @@
-use anyhow::Result;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
 use nm_common::config::AgentConfig;
+use tokio::sync::watch;
 
-pub fn load(path: &str) -> Result<AgentConfig> {
-    let mut config = AgentConfig::default();
+/// How often the config file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Top-level keys of the old flat format and the section key they moved to.
+const LEGACY_KEYS: &[(&str, &str, &str)] = &[
+    ("default_timeout_ms", "probe", "default_timeout_ms"),
+    ("max_concurrent_probes", "probe", "max_concurrent_probes"),
+    ("log_level", "logging", "level"),
+    ("log_file", "logging", "file"),
+];
 
-    // Try to load from file
-    if !std::path::Path::new(path).exists() {
+pub fn load(path: &str) -> Result<AgentConfig> {
+    let mut config = if Path::new(path).exists() {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file '{}'", path))?;
+        parse(&content).with_context(|| format!("Invalid config file '{}'", path))?
+    } else {
         eprintln!("WARNING: Config file not found at '{}'. Using defaults (random agent_id, empty api_key). Authentication will fail.", path);
-    }
-    if std::path::Path::new(path).exists() {
-        let content = std::fs::read_to_string(path)?;
-        // Simple TOML-like parsing for key = "value" pairs
-        for line in content.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
-                continue;
-            }
-            if let Some((key, value)) = line.split_once('=') {
-                let key = key.trim();
-                let value = value.trim().trim_matches('"');
-                match key {
-                    "server_url" | "url" => config.server_url = value.to_string(),
-                    "agent_id" => config.agent_id = value.to_string(),
-                    "api_key" => config.api_key = value.to_string(),
-                    "log_level" => config.log_level = value.to_string(),
-                    "log_file" => config.log_file = Some(value.to_string()),
-                    "reconnect_max_delay_secs" => {
-                        config.reconnect_max_delay_secs = value.parse().unwrap_or(60);
-                    }
-                    "default_timeout_ms" => {
-                        config.default_timeout_ms = value.parse().unwrap_or(2000);
-                    }
-                    _ => {}
-                }
-            }
-        }
-    }
+        AgentConfig::default()
+    };
 
     // Override from environment
     if let Ok(v) = std::env::var("NM_SERVER_URL") {
@@ -48,8 +37,110 @@ pub fn load(path: &str) -> Result<AgentConfig> {
         config.api_key = v;
     }
     if let Ok(v) = std::env::var("NM_LOG_LEVEL") {
-        config.log_level = v;
+        config.logging.level = v;
     }
 
     Ok(config)
 }
+
+fn parse(content: &str) -> Result<AgentConfig> {
+    let mut table: toml::Table = content.parse()?;
+
+    // Configs written by older installers use flat keys; move them into
+    // their section unless the section already sets them.
+    for (old, section, key) in LEGACY_KEYS {
+        let Some(value) = table.remove(*old) else {
+            continue;
+        };
+        eprintln!("WARNING: '{old}' is deprecated, use '{key}' in the [{section}] section");
+        let section = table
+            .entry(*section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if let toml::Value::Table(section) = section {
+            section.entry(*key).or_insert(value);
+        }
+    }
+
+    Ok(toml::Value::Table(table).try_into()?)
+}
+
+/// Watch the config file and publish every valid change. Settings that need
+/// a restart keep their running values; everything else takes effect through
+/// the receivers of `config_tx`.
+pub async fn watch(path: String, config_tx: watch::Sender<AgentConfig>) {
+    let mut last_modified = modified(&path);
+
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+
+        let current = modified(&path);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+
+        let mut config = match load(&path) {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::error!("Config reload failed, keeping the running config: {e:#}");
+                continue;
+            }
+        };
+
+        let running = config_tx.borrow().clone();
+        if running.identity_differs(&config) {
+            tracing::warn!(
+                "server_url, agent_id, api_key, tls, spool.path and probe.privileged_helper changes take effect after a restart"
+            );
+            config.server_url = running.server_url.clone();
+            config.agent_id = running.agent_id.clone();
+            config.api_key = running.api_key.clone();
+            config.spool.path = running.spool.path.clone();
+            config.probe.privileged_helper = running.probe.privileged_helper;
+            config.tls = running.tls.clone();
+        }
+        if config == running {
+            continue;
+        }
+
+        tracing::info!(path = %path, "Config reloaded");
+        if config_tx.send(config).is_err() {
+            break;
+        }
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sections_and_legacy_keys() {
+        let config = parse(
+            r#"
+            server_url = "ws://nm.example:8080/ws/agent"
+            agent_id = "9b2f6a3e-4c1d-4e2a-8f5b-0d7c6e1a2b3c"
+            api_key = "secret"
+            log_level = "debug"
+
+            [probe]
+            max_concurrent_probes = 20
+
+            [traffic]
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.probe.max_concurrent_probes, 20);
+        assert_eq!(config.probe.default_timeout_ms, 2000);
+        assert!(!config.traffic.enabled);
+        assert_eq!(config.logging.level, "debug");
+
+        let err = parse("[probe]\nmax_concurent_probes = 20\n").unwrap_err();
+        assert!(err.to_string().contains("unknown field `max_concurent_probes`"), "{err}");
+    }
+}
@@ -1,28 +1,74 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use futures_util::{SinkExt, StreamExt};
 use nm_common::config::AgentConfig;
 use nm_common::protocol::*;
-use crate::scheduler::TargetCommand;
-use tokio::sync::mpsc;
-use tokio_tungstenite::connect_async;
+use crate::scheduler::{SchedulerStats, TargetCommand};
+use crate::spool::{is_spoolable, Spool};
+use crate::system_info::SystemInfo;
+use tokio::sync::{mpsc, watch};
+use tokio_tungstenite::connect_async_tls_with_config;
 use tokio_tungstenite::tungstenite::Message;
 
+/// Spooled messages sent per `SpooledBatch`.
+const SPOOL_BATCH_SIZE: usize = 100;
+
+/// A spooled batch not acknowledged within this time is sent again.
+const SPOOL_ACK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Everything this agent build supports, advertised in the AuthRequest.
+const CAPABILITIES: &[&str] = &[
+    capability::CONFIG_UPDATE,
+    capability::TCP_SYN,
+    capability::SPOOL,
+    capability::IPV6,
+    capability::DISCOVERY_SCAN,
+    capability::SESSION_RESUME,
+];
+
 pub async fn run(
-    config: AgentConfig,
+    config_rx: watch::Receiver<AgentConfig>,
     mut outgoing_rx: mpsc::Receiver<WsEnvelope>,
     target_tx: mpsc::Sender<TargetCommand>,
     outgoing_tx: mpsc::Sender<WsEnvelope>,
+    stats: Arc<SchedulerStats>,
 ) {
     let mut reconnect_delay = Duration::from_secs(1);
-    let max_delay = Duration::from_secs(config.reconnect_max_delay_secs);
     let started_at = Instant::now();
     let mut active_target_count: u32 = 0;
+    let mut system_info = SystemInfo::new();
+    // Skipped rounds up to the last heartbeat; each heartbeat carries the increase.
+    let mut skipped_reported = 0;
+
+    // Identity settings never change on reload, so one copy serves the whole run
+    let config = config_rx.borrow().clone();
+    let mut spool = match Spool::open(&config.spool.path, spool_max_bytes(&config)) {
+        Ok(s) => Some(s),
+        Err(e) => {
+            tracing::warn!(path = %config.spool.path, error = %e, "Cannot open spool, data probed while offline will be lost");
+            None
+        }
+    };
 
     loop {
+        if let Some(spool) = spool.as_mut() {
+            spool.set_max_bytes(spool_max_bytes(&config_rx.borrow()));
+        }
+
         tracing::info!(url = %config.server_url, "Connecting to server...");
 
-        match connect_async(&config.server_url).await {
+        // Certificate files are read on every attempt, so fixing them needs no restart
+        let connect = async {
+            let connector = crate::tls::connector(&config.tls)?;
+            // Not `?`: the WebSocket error's message already includes its cause
+            connect_async_tls_with_config(&config.server_url, None, false, connector)
+                .await
+                .map_err(anyhow::Error::msg)
+        };
+
+        match buffer_while(connect, &mut outgoing_rx, &mut spool).await {
             Ok((ws_stream, _)) => {
                 reconnect_delay = Duration::from_secs(1);
                 tracing::info!("WebSocket connected");
@@ -34,10 +80,10 @@ pub async fn run(
                     agent_id: config.agent_id.parse().unwrap_or_default(),
                     api_key: config.api_key.clone(),
                     agent_version: env!("CARGO_PKG_VERSION").to_string(),
-                    hostname: hostname::get()
-                        .map(|h| h.to_string_lossy().to_string())
-                        .unwrap_or_else(|_| "unknown".to_string()),
-                    os_info: std::env::consts::OS.to_string(),
+                    hostname: SystemInfo::hostname(),
+                    os_info: SystemInfo::os_info(),
+                    protocol_version: PROTOCOL_VERSION,
+                    capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
                 }));
 
                 let auth_bytes = match rmp_serde::to_vec(&auth) {
@@ -52,22 +98,40 @@ pub async fn run(
                     continue;
                 }
 
+                // Servers that cannot ingest SpooledBatch never ack it, so the
+                // spool is kept for a later server instead of replayed
+                let mut replay_spool = false;
+
                 // Wait for AuthResponse
-                if let Some(Ok(msg)) = ws_rx.next().await {
+                if let Some(Ok(msg)) = buffer_while(ws_rx.next(), &mut outgoing_rx, &mut spool).await {
                     match msg {
                         Message::Binary(data) => {
                             if let Ok(envelope) = rmp_serde::from_slice::<WsEnvelope>(&data) {
                                 if let WsPayload::AuthResponse(resp) = envelope.payload {
                                     if resp.success {
-                                        tracing::info!("Authentication successful");
+                                        tracing::info!(
+                                            protocol_version = resp.protocol_version,
+                                            "Authentication successful"
+                                        );
+                                        replay_spool = resp.capabilities.iter().any(|c| c == capability::SPOOL);
+                                        if !replay_spool && spool.as_ref().is_some_and(|s| !s.is_empty()) {
+                                            tracing::warn!("Server cannot replay spooled data, keeping it on disk");
+                                        }
                                         active_target_count = resp.assigned_targets.len() as u32;
                                         // Send assigned targets to scheduler
                                         for target in resp.assigned_targets {
+                                            let mut target = target.into_config();
+                                            continue_after_spool(&mut target, spool.as_ref());
                                             let _ = target_tx.send(TargetCommand::Add(target)).await;
                                         }
                                     } else {
                                         tracing::error!("Auth failed: {:?}", resp.error);
-                                        tokio::time::sleep(Duration::from_secs(30)).await;
+                                        buffer_while(
+                                            tokio::time::sleep(Duration::from_secs(30)),
+                                            &mut outgoing_rx,
+                                            &mut spool,
+                                        )
+                                        .await;
                                         continue;
                                     }
                                 }
@@ -80,13 +144,54 @@ pub async fn run(
                     }
                 }
 
+                if let Some(dropped) = spool.as_mut().map(Spool::take_dropped).filter(|&n| n > 0) {
+                    tracing::warn!(dropped = dropped, "Messages lost while offline because the spool was full");
+                }
+
                 // Main communication loop
                 let mut heartbeat_interval = tokio::time::interval(Duration::from_secs(30));
+                // Spooled batch awaiting its ack: (batch msg_id, spool offset after it, sent at)
+                let mut in_flight: Option<(uuid::Uuid, u64, Instant)> = None;
 
                 loop {
+                    // Replay the spool one acknowledged batch at a time
+                    if in_flight.is_none() && replay_spool {
+                        if let Some(spool) = spool.as_mut().filter(|s| !s.is_empty()) {
+                            match spool.read_batch(SPOOL_BATCH_SIZE) {
+                                Ok((messages, end)) => {
+                                    let count = messages.len();
+                                    let batch = WsEnvelope::new(WsPayload::SpooledBatch(SpooledBatch { messages }));
+                                    let bytes = match rmp_serde::to_vec(&batch) {
+                                        Ok(b) => b,
+                                        Err(e) => {
+                                            tracing::error!("Failed to serialize spooled batch, skipping it: {e}");
+                                            let _ = spool.commit(end);
+                                            continue;
+                                        }
+                                    };
+                                    if ws_tx.send(Message::Binary(bytes.into())).await.is_err() {
+                                        tracing::error!("Failed to send spooled batch, reconnecting...");
+                                        break;
+                                    }
+                                    tracing::debug!(messages = count, "Spooled batch sent");
+                                    in_flight = Some((batch.msg_id, end, Instant::now()));
+                                }
+                                Err(e) => {
+                                    tracing::error!(error = %e, "Spool unreadable, discarding it");
+                                    let _ = spool.commit(u64::MAX);
+                                }
+                            }
+                        }
+                    }
+
                     tokio::select! {
                         // Send outgoing messages from probe scheduler
                         Some(msg) = outgoing_rx.recv() => {
+                            // New data queues behind the spool so the server sees it in order
+                            if let Some(spool) = spool.as_mut().filter(|s| replay_spool && !s.is_empty() && is_spoolable(&msg.payload)) {
+                                spool.push(&msg);
+                                continue;
+                            }
                             let bytes = match rmp_serde::to_vec(&msg) {
                                 Ok(b) => b,
                                 Err(e) => {
@@ -96,6 +201,7 @@ pub async fn run(
                             };
                             if ws_tx.send(Message::Binary(bytes.into())).await.is_err() {
                                 tracing::error!("Failed to send message, reconnecting...");
+                                spool_message(&mut spool, msg);
                                 break;
                             }
                         }
@@ -105,7 +211,26 @@ pub async fn run(
                             match msg {
                                 Message::Binary(data) => {
                                     if let Ok(envelope) = rmp_serde::from_slice::<WsEnvelope>(&data) {
-                                        handle_server_message(envelope, &target_tx, &config, &outgoing_tx).await;
+                                        if let WsPayload::AckResponse(ack) = &envelope.payload {
+                                            if let Some((batch_id, end, _)) = in_flight.filter(|(id, ..)| *id == ack.ack_msg_id) {
+                                                in_flight = None;
+                                                if ack.success {
+                                                    tracing::debug!(msg_id = %batch_id, "Spooled batch acknowledged");
+                                                    if let Some(spool) = spool.as_mut() {
+                                                        if let Err(e) = spool.commit(end) {
+                                                            tracing::error!(error = %e, "Failed to record spool progress");
+                                                        }
+                                                        if spool.is_empty() {
+                                                            tracing::info!("Spool replay complete");
+                                                        }
+                                                    }
+                                                } else {
+                                                    tracing::warn!(error = ?ack.error, "Server rejected spooled batch, retrying");
+                                                }
+                                                continue;
+                                            }
+                                        }
+                                        handle_server_message(envelope, &target_tx, &config_rx, &outgoing_tx, spool.as_ref()).await;
                                     }
                                 }
                                 Message::Close(_) => {
@@ -118,12 +243,24 @@ pub async fn run(
 
                         // Send periodic heartbeat
                         _ = heartbeat_interval.tick() => {
+                            if in_flight.is_some_and(|(_, _, sent_at)| sent_at.elapsed() > SPOOL_ACK_TIMEOUT) {
+                                tracing::warn!("Spooled batch not acknowledged, sending it again");
+                                in_flight = None;
+                            }
+
+                            system_info.refresh();
+                            let skipped_total = stats.skipped_rounds.load(Ordering::Relaxed);
                             let hb = WsEnvelope::new(WsPayload::Heartbeat(AgentHeartbeat {
                                 agent_id: config.agent_id.parse().unwrap_or_default(),
                                 active_target_count,
                                 uptime_seconds: started_at.elapsed().as_secs(),
-                                cpu_usage_pct: 0.0,
-                                memory_usage_mb: 0,
+                                cpu_usage_pct: system_info.cpu_usage(),
+                                memory_usage_mb: system_info.memory_usage_mb(),
+                                memory_total_mb: system_info.memory_total_mb(),
+                                load_avg_1m: SystemInfo::load_avg_1m(),
+                                agent_rss_mb: system_info.agent_rss_mb(),
+                                queued_rounds: stats.queued_rounds.load(Ordering::Relaxed),
+                                skipped_rounds: skipped_total - std::mem::replace(&mut skipped_reported, skipped_total),
                             }));
                             let bytes = match rmp_serde::to_vec(&hb) {
                                 Ok(b) => b,
@@ -140,25 +277,63 @@ pub async fn run(
                 }
             }
             Err(e) => {
-                tracing::error!(error = %e, "Connection failed");
+                tracing::error!("Connection failed: {e:#}");
             }
         }
 
         tracing::info!(delay = ?reconnect_delay, "Reconnecting in...");
-        tokio::time::sleep(reconnect_delay).await;
+        buffer_while(tokio::time::sleep(reconnect_delay), &mut outgoing_rx, &mut spool).await;
+        let max_delay = Duration::from_secs(config_rx.borrow().reconnect_max_delay_secs);
         reconnect_delay = (reconnect_delay * 2).min(max_delay);
     }
 }
 
+fn spool_max_bytes(config: &AgentConfig) -> u64 {
+    config.spool.max_mb.saturating_mul(1024 * 1024)
+}
+
+/// Drive `fut` to completion while spooling whatever the scheduler produces
+/// in the meantime, so probing never stalls on a missing connection.
+async fn buffer_while<F: std::future::Future>(
+    fut: F,
+    outgoing_rx: &mut mpsc::Receiver<WsEnvelope>,
+    spool: &mut Option<Spool>,
+) -> F::Output {
+    tokio::pin!(fut);
+    loop {
+        tokio::select! {
+            output = &mut fut => return output,
+            Some(msg) = outgoing_rx.recv() => spool_message(spool, msg),
+        }
+    }
+}
+
+fn spool_message(spool: &mut Option<Spool>, msg: WsEnvelope) {
+    if let Some(spool) = spool.as_mut().filter(|_| is_spoolable(&msg.payload)) {
+        spool.push(&msg);
+    }
+}
+
+/// Rounds still waiting in the spool are not known to the server yet, so a
+/// resumed session must not number new rounds from what the server has seen.
+fn continue_after_spool(target: &mut TargetConfig, spool: Option<&Spool>) {
+    if let Some(spool) = spool {
+        target.first_round = target.first_round.max(spool.next_round(target.session_id));
+    }
+}
+
 async fn handle_server_message(
     envelope: WsEnvelope,
     target_tx: &mpsc::Sender<TargetCommand>,
-    config: &AgentConfig,
+    config_rx: &watch::Receiver<AgentConfig>,
     outgoing_tx: &mpsc::Sender<WsEnvelope>,
+    spool: Option<&Spool>,
 ) {
     match envelope.payload {
         WsPayload::TargetAssignment(assignment) => {
             for target in assignment.targets {
+                let mut target = target.into_config();
+                continue_after_spool(&mut target, spool);
                 let _ = target_tx.send(TargetCommand::Add(target)).await;
             }
         }
@@ -171,10 +346,13 @@ async fn handle_server_message(
         }
         WsPayload::ConfigUpdate(update) => {
             tracing::info!(target_id = %update.target_id, "Received config update");
-            // TODO: Update running probe configuration
+            let _ = target_tx
+                .send(TargetCommand::Update { msg_id: envelope.msg_id, update })
+                .await;
         }
         WsPayload::UpdateCommand(cmd) => {
             tracing::info!(version = %cmd.version, "Received update command");
+            let config = config_rx.borrow();
             let server_url = config.server_url.clone();
             let agent_id: uuid::Uuid = config.agent_id.parse().unwrap_or_default();
             let tx = outgoing_tx.clone();
@@ -182,6 +360,13 @@ async fn handle_server_message(
                 crate::updater::perform_update(cmd, server_url, agent_id, tx).await;
             });
         }
+        WsPayload::DiscoveryScan(cmd) => {
+            let config = config_rx.borrow();
+            let agent_id: uuid::Uuid = config.agent_id.parse().unwrap_or_default();
+            let discovery = config.discovery.clone();
+            let tx = outgoing_tx.clone();
+            tokio::spawn(crate::discovery::run_scan(cmd, agent_id, discovery, tx));
+        }
         _ => {
             tracing::debug!("Unhandled server message");
         }
@@ -0,0 +1,423 @@
+//! Local network discovery, run when the server sends a `DiscoveryScan`.
+//!
+//! Every address of the agent's IPv4 subnets (or of the subnets the server
+//! asked for) is pinged, and each IPv6 interface sends one all-nodes echo.
+//! The OS neighbor table is read afterwards: hosts that drop ICMP still
+//! answer ARP or NDP, so they show up there, together with every host's MAC
+//! address. Live hosts are then named by reverse DNS, mDNS or NetBIOS. The
+//! server adds OUI vendors from its own databases.
+
+mod names;
+mod neighbors;
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::Utc;
+use futures_util::{stream, StreamExt};
+use nm_common::config::AgentDiscoveryConfig;
+use nm_common::protocol::{
+    DiscoveredHost, DiscoveryScanCommand, NetworkDiscoveryReport, WsEnvelope, WsPayload,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::probe::ProbeResult;
+use neighbors::{LocalAddress, Neighbor};
+
+/// Echo requests in flight at once during the sweep.
+const MAX_CONCURRENT_PINGS: usize = 64;
+
+/// Hosts on the local link answer within milliseconds.
+const PING_TIMEOUT_MS: u64 = 1000;
+const PING_TTL: u8 = 64;
+const PING_SIZE: u16 = 64;
+
+/// Hosts being named at once.
+const MAX_CONCURRENT_NAME_LOOKUPS: usize = 32;
+
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+static SCAN_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Run one scan and send its report. Only one scan runs at a time; a request
+/// arriving meanwhile is answered with an error report.
+pub async fn run_scan(
+    cmd: DiscoveryScanCommand,
+    agent_id: Uuid,
+    config: AgentDiscoveryConfig,
+    outgoing_tx: mpsc::Sender<WsEnvelope>,
+) {
+    let started_at = Utc::now();
+    tracing::info!(scan_id = %cmd.scan_id, subnets = ?cmd.subnets, "Network discovery scan starting");
+
+    let result = if !config.enabled {
+        Err("discovery is disabled in the agent config".to_string())
+    } else if let Some(_running) = ScanGuard::acquire() {
+        scan(&cmd.subnets, config.max_hosts).await
+    } else {
+        Err("another discovery scan is running".to_string())
+    };
+
+    let (subnets, devices, error) = match result {
+        Ok((subnets, devices)) => {
+            tracing::info!(scan_id = %cmd.scan_id, devices = devices.len(), "Network discovery scan finished");
+            (subnets.iter().map(Subnet::to_string).collect(), devices, None)
+        }
+        Err(e) => {
+            tracing::warn!(scan_id = %cmd.scan_id, error = %e, "Network discovery scan failed");
+            (Vec::new(), Vec::new(), Some(e))
+        }
+    };
+
+    let report = NetworkDiscoveryReport {
+        scan_id: cmd.scan_id,
+        agent_id,
+        started_at,
+        finished_at: Utc::now(),
+        subnets,
+        devices,
+        error,
+    };
+    if outgoing_tx
+        .send(WsEnvelope::new(WsPayload::NetworkDiscovery(report)))
+        .await
+        .is_err()
+    {
+        tracing::warn!("Failed to queue discovery report (connection down?)");
+    }
+}
+
+/// Marks a scan as running until dropped.
+struct ScanGuard;
+
+impl ScanGuard {
+    fn acquire() -> Option<Self> {
+        (!SCAN_RUNNING.swap(true, Ordering::AcqRel)).then_some(ScanGuard)
+    }
+}
+
+impl Drop for ScanGuard {
+    fn drop(&mut self) {
+        SCAN_RUNNING.store(false, Ordering::Release);
+    }
+}
+
+async fn scan(requested: &[String], max_hosts: u32) -> Result<(Vec<Subnet>, Vec<DiscoveredHost>), String> {
+    let local = neighbors::local_addresses();
+    let own: HashSet<IpAddr> = local.iter().map(|a| a.ip).collect();
+
+    let subnets = if requested.is_empty() {
+        local_subnets(&local, max_hosts)
+    } else {
+        requested
+            .iter()
+            .map(|s| {
+                let subnet = Subnet::parse(s).ok_or_else(|| format!("invalid subnet \"{s}\""))?;
+                fit_subnet(subnet, &local, max_hosts)
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    if subnets.is_empty() {
+        return Err("no subnets to scan".to_string());
+    }
+    let in_scope = |ip: &IpAddr| !own.contains(ip) && subnets.iter().any(|s| s.contains(*ip));
+
+    // Subnets too large to sweep (IPv6 prefixes) rely on NDP alone.
+    let sweep: Vec<IpAddr> = subnets
+        .iter()
+        .filter(|s| s.host_count() <= max_hosts as u128)
+        .flat_map(Subnet::hosts)
+        .filter(|ip| !own.contains(ip))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .take(max_hosts as usize)
+        .collect();
+
+    for addr in local.iter().filter(|a| a.ip.is_ipv6() && subnets.iter().any(|s| s.contains(a.ip))) {
+        solicit(addr).await;
+    }
+    let mut latencies = ping_all(sweep).await;
+
+    // IPv6 hosts are only known from the neighbor table; ping them as well.
+    let ipv6_neighbors = read_neighbors()
+        .await
+        .into_iter()
+        .map(|n| n.ip)
+        .filter(|ip| ip.is_ipv6() && in_scope(ip))
+        .collect::<BTreeSet<_>>();
+    latencies.extend(ping_all(ipv6_neighbors.into_iter().collect()).await);
+
+    let mut hosts: BTreeMap<IpAddr, (Option<[u8; 6]>, Option<u32>)> = latencies
+        .into_iter()
+        .map(|(ip, rtt_us)| (ip, (None, Some(rtt_us))))
+        .collect();
+    for neighbor in read_neighbors().await {
+        // Multicast and broadcast entries are not hosts
+        if !in_scope(&neighbor.ip) || neighbor.mac[0] & 1 != 0 {
+            continue;
+        }
+        if neighbor.reachable || hosts.contains_key(&neighbor.ip) {
+            hosts.entry(neighbor.ip).or_default().0 = Some(neighbor.mac);
+        }
+    }
+
+    let devices = stream::iter(hosts)
+        .map(|(ip, (mac, latency_us))| async move {
+            DiscoveredHost {
+                ip_address: ip.to_string(),
+                mac_address: mac.map(format_mac),
+                hostname: names::lookup(ip).await,
+                latency_us,
+            }
+        })
+        .buffered(MAX_CONCURRENT_NAME_LOOKUPS)
+        .collect()
+        .await;
+
+    Ok((subnets, devices))
+}
+
+/// The subnets of the agent's own addresses, IPv4 ones narrowed to
+/// `max_hosts` around the agent. Link-local IPv6 prefixes are left out:
+/// those addresses need a scope to be probed or added as targets.
+fn local_subnets(local: &[LocalAddress], max_hosts: u32) -> Vec<Subnet> {
+    let mut subnets = Vec::new();
+    for addr in local {
+        let is_link_local = matches!(addr.ip, IpAddr::V6(v6) if (v6.segments()[0] & 0xffc0) == 0xfe80);
+        if is_link_local || addr.prefix_len >= max_prefix_len(addr.ip) {
+            continue;
+        }
+        let mut subnet = Subnet::new(addr.ip, addr.prefix_len);
+        if addr.ip.is_ipv4() {
+            subnet = subnet.narrow(addr.ip, max_hosts);
+        }
+        if !subnets.contains(&subnet) {
+            subnets.push(subnet);
+        }
+    }
+    subnets
+}
+
+/// A requested IPv4 subnet larger than `max_hosts` is narrowed around the
+/// agent's address when the agent is in it, and refused otherwise.
+fn fit_subnet(subnet: Subnet, local: &[LocalAddress], max_hosts: u32) -> Result<Subnet, String> {
+    if subnet.network.is_ipv6() || subnet.host_count() <= max_hosts as u128 {
+        return Ok(subnet);
+    }
+    match local.iter().find(|a| subnet.contains(a.ip)) {
+        Some(addr) => Ok(subnet.narrow(addr.ip, max_hosts)),
+        None => Err(format!("{subnet} has more than max_hosts ({max_hosts}) addresses")),
+    }
+}
+
+/// Round trip of every address that answered an echo request.
+async fn ping_all(addrs: Vec<IpAddr>) -> Vec<(IpAddr, u32)> {
+    stream::iter(addrs)
+        .map(|ip| async move {
+            let result = crate::probe::send_echo(ip, PING_TTL, PING_SIZE, PING_TIMEOUT_MS).await;
+            match result {
+                ProbeResult { responding_ip: Some(from), rtt_us: Some(rtt_us), .. } if from == ip => Some((ip, rtt_us)),
+                _ => None,
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_PINGS)
+        .filter_map(|answered| async move { answered })
+        .collect()
+        .await
+}
+
+async fn read_neighbors() -> Vec<Neighbor> {
+    match tokio::task::spawn_blocking(neighbors::neighbor_table).await {
+        Ok(Ok(neighbors)) => neighbors,
+        Ok(Err(e)) => {
+            tracing::warn!("Cannot read the neighbor table: {e}");
+            Vec::new()
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+/// `solicit_all_nodes` from an IPv6 address, in the probe helper if there is one.
+async fn solicit(addr: &LocalAddress) {
+    let IpAddr::V6(source) = addr.ip else {
+        return;
+    };
+    #[cfg(target_os = "linux")]
+    if let Some(helper) = crate::privsep::client() {
+        helper.solicit_all_nodes(source, addr.if_index).await;
+        return;
+    }
+    solicit_all_nodes(source, addr.if_index);
+}
+
+/// Send one ICMPv6 echo request to ff02::1 from `source`. Every host on the
+/// link answers to that source address and resolves it through NDP first,
+/// which leaves the host's own address of the same scope in the neighbor
+/// table.
+pub(crate) fn solicit_all_nodes(source: Ipv6Addr, if_index: u32) {
+    let send = || -> std::io::Result<()> {
+        let socket = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?;
+        socket.bind(&SocketAddr::V6(SocketAddrV6::new(source, 0, 0, 0)).into())?;
+        socket.set_multicast_if_v6(if_index)?;
+        // Echo request; the kernel fills in the checksum of ICMPv6 raw sockets
+        let packet = [128, 0, 0, 0, b'n', b'm', 0, 1];
+        let dest = SocketAddr::V6(SocketAddrV6::new(ALL_NODES, 0, 0, if_index));
+        socket.send_to(&packet, &dest.into())?;
+        Ok(())
+    };
+    if let Err(e) = send() {
+        tracing::debug!(source = %source, error = %e, "All-nodes echo failed");
+    }
+}
+
+fn format_mac(mac: [u8; 6]) -> String {
+    mac.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(":")
+}
+
+fn max_prefix_len(ip: IpAddr) -> u8 {
+    if ip.is_ipv4() {
+        32
+    } else {
+        128
+    }
+}
+
+/// An address block; `network` has its host bits cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Subnet {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Subnet {
+    fn new(addr: IpAddr, prefix_len: u8) -> Self {
+        let max = max_prefix_len(addr);
+        let prefix_len = prefix_len.min(max);
+        let mask = u128::MAX.checked_shl((max - prefix_len) as u32).unwrap_or(0);
+        Self {
+            network: from_bits(addr.is_ipv4(), to_bits(addr) & mask),
+            prefix_len,
+        }
+    }
+
+    /// "a.b.c.d/n" or "x::/n"; a bare address is a single host.
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr.parse::<IpAddr>().ok()?, Some(len.parse::<u8>().ok()?)),
+            None => (s.parse::<IpAddr>().ok()?, None),
+        };
+        let max = max_prefix_len(addr);
+        let prefix_len = prefix_len.unwrap_or(max);
+        (prefix_len <= max).then(|| Self::new(addr, prefix_len))
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        ip.is_ipv4() == self.network.is_ipv4() && Self::new(ip, self.prefix_len).network == self.network
+    }
+
+    fn size(&self) -> u128 {
+        1u128
+            .checked_shl((max_prefix_len(self.network) - self.prefix_len) as u32)
+            .unwrap_or(u128::MAX)
+    }
+
+    /// IPv4 subnets larger than /31 lose their network and broadcast address.
+    fn has_broadcast(&self) -> bool {
+        self.network.is_ipv4() && self.prefix_len < 31
+    }
+
+    fn host_count(&self) -> u128 {
+        if self.has_broadcast() {
+            self.size() - 2
+        } else {
+            self.size()
+        }
+    }
+
+    fn hosts(&self) -> impl Iterator<Item = IpAddr> {
+        let v4 = self.network.is_ipv4();
+        let first = to_bits(self.network);
+        let last = first.saturating_add(self.size() - 1);
+        let (first, last) = if self.has_broadcast() { (first + 1, last - 1) } else { (first, last) };
+        (first..=last).map(move |bits| from_bits(v4, bits))
+    }
+
+    /// The block of at most `max_hosts` addresses around `addr`.
+    fn narrow(self, addr: IpAddr, max_hosts: u32) -> Self {
+        let mut subnet = self;
+        while subnet.host_count() > max_hosts as u128 && subnet.prefix_len < max_prefix_len(addr) {
+            subnet = Subnet::new(addr, subnet.prefix_len + 1);
+        }
+        subnet
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+fn to_bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => u32::from(v4) as u128,
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+fn from_bits(v4: bool, bits: u128) -> IpAddr {
+    if v4 {
+        IpAddr::V4(Ipv4Addr::from(bits as u32))
+    } else {
+        IpAddr::V6(Ipv6Addr::from(bits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subnets() {
+        let subnet = Subnet::parse("192.168.1.77/24").unwrap();
+        assert_eq!(subnet.to_string(), "192.168.1.0/24");
+        assert!(subnet.contains("192.168.1.255".parse().unwrap()));
+        assert!(!subnet.contains("192.168.2.1".parse().unwrap()));
+        assert!(!subnet.contains("::1".parse().unwrap()));
+        assert_eq!(subnet.host_count(), 254);
+        let hosts: Vec<IpAddr> = subnet.hosts().collect();
+        assert_eq!(hosts.first(), Some(&"192.168.1.1".parse().unwrap()));
+        assert_eq!(hosts.last(), Some(&"192.168.1.254".parse().unwrap()));
+
+        assert_eq!(Subnet::parse("10.0.0.1").unwrap().hosts().count(), 1);
+        assert_eq!(Subnet::parse("2001:db8::/64").unwrap().to_string(), "2001:db8::/64");
+        assert_eq!(Subnet::parse("10.0.0.0/33"), None);
+        assert_eq!(Subnet::parse("example.com/24"), None);
+    }
+
+    #[test]
+    fn large_subnets_are_narrowed_around_the_agent() {
+        let local = [
+            LocalAddress { ip: "10.20.37.5".parse().unwrap(), prefix_len: 16, if_index: 2 },
+            LocalAddress { ip: "fe80::1".parse().unwrap(), prefix_len: 64, if_index: 2 },
+            LocalAddress { ip: "2001:db8::5".parse().unwrap(), prefix_len: 64, if_index: 2 },
+            LocalAddress { ip: "100.64.0.9".parse().unwrap(), prefix_len: 32, if_index: 3 },
+        ];
+        let subnets: Vec<String> = local_subnets(&local, 1024).iter().map(Subnet::to_string).collect();
+        assert_eq!(subnets, ["10.20.36.0/22", "2001:db8::/64"]);
+
+        let requested = Subnet::parse("10.20.0.0/16").unwrap();
+        assert_eq!(fit_subnet(requested, &local, 254).unwrap().to_string(), "10.20.37.0/24");
+        assert!(fit_subnet(Subnet::parse("172.16.0.0/16").unwrap(), &local, 1024).is_err());
+    }
+
+    #[test]
+    fn formats_mac_addresses() {
+        assert_eq!(format_mac([0x00, 0x1b, 0x21, 0xaa, 0x0b, 0xff]), "00:1b:21:aa:0b:ff");
+    }
+}
@@ -0,0 +1,292 @@
+//! Host names for discovered devices: reverse DNS through the system
+//! resolver, a unicast mDNS PTR query (Apple devices, printers, Linux with
+//! Avahi) and a NetBIOS node status query (Windows, Samba).
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use uuid::Uuid;
+
+/// How long each of the three lookups may take; they run side by side.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+const MDNS_PORT: u16 = 5353;
+const NETBIOS_NS_PORT: u16 = 137;
+
+const DNS_HEADER_LEN: usize = 12;
+const DNS_TYPE_PTR: u16 = 12;
+const DNS_CLASS_IN: u16 = 1;
+/// Top bit of an mDNS question class: answer by unicast (RFC 6762 5.4).
+const MDNS_UNICAST_RESPONSE: u16 = 0x8000;
+
+const NETBIOS_TYPE_NBSTAT: u16 = 0x21;
+const NETBIOS_NAME_ENTRY_LEN: usize = 18;
+const NETBIOS_GROUP_NAME: u16 = 0x8000;
+
+/// The best name known for `ip`: DNS, then mDNS, then NetBIOS.
+pub async fn lookup(ip: IpAddr) -> Option<String> {
+    let (dns, mdns, netbios) = tokio::join!(reverse_dns(ip), mdns(ip), netbios(ip));
+    dns.or(mdns).or(netbios)
+}
+
+async fn reverse_dns(ip: IpAddr) -> Option<String> {
+    let lookup = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip).ok());
+    tokio::time::timeout(QUERY_TIMEOUT, lookup)
+        .await
+        .ok()?
+        .ok()?
+        // Without a PTR record some resolvers hand back the address itself
+        .filter(|name| name.parse::<IpAddr>().is_err())
+}
+
+async fn mdns(ip: IpAddr) -> Option<String> {
+    let id = query_id();
+    let query = dns_query(id, &reverse_name(ip), DNS_TYPE_PTR, DNS_CLASS_IN | MDNS_UNICAST_RESPONSE);
+    let reply = query_udp(SocketAddr::new(ip, MDNS_PORT), id, &query).await?;
+    parse_ptr_answer(&reply)
+}
+
+async fn netbios(ip: IpAddr) -> Option<String> {
+    if !ip.is_ipv4() {
+        return None;
+    }
+    let id = query_id();
+    let reply = query_udp(SocketAddr::new(ip, NETBIOS_NS_PORT), id, &netbios_status_query(id)).await?;
+    parse_netbios_name(&reply)
+}
+
+fn query_id() -> u16 {
+    Uuid::new_v4().as_u128() as u16
+}
+
+/// Send `query` and wait for a reply from `dest` carrying the same id.
+async fn query_udp(dest: SocketAddr, id: u16, query: &[u8]) -> Option<Vec<u8>> {
+    let bind: SocketAddr = match dest {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await.ok()?;
+    socket.connect(dest).await.ok()?;
+    socket.send(query).await.ok()?;
+
+    let mut buf = vec![0u8; 9000];
+    tokio::time::timeout(QUERY_TIMEOUT, async {
+        loop {
+            let n = socket.recv(&mut buf).await.ok()?;
+            if n >= DNS_HEADER_LEN && buf[..2] == id.to_be_bytes() {
+                return Some(buf[..n].to_vec());
+            }
+        }
+    })
+    .await
+    .ok()?
+}
+
+/// The in-addr.arpa / ip6.arpa name of an address.
+fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(v6) => {
+            let mut name = String::with_capacity(72);
+            for byte in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// A DNS message with one question and no records.
+fn dns_query(id: u16, name: &str, qtype: u16, qclass: u16) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(DNS_HEADER_LEN + name.len() + 6);
+    msg.extend_from_slice(&id.to_be_bytes());
+    // flags 0, one question, no answer/authority/additional records
+    msg.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&qclass.to_be_bytes());
+    msg
+}
+
+/// The target of the first PTR record in the answer section.
+fn parse_ptr_answer(msg: &[u8]) -> Option<String> {
+    let questions = be16(msg, 4)?;
+    let answers = be16(msg, 6)?;
+    let mut pos = DNS_HEADER_LEN;
+    for _ in 0..questions {
+        pos = skip_name(msg, pos)? + 4;
+    }
+    for _ in 0..answers {
+        pos = skip_name(msg, pos)?;
+        let rtype = be16(msg, pos)?;
+        let rdata_len = be16(msg, pos + 8)? as usize;
+        let rdata = pos + 10;
+        if rtype == DNS_TYPE_PTR {
+            return read_name(msg, rdata).filter(|name| !name.is_empty());
+        }
+        pos = rdata + rdata_len;
+    }
+    None
+}
+
+/// Position just past the (possibly compressed) name at `pos`.
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(pos)?;
+        if len & 0xc0 == 0xc0 {
+            return Some(pos + 2);
+        }
+        if len == 0 {
+            return Some(pos + 1);
+        }
+        pos += 1 + len as usize;
+    }
+}
+
+/// The dotted name at `pos`, following compression pointers.
+fn read_name(msg: &[u8], mut pos: usize) -> Option<String> {
+    let mut labels = Vec::new();
+    let mut jumps = 0;
+    loop {
+        let len = *msg.get(pos)? as usize;
+        if len & 0xc0 == 0xc0 {
+            // Guard against pointer loops in malformed replies
+            jumps += 1;
+            if jumps > 16 {
+                return None;
+            }
+            pos = ((len & 0x3f) << 8) | *msg.get(pos + 1)? as usize;
+            continue;
+        }
+        if len == 0 {
+            return Some(labels.join("."));
+        }
+        let label = msg.get(pos + 1..pos + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += 1 + len;
+    }
+}
+
+/// A node status request for the wildcard name "*" (RFC 1002 4.2.17).
+fn netbios_status_query(id: u16) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(50);
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    // First-level encoding: each nibble of the 16-byte name as 'A' + nibble
+    let mut name = [0u8; 16];
+    name[0] = b'*';
+    msg.push(32);
+    for byte in name {
+        msg.push(b'A' + (byte >> 4));
+        msg.push(b'A' + (byte & 0x0f));
+    }
+    msg.push(0);
+    msg.extend_from_slice(&NETBIOS_TYPE_NBSTAT.to_be_bytes());
+    msg.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+    msg
+}
+
+/// The machine name from a node status response: the first unique name
+/// with the workstation suffix 0x00.
+fn parse_netbios_name(msg: &[u8]) -> Option<String> {
+    let questions = be16(msg, 4)?;
+    if be16(msg, 6)? == 0 {
+        return None;
+    }
+    let mut pos = DNS_HEADER_LEN;
+    for _ in 0..questions {
+        pos = skip_name(msg, pos)? + 4;
+    }
+    pos = skip_name(msg, pos)?;
+    if be16(msg, pos)? != NETBIOS_TYPE_NBSTAT {
+        return None;
+    }
+    let rdata = pos + 10;
+    let count = *msg.get(rdata)? as usize;
+    (0..count)
+        .map_while(|i| {
+            let start = rdata + 1 + i * NETBIOS_NAME_ENTRY_LEN;
+            msg.get(start..start + NETBIOS_NAME_ENTRY_LEN)
+        })
+        .find(|entry| entry[15] == 0x00 && u16::from_be_bytes([entry[16], entry[17]]) & NETBIOS_GROUP_NAME == 0)
+        .map(|entry| String::from_utf8_lossy(&entry[..15]).trim_end().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+fn be16(msg: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*msg.get(pos)?, *msg.get(pos + 1)?]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_names() {
+        assert_eq!(reverse_name("192.168.1.20".parse().unwrap()), "20.1.168.192.in-addr.arpa");
+        let v6 = reverse_name("2001:db8::1".parse().unwrap());
+        assert!(v6.starts_with("1.0.0.0.0.0.0.0."));
+        assert!(v6.ends_with(".8.b.d.0.1.0.0.2.ip6.arpa"));
+    }
+
+    #[test]
+    fn parses_mdns_ptr_answer() {
+        let question = "20.1.168.192.in-addr.arpa";
+        let mut reply = dns_query(7, question, DNS_TYPE_PTR, DNS_CLASS_IN);
+        reply[2] = 0x84; // response, authoritative
+        reply[7] = 1; // one answer
+        // Answer name compressed to the question at offset 12
+        reply.extend_from_slice(&[0xc0, 12]);
+        reply.extend_from_slice(&DNS_TYPE_PTR.to_be_bytes());
+        reply.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        reply.extend_from_slice(&120u32.to_be_bytes());
+        // "printer" + pointer to a "local" label placed after the record
+        let local_at = reply.len() + 2 + 8 + 2;
+        reply.extend_from_slice(&10u16.to_be_bytes());
+        reply.push(7);
+        reply.extend_from_slice(b"printer");
+        reply.extend_from_slice(&[0xc0, local_at as u8]);
+        reply.push(5);
+        reply.extend_from_slice(b"local");
+        reply.push(0);
+
+        assert_eq!(parse_ptr_answer(&reply).as_deref(), Some("printer.local"));
+        // No answers
+        assert_eq!(parse_ptr_answer(&dns_query(7, question, DNS_TYPE_PTR, DNS_CLASS_IN)), None);
+    }
+
+    #[test]
+    fn parses_netbios_node_status() {
+        let query = netbios_status_query(9);
+        assert_eq!(query.len(), 50);
+        assert_eq!(&query[13..15], b"CK"); // '*' = 0x2a
+
+        // Response: no question, the answer name copied from the query
+        let mut reply = query[..DNS_HEADER_LEN].to_vec();
+        reply[2] = 0x84;
+        reply[5] = 0;
+        reply[7] = 1;
+        reply.extend_from_slice(&query[DNS_HEADER_LEN..DNS_HEADER_LEN + 34]);
+        reply.extend_from_slice(&NETBIOS_TYPE_NBSTAT.to_be_bytes());
+        reply.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        reply.extend_from_slice(&0u32.to_be_bytes());
+        reply.extend_from_slice(&(1 + 2 * NETBIOS_NAME_ENTRY_LEN as u16).to_be_bytes());
+        reply.push(2);
+        for (name, suffix, flags) in [("WORKGROUP", 0x00, 0x8400u16), ("DESKTOP-1", 0x00, 0x0400)] {
+            reply.extend_from_slice(format!("{name:<15}").as_bytes());
+            reply.push(suffix);
+            reply.extend_from_slice(&flags.to_be_bytes());
+        }
+
+        assert_eq!(parse_netbios_name(&reply).as_deref(), Some("DESKTOP-1"));
+    }
+}
@@ -0,0 +1,366 @@
+//! The agent's own interface addresses and the OS neighbor (ARP/NDP) table.
+
+use std::io;
+use std::net::IpAddr;
+
+pub use platform::{local_addresses, neighbor_table};
+
+/// An address on an interface that is up, other than loopback.
+#[derive(Debug, Clone)]
+pub struct LocalAddress {
+    pub ip: IpAddr,
+    pub prefix_len: u8,
+    pub if_index: u32,
+}
+
+/// A neighbor table entry with an Ethernet address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Neighbor {
+    pub ip: IpAddr,
+    pub mac: [u8; 6],
+    /// Confirmed reachable just now, as opposed to a stale or probing entry.
+    pub reachable: bool,
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    //! Addresses come from `getifaddrs`, neighbors from a netlink
+    //! `RTM_GETNEIGH` dump, which covers ARP and NDP entries alike.
+
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    const NLMSG_HDR_LEN: usize = 16;
+    const NDMSG_LEN: usize = 12;
+    // Offset of ndm_state in struct ndmsg
+    const NDMSG_STATE: usize = 8;
+    const NDA_DST: u16 = 1;
+    const NDA_LLADDR: u16 = 2;
+
+    // Neighbor states (include/uapi/linux/neighbour.h)
+    const NUD_INCOMPLETE: u16 = 0x01;
+    const NUD_REACHABLE: u16 = 0x02;
+    const NUD_FAILED: u16 = 0x20;
+    const NUD_NOARP: u16 = 0x40;
+
+    pub fn local_addresses() -> Vec<LocalAddress> {
+        let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+        if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+            tracing::warn!("Cannot list interface addresses: {}", io::Error::last_os_error());
+            return Vec::new();
+        }
+
+        let mut addrs = Vec::new();
+        let mut cur = ifap;
+        while let Some(ifa) = unsafe { cur.as_ref() } {
+            cur = ifa.ifa_next;
+            let flags = ifa.ifa_flags as libc::c_int;
+            if flags & libc::IFF_UP == 0 || flags & libc::IFF_LOOPBACK != 0 {
+                continue;
+            }
+            let (Some(ip), Some(mask)) = (unsafe { sockaddr_ip(ifa.ifa_addr) }, unsafe { sockaddr_ip(ifa.ifa_netmask) })
+            else {
+                continue;
+            };
+            let prefix_len = match mask {
+                IpAddr::V4(mask) => u32::from(mask).count_ones(),
+                IpAddr::V6(mask) => u128::from(mask).count_ones(),
+            };
+            addrs.push(LocalAddress {
+                ip,
+                prefix_len: prefix_len as u8,
+                if_index: unsafe { libc::if_nametoindex(ifa.ifa_name) },
+            });
+        }
+        unsafe { libc::freeifaddrs(ifap) };
+        addrs
+    }
+
+    unsafe fn sockaddr_ip(sa: *const libc::sockaddr) -> Option<IpAddr> {
+        let family = unsafe { sa.as_ref() }?.sa_family as libc::c_int;
+        match family {
+            libc::AF_INET => {
+                let sin = unsafe { &*(sa as *const libc::sockaddr_in) };
+                Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr))))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(sa as *const libc::sockaddr_in6) };
+                Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn neighbor_table() -> io::Result<Vec<Neighbor>> {
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut req = [0u8; NLMSG_HDR_LEN + NDMSG_LEN];
+        req[0..4].copy_from_slice(&((NLMSG_HDR_LEN + NDMSG_LEN) as u32).to_ne_bytes());
+        req[4..6].copy_from_slice(&libc::RTM_GETNEIGH.to_ne_bytes());
+        req[6..8].copy_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+        req[8..12].copy_from_slice(&1u32.to_ne_bytes());
+        // ndmsg: family AF_UNSPEC for both ARP and NDP entries
+        req[NLMSG_HDR_LEN] = libc::AF_UNSPEC as u8;
+
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let sent = unsafe {
+            libc::sendto(
+                fd.as_raw_fd(),
+                req.as_ptr() as *const libc::c_void,
+                req.len(),
+                0,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut neighbors = Vec::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            let mut msgs = &buf[..n as usize];
+            while msgs.len() >= NLMSG_HDR_LEN {
+                let len = u32::from_ne_bytes([msgs[0], msgs[1], msgs[2], msgs[3]]) as usize;
+                let msg_type = u16::from_ne_bytes([msgs[4], msgs[5]]);
+                if len < NLMSG_HDR_LEN || len > msgs.len() {
+                    break;
+                }
+                match msg_type as libc::c_int {
+                    libc::NLMSG_DONE => return Ok(neighbors),
+                    libc::NLMSG_ERROR => {
+                        let errno = msgs
+                            .get(16..20)
+                            .map_or(libc::EIO, |e| -i32::from_ne_bytes([e[0], e[1], e[2], e[3]]));
+                        return Err(io::Error::from_raw_os_error(errno));
+                    }
+                    _ if msg_type == libc::RTM_NEWNEIGH => {
+                        neighbors.extend(parse_neigh_msg(&msgs[NLMSG_HDR_LEN..len]));
+                    }
+                    _ => {}
+                }
+                msgs = &msgs[align4(len).min(msgs.len())..];
+            }
+        }
+    }
+
+    /// One ndmsg with its attributes. None for unresolved and failed
+    /// entries and for links without Ethernet addresses.
+    fn parse_neigh_msg(msg: &[u8]) -> Option<Neighbor> {
+        let state = msg.get(NDMSG_STATE..NDMSG_STATE + 2)?;
+        let state = u16::from_ne_bytes([state[0], state[1]]);
+        if state == 0 || state & (NUD_INCOMPLETE | NUD_FAILED | NUD_NOARP) != 0 {
+            return None;
+        }
+
+        let (mut ip, mut mac) = (None, None);
+        let mut attrs = msg.get(NDMSG_LEN..)?;
+        while attrs.len() >= 4 {
+            let len = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+            let attr_type = u16::from_ne_bytes([attrs[2], attrs[3]]);
+            if len < 4 || len > attrs.len() {
+                break;
+            }
+            let payload = &attrs[4..len];
+            match attr_type {
+                NDA_DST => {
+                    ip = match payload.len() {
+                        4 => Some(IpAddr::from(<[u8; 4]>::try_from(payload).unwrap())),
+                        16 => Some(IpAddr::from(<[u8; 16]>::try_from(payload).unwrap())),
+                        _ => None,
+                    }
+                }
+                NDA_LLADDR => mac = <[u8; 6]>::try_from(payload).ok(),
+                _ => {}
+            }
+            attrs = &attrs[align4(len).min(attrs.len())..];
+        }
+
+        Some(Neighbor {
+            ip: ip?,
+            mac: mac?,
+            reachable: state & NUD_REACHABLE != 0,
+        })
+    }
+
+    fn align4(len: usize) -> usize {
+        (len + 3) & !3
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn neigh_msg(state: u16, dst: &[u8], lladdr: Option<[u8; 6]>) -> Vec<u8> {
+            let mut msg = vec![0u8; NDMSG_LEN];
+            msg[NDMSG_STATE..NDMSG_STATE + 2].copy_from_slice(&state.to_ne_bytes());
+            let mut attr = |attr_type: u16, payload: &[u8]| {
+                msg.extend_from_slice(&(4 + payload.len() as u16).to_ne_bytes());
+                msg.extend_from_slice(&attr_type.to_ne_bytes());
+                msg.extend_from_slice(payload);
+                msg.resize(align4(msg.len()), 0);
+            };
+            attr(NDA_DST, dst);
+            if let Some(mac) = lladdr {
+                attr(NDA_LLADDR, &mac);
+            }
+            msg
+        }
+
+        #[test]
+        fn parses_neighbor_entries() {
+            let mac = [0x00, 0x1b, 0x21, 0x0a, 0x0b, 0x0c];
+            assert_eq!(
+                parse_neigh_msg(&neigh_msg(NUD_REACHABLE, &[192, 168, 1, 20], Some(mac))),
+                Some(Neighbor { ip: IpAddr::from([192, 168, 1, 20]), mac, reachable: true })
+            );
+
+            let v6: Ipv6Addr = "2001:db8::20".parse().unwrap();
+            let stale = parse_neigh_msg(&neigh_msg(0x04, &v6.octets(), Some(mac))).unwrap();
+            assert_eq!(stale.ip, IpAddr::V6(v6));
+            assert!(!stale.reachable);
+
+            // Unresolved entries carry no link-layer address
+            assert_eq!(parse_neigh_msg(&neigh_msg(NUD_INCOMPLETE, &[192, 168, 1, 21], None)), None);
+            assert_eq!(parse_neigh_msg(&neigh_msg(NUD_FAILED, &[192, 168, 1, 22], Some(mac))), None);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    //! Addresses come from `GetAdaptersAddresses`, neighbors from
+    //! `GetIpNetTable2`, which covers ARP and NDP entries alike.
+
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use windows::Win32::Foundation::{ERROR_BUFFER_OVERFLOW, NO_ERROR};
+    use windows::Win32::NetworkManagement::IpHelper::{
+        FreeMibTable, GetAdaptersAddresses, GetIpNetTable2, GAA_FLAG_SKIP_ANYCAST,
+        GAA_FLAG_SKIP_DNS_SERVER, GAA_FLAG_SKIP_MULTICAST, IF_TYPE_SOFTWARE_LOOPBACK,
+        IP_ADAPTER_ADDRESSES_LH, MIB_IPNET_TABLE2,
+    };
+    use windows::Win32::NetworkManagement::Ndis::IfOperStatusUp;
+    use windows::Win32::Networking::WinSock::{
+        NlnsIncomplete, NlnsReachable, NlnsUnreachable, AF_INET, AF_INET6, AF_UNSPEC, SOCKADDR,
+        SOCKADDR_IN, SOCKADDR_IN6,
+    };
+
+    pub fn local_addresses() -> Vec<LocalAddress> {
+        let flags = GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_SKIP_DNS_SERVER;
+        let mut size: u32 = 16 * 1024;
+        // u64 elements keep the adapter structs aligned
+        let mut buf: Vec<u64>;
+        loop {
+            buf = vec![0u64; size as usize / 8 + 1];
+            let ret = unsafe {
+                GetAdaptersAddresses(
+                    AF_UNSPEC.0 as u32,
+                    flags,
+                    None,
+                    Some(buf.as_mut_ptr() as *mut IP_ADAPTER_ADDRESSES_LH),
+                    &mut size,
+                )
+            };
+            if ret == ERROR_BUFFER_OVERFLOW.0 {
+                continue;
+            }
+            if ret != NO_ERROR.0 {
+                tracing::warn!("Cannot list interface addresses: {}", io::Error::from_raw_os_error(ret as i32));
+                return Vec::new();
+            }
+            break;
+        }
+
+        let mut addrs = Vec::new();
+        let mut adapter = buf.as_ptr() as *const IP_ADAPTER_ADDRESSES_LH;
+        while let Some(a) = unsafe { adapter.as_ref() } {
+            adapter = a.Next;
+            if a.OperStatus != IfOperStatusUp || a.IfType == IF_TYPE_SOFTWARE_LOOPBACK {
+                continue;
+            }
+            let mut unicast = a.FirstUnicastAddress;
+            while let Some(u) = unsafe { unicast.as_ref() } {
+                unicast = u.Next;
+                let Some(ip) = (unsafe { sockaddr_ip(u.Address.lpSockaddr) }) else {
+                    continue;
+                };
+                let if_index = match ip {
+                    IpAddr::V4(_) => unsafe { a.Anonymous1.Anonymous.IfIndex },
+                    IpAddr::V6(_) => a.Ipv6IfIndex,
+                };
+                addrs.push(LocalAddress { ip, prefix_len: u.OnLinkPrefixLength, if_index });
+            }
+        }
+        addrs
+    }
+
+    unsafe fn sockaddr_ip(sa: *const SOCKADDR) -> Option<IpAddr> {
+        let family = unsafe { sa.as_ref() }?.sa_family;
+        if family == AF_INET {
+            let sin = unsafe { &*(sa as *const SOCKADDR_IN) };
+            Some(IpAddr::V4(Ipv4Addr::from(unsafe { sin.sin_addr.S_un.S_addr }.to_ne_bytes())))
+        } else if family == AF_INET6 {
+            let sin6 = unsafe { &*(sa as *const SOCKADDR_IN6) };
+            Some(IpAddr::V6(Ipv6Addr::from(unsafe { sin6.sin6_addr.u.Byte })))
+        } else {
+            None
+        }
+    }
+
+    pub fn neighbor_table() -> io::Result<Vec<Neighbor>> {
+        let mut table: *mut MIB_IPNET_TABLE2 = std::ptr::null_mut();
+        let ret = unsafe { GetIpNetTable2(AF_UNSPEC, &mut table) };
+        if ret != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(ret.0 as i32));
+        }
+
+        let rows = unsafe { std::slice::from_raw_parts((*table).Table.as_ptr(), (*table).NumEntries as usize) };
+        let neighbors = rows
+            .iter()
+            .filter(|row| {
+                row.PhysicalAddressLength == 6 && row.State != NlnsUnreachable && row.State != NlnsIncomplete
+            })
+            .filter_map(|row| {
+                let ip = unsafe { sockaddr_ip(&row.Address as *const _ as *const SOCKADDR) }?;
+                Some(Neighbor {
+                    ip,
+                    mac: row.PhysicalAddress[..6].try_into().unwrap(),
+                    reachable: row.State == NlnsReachable,
+                })
+            })
+            .collect();
+        unsafe { FreeMibTable(table as *const _) };
+        Ok(neighbors)
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+mod platform {
+    use super::*;
+
+    pub fn local_addresses() -> Vec<LocalAddress> {
+        Vec::new()
+    }
+
+    pub fn neighbor_table() -> io::Result<Vec<Neighbor>> {
+        Ok(Vec::new())
+    }
+}
@@ -0,0 +1,158 @@
+//! Background enrichment of hop addresses: reverse DNS, ASN and geolocation.
+//!
+//! The scheduler reports every responding hop. The first time a
+//! (session, hop, address) combination is seen, the address is looked up
+//! and the result is sent to the server as a `HopMetadataUpdate`. DNS
+//! answers are cached per address, so a router shared by many targets is
+//! resolved once per `dns_cache_ttl_secs`.
+
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use lru::LruCache;
+use maxminddb::{MaxMindDBError, Reader};
+use nm_common::config::AgentConfig;
+use nm_common::ip_info::IpInfo;
+use nm_common::protocol::{HopMetadataUpdate, WsEnvelope, WsPayload};
+use serde_json::Value;
+use tokio::sync::{mpsc, watch, Semaphore};
+use uuid::Uuid;
+
+use crate::resolver::DnsResolver;
+
+/// Capacity of the channel from the scheduler; sightings that do not fit are
+/// dropped and picked up again in a later round.
+pub const SIGHTING_QUEUE_SIZE: usize = 1024;
+
+/// (session, hop, address) combinations remembered as already enriched.
+const SEEN_CAPACITY: usize = 65_536;
+
+const DNS_CACHE_CAPACITY: usize = 16_384;
+
+/// Reverse lookups can take seconds each; run this many at once.
+const MAX_CONCURRENT_LOOKUPS: usize = 16;
+
+/// A hop address that answered in a probe round.
+pub struct HopSighting {
+    pub session_id: Uuid,
+    pub hop_number: u8,
+    pub ip: IpAddr,
+}
+
+/// Run the enrichment loop until the scheduler side of `sighting_rx` closes.
+pub async fn run(
+    mut config_rx: watch::Receiver<AgentConfig>,
+    mut sighting_rx: mpsc::Receiver<HopSighting>,
+    outgoing_tx: mpsc::Sender<WsEnvelope>,
+) {
+    let mut config = config_rx.borrow_and_update().clone();
+    let mut lookups = Arc::new(Lookups::new(&config));
+    let mut seen: LruCache<(Uuid, u8, IpAddr), ()> =
+        LruCache::new(NonZeroUsize::new(SEEN_CAPACITY).unwrap());
+    let lookup_slots = Arc::new(Semaphore::new(MAX_CONCURRENT_LOOKUPS));
+
+    loop {
+        tokio::select! {
+            sighting = sighting_rx.recv() => {
+                let Some(sighting) = sighting else {
+                    break;
+                };
+                if !config.enrichment.enabled
+                    || seen.put((sighting.session_id, sighting.hop_number, sighting.ip), ()).is_some()
+                {
+                    continue;
+                }
+                let Ok(slot) = lookup_slots.clone().acquire_owned().await else {
+                    break;
+                };
+                let lookups = lookups.clone();
+                let outgoing_tx = outgoing_tx.clone();
+                tokio::spawn(async move {
+                    let update = lookups.lookup(&sighting).await;
+                    drop(slot);
+                    let Some(update) = update else {
+                        return;
+                    };
+                    let envelope = WsEnvelope::new(WsPayload::HopMetadata(update));
+                    if outgoing_tx.send(envelope).await.is_err() {
+                        tracing::warn!("Failed to queue hop metadata (connection down?)");
+                    }
+                });
+            }
+            Ok(()) = config_rx.changed() => {
+                let new_config = config_rx.borrow_and_update().clone();
+                if new_config.enrichment != config.enrichment
+                    || new_config.dns_cache_ttl_secs != config.dns_cache_ttl_secs
+                {
+                    // New databases may know more; look every hop up again.
+                    lookups = Arc::new(Lookups::new(&new_config));
+                    seen.clear();
+                }
+                config = new_config;
+            }
+        }
+    }
+}
+
+/// Reverse DNS plus the configured `.mmdb` databases.
+struct Lookups {
+    dns: DnsResolver,
+    databases: Vec<(String, Reader<Vec<u8>>)>,
+}
+
+impl Lookups {
+    fn new(config: &AgentConfig) -> Self {
+        let databases = config
+            .enrichment
+            .mmdb_files
+            .iter()
+            .filter_map(|path| match Reader::open_readfile(path) {
+                Ok(reader) => {
+                    tracing::info!(path = %path, kind = %reader.metadata.database_type, "Loaded IP database");
+                    Some((path.clone(), reader))
+                }
+                Err(e) => {
+                    tracing::warn!(path = %path, error = %e, "Cannot open IP database, skipping it");
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            dns: DnsResolver::new(DNS_CACHE_CAPACITY, Duration::from_secs(config.dns_cache_ttl_secs)),
+            databases,
+        }
+    }
+
+    /// None when nothing at all is known about the address.
+    async fn lookup(&self, sighting: &HopSighting) -> Option<HopMetadataUpdate> {
+        let hostname = self.dns.reverse_lookup(sighting.ip).await;
+
+        let mut info = IpInfo::default();
+        for (path, reader) in &self.databases {
+            match reader.lookup::<Value>(sighting.ip) {
+                Ok(record) => info.merge_mmdb_record(&record),
+                Err(MaxMindDBError::AddressNotFoundError(_)) => {}
+                Err(e) => tracing::debug!(path = %path, ip = %sighting.ip, error = %e, "IP database lookup failed"),
+            }
+        }
+
+        if hostname.is_none() && info.is_empty() {
+            return None;
+        }
+        Some(HopMetadataUpdate {
+            session_id: sighting.session_id,
+            hop_number: sighting.hop_number,
+            ip_address: sighting.ip.to_canonical().to_string(),
+            hostname,
+            asn: info.asn,
+            as_name: info.as_name,
+            geo_country: info.country,
+            geo_city: info.city,
+            geo_lat: info.lat,
+            geo_lon: info.lon,
+        })
+    }
+}
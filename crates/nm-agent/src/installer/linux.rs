@@ -0,0 +1,301 @@
+//! systemd install: the binary goes to `/usr/local/bin`, the config to
+//! `/etc/nm-agent` (readable by the service user only) and the spool to the
+//! unit's state directory. The service runs as the unprivileged `nm-agent`
+//! user with `CAP_NET_RAW` for raw probe sockets instead of as root.
+
+use std::ffi::CString;
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+use super::{
+    config_file, connection, local_hostname, normalize_server_addr, register_with_server, CLIENT_CERT_FILENAME,
+    CLIENT_KEY_FILENAME,
+};
+use crate::{CONFIG_FILENAME, INSTALL_DIR, SERVICE_NAME};
+
+const BIN_PATH: &str = "/usr/local/bin/nm-agent";
+/// Created by systemd from the unit's `StateDirectory=`.
+const STATE_DIR: &str = "/var/lib/nm-agent";
+const UNIT_DIR: &str = "/etc/systemd/system";
+const SERVICE_USER: &str = "nm-agent";
+
+/// Install the agent: create the service user, copy the binary, register
+/// with the server, write the config and enable+start the systemd unit.
+pub fn install(server: &str, server_fingerprint: Option<&str>) -> Result<()> {
+    println!("Network Master Agent Installer");
+    println!("==============================");
+
+    let server_addr = normalize_server_addr(server);
+    let api_base = format!("http://{}/api/v1", server_addr);
+
+    // 1. Check we can manage system services
+    println!("[1/6] Checking prerequisites...");
+    require_root()?;
+    if !Path::new("/run/systemd/system").exists() {
+        bail!("systemd is not running on this host. Start the agent with `nm-agent run` instead.");
+    }
+
+    // 2. Service account
+    println!("[2/6] Creating service user {}...", SERVICE_USER);
+    if user_exists() {
+        println!("       User already exists.");
+    } else {
+        run(
+            "useradd",
+            &["--system", "--user-group", "--no-create-home", "--home-dir", STATE_DIR, "--shell", nologin_shell(), SERVICE_USER],
+        )?;
+    }
+    let gid = service_gid().context("Service group is missing after creating the user")?;
+
+    // 3. Copy binary, via a rename so a running agent's binary is not written to
+    println!("[3/6] Copying agent binary...");
+    let current_exe = std::env::current_exe()?;
+    if current_exe != Path::new(BIN_PATH) {
+        let staged = format!("{}.new", BIN_PATH);
+        fs::copy(&current_exe, &staged).context("Failed to copy binary to /usr/local/bin")?;
+        fs::set_permissions(&staged, Permissions::from_mode(0o755))?;
+        fs::rename(&staged, BIN_PATH).context("Failed to replace agent binary")?;
+    }
+
+    // 4. Register with server
+    println!("[4/6] Registering with server at {}...", server_addr);
+    let hostname = local_hostname();
+    let registration = register_with_server(&api_base, &hostname, server_fingerprint)?;
+    println!("       Registered as: {} ({})", hostname, &registration.agent_id[..8]);
+
+    // 5. Write config and client certificate; they hold the API key and the
+    // certificate's key, so only root and the service may read them
+    println!("[5/6] Writing configuration...");
+    let config_dir = Path::new(INSTALL_DIR);
+    fs::create_dir_all(config_dir).context("Failed to create config directory")?;
+    fs::set_permissions(config_dir, Permissions::from_mode(0o750))?;
+    std::os::unix::fs::chown(config_dir, Some(0), Some(gid))?;
+    let certificate = registration.client.as_ref().map(|client| &client.certificate);
+    let connection = connection(&server_addr, config_dir, certificate);
+    if let Some(client) = &registration.client {
+        write_private(&config_dir.join(CLIENT_CERT_FILENAME), &client.certificate.cert_pem, gid)
+            .context("Failed to write client certificate")?;
+        write_private(&config_dir.join(CLIENT_KEY_FILENAME), &client.key_pem, gid).context("Failed to write client key")?;
+    }
+    let spool_path = Path::new(STATE_DIR).join("nm-agent.spool");
+    let config_content = config_file(&connection, &registration.agent_id, &registration.api_key, &spool_path);
+    write_private(&config_dir.join(CONFIG_FILENAME), &config_content, gid).context("Failed to write config file")?;
+
+    // 6. Install and start the systemd unit
+    println!("[6/6] Installing systemd service...");
+    let unit = unit_name();
+    fs::write(unit_path(), unit_file()).context("Failed to write systemd unit")?;
+    run("systemctl", &["daemon-reload"])?;
+    run("systemctl", &["enable", "--quiet", &unit])?;
+    // restart rather than start, so a reinstall picks up the new config
+    run("systemctl", &["restart", &unit])?;
+    println!("       Service installed and started.");
+
+    println!();
+    println!("Installation complete!");
+    println!("  Service name: {}", unit);
+    println!("  Binary:       {}", BIN_PATH);
+    println!("  Config:       {}/{}", INSTALL_DIR, CONFIG_FILENAME);
+    println!("  Server:       {}", connection.ws_url);
+    println!();
+    println!("The agent is now running as a background service.");
+    println!("Manage it with: systemctl stop/start {}; logs: journalctl -u {}", SERVICE_NAME, SERVICE_NAME);
+
+    Ok(())
+}
+
+/// Uninstall: stop and remove the unit, delete files, remove the service user.
+pub fn uninstall() -> Result<()> {
+    println!("Network Master Agent Uninstaller");
+    println!("================================");
+    require_root()?;
+
+    // 1. Stop and remove service
+    println!("[1/3] Removing systemd service...");
+    let unit_path = unit_path();
+    if Path::new(&unit_path).exists() {
+        if let Err(e) = run("systemctl", &["disable", "--now", "--quiet", &unit_name()]) {
+            println!("       Warning: {}", e);
+        }
+        fs::remove_file(&unit_path).context("Failed to remove systemd unit")?;
+        run("systemctl", &["daemon-reload"])?;
+        println!("       Service removed.");
+    } else {
+        println!("       Service not found (already removed).");
+    }
+
+    // 2. Remove config, state and binary
+    println!("[2/3] Removing files...");
+    for dir in [INSTALL_DIR, STATE_DIR] {
+        if Path::new(dir).exists() {
+            match fs::remove_dir_all(dir) {
+                Ok(_) => println!("       Removed {}", dir),
+                Err(e) => println!("       Warning: Could not fully remove directory {}: {}", dir, e),
+            }
+        }
+    }
+    if Path::new(BIN_PATH).exists() {
+        match fs::remove_file(BIN_PATH) {
+            Ok(_) => println!("       Removed {}", BIN_PATH),
+            Err(e) => println!("       Warning: Could not remove {}: {}", BIN_PATH, e),
+        }
+    }
+
+    // 3. Remove the service account (userdel also drops its private group)
+    println!("[3/3] Removing service user...");
+    if user_exists() {
+        run("userdel", &[SERVICE_USER])?;
+        println!("       Removed user {}.", SERVICE_USER);
+    } else {
+        println!("       User not found (already removed).");
+    }
+    if service_gid().is_some() {
+        run("groupdel", &[SERVICE_USER])?;
+    }
+
+    println!();
+    println!("Uninstall complete.");
+
+    Ok(())
+}
+
+fn unit_name() -> String {
+    format!("{}.service", SERVICE_NAME)
+}
+
+fn unit_path() -> String {
+    format!("{}/{}", UNIT_DIR, unit_name())
+}
+
+/// Hardened unit: the agent needs raw sockets (`CAP_NET_RAW`), netlink for
+/// neighbor/socket tables and read access to /proc, and nothing else.
+/// `KillSignal=SIGINT` takes the agent's Ctrl+C path so it shuts down cleanly.
+fn unit_file() -> String {
+    format!(
+        r#"# Network Master Agent (generated by `nm-agent install`)
+[Unit]
+Description=Network Master Agent
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=simple
+User={user}
+Group={user}
+ExecStart={bin} --config {config_dir}/{config_file} run
+Restart=always
+RestartSec=5
+KillSignal=SIGINT
+StateDirectory=nm-agent
+StateDirectoryMode=0750
+UMask=0077
+
+# Raw ICMP/TCP probe sockets. Only the probe helper process keeps it; the
+# agent drops it at startup (probe.privileged_helper).
+AmbientCapabilities=CAP_NET_RAW
+CapabilityBoundingSet=CAP_NET_RAW
+NoNewPrivileges=yes
+
+ProtectSystem=strict
+ProtectHome=yes
+PrivateTmp=yes
+PrivateDevices=yes
+ProtectClock=yes
+ProtectHostname=yes
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectKernelLogs=yes
+ProtectControlGroups=yes
+RestrictAddressFamilies=AF_INET AF_INET6 AF_NETLINK AF_UNIX
+RestrictNamespaces=yes
+RestrictRealtime=yes
+RestrictSUIDSGID=yes
+LockPersonality=yes
+MemoryDenyWriteExecute=yes
+SystemCallArchitectures=native
+SystemCallFilter=@system-service
+SystemCallErrorNumber=EPERM
+
+[Install]
+WantedBy=multi-user.target
+"#,
+        user = SERVICE_USER,
+        bin = BIN_PATH,
+        config_dir = INSTALL_DIR,
+        config_file = CONFIG_FILENAME,
+    )
+}
+
+/// Write `path` as root:`gid` 0640 through a fresh temporary file, so secrets
+/// are never readable by others, not even briefly on a reinstall.
+fn write_private(path: &Path, content: &str, gid: u32) -> Result<()> {
+    let mut staged = path.as_os_str().to_owned();
+    staged.push(".new");
+    let _ = fs::remove_file(&staged);
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&staged)?;
+    file.write_all(content.as_bytes())?;
+    std::os::unix::fs::fchown(&file, Some(0), Some(gid))?;
+    file.set_permissions(Permissions::from_mode(0o640))?;
+    file.sync_all()?;
+    fs::rename(&staged, path)?;
+    Ok(())
+}
+
+fn require_root() -> Result<()> {
+    // SAFETY: geteuid has no preconditions.
+    if unsafe { libc::geteuid() } != 0 {
+        bail!("Installing a system service needs root. Run with sudo.");
+    }
+    Ok(())
+}
+
+fn user_exists() -> bool {
+    let name = CString::new(SERVICE_USER).expect("user name has no NUL");
+    // SAFETY: name is a valid C string; the result is only checked for NULL.
+    !unsafe { libc::getpwnam(name.as_ptr()) }.is_null()
+}
+
+fn service_gid() -> Option<u32> {
+    let name = CString::new(SERVICE_USER).expect("group name has no NUL");
+    // SAFETY: name is a valid C string; the entry is read before any other
+    // getgr* call can reuse its storage.
+    unsafe { libc::getgrnam(name.as_ptr()).as_ref() }.map(|group| group.gr_gid)
+}
+
+fn nologin_shell() -> &'static str {
+    ["/usr/sbin/nologin", "/sbin/nologin"]
+        .into_iter()
+        .find(|shell| Path::new(shell).exists())
+        .unwrap_or("/bin/false")
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if !status.success() {
+        bail!("`{} {}` failed ({})", program, args.join(" "), status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_runs_unprivileged_with_raw_sockets() {
+        let unit = unit_file();
+        assert!(unit.contains("ExecStart=/usr/local/bin/nm-agent --config /etc/nm-agent/nm-agent.toml run\n"));
+        assert!(unit.contains("User=nm-agent\n"));
+        assert!(unit.contains("AmbientCapabilities=CAP_NET_RAW\n"));
+        assert!(unit.contains("CapabilityBoundingSet=CAP_NET_RAW\n"));
+        assert!(unit.contains("StateDirectory=nm-agent\n"));
+    }
+}
@@ -0,0 +1,459 @@
+use anyhow::{Context, Result, bail};
+use nm_common::models::AgentCertificate;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+#[cfg(not(target_os = "linux"))]
+use crate::{INSTALL_DIR, CONFIG_FILENAME, SERVICE_NAME};
+
+#[cfg(target_os = "linux")]
+mod linux;
+
+#[cfg(target_os = "linux")]
+pub use linux::{install, uninstall};
+
+/// Client certificate files, written next to the config.
+const CLIENT_CERT_FILENAME: &str = "client.pem";
+const CLIENT_KEY_FILENAME: &str = "client-key.pem";
+
+#[derive(Deserialize)]
+struct RegistrationResponse {
+    agent: AgentInfo,
+    api_key: String,
+    /// Only for a signing request, when the server runs the mTLS agent listener
+    #[serde(default)]
+    certificate: Option<AgentCertificate>,
+}
+
+struct Registration {
+    agent_id: String,
+    api_key: String,
+    /// Only when the installer was given the listener's fingerprint
+    client: Option<ClientCredentials>,
+}
+
+/// A client certificate and the key it was issued for, which never left
+/// this host.
+struct ClientCredentials {
+    certificate: AgentCertificate,
+    key_pem: String,
+}
+
+/// Where the agent connects and with which certificate files.
+struct Connection {
+    ws_url: String,
+    tls: Option<TlsFiles>,
+}
+
+struct TlsFiles {
+    cert: PathBuf,
+    key: PathBuf,
+    server_fingerprint: String,
+}
+
+#[derive(Deserialize)]
+struct AgentInfo {
+    id: String,
+}
+
+/// Install the agent: copy binary, register with server, write config, create+start service.
+#[cfg(not(target_os = "linux"))]
+pub fn install(server: &str, server_fingerprint: Option<&str>) -> Result<()> {
+    println!("Network Master Agent Installer");
+    println!("==============================");
+
+    // Normalize server address
+    let server_addr = normalize_server_addr(server);
+    let api_base = format!("http://{}/api/v1", server_addr);
+
+    // 1. Create install directory
+    println!("[1/5] Creating install directory...");
+    let install_dir = PathBuf::from(INSTALL_DIR);
+    std::fs::create_dir_all(&install_dir)
+        .context("Failed to create install directory. Run as Administrator.")?;
+
+    // 2. Copy binary to install dir
+    println!("[2/5] Copying agent binary...");
+    let current_exe = std::env::current_exe()?;
+    let dest_exe = install_dir.join("nm-agent.exe");
+    // Don't copy over ourselves if already running from install dir
+    if current_exe != dest_exe {
+        std::fs::copy(&current_exe, &dest_exe)
+            .context("Failed to copy binary to install directory")?;
+    }
+
+    // 3. Register with server
+    println!("[3/5] Registering with server at {}...", server_addr);
+    let hostname = local_hostname();
+    let registration = register_with_server(&api_base, &hostname, server_fingerprint)?;
+    println!("       Registered as: {} ({})", hostname, &registration.agent_id[..8]);
+
+    // 4. Write config
+    println!("[4/5] Writing configuration...");
+    let certificate = registration.client.as_ref().map(|client| &client.certificate);
+    let connection = connection(&server_addr, &install_dir, certificate);
+    if let Some(client) = &registration.client {
+        std::fs::write(install_dir.join(CLIENT_CERT_FILENAME), &client.certificate.cert_pem)
+            .context("Failed to write client certificate")?;
+        std::fs::write(install_dir.join(CLIENT_KEY_FILENAME), &client.key_pem)
+            .context("Failed to write client key")?;
+    }
+    let config_path = install_dir.join(CONFIG_FILENAME);
+    let spool_path = install_dir.join("nm-agent.spool");
+    let config_content = config_file(&connection, &registration.agent_id, &registration.api_key, &spool_path);
+    std::fs::write(&config_path, config_content)
+        .context("Failed to write config file")?;
+
+    // 5. Install and start Windows service
+    println!("[5/5] Installing Windows service...");
+    install_windows_service(&dest_exe)?;
+
+    println!();
+    println!("Installation complete!");
+    println!("  Service name: {}", SERVICE_NAME);
+    println!("  Install dir:  {}", INSTALL_DIR);
+    println!("  Server:       {}", server_addr);
+    println!();
+    println!("The agent is now running as a background service.");
+    println!("Manage it with: sc stop/start {}", SERVICE_NAME);
+
+    Ok(())
+}
+
+/// Uninstall: stop service, remove service, delete files.
+#[cfg(not(target_os = "linux"))]
+pub fn uninstall() -> Result<()> {
+    println!("Network Master Agent Uninstaller");
+    println!("================================");
+
+    // 1. Stop and remove service
+    println!("[1/2] Removing Windows service...");
+    remove_windows_service()?;
+
+    // 2. Remove install directory
+    println!("[2/2] Removing files...");
+    let install_dir = PathBuf::from(INSTALL_DIR);
+    if install_dir.exists() {
+        // Give the service a moment to fully stop
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        match std::fs::remove_dir_all(&install_dir) {
+            Ok(_) => println!("       Removed {}", INSTALL_DIR),
+            Err(e) => println!("       Warning: Could not fully remove directory: {}", e),
+        }
+    }
+
+    println!();
+    println!("Uninstall complete.");
+
+    Ok(())
+}
+
+fn normalize_server_addr(server: &str) -> String {
+    let addr = server
+        .trim_start_matches("http://")
+        .trim_start_matches("https://")
+        .trim_start_matches("ws://")
+        .trim_start_matches("wss://")
+        .trim_end_matches('/')
+        .to_string();
+
+    // Add default port if not specified
+    if !addr.contains(':') {
+        format!("{}:8080", addr)
+    } else {
+        addr
+    }
+}
+
+/// The mTLS listener when the server issued a client certificate (pinning
+/// the listener's certificate), otherwise the plain WebSocket endpoint.
+fn connection(server_addr: &str, config_dir: &Path, certificate: Option<&AgentCertificate>) -> Connection {
+    match certificate {
+        Some(cert) => {
+            let host = server_addr.rsplit_once(':').map_or(server_addr, |(host, _)| host);
+            Connection {
+                ws_url: format!("wss://{}:{}/ws/agent", host, cert.tls_port),
+                tls: Some(TlsFiles {
+                    cert: config_dir.join(CLIENT_CERT_FILENAME),
+                    key: config_dir.join(CLIENT_KEY_FILENAME),
+                    server_fingerprint: cert.server_fingerprint.clone(),
+                }),
+            }
+        }
+        None => Connection {
+            ws_url: format!("ws://{}/ws/agent", server_addr),
+            tls: None,
+        },
+    }
+}
+
+/// Config written by the installer; the service logs to its default place.
+fn config_file(connection: &Connection, agent_id: &str, api_key: &str, spool_path: &Path) -> String {
+    let tls = match &connection.tls {
+        Some(files) => format!(
+            r#"
+[tls]
+client_cert = '{}'
+client_key = '{}'
+server_fingerprint = "{}"
+"#,
+            files.cert.display(),
+            files.key.display(),
+            files.server_fingerprint
+        ),
+        None => String::new(),
+    };
+    format!(
+        r#"# Network Master Agent Configuration (auto-generated)
+# Changes to [probe], [traffic], [logging] and [spool] max_mb apply without a restart.
+server_url = "{ws_url}"
+agent_id = "{agent_id}"
+api_key = "{api_key}"
+reconnect_max_delay_secs = 60
+
+[probe]
+default_timeout_ms = 2000
+max_concurrent_probes = 100
+
+[traffic]
+enabled = true
+poll_interval_ms = 5000
+
+[logging]
+level = "info"
+
+[spool]
+path = '{spool_path}'
+max_mb = 64
+{tls}"#,
+        ws_url = connection.ws_url,
+        spool_path = spool_path.display()
+    )
+}
+
+fn local_hostname() -> String {
+    hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Register this host. With `server_fingerprint`, a client key is made here
+/// and only a signing request for it is sent.
+fn register_with_server(api_base: &str, hostname: &str, server_fingerprint: Option<&str>) -> Result<Registration> {
+    let key = match server_fingerprint {
+        Some(fingerprint) => {
+            crate::tls::parse_fingerprint(fingerprint).context("Invalid --server-fingerprint")?;
+            Some(rcgen::KeyPair::generate().context("Failed to generate client key")?)
+        }
+        None => None,
+    };
+    let csr_pem = match &key {
+        Some(key) => Some(rcgen::CertificateParams::default().serialize_request(key)?.pem()?),
+        None => None,
+    };
+
+    let url = format!("{}/agents", api_base);
+    let body = serde_json::json!({ "name": hostname, "csr_pem": csr_pem });
+
+    let client = reqwest::blocking::Client::new();
+    let resp = client
+        .post(&url)
+        .json(&body)
+        .timeout(std::time::Duration::from_secs(10))
+        .send()
+        .context("Failed to connect to server. Is it running?")?;
+
+    if !resp.status().is_success() {
+        bail!(
+            "Server returned error {}: {}",
+            resp.status(),
+            resp.text().unwrap_or_default()
+        );
+    }
+
+    let reg: RegistrationResponse = resp.json().context("Invalid response from server")?;
+    let client = match (key, server_fingerprint) {
+        (Some(key), Some(fingerprint)) => Some(ClientCredentials {
+            certificate: pinned_certificate(reg.certificate, fingerprint)?,
+            key_pem: key.serialize_pem(),
+        }),
+        _ => None,
+    };
+
+    Ok(Registration {
+        agent_id: reg.agent.id,
+        api_key: reg.api_key,
+        client,
+    })
+}
+
+/// The certificate from the registration response, pinning the listener
+/// given on the command line. The response comes over plain HTTP, so the
+/// fingerprint in it is only checked, never trusted.
+fn pinned_certificate(certificate: Option<AgentCertificate>, server_fingerprint: &str) -> Result<AgentCertificate> {
+    let Some(mut certificate) = certificate else {
+        bail!("The server did not issue a client certificate. Is its mTLS agent listener enabled?");
+    };
+    let reported = crate::tls::parse_fingerprint(&certificate.server_fingerprint).ok();
+    if reported != Some(crate::tls::parse_fingerprint(server_fingerprint)?) {
+        bail!(
+            "The server reports listener fingerprint {}, not {}. Check --server-fingerprint against the server log.",
+            certificate.server_fingerprint,
+            server_fingerprint
+        );
+    }
+    certificate.server_fingerprint = server_fingerprint.to_string();
+    Ok(certificate)
+}
+
+#[cfg(windows)]
+fn install_windows_service(exe_path: &PathBuf) -> Result<()> {
+    use std::ffi::OsString;
+    use windows_service::{
+        service::{
+            ServiceAccess, ServiceErrorControl, ServiceInfo, ServiceStartType, ServiceType,
+        },
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    let manager =
+        ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CREATE_SERVICE)
+            .context("Failed to open Service Manager. Run as Administrator.")?;
+
+    // Check if service already exists
+    if let Ok(service) = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS) {
+        // Stop it if running
+        let _ = service.stop();
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        let _ = service.delete();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        drop(service);
+    }
+
+    let service_info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("Network Master Agent"),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: exe_path.clone(),
+        launch_arguments: vec![],
+        dependencies: vec![],
+        account_name: None, // LocalSystem
+        account_password: None,
+    };
+
+    let service = manager
+        .create_service(&service_info, ServiceAccess::START | ServiceAccess::QUERY_STATUS)
+        .context("Failed to create service")?;
+
+    // Set service description
+    let _ = service.set_description("Network Master monitoring agent - collects network trace data");
+
+    // Start it
+    service
+        .start::<String>(&[])
+        .context("Failed to start service")?;
+
+    println!("       Service installed and started.");
+    Ok(())
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn install_windows_service(_exe_path: &PathBuf) -> Result<()> {
+    println!("       Windows service installation skipped (not on Windows).");
+    println!("       Run with: nm-agent run");
+    Ok(())
+}
+
+#[cfg(windows)]
+fn remove_windows_service() -> Result<()> {
+    use windows_service::{
+        service::ServiceAccess,
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    let manager =
+        ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+            .context("Failed to open Service Manager. Run as Administrator.")?;
+
+    match manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::STOP | ServiceAccess::DELETE | ServiceAccess::QUERY_STATUS,
+    ) {
+        Ok(service) => {
+            let _ = service.stop();
+            std::thread::sleep(std::time::Duration::from_secs(2));
+            service.delete().context("Failed to delete service")?;
+            println!("       Service removed.");
+        }
+        Err(_) => {
+            println!("       Service not found (already removed).");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn remove_windows_service() -> Result<()> {
+    println!("       Not on Windows, nothing to remove.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nm_common::config::AgentConfig;
+
+    #[test]
+    fn generated_config_loads() {
+        let connection = connection("10.0.0.1:8080", Path::new("/etc/nm-agent"), None);
+        let config: AgentConfig = toml::from_str(&config_file(
+            &connection,
+            "00000000-0000-0000-0000-000000000001",
+            "key",
+            Path::new("/var/lib/nm-agent/nm-agent.spool"),
+        ))
+        .unwrap();
+        assert_eq!(config.server_url, "ws://10.0.0.1:8080/ws/agent");
+        assert_eq!(config.spool.path, "/var/lib/nm-agent/nm-agent.spool");
+        assert_eq!(config.tls, Default::default());
+    }
+
+    #[test]
+    fn generated_config_pins_mtls_listener() {
+        let cert = AgentCertificate {
+            cert_pem: String::new(),
+            ca_cert_pem: String::new(),
+            server_fingerprint: "ab".repeat(32),
+            tls_port: 8443,
+        };
+        let connection = connection("10.0.0.1:8080", Path::new("/etc/nm-agent"), Some(&cert));
+        let config: AgentConfig = toml::from_str(&config_file(
+            &connection,
+            "00000000-0000-0000-0000-000000000001",
+            "key",
+            Path::new("/var/lib/nm-agent/nm-agent.spool"),
+        ))
+        .unwrap();
+        assert_eq!(config.server_url, "wss://10.0.0.1:8443/ws/agent");
+        assert_eq!(config.tls.client_key.as_deref(), Some("/etc/nm-agent/client-key.pem"));
+        assert_eq!(config.tls.server_fingerprint, Some("ab".repeat(32)));
+    }
+
+    #[test]
+    fn certificate_must_match_the_pinned_listener() {
+        let cert = |fingerprint: &str| AgentCertificate {
+            cert_pem: String::new(),
+            ca_cert_pem: String::new(),
+            server_fingerprint: fingerprint.to_string(),
+            tls_port: 8443,
+        };
+        let pinned = "ab:".repeat(31) + "AB";
+        let certificate = pinned_certificate(Some(cert(&"ab".repeat(32))), &pinned).unwrap();
+        assert_eq!(certificate.server_fingerprint, pinned);
+
+        assert!(pinned_certificate(Some(cert(&"cd".repeat(32))), &pinned).is_err());
+        assert!(pinned_certificate(None, &pinned).is_err());
+    }
+}
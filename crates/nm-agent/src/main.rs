@@ -1,13 +1,22 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use nm_common::config::{AgentConfig, AgentLoggingConfig};
+use tokio::sync::watch;
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
 mod config;
 mod connection;
+mod discovery;
+mod enrichment;
 mod installer;
+#[cfg(target_os = "linux")]
+mod privsep;
 mod probe;
 mod resolver;
 mod scheduler;
+mod spool;
 mod system_info;
+mod tls;
 mod trace_manager;
 mod traffic_monitor;
 mod updater;
@@ -15,9 +24,16 @@ mod updater;
 #[cfg(windows)]
 mod service;
 
+#[cfg(not(target_os = "linux"))]
 const INSTALL_DIR: &str = r"C:\Program Files\NetworkMaster";
+/// Config directory of a systemd install; binary and state live elsewhere.
+#[cfg(target_os = "linux")]
+const INSTALL_DIR: &str = "/etc/nm-agent";
 const CONFIG_FILENAME: &str = "nm-agent.toml";
+#[cfg(not(target_os = "linux"))]
 const SERVICE_NAME: &str = "NetworkMasterAgent";
+#[cfg(target_os = "linux")]
+const SERVICE_NAME: &str = "nm-agent";
 
 #[derive(Parser)]
 #[command(name = "nm-agent", about = "Network Master Agent")]
@@ -36,24 +52,35 @@ struct Args {
 
 #[derive(Subcommand)]
 enum Command {
-    /// Install as a Windows service and auto-register with the server
+    /// Install as a Windows or systemd service and auto-register with the server
     Install {
         /// Server address (IP or hostname, optionally with port)
         #[arg(long)]
         server: String,
+        /// SHA-256 fingerprint of the server's mTLS listener, as logged at
+        /// startup. Gets the agent a client certificate and connects over
+        /// mutual TLS.
+        #[arg(long)]
+        server_fingerprint: Option<String>,
     },
-    /// Uninstall the Windows service and remove files
+    /// Uninstall the service and remove files
     Uninstall,
     /// Run in foreground (not as a service)
     Run,
+    /// Raw-socket probe helper, started by the agent itself
+    #[command(hide = true)]
+    ProbeHelper {
+        #[arg(long, default_value = "info")]
+        log_level: String,
+    },
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
     match args.command {
-        Some(Command::Install { server }) => {
-            installer::install(&server)?;
+        Some(Command::Install { server, server_fingerprint }) => {
+            installer::install(&server, server_fingerprint.as_deref())?;
         }
         Some(Command::Uninstall) => {
             installer::uninstall()?;
@@ -61,6 +88,15 @@ fn main() -> Result<()> {
         Some(Command::Run) => {
             run_foreground(args.config)?;
         }
+        #[cfg(target_os = "linux")]
+        Some(Command::ProbeHelper { log_level }) => {
+            init_logging(&AgentLoggingConfig { level: log_level, file: None }, None)?;
+            privsep::run_helper()?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(Command::ProbeHelper { .. }) => {
+            anyhow::bail!("the probe helper is only used on Linux");
+        }
         None if args.foreground => {
             run_foreground(args.config)?;
         }
@@ -90,28 +126,35 @@ fn main() -> Result<()> {
 }
 
 fn run_foreground(config_path: String) -> Result<()> {
-    let rt = tokio::runtime::Runtime::new()?;
-    rt.block_on(async {
-        // Try install dir config first, then local
-        let cfg_path = if std::path::Path::new(&config_path).exists() {
-            config_path
+    // Try install dir config first, then local
+    let cfg_path = if std::path::Path::new(&config_path).exists() {
+        config_path
+    } else {
+        let install_cfg = std::path::Path::new(INSTALL_DIR).join(CONFIG_FILENAME);
+        if install_cfg.exists() {
+            install_cfg.to_string_lossy().into_owned()
         } else {
-            let install_cfg = format!("{}\\{}", INSTALL_DIR, CONFIG_FILENAME);
-            if std::path::Path::new(&install_cfg).exists() {
-                install_cfg
-            } else {
-                config_path
-            }
-        };
+            config_path
+        }
+    };
+
+    let config = config::load(&cfg_path)?;
+    let log_handle = init_logging(&config.logging, None)?;
 
-        let config = config::load(&cfg_path)?;
+    // The helper has to start while this process is still single-threaded
+    #[cfg(target_os = "linux")]
+    let helper = if config.probe.privileged_helper {
+        Some(privsep::start(&config.logging.level)?)
+    } else {
+        None
+    };
 
-        tracing_subscriber::fmt()
-            .with_env_filter(
-                tracing_subscriber::EnvFilter::try_from_default_env()
-                    .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&config.log_level)),
-            )
-            .init();
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async {
+        #[cfg(target_os = "linux")]
+        if let Some(helper) = helper {
+            privsep::connect(helper)?;
+        }
 
         tracing::info!("Network Master Agent starting in foreground mode");
 
@@ -127,46 +170,122 @@ fn run_foreground(config_path: String) -> Result<()> {
             let _ = shutdown_tx.send(());
         });
 
-        run_agent(config, shutdown_rx).await?;
+        run_agent(config, cfg_path, log_handle, shutdown_rx).await?;
         ctrl_c.abort();
         Ok(())
     })
 }
 
+/// Handle for swapping the log filter of the running subscriber.
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Install the global subscriber, writing to `logging.file` if set, else to
+/// `default_file`, else to stdout. `RUST_LOG` takes precedence over the
+/// configured level.
+pub fn init_logging(logging: &AgentLoggingConfig, default_file: Option<&str>) -> Result<LogFilterHandle> {
+    use tracing_subscriber::fmt::writer::BoxMakeWriter;
+    use tracing_subscriber::prelude::*;
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&logging.level));
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let log_file = logging.file.as_deref().or(default_file);
+    let writer = match log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+            BoxMakeWriter::new(std::sync::Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_ansi(log_file.is_none()).with_writer(writer))
+        .init();
+    Ok(handle)
+}
+
+/// Apply `logging.level` changes from reloaded configs.
+async fn watch_log_level(mut config_rx: watch::Receiver<AgentConfig>, handle: LogFilterHandle) {
+    let mut level = config_rx.borrow_and_update().logging.level.clone();
+    while config_rx.changed().await.is_ok() {
+        let new_level = config_rx.borrow_and_update().logging.level.clone();
+        if new_level == level {
+            continue;
+        }
+        if std::env::var_os("RUST_LOG").is_some() {
+            tracing::warn!("RUST_LOG is set, ignoring logging.level change");
+        } else {
+            match EnvFilter::try_new(&new_level) {
+                Ok(filter) => match handle.reload(filter) {
+                    Ok(()) => tracing::info!(level = %new_level, "Log level changed"),
+                    Err(e) => tracing::warn!("Failed to change log level: {e}"),
+                },
+                Err(e) => tracing::warn!(level = %new_level, "Invalid logging.level: {e}"),
+            }
+        }
+        level = new_level;
+    }
+}
+
 pub async fn run_agent(
-    config: nm_common::config::AgentConfig,
+    config: AgentConfig,
+    config_path: String,
+    log_handle: LogFilterHandle,
     mut shutdown_rx: tokio::sync::oneshot::Receiver<()>,
 ) -> Result<()> {
     let agent_id: uuid::Uuid = config.agent_id.parse()?;
 
     tracing::info!(agent_id = %agent_id, server = %config.server_url, "Connecting to server");
 
+    // Runtime settings follow edits to the config file
+    let (config_tx, config_rx) = watch::channel(config);
+    let watcher_task = tokio::spawn(config::watch(config_path, config_tx));
+    let log_level_task = tokio::spawn(watch_log_level(config_rx.clone(), log_handle));
+
     // Create channels for inter-task communication
     let (outgoing_tx, outgoing_rx) = tokio::sync::mpsc::channel::<nm_common::protocol::WsEnvelope>(1024);
     let (target_tx, target_rx) = tokio::sync::mpsc::channel::<scheduler::TargetCommand>(64);
+    let (sighting_tx, sighting_rx) = tokio::sync::mpsc::channel(enrichment::SIGHTING_QUEUE_SIZE);
+
+    let scheduler_stats = std::sync::Arc::new(scheduler::SchedulerStats::default());
 
     // Spawn connection manager
-    let conn_config = config.clone();
+    let conn_config = config_rx.clone();
     let conn_outgoing_rx = outgoing_rx;
     let conn_target_tx = target_tx;
     let conn_outgoing_tx = outgoing_tx.clone();
+    let conn_stats = scheduler_stats.clone();
     let connection_task = tokio::spawn(async move {
-        connection::run(conn_config, conn_outgoing_rx, conn_target_tx, conn_outgoing_tx).await;
+        connection::run(conn_config, conn_outgoing_rx, conn_target_tx, conn_outgoing_tx, conn_stats).await;
     });
 
     // Spawn probe scheduler
-    let sched_config = config.clone();
+    let sched_config = config_rx.clone();
     let sched_outgoing_tx = outgoing_tx.clone();
     let scheduler_task = tokio::spawn(async move {
-        scheduler::run(sched_config, target_rx, sched_outgoing_tx).await;
+        scheduler::run(sched_config, target_rx, sched_outgoing_tx, scheduler_stats, sighting_tx).await;
+    });
+
+    // Spawn hop enrichment
+    let enrich_config = config_rx.clone();
+    let enrich_outgoing_tx = outgoing_tx.clone();
+    let enrichment_task = tokio::spawn(async move {
+        enrichment::run(enrich_config, sighting_rx, enrich_outgoing_tx).await;
     });
 
     // Spawn traffic monitor
+    let traffic_config = config_rx;
     let traffic_outgoing_tx = outgoing_tx.clone();
     let traffic_task = tokio::spawn(async move {
-        traffic_monitor::run(agent_id, traffic_outgoing_tx).await;
+        traffic_monitor::run(agent_id, traffic_config, traffic_outgoing_tx).await;
     });
 
+    #[cfg(target_os = "linux")]
+    let helper_exited = privsep::exited();
+    #[cfg(not(target_os = "linux"))]
+    let helper_exited = std::future::pending::<()>();
+
     // Wait for shutdown
     tokio::select! {
         _ = &mut shutdown_rx => {
@@ -181,7 +300,15 @@ pub async fn run_agent(
         _ = traffic_task => {
             tracing::error!("Traffic monitor exited unexpectedly");
         }
+        _ = enrichment_task => {
+            tracing::error!("Hop enrichment exited unexpectedly");
+        }
+        _ = helper_exited => {
+            tracing::error!("Probe helper exited unexpectedly");
+        }
     }
+    watcher_task.abort();
+    log_level_task.abort();
 
     Ok(())
 }
@@ -0,0 +1,348 @@
+//! Privilege separation for raw-socket probing (Linux).
+//!
+//! At startup, before any thread exists, the agent starts itself again as
+//! `nm-agent probe-helper` with one end of a Unix socket pair as stdin, and
+//! then drops every capability it holds. The helper keeps only `CAP_NET_RAW`,
+//! sets no_new_privs and installs a seccomp filter, and from then on sends
+//! the probe rounds, echo requests and all-nodes solicitations the agent asks
+//! for. The WebSocket client, the updater and the traffic monitor never run
+//! with privileges.
+//!
+//! Both directions carry length-prefixed msgpack frames tagged with a request
+//! id, so any number of probes can be in flight at once. The helper treats
+//! requests as untrusted and caps their sizes and timeouts.
+
+mod sandbox;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::ops::RangeInclusive;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::{Context, Result};
+use nm_common::protocol::ProbeMethod;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::{mpsc, oneshot, watch, Mutex as AsyncMutex, Semaphore};
+
+use crate::probe::ProbeResult;
+
+/// Largest frame either side accepts.
+const MAX_FRAME_LEN: usize = 1 << 20;
+
+/// Requests the helper works on at once; the rest wait in the socket.
+const MAX_IN_FLIGHT: usize = 4096;
+
+/// Limits the helper applies to every request.
+const MAX_TTL: u8 = 64;
+const MAX_TIMEOUT_MS: u64 = 30_000;
+
+static CLIENT: OnceLock<HelperClient> = OnceLock::new();
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Frame<T> {
+    id: u64,
+    body: T,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Request {
+    Round {
+        method: ProbeMethod,
+        dest: IpAddr,
+        ttls: RangeInclusive<u8>,
+        packet_size: u16,
+        timeout_ms: u64,
+        port: Option<u16>,
+        flow_id: Option<u16>,
+    },
+    Echo {
+        dest: IpAddr,
+        ttl: u8,
+        packet_size: u16,
+        timeout_ms: u64,
+    },
+    SolicitAllNodes {
+        source: Ipv6Addr,
+        if_index: u32,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Response {
+    Round(Vec<ProbeResult>),
+    Echo(ProbeResult),
+    Done,
+}
+
+/// A started helper, before the agent's runtime exists.
+pub struct Helper {
+    child: Child,
+    stream: StdUnixStream,
+}
+
+/// Start the helper, then drop the agent's own capabilities. Must be called
+/// before the agent starts any thread, since capabilities are per thread.
+pub fn start(log_level: &str) -> Result<Helper> {
+    let (stream, helper_end) = StdUnixStream::pair().context("Failed to create probe helper socket")?;
+    let child = Command::new(std::env::current_exe()?)
+        .args(["probe-helper", "--log-level", log_level])
+        .stdin(Stdio::from(OwnedFd::from(helper_end)))
+        .spawn()
+        .context("Failed to start probe helper")?;
+
+    sandbox::drop_all_capabilities()?;
+    tracing::info!(pid = child.id(), "Probe helper started, agent capabilities dropped");
+    Ok(Helper { child, stream })
+}
+
+/// Route probes through the helper from now on. Needs a Tokio runtime.
+pub fn connect(helper: Helper) -> Result<()> {
+    helper.stream.set_nonblocking(true)?;
+    let (reader, writer) = UnixStream::from_std(helper.stream)?.into_split();
+    let (closed_tx, _) = watch::channel(false);
+    let client = HelperClient {
+        next_id: AtomicU64::new(1),
+        pending: Arc::new(Mutex::new(HashMap::new())),
+        writer: AsyncMutex::new(writer),
+        closed: closed_tx,
+    };
+    tokio::spawn(read_responses(reader, client.pending.clone(), client.closed.clone(), helper.child));
+    if CLIENT.set(client).is_err() {
+        anyhow::bail!("probe helper already connected");
+    }
+    Ok(())
+}
+
+/// The helper, if the agent runs with one.
+pub fn client() -> Option<&'static HelperClient> {
+    CLIENT.get()
+}
+
+/// Resolves when the helper has gone away; never without a helper. The agent
+/// cannot start a new one, having dropped its capabilities, so it exits and
+/// lets the service manager restart it.
+pub async fn exited() {
+    match CLIENT.get() {
+        Some(client) => {
+            let _ = client.closed.subscribe().wait_for(|closed| *closed).await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Response>>>>;
+
+pub struct HelperClient {
+    next_id: AtomicU64,
+    pending: Pending,
+    writer: AsyncMutex<tokio::net::unix::OwnedWriteHalf>,
+    closed: watch::Sender<bool>,
+}
+
+impl HelperClient {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn send_round(
+        &self,
+        method: ProbeMethod,
+        dest: IpAddr,
+        ttls: RangeInclusive<u8>,
+        packet_size: u16,
+        timeout_ms: u64,
+        port: Option<u16>,
+        flow_id: Option<u16>,
+    ) -> Vec<ProbeResult> {
+        let request = Request::Round { method, dest, ttls: ttls.clone(), packet_size, timeout_ms, port, flow_id };
+        match self.call(request).await {
+            Some(Response::Round(results)) => results,
+            _ => ttls.map(ProbeResult::lost).collect(),
+        }
+    }
+
+    pub async fn send_echo(&self, dest: IpAddr, ttl: u8, packet_size: u16, timeout_ms: u64) -> ProbeResult {
+        match self.call(Request::Echo { dest, ttl, packet_size, timeout_ms }).await {
+            Some(Response::Echo(result)) => result,
+            _ => ProbeResult::lost(ttl),
+        }
+    }
+
+    pub async fn solicit_all_nodes(&self, source: Ipv6Addr, if_index: u32) {
+        self.call(Request::SolicitAllNodes { source, if_index }).await;
+    }
+
+    async fn call(&self, request: Request) -> Option<Response> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        let sent = write_frame(&mut *self.writer.lock().await, &Frame { id, body: request }).await;
+        if let Err(e) = sent {
+            tracing::debug!("Probe helper request failed: {e}");
+            self.pending.lock().unwrap().remove(&id);
+            return None;
+        }
+        rx.await.ok()
+    }
+}
+
+/// Hand responses to their callers until the helper goes away, then fail
+/// whatever is still waiting.
+async fn read_responses(
+    mut reader: tokio::net::unix::OwnedReadHalf,
+    pending: Pending,
+    closed: watch::Sender<bool>,
+    mut child: Child,
+) {
+    loop {
+        match read_frame::<_, Response>(&mut reader).await {
+            Ok(Some(frame)) => {
+                if let Some(tx) = pending.lock().unwrap().remove(&frame.id) {
+                    let _ = tx.send(frame.body);
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                tracing::error!("Bad message from probe helper: {e}");
+                let _ = child.kill();
+                break;
+            }
+        }
+    }
+    pending.lock().unwrap().clear();
+    let status = tokio::task::spawn_blocking(move || child.wait()).await;
+    tracing::error!(status = ?status, "Probe helper exited");
+    closed.send_replace(true);
+}
+
+/// `nm-agent probe-helper`: serve probe requests from the agent on stdin.
+pub fn run_helper() -> Result<()> {
+    // SAFETY: fd 0 is ours and nothing else in this process uses it.
+    let stream = StdUnixStream::from(unsafe { OwnedFd::from_raw_fd(0) });
+    stream
+        .local_addr()
+        .context("probe-helper is started by the agent and cannot be run by hand")?;
+
+    let raw_sockets = sandbox::restrict_helper()?;
+    tracing::info!(raw_sockets, "Probe helper sandboxed");
+
+    // Single-threaded, so every thread the probes start inherits the sandbox
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    rt.block_on(serve(stream))
+}
+
+async fn serve(stream: StdUnixStream) -> Result<()> {
+    stream.set_nonblocking(true)?;
+    let (mut reader, mut writer) = UnixStream::from_std(stream)?.into_split();
+
+    let (response_tx, mut response_rx) = mpsc::channel::<Frame<Response>>(256);
+    let writer_task = tokio::spawn(async move {
+        while let Some(frame) = response_rx.recv().await {
+            if write_frame(&mut writer, &frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let limit = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    while let Some(frame) = read_frame::<_, Request>(&mut reader).await? {
+        let permit = limit.clone().acquire_owned().await?;
+        let response_tx = response_tx.clone();
+        tokio::spawn(async move {
+            let body = handle(frame.body).await;
+            let _ = response_tx.send(Frame { id: frame.id, body }).await;
+            drop(permit);
+        });
+    }
+
+    // The agent closed its end
+    writer_task.abort();
+    Ok(())
+}
+
+async fn handle(request: Request) -> Response {
+    match request {
+        Request::Round { method, dest, ttls, packet_size, timeout_ms, port, flow_id } => {
+            Response::Round(
+                crate::probe::send_round_local(
+                    method,
+                    dest,
+                    (*ttls.start()).max(1)..=(*ttls.end()).min(MAX_TTL),
+                    packet_size,
+                    timeout_ms.min(MAX_TIMEOUT_MS),
+                    port,
+                    flow_id,
+                )
+                .await,
+            )
+        }
+        Request::Echo { dest, ttl, packet_size, timeout_ms } => Response::Echo(
+            crate::probe::icmp_win::send_icmp_probe(dest, ttl, packet_size, timeout_ms.min(MAX_TIMEOUT_MS), None)
+                .await,
+        ),
+        Request::SolicitAllNodes { source, if_index } => {
+            crate::discovery::solicit_all_nodes(source, if_index);
+            Response::Done
+        }
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin, T: Serialize>(writer: &mut W, frame: &Frame<T>) -> std::io::Result<()> {
+    let body = rmp_serde::to_vec(frame).map_err(std::io::Error::other)?;
+    let mut buf = Vec::with_capacity(4 + body.len());
+    buf.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&body);
+    writer.write_all(&buf).await
+}
+
+/// Next frame, or None at a clean end of stream.
+async fn read_frame<R: AsyncRead + Unpin, T: DeserializeOwned>(reader: &mut R) -> std::io::Result<Option<Frame<T>>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("frame of {len} bytes")));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    rmp_serde::from_slice(&body)
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frames_round_trip() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        let request = Request::Round {
+            method: ProbeMethod::Tcp,
+            dest: "2001:db8::1".parse().unwrap(),
+            ttls: 21..=30,
+            packet_size: 64,
+            timeout_ms: 2000,
+            port: Some(443),
+            flow_id: Some(7),
+        };
+        let writer = tokio::spawn(async move {
+            write_frame(&mut a, &Frame { id: 42, body: request }).await.unwrap();
+        });
+        let frame = read_frame::<_, Request>(&mut b).await.unwrap().unwrap();
+        writer.await.unwrap();
+        assert_eq!(frame.id, 42);
+        assert!(matches!(frame.body, Request::Round { ref ttls, port: Some(443), flow_id: Some(7), .. } if *ttls == (21..=30)));
+        // The writer is gone: clean end of stream
+        assert!(read_frame::<_, Request>(&mut b).await.unwrap().is_none());
+    }
+}
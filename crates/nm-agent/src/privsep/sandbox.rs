@@ -0,0 +1,248 @@
+//! Capability dropping and the helper's seccomp filter.
+//!
+//! Capabilities are per thread, so both sides must call into here before
+//! they start any thread; threads created later inherit the reduced set and
+//! the filter.
+
+use std::io;
+
+use anyhow::{Context, Result};
+
+const CAP_NET_RAW: u32 = 13;
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Drop every capability of the agent process for good: no_new_privs keeps
+/// it from getting any back through an exec.
+pub fn drop_all_capabilities() -> Result<()> {
+    restrict_capabilities(None).context("Failed to drop capabilities")?;
+    set_no_new_privs()
+}
+
+/// Reduce the helper to `CAP_NET_RAW` (if it had it) and install the seccomp
+/// filter. Returns whether raw sockets are available.
+pub fn restrict_helper() -> Result<bool> {
+    let raw = restrict_capabilities(Some(CAP_NET_RAW)).context("Failed to drop capabilities")?;
+    set_no_new_privs()?;
+    install_filter().context("Failed to install seccomp filter")?;
+    Ok(raw)
+}
+
+/// Clear the ambient set, shrink the bounding set (when allowed to) and set
+/// effective = permitted = `keep`, if currently permitted. Returns whether
+/// `keep` was kept.
+fn restrict_capabilities(keep: Option<u32>) -> io::Result<bool> {
+    // SAFETY: prctl with integer arguments only.
+    if unsafe { libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_CLEAR_ALL, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Shrinking the bounding set needs CAP_SETPCAP, which only root has;
+    // without it no_new_privs is what stops an exec from adding capabilities.
+    for cap in 0..64 {
+        if Some(cap) == keep {
+            continue;
+        }
+        // SAFETY: prctl with integer arguments only.
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) } != 0 {
+            match io::Error::last_os_error().raw_os_error() {
+                // Past the last capability this kernel knows
+                Some(libc::EINVAL) => break,
+                Some(libc::EPERM) => break,
+                _ => return Err(io::Error::last_os_error()),
+            }
+        }
+    }
+
+    let mut header = CapHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+    let mut data = [CapData::default(); 2];
+    // SAFETY: header and data have the layout of the v3 capget ABI.
+    if unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let kept = keep.filter(|&cap| data[(cap / 32) as usize].permitted & (1 << (cap % 32)) != 0);
+
+    let mut reduced = [CapData::default(); 2];
+    if let Some(cap) = kept {
+        let bit = 1 << (cap % 32);
+        reduced[(cap / 32) as usize] = CapData { effective: bit, permitted: bit, inheritable: 0 };
+    }
+    let header = CapHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+    // SAFETY: header and reduced have the layout of the v3 capset ABI.
+    if unsafe { libc::syscall(libc::SYS_capset, &header, reduced.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(kept.is_some())
+}
+
+fn set_no_new_privs() -> Result<()> {
+    // SAFETY: prctl with integer arguments only.
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error()).context("Failed to set no_new_privs");
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E; // AUDIT_ARCH_X86_64
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7; // AUDIT_ARCH_AARCH64
+/// x32 syscalls share the x86_64 audit arch and are told apart by this bit.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// `struct seccomp_data` offsets: nr, arch, and the low half of `args[0]`
+/// (both targets are little-endian).
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const SECCOMP_DATA_NR: u32 = 0;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const SECCOMP_DATA_ARCH: u32 = 4;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const SECCOMP_DATA_ARG0: u32 = 16;
+
+/// What the helper does after startup: an async runtime, probe threads and
+/// IP sockets. Files cannot be opened and nothing can be executed.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_close,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    // sockets (socket(2) itself is checked separately; Tokio's signal
+    // handling uses a socketpair)
+    libc::SYS_socketpair,
+    libc::SYS_bind,
+    libc::SYS_connect,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_sendto,
+    libc::SYS_sendmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_recvfrom,
+    libc::SYS_recvmsg,
+    libc::SYS_recvmmsg,
+    libc::SYS_shutdown,
+    // event loop and timers
+    libc::SYS_ppoll,
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_epoll_pwait2,
+    libc::SYS_eventfd2,
+    libc::SYS_pipe2,
+    libc::SYS_futex,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_gettimeofday,
+    // memory
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    // threads and signals
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_prctl,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_restart_syscall,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_tgkill,
+    libc::SYS_getrandom,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+];
+
+/// Allow-list filter; anything else fails with EPERM, and socket(2) is
+/// limited to IPv4 and IPv6.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn install_filter() -> io::Result<()> {
+    use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    let jump = |code: u32, k: u32, jt: u8, jf: u8| libc::sock_filter { code: code as u16, jt, jf, k };
+    let stmt = |code: u32, k: u32| jump(code, k, 0, 0);
+    let allow = stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW);
+    let deny = |errno: i32| stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ERRNO | errno as u32);
+    let kill = stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS);
+
+    let mut program = vec![
+        stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_ARCH),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+        kill,
+        stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_NR),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    program.extend([jump(BPF_JMP | libc::BPF_JSET | BPF_K, X32_SYSCALL_BIT, 0, 1), kill]);
+
+    program.extend([
+        jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_socket as u32, 0, 5),
+        stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_ARG0),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, libc::AF_INET as u32, 2, 0),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, libc::AF_INET6 as u32, 1, 0),
+        deny(libc::EAFNOSUPPORT),
+        allow,
+    ]);
+    for &nr in ALLOWED_SYSCALLS {
+        program.extend([jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1), allow]);
+    }
+    program.push(deny(libc::EPERM));
+
+    let fprog = libc::sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_mut_ptr(),
+    };
+    // SAFETY: fprog points at `program`, which outlives the call; the kernel copies it.
+    let rc = unsafe {
+        libc::prctl(
+            libc::PR_SET_SECCOMP,
+            libc::SECCOMP_MODE_FILTER as libc::c_ulong,
+            &fprog as *const libc::sock_fprog as libc::c_ulong,
+            0,
+            0,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn install_filter() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no seccomp filter for this architecture"))
+}
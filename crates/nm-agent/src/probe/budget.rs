@@ -0,0 +1,148 @@
+//! The agent-wide limit on probes in flight (`max_concurrent_probes`).
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use nm_common::protocol::ProbeMethod;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::ProbeResult;
+
+/// One permit per probe in flight, shared by the regular rounds and the
+/// discovery of every target. A round wider than the limit is probed in
+/// batches of at most the limit, each holding a permit per probe.
+#[derive(Clone)]
+pub struct ProbeBudget {
+    permits: Arc<Semaphore>,
+    limit: Arc<AtomicU32>,
+}
+
+/// How a round is probed, apart from which TTLs.
+#[derive(Debug, Clone, Copy)]
+pub struct RoundProbe {
+    pub method: ProbeMethod,
+    pub dest: IpAddr,
+    pub packet_size: u16,
+    pub timeout_ms: u64,
+    pub port: Option<u16>,
+    pub flow_id: Option<u16>,
+}
+
+impl ProbeBudget {
+    pub fn new(limit: u32) -> Self {
+        let limit = clamp_limit(limit);
+        Self {
+            permits: Arc::new(Semaphore::new(limit as usize)),
+            limit: Arc::new(AtomicU32::new(limit)),
+        }
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Change the limit; returns false if it was already `limit`. Permits
+    /// held by running rounds can only be taken back once released.
+    pub fn set_limit(&self, limit: u32) -> bool {
+        let limit = clamp_limit(limit);
+        let old = self.limit.swap(limit, Ordering::Relaxed);
+        if limit > old {
+            self.permits.add_permits((limit - old) as usize);
+        } else if limit < old {
+            let permits = self.permits.clone();
+            tokio::spawn(async move {
+                if let Ok(permits) = permits.acquire_many_owned(old - limit).await {
+                    permits.forget();
+                }
+            });
+        }
+        limit != old
+    }
+
+    /// Probes in the first batch of a round up to `max_ttl`.
+    pub fn first_batch(&self, max_ttl: u8) -> u32 {
+        batch_len(max_ttl as u16, self.limit())
+    }
+
+    /// Permits for `probes` probes, once they are free.
+    pub async fn acquire(&self, probes: u32) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().acquire_many_owned(probes).await.ok()
+    }
+
+    /// Probe TTLs 1 to `max_ttl` like `probe::send_round`, one batch at a
+    /// time. `held` are permits the caller already holds for the first batch.
+    pub async fn send_round(
+        &self,
+        round: RoundProbe,
+        max_ttl: u8,
+        mut held: Option<OwnedSemaphorePermit>,
+    ) -> Vec<ProbeResult> {
+        let mut results = Vec::with_capacity(max_ttl as usize);
+        let (mut next_ttl, max_ttl) = (1u16, max_ttl as u16);
+        while next_ttl <= max_ttl {
+            let remaining = max_ttl - next_ttl + 1;
+            let permits = match held.take() {
+                Some(permits) => permits,
+                None => match self.acquire(batch_len(remaining, self.limit())).await {
+                    Some(permits) => permits,
+                    None => break,
+                },
+            };
+            let size = permits.num_permits().clamp(1, remaining as usize) as u16;
+            results.extend(
+                super::send_round(
+                    round.method,
+                    round.dest,
+                    next_ttl as u8..=(next_ttl + size - 1) as u8,
+                    round.packet_size,
+                    round.timeout_ms,
+                    round.port,
+                    round.flow_id,
+                )
+                .await,
+            );
+            drop(permits);
+            next_ttl += size;
+        }
+
+        results.extend((next_ttl..=max_ttl).map(|ttl| ProbeResult::lost(ttl as u8)));
+        results
+    }
+}
+
+fn clamp_limit(limit: u32) -> u32 {
+    limit.clamp(1, Semaphore::MAX_PERMITS.min(u32::MAX as usize) as u32)
+}
+
+fn batch_len(remaining: u16, limit: u32) -> u32 {
+    (remaining as u32).min(limit).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_wider_than_the_limit_are_batched() {
+        assert_eq!(batch_len(30, 20), 20);
+        assert_eq!(batch_len(10, 20), 10);
+        assert_eq!(batch_len(0, 20), 1);
+        assert_eq!(ProbeBudget::new(0).limit(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lowered_limit_applies_once_permits_are_released() {
+        let budget = ProbeBudget::new(10);
+        let held = budget.acquire(10).await.unwrap();
+
+        assert!(budget.set_limit(4));
+        assert!(!budget.set_limit(4));
+        drop(held);
+        tokio::task::yield_now().await;
+        assert_eq!(budget.permits.available_permits(), 4);
+
+        assert!(budget.set_limit(6));
+        assert_eq!(budget.permits.available_permits(), 6);
+    }
+}
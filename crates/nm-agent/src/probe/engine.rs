@@ -1,50 +1,46 @@
 use std::net::IpAddr;
 
+use chrono::{DateTime, Utc};
 use nm_common::protocol::{HopSample, TargetConfig, TraceRoundReport};
 use uuid::Uuid;
-use chrono::Utc;
 
+use super::budget::RoundProbe;
 use super::ProbeResult;
 
-/// Execute a full trace round for a target.
-/// Sends probes in parallel to all TTL values.
-pub async fn execute_round(
+/// How a round of `target` is probed: all TTLs of a batch at once
+/// (flow-stable TCP goes one TTL at a time).
+pub fn round_probe(target: &TargetConfig, dest_ip: IpAddr, timeout_ms: u64) -> RoundProbe {
+    RoundProbe {
+        method: target.probe_method,
+        dest: dest_ip,
+        packet_size: target.packet_size,
+        timeout_ms,
+        port: target.probe_port,
+        flow_id: target.flow_stable.then(|| super::flow_id_for(target.target_id)),
+    }
+}
+
+/// The report of a probed round. `sent_at` is the start of the round's slot,
+/// not when its probes went out or timed out, so rounds stay aligned to the
+/// schedule even when some of them wait for silent hops.
+pub fn round_report(
     target: &TargetConfig,
     session_id: Uuid,
     round_number: u64,
-    dest_ip: IpAddr,
-    known_hops: u8,
-    timeout_ms: u64,
+    sent_at: DateTime<Utc>,
+    results: Vec<ProbeResult>,
 ) -> TraceRoundReport {
-    let max_ttl = known_hops.max(target.max_hops).min(30);
-
-    // Send all probes in parallel
-    let mut futures = Vec::with_capacity(max_ttl as usize);
-    for ttl in 1..=max_ttl {
-        let method = target.probe_method;
-        let packet_size = target.packet_size;
-        let port = target.probe_port;
-        futures.push(tokio::spawn(async move {
-            super::send_probe(method, dest_ip, ttl, packet_size, timeout_ms, port).await
-        }));
-    }
-
-    let mut hops = Vec::with_capacity(max_ttl as usize);
-    for (i, future) in futures.into_iter().enumerate() {
-        let result = future.await.unwrap_or(ProbeResult {
-            hop_number: (i + 1) as u8,
-            responding_ip: None,
-            rtt_us: None,
-            timed_out: true,
-            ttl_received: None,
-        });
-
+    let mut hops = Vec::with_capacity(results.len());
+    for result in results {
         hops.push(HopSample {
             hop_number: result.hop_number,
             ip_address: result.responding_ip.map(|ip| ip.to_string()),
             rtt_us: result.rtt_us,
             is_lost: result.timed_out,
             ttl_received: result.ttl_received,
+            tcp_reply: result.tcp_reply,
+            mpls_labels: result.mpls_labels,
+            jitter_us: None,
         });
     }
 
@@ -52,7 +48,14 @@ pub async fn execute_round(
         target_id: target.target_id,
         session_id,
         round_number,
-        sent_at: Utc::now(),
+        sent_at,
         hops,
+        probe_method: Some(target.probe_method),
+        packet_size: Some(target.packet_size),
     }
 }
+
+/// Number of TTLs one round probes, i.e. how many probes it has in flight.
+pub fn round_ttl_count(target: &TargetConfig, known_hops: u8) -> u8 {
+    known_hops.max(target.max_hops).min(30)
+}
@@ -11,10 +11,11 @@ pub async fn send_icmp_probe(
     ttl: u8,
     packet_size: u16,
     timeout_ms: u64,
+    flow_id: Option<u16>,
 ) -> ProbeResult {
     // Run blocking socket operations in a spawn_blocking to avoid blocking the runtime
     let result = tokio::task::spawn_blocking(move || {
-        send_icmp_probe_sync(dest, ttl, packet_size, timeout_ms)
+        send_icmp_probe_sync(dest, ttl, packet_size, timeout_ms, flow_id)
     })
     .await;
 
@@ -26,6 +27,8 @@ pub async fn send_icmp_probe(
             rtt_us: None,
             timed_out: true,
             ttl_received: None,
+            tcp_reply: None,
+            mpls_labels: Vec::new(),
         },
     }
 }
@@ -35,13 +38,14 @@ fn send_icmp_probe_sync(
     ttl: u8,
     packet_size: u16,
     timeout_ms: u64,
+    flow_id: Option<u16>,
 ) -> ProbeResult {
-    let domain = match dest {
-        IpAddr::V4(_) => Domain::IPV4,
-        IpAddr::V6(_) => Domain::IPV6,
+    let (domain, protocol, echo_request, echo_reply, time_exceeded, unreachable) = match dest {
+        IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4, 8, 0, 11, 3),
+        IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6, 128, 129, 3, 1),
     };
 
-    let socket = match Socket::new(domain, Type::RAW, Some(Protocol::ICMPV4)) {
+    let socket = match Socket::new(domain, Type::RAW, Some(protocol)) {
         Ok(s) => s,
         Err(e) => {
             tracing::debug!("Failed to create raw socket: {} (try running as admin)", e);
@@ -51,28 +55,32 @@ fn send_icmp_probe_sync(
                 rtt_us: None,
                 timed_out: true,
                 ttl_received: None,
+                tcp_reply: None,
+                mpls_labels: Vec::new(),
             };
         }
     };
 
-    // Set TTL
-    if socket.set_ttl(ttl as u32).is_err() {
+    // Set TTL / hop limit
+    if super::set_hop_limit(&socket, dest, ttl).is_err() {
         return ProbeResult {
             hop_number: ttl,
             responding_ip: None,
             rtt_us: None,
             timed_out: true,
             ttl_received: None,
+            tcp_reply: None,
+            mpls_labels: Vec::new(),
         };
     }
 
     // Set receive timeout
     let _ = socket.set_read_timeout(Some(Duration::from_millis(timeout_ms)));
 
-    // Build ICMP Echo Request
-    let payload_size = (packet_size as usize).saturating_sub(8).max(0);
-    let mut buf = vec![0u8; 8 + payload_size];
-    buf[0] = 8; // Type: Echo Request
+    // Build ICMP Echo Request (room for the checksum-pinning word in flow mode)
+    let min_size = if flow_id.is_some() { 10 } else { 8 };
+    let mut buf = vec![0u8; (packet_size as usize).max(min_size)];
+    buf[0] = echo_request;
     buf[1] = 0; // Code: 0
 
     // Identifier: use TTL as a simple identifier
@@ -82,9 +90,16 @@ fn send_icmp_probe_sync(
     // Sequence number
     buf[6..8].copy_from_slice(&(ttl as u16).to_be_bytes());
 
-    // Compute checksum
-    let checksum = icmp_checksum(&buf);
-    buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+    // Compute checksum (the kernel computes it for ICMPv6, which covers the pseudo-header)
+    if let Some(flow_id) = flow_id {
+        pin_checksum(&mut buf, flow_checksum(flow_id));
+        if dest.is_ipv6() {
+            buf[2..4].fill(0);
+        }
+    } else if dest.is_ipv4() {
+        let checksum = icmp_checksum(&buf);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
 
     let send_time = Instant::now();
 
@@ -97,6 +112,8 @@ fn send_icmp_probe_sync(
             rtt_us: None,
             timed_out: true,
             ttl_received: None,
+            tcp_reply: None,
+            mpls_labels: Vec::new(),
         };
     }
 
@@ -110,22 +127,28 @@ fn send_icmp_probe_sync(
                 .as_socket()
                 .map(|sa| sa.ip());
 
-            // Parse ICMP response type (after IP header, typically 20 bytes for IPv4)
-            let icmp_offset = if n > 20 { 20 } else { 0 };
+            // Parse ICMP response type (after IP header, typically 20 bytes for IPv4;
+            // raw ICMPv6 sockets never include the IPv6 header)
+            let icmp_offset = if dest.is_ipv4() && n > 20 { 20 } else { 0 };
             let icmp_type = recv_buf.get(icmp_offset).copied().unwrap_or(0);
 
-            // Type 0 = Echo Reply (reached destination)
-            // Type 11 = Time Exceeded (intermediate hop)
-            // Type 3 = Destination Unreachable
-            let is_valid = icmp_type == 0 || icmp_type == 11 || icmp_type == 3;
+            let is_valid =
+                icmp_type == echo_reply || icmp_type == time_exceeded || icmp_type == unreachable;
 
             if is_valid {
+                let mpls_labels = if icmp_type == echo_reply {
+                    Vec::new()
+                } else {
+                    super::mpls::parse_label_stack(&recv_buf[icmp_offset..], dest.is_ipv6())
+                };
                 ProbeResult {
                     hop_number: ttl,
                     responding_ip,
                     rtt_us: Some(rtt.as_micros() as u32),
                     timed_out: false,
                     ttl_received: None,
+                    tcp_reply: None,
+                    mpls_labels,
                 }
             } else {
                 ProbeResult {
@@ -134,6 +157,8 @@ fn send_icmp_probe_sync(
                     rtt_us: None,
                     timed_out: true,
                     ttl_received: None,
+                    tcp_reply: None,
+                    mpls_labels: Vec::new(),
                 }
             }
         }
@@ -143,18 +168,20 @@ fn send_icmp_probe_sync(
             rtt_us: None,
             timed_out: true,
             ttl_received: None,
+            tcp_reply: None,
+            mpls_labels: Vec::new(),
         },
     }
 }
 
-fn icmp_checksum(data: &[u8]) -> u16 {
+pub(super) fn icmp_checksum(data: &[u8]) -> u16 {
     let mut sum: u32 = 0;
     let mut i = 0;
     while i < data.len() - 1 {
         sum += u16::from_be_bytes([data[i], data[i + 1]]) as u32;
         i += 2;
     }
-    if data.len() % 2 != 0 {
+    if !data.len().is_multiple_of(2) {
         sum += (data[data.len() - 1] as u32) << 8;
     }
     while (sum >> 16) != 0 {
@@ -162,3 +189,23 @@ fn icmp_checksum(data: &[u8]) -> u16 {
     }
     !(sum as u16)
 }
+
+/// Checksum value used for every probe of a flow.
+pub(super) fn flow_checksum(flow_id: u16) -> u16 {
+    // Avoid the two one's-complement encodings of zero.
+    flow_id.clamp(1, 0xFFFE)
+}
+
+/// Pin the checksum of an Echo Request (Paris traceroute): rewrite the first
+/// payload word so the message checksums to `checksum` whatever its identifier
+/// and sequence number, then store it. `buf` must be at least 10 bytes.
+pub(super) fn pin_checksum(buf: &mut [u8], checksum: u16) {
+    buf[2..4].fill(0);
+    buf[8..10].fill(0);
+    let sum = !icmp_checksum(buf);
+    // One's-complement difference between the sum we want and the sum we have
+    let mut word = (!checksum) as u32 + (!sum) as u32;
+    word = (word & 0xFFFF) + (word >> 16);
+    buf[8..10].copy_from_slice(&(word as u16).to_be_bytes());
+    buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+}
@@ -0,0 +1,652 @@
+//! Linux ICMP probe engine.
+//!
+//! All ICMP probes in the agent share one socket per address family and one
+//! receive thread per socket. Every probe is given a unique Echo sequence
+//! number and registered in a pending table; the receive thread matches each
+//! reply back to its probe using the identifier and sequence from the Echo
+//! Reply itself, or from the original Echo Request quoted inside Time Exceeded
+//! / Destination Unreachable. The quoted destination must also match the
+//! probe's target, so concurrent rounds for different targets can no longer
+//! steal each other's replies.
+//!
+//! A raw socket is preferred. Without CAP_NET_RAW the engine falls back to an
+//! unprivileged `SOCK_DGRAM` ICMP ("ping") socket, which the kernel allows when
+//! the agent's group is within `net.ipv4.ping_group_range`. On ping sockets the
+//! kernel owns the identifier and delivers ICMP errors through the socket error
+//! queue (`IP_RECVERR` / `IPV6_RECVERR`) instead of the normal receive path.
+//!
+//! The kernel also writes the socket's own identifier into every request and
+//! recomputes the checksum. The engine binds the ping socket up front and
+//! builds requests with that same identifier, so a flow's pinned checksum is
+//! what goes on the wire and flow-stable ICMP holds on either socket kind.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Mutex, Once, OnceLock};
+use std::time::{Duration, Instant};
+
+use nm_common::protocol::MplsLabel;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::sync::oneshot;
+
+use super::linux::{poll_socket, recv_message, set_int_opt, RawMessage};
+use super::mpls::parse_label_stack;
+use super::ProbeResult;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const IPPROTO_ICMP: u8 = 1;
+
+const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+const IPPROTO_ICMPV6: u8 = 58;
+
+static ENGINE_V4: OnceLock<Option<IcmpEngine>> = OnceLock::new();
+static ENGINE_V6: OnceLock<Option<IcmpEngine>> = OnceLock::new();
+static RECEIVER_V4: Once = Once::new();
+static RECEIVER_V6: Once = Once::new();
+
+pub async fn send_icmp_probe(
+    dest: IpAddr,
+    ttl: u8,
+    packet_size: u16,
+    timeout_ms: u64,
+    flow_id: Option<u16>,
+) -> ProbeResult {
+    let fail = ProbeResult {
+        hop_number: ttl,
+        responding_ip: None,
+        rtt_us: None,
+        timed_out: true,
+        ttl_received: None,
+        tcp_reply: None,
+        mpls_labels: Vec::new(),
+    };
+
+    let family = Family::of(dest);
+    let Some(engine) = engine(family) else {
+        return fail;
+    };
+
+    let (seq, rx) = engine.register(dest);
+    let sent_at = match engine.send(dest, ttl, seq, packet_size, flow_id) {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::debug!(dest = %dest, ttl = ttl, error = %e, "ICMP send failed");
+            engine.cancel(seq);
+            return fail;
+        }
+    };
+
+    match tokio::time::timeout(Duration::from_millis(timeout_ms), rx).await {
+        Ok(Ok(reply)) => ProbeResult {
+            hop_number: ttl,
+            responding_ip: Some(reply.responding_ip),
+            rtt_us: Some(reply.received_at.saturating_duration_since(sent_at).as_micros() as u32),
+            timed_out: false,
+            ttl_received: reply.ttl_received,
+            tcp_reply: None,
+            mpls_labels: reply.mpls_labels,
+        },
+        _ => {
+            engine.cancel(seq);
+            fail
+        }
+    }
+}
+
+/// Get the shared engine for a family, opening the socket and starting the
+/// receive thread on first use.
+fn engine(family: Family) -> Option<&'static IcmpEngine> {
+    let (cell, receiver) = match family {
+        Family::V4 => (&ENGINE_V4, &RECEIVER_V4),
+        Family::V6 => (&ENGINE_V6, &RECEIVER_V6),
+    };
+
+    let engine = cell
+        .get_or_init(|| match IcmpEngine::open(family) {
+            Ok(e) => {
+                tracing::info!(family = ?family, kind = ?e.kind, "ICMP probe engine started");
+                Some(e)
+            }
+            Err(e) => {
+                tracing::warn!(
+                    family = ?family,
+                    "Failed to open ICMP socket: {} (grant CAP_NET_RAW or widen net.ipv4.ping_group_range)",
+                    e
+                );
+                None
+            }
+        })
+        .as_ref()?;
+
+    receiver.call_once(|| {
+        let spawned = std::thread::Builder::new()
+            .name(format!("icmp-recv-{:?}", family).to_lowercase())
+            .spawn(move || engine.recv_loop());
+        if let Err(e) = spawned {
+            tracing::error!("Failed to start ICMP receive thread: {}", e);
+        }
+    });
+
+    Some(engine)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    fn of(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SocketKind {
+    /// `SOCK_RAW`: we see every ICMP packet and own the identifier.
+    Raw,
+    /// `SOCK_DGRAM` ping socket: the kernel filters replies and rewrites the identifier.
+    Dgram,
+}
+
+struct Pending {
+    dest: IpAddr,
+    tx: oneshot::Sender<Reply>,
+}
+
+struct Reply {
+    responding_ip: IpAddr,
+    received_at: Instant,
+    ttl_received: Option<u8>,
+    mpls_labels: Vec<MplsLabel>,
+}
+
+/// An ICMP message that answers one of our Echo Requests.
+#[derive(Debug, PartialEq, Eq)]
+struct ParsedReply {
+    icmp_type: u8,
+    source: IpAddr,
+    /// Identifier of the Echo Request, when visible (not on ping sockets).
+    ident: Option<u16>,
+    seq: u16,
+    /// Destination of the original datagram, for ICMP errors.
+    quoted_dest: Option<IpAddr>,
+    /// IP TTL / hop limit of the reply itself, for Echo Replies.
+    ttl: Option<u8>,
+    /// MPLS label stack from the error's extension objects.
+    mpls_labels: Vec<MplsLabel>,
+}
+
+struct IcmpEngine {
+    socket: Socket,
+    family: Family,
+    kind: SocketKind,
+    ident: u16,
+    next_seq: AtomicU16,
+    /// Serializes the set-TTL + send pair on the shared socket.
+    send_lock: Mutex<()>,
+    pending: Mutex<HashMap<u16, Pending>>,
+}
+
+impl IcmpEngine {
+    fn open(family: Family) -> std::io::Result<Self> {
+        let (domain, protocol) = match family {
+            Family::V4 => (Domain::IPV4, Protocol::ICMPV4),
+            Family::V6 => (Domain::IPV6, Protocol::ICMPV6),
+        };
+
+        let (socket, kind, ident) = match Socket::new(domain, Type::RAW, Some(protocol)) {
+            Ok(s) => {
+                // ICMPv6 raw sockets do not deliver the IPv6 header, so ask for the hop limit.
+                if family == Family::V6 {
+                    set_int_opt(&s, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+                }
+                (s, SocketKind::Raw, std::process::id() as u16)
+            }
+            Err(raw_err) => {
+                tracing::debug!("Raw ICMP socket unavailable ({}), trying ping socket", raw_err);
+                let s = Socket::new(domain, Type::DGRAM, Some(protocol))?;
+                match family {
+                    Family::V4 => {
+                        set_int_opt(&s, libc::IPPROTO_IP, libc::IP_RECVERR, 1)?;
+                        set_int_opt(&s, libc::IPPROTO_IP, libc::IP_RECVTTL, 1)?;
+                    }
+                    Family::V6 => {
+                        set_int_opt(&s, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1)?;
+                        set_int_opt(&s, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+                    }
+                }
+                // Binding to port 0 makes the kernel pick the identifier now,
+                // and it reads back as the local port.
+                let any: IpAddr = match family {
+                    Family::V4 => Ipv4Addr::UNSPECIFIED.into(),
+                    Family::V6 => Ipv6Addr::UNSPECIFIED.into(),
+                };
+                s.bind(&SocketAddr::new(any, 0).into())?;
+                let ident = s
+                    .local_addr()?
+                    .as_socket()
+                    .map(|addr| addr.port())
+                    .ok_or_else(|| std::io::Error::other("ping socket has no local port"))?;
+                (s, SocketKind::Dgram, ident)
+            }
+        };
+
+        Ok(Self {
+            socket,
+            family,
+            kind,
+            ident,
+            next_seq: AtomicU16::new(1),
+            send_lock: Mutex::new(()),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Allocate a sequence number that is not currently in flight.
+    fn register(&self, dest: IpAddr) -> (u16, oneshot::Receiver<Reply>) {
+        let (tx, rx) = oneshot::channel();
+        let mut pending = self.pending.lock().unwrap();
+        loop {
+            let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+            if let Entry::Vacant(slot) = pending.entry(seq) {
+                slot.insert(Pending { dest, tx });
+                return (seq, rx);
+            }
+        }
+    }
+
+    fn cancel(&self, seq: u16) {
+        self.pending.lock().unwrap().remove(&seq);
+    }
+
+    fn send(
+        &self,
+        dest: IpAddr,
+        ttl: u8,
+        seq: u16,
+        packet_size: u16,
+        flow_id: Option<u16>,
+    ) -> std::io::Result<Instant> {
+        let packet = build_echo_request(self.family, self.ident, seq, packet_size, flow_id);
+        let addr = SocketAddr::new(dest, 0);
+
+        let _guard = self.send_lock.lock().unwrap();
+        super::set_hop_limit(&self.socket, dest, ttl)?;
+        let sent_at = Instant::now();
+        self.socket.send_to(&packet, &addr.into())?;
+        Ok(sent_at)
+    }
+
+    /// Hand a parsed reply to the probe waiting on its sequence number.
+    fn dispatch(&self, reply: ParsedReply, received_at: Instant) {
+        if let Some(ident) = reply.ident {
+            if ident != self.ident {
+                return;
+            }
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        let Some(entry) = pending.get(&reply.seq) else {
+            return;
+        };
+
+        let matches = match reply.quoted_dest {
+            Some(quoted) => quoted == entry.dest,
+            None => reply.source == entry.dest,
+        };
+        if !matches {
+            return;
+        }
+
+        if let Some(entry) = pending.remove(&reply.seq) {
+            let _ = entry.tx.send(Reply {
+                responding_ip: reply.source,
+                received_at,
+                ttl_received: reply.ttl,
+                mpls_labels: reply.mpls_labels,
+            });
+        }
+    }
+
+    fn recv_loop(&self) {
+        let fd = self.socket.as_raw_fd();
+        loop {
+            let (readable, error_pending) = match poll_socket(fd, None) {
+                Ok(r) => r,
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::Interrupted {
+                        tracing::warn!("ICMP poll failed: {}", e);
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                    continue;
+                }
+            };
+
+            // POLLERR is reported whenever the error queue is non-empty.
+            if error_pending {
+                while let Some(msg) = recv_message(fd, true) {
+                    let received_at = Instant::now();
+                    if let Some(reply) = self.parse(msg, true) {
+                        self.dispatch(reply, received_at);
+                    }
+                }
+            }
+            if readable {
+                while let Some(msg) = recv_message(fd, false) {
+                    let received_at = Instant::now();
+                    if let Some(reply) = self.parse(msg, false) {
+                        self.dispatch(reply, received_at);
+                    }
+                }
+            }
+        }
+    }
+
+    fn parse(&self, msg: RawMessage, errqueue: bool) -> Option<ParsedReply> {
+        match (self.kind, self.family) {
+            (SocketKind::Raw, Family::V4) => parse_raw_reply_v4(&msg.data),
+            (SocketKind::Raw, Family::V6) => {
+                let mut reply = parse_raw_reply_v6(&msg.data, msg.peer?.ip())?;
+                if reply.icmp_type == ICMPV6_ECHO_REPLY {
+                    reply.ttl = msg.hop_limit;
+                }
+                Some(reply)
+            }
+            (SocketKind::Dgram, _) => parse_dgram_reply(self.family, msg, errqueue),
+        }
+    }
+}
+
+/// Build an Echo Request. With a flow id, the checksum is pinned to a value
+/// derived from it, so every probe of the flow hashes the same on ECMP routers.
+/// `ident` must be the identifier the kernel will send (the bound one on ping
+/// sockets), or the kernel's recomputed checksum moves away from the pin.
+fn build_echo_request(
+    family: Family,
+    ident: u16,
+    seq: u16,
+    packet_size: u16,
+    flow_id: Option<u16>,
+) -> Vec<u8> {
+    let min_size = if flow_id.is_some() { 10 } else { 8 };
+    let mut buf = vec![0u8; (packet_size as usize).max(min_size)];
+    buf[0] = match family {
+        Family::V4 => ICMP_ECHO_REQUEST,
+        Family::V6 => ICMPV6_ECHO_REQUEST,
+    };
+    buf[4..6].copy_from_slice(&ident.to_be_bytes());
+    buf[6..8].copy_from_slice(&seq.to_be_bytes());
+
+    if let Some(flow_id) = flow_id {
+        // For ICMPv6 the kernel adds the (constant) pseudo-header to the sum, so
+        // pinning the message sum pins the final checksum as well.
+        super::icmp::pin_checksum(&mut buf, super::icmp::flow_checksum(flow_id));
+        if family == Family::V6 {
+            buf[2..4].fill(0);
+        }
+    } else if family == Family::V4 {
+        // The kernel fills in the ICMPv6 checksum (it covers the pseudo-header).
+        let checksum = super::icmp::icmp_checksum(&buf);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    buf
+}
+
+/// Parse a message from a ping socket. Echo Replies and the error-queue copy
+/// of our Echo Request both start with the ICMP header, and the kernel has
+/// already checked the identifier.
+fn parse_dgram_reply(family: Family, msg: RawMessage, errqueue: bool) -> Option<ParsedReply> {
+    if msg.data.len() < 8 {
+        return None;
+    }
+    let seq = u16::from_be_bytes([msg.data[6], msg.data[7]]);
+
+    if errqueue {
+        let error = msg.error?;
+        return Some(ParsedReply {
+            icmp_type: error.icmp_type,
+            source: error.offender,
+            ident: None,
+            seq,
+            // The error queue reports the original destination as the peer.
+            quoted_dest: msg.peer.map(|p| p.ip()),
+            ttl: None,
+            // Only the quoted datagram reaches the error queue, not the
+            // extension objects after it.
+            mpls_labels: Vec::new(),
+        });
+    }
+
+    let echo_reply = match family {
+        Family::V4 => ICMP_ECHO_REPLY,
+        Family::V6 => ICMPV6_ECHO_REPLY,
+    };
+    if msg.data[0] != echo_reply {
+        return None;
+    }
+    Some(ParsedReply {
+        icmp_type: echo_reply,
+        source: msg.peer?.ip(),
+        ident: None,
+        seq,
+        quoted_dest: None,
+        ttl: msg.hop_limit,
+        mpls_labels: Vec::new(),
+    })
+}
+
+/// Parse a packet read from a raw ICMPv4 socket (IPv4 header included).
+fn parse_raw_reply_v4(data: &[u8]) -> Option<ParsedReply> {
+    let (ip_header_len, ttl, source) = parse_ipv4_header(data)?;
+    let icmp = data.get(ip_header_len..)?;
+    if icmp.len() < 8 {
+        return None;
+    }
+
+    match icmp[0] {
+        ICMP_ECHO_REPLY => Some(ParsedReply {
+            icmp_type: ICMP_ECHO_REPLY,
+            source: IpAddr::V4(source),
+            ident: Some(u16::from_be_bytes([icmp[4], icmp[5]])),
+            seq: u16::from_be_bytes([icmp[6], icmp[7]]),
+            quoted_dest: None,
+            ttl: Some(ttl),
+            mpls_labels: Vec::new(),
+        }),
+        icmp_type @ (ICMP_TIME_EXCEEDED | ICMP_DEST_UNREACHABLE) => {
+            // The error quotes our original IPv4 header plus at least 8 bytes of the Echo Request.
+            let inner = &icmp[8..];
+            let (inner_header_len, _, _) = parse_ipv4_header(inner)?;
+            if inner[9] != IPPROTO_ICMP {
+                return None;
+            }
+            let quoted_dest = Ipv4Addr::new(inner[16], inner[17], inner[18], inner[19]);
+            let echo = inner.get(inner_header_len..)?;
+            if echo.len() < 8 || echo[0] != ICMP_ECHO_REQUEST {
+                return None;
+            }
+            Some(ParsedReply {
+                icmp_type,
+                source: IpAddr::V4(source),
+                ident: Some(u16::from_be_bytes([echo[4], echo[5]])),
+                seq: u16::from_be_bytes([echo[6], echo[7]]),
+                quoted_dest: Some(IpAddr::V4(quoted_dest)),
+                ttl: None,
+                mpls_labels: parse_label_stack(icmp, false),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Parse a packet read from a raw ICMPv6 socket. The kernel strips the IPv6
+/// header, so the source comes from the socket address.
+fn parse_raw_reply_v6(icmp: &[u8], source: IpAddr) -> Option<ParsedReply> {
+    if icmp.len() < 8 {
+        return None;
+    }
+
+    match icmp[0] {
+        ICMPV6_ECHO_REPLY => Some(ParsedReply {
+            icmp_type: ICMPV6_ECHO_REPLY,
+            source,
+            ident: Some(u16::from_be_bytes([icmp[4], icmp[5]])),
+            seq: u16::from_be_bytes([icmp[6], icmp[7]]),
+            quoted_dest: None,
+            ttl: None,
+            mpls_labels: Vec::new(),
+        }),
+        icmp_type @ (ICMPV6_TIME_EXCEEDED | ICMPV6_DEST_UNREACHABLE) => {
+            // The error quotes as much of the original packet as fits; we only
+            // need the fixed IPv6 header and the first 8 bytes of the Echo Request.
+            let inner = &icmp[8..];
+            if inner.len() < 48 || inner[0] >> 4 != 6 || inner[6] != IPPROTO_ICMPV6 {
+                return None;
+            }
+            let mut dest = [0u8; 16];
+            dest.copy_from_slice(&inner[24..40]);
+            let echo = &inner[40..];
+            if echo[0] != ICMPV6_ECHO_REQUEST {
+                return None;
+            }
+            Some(ParsedReply {
+                icmp_type,
+                source,
+                ident: Some(u16::from_be_bytes([echo[4], echo[5]])),
+                seq: u16::from_be_bytes([echo[6], echo[7]]),
+                quoted_dest: Some(IpAddr::V6(Ipv6Addr::from(dest))),
+                ttl: None,
+                mpls_labels: parse_label_stack(icmp, true),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Returns (header length, TTL, source address) of an IPv4 header.
+pub(super) fn parse_ipv4_header(data: &[u8]) -> Option<(usize, u8, Ipv4Addr)> {
+    if data.len() < 20 || data[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = ((data[0] & 0x0F) as usize) * 4;
+    if header_len < 20 || data.len() < header_len {
+        return None;
+    }
+    Some((header_len, data[8], Ipv4Addr::new(data[12], data[13], data[14], data[15])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_header(src: Ipv4Addr, dst: Ipv4Addr, ttl: u8, payload_len: usize) -> Vec<u8> {
+        let mut h = vec![0u8; 20];
+        h[0] = 0x45;
+        h[2..4].copy_from_slice(&((20 + payload_len) as u16).to_be_bytes());
+        h[8] = ttl;
+        h[9] = IPPROTO_ICMP;
+        h[12..16].copy_from_slice(&src.octets());
+        h[16..20].copy_from_slice(&dst.octets());
+        h
+    }
+
+    #[test]
+    fn parses_echo_reply() {
+        let dest = Ipv4Addr::new(8, 8, 8, 8);
+        let mut echo = build_echo_request(Family::V4, 0x1234, 42, 64, None);
+        echo[0] = ICMP_ECHO_REPLY;
+        let mut pkt = ipv4_header(dest, Ipv4Addr::new(10, 0, 0, 2), 57, echo.len());
+        pkt.extend_from_slice(&echo);
+
+        let reply = parse_raw_reply_v4(&pkt).unwrap();
+        assert_eq!(reply.icmp_type, ICMP_ECHO_REPLY);
+        assert_eq!(reply.source, IpAddr::V4(dest));
+        assert_eq!(reply.ident, Some(0x1234));
+        assert_eq!(reply.seq, 42);
+        assert_eq!(reply.ttl, Some(57));
+        assert_eq!(reply.quoted_dest, None);
+    }
+
+    #[test]
+    fn parses_time_exceeded_quote() {
+        let router = Ipv4Addr::new(192, 0, 2, 1);
+        let dest = Ipv4Addr::new(8, 8, 8, 8);
+        let me = Ipv4Addr::new(10, 0, 0, 2);
+
+        let echo = build_echo_request(Family::V4, 0x1234, 7, 64, None);
+        let mut quoted = ipv4_header(me, dest, 1, echo.len());
+        quoted.extend_from_slice(&echo[..8]);
+
+        let mut icmp = vec![ICMP_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0];
+        icmp.extend_from_slice(&quoted);
+        let mut pkt = ipv4_header(router, me, 254, icmp.len());
+        pkt.extend_from_slice(&icmp);
+
+        let reply = parse_raw_reply_v4(&pkt).unwrap();
+        assert_eq!(reply.icmp_type, ICMP_TIME_EXCEEDED);
+        assert_eq!(reply.source, IpAddr::V4(router));
+        assert_eq!(reply.ident, Some(0x1234));
+        assert_eq!(reply.seq, 7);
+        assert_eq!(reply.quoted_dest, Some(IpAddr::V4(dest)));
+        assert_eq!(reply.ttl, None);
+    }
+
+    #[test]
+    fn parses_icmpv6_time_exceeded_quote() {
+        let router: IpAddr = "2001:db8::1".parse().unwrap();
+        let dest: Ipv6Addr = "2001:4860:4860::8888".parse().unwrap();
+
+        let echo = build_echo_request(Family::V6, 0xBEEF, 9, 64, None);
+        let mut quoted = vec![0u8; 40];
+        quoted[0] = 0x60;
+        quoted[6] = IPPROTO_ICMPV6;
+        quoted[7] = 1;
+        quoted[24..40].copy_from_slice(&dest.octets());
+        quoted.extend_from_slice(&echo);
+
+        let mut icmp = vec![ICMPV6_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0];
+        icmp.extend_from_slice(&quoted);
+
+        let reply = parse_raw_reply_v6(&icmp, router).unwrap();
+        assert_eq!(reply.icmp_type, ICMPV6_TIME_EXCEEDED);
+        assert_eq!(reply.source, router);
+        assert_eq!(reply.ident, Some(0xBEEF));
+        assert_eq!(reply.seq, 9);
+        assert_eq!(reply.quoted_dest, Some(IpAddr::V6(dest)));
+    }
+
+    #[test]
+    fn flow_id_pins_checksum_across_sequence_numbers() {
+        let a = build_echo_request(Family::V4, 0x1234, 1, 64, Some(0x4242));
+        let b = build_echo_request(Family::V4, 0x1234, 2, 64, Some(0x4242));
+        let c = build_echo_request(Family::V4, 0x1234, 1, 64, Some(0x4243));
+        assert_eq!(a[2..4], b[2..4]);
+        assert_ne!(a[2..4], c[2..4]);
+        assert_ne!(a[6..8], b[6..8]);
+    }
+
+    #[test]
+    fn ignores_other_icmp_types() {
+        let echo = build_echo_request(Family::V4, 1, 1, 64, None);
+        let pkt = {
+            let mut p = ipv4_header(Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST, 64, echo.len());
+            p.extend_from_slice(&echo);
+            p
+        };
+        assert!(parse_raw_reply_v4(&pkt).is_none());
+    }
+}
@@ -1,7 +1,9 @@
-//! Windows ICMP probe using IcmpSendEcho API.
+//! Windows ICMP probe using the IcmpSendEcho / Icmp6SendEcho2 APIs.
 //! This does NOT require admin/elevated privileges, unlike raw sockets.
 
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::IpAddr;
+#[cfg(windows)]
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 use super::ProbeResult;
 
@@ -19,6 +21,7 @@ mod ffi {
     pub type IPAddr = u32; // IPv4 address in network byte order
 
     pub const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+    pub const AF_INET6: USHORT = 23;
 
     // IP_STATUS codes
     pub const IP_SUCCESS: ULONG = 0;
@@ -48,9 +51,35 @@ mod ffi {
         pub options: IP_OPTION_INFORMATION,
     }
 
+    #[repr(C)]
+    pub struct SOCKADDR_IN6 {
+        pub sin6_family: USHORT,
+        pub sin6_port: USHORT,
+        pub sin6_flowinfo: ULONG,
+        pub sin6_addr: [UCHAR; 16],
+        pub sin6_scope_id: ULONG,
+    }
+
+    /// `IPV6_ADDRESS_EX` is declared with `#pragma pack(1)`.
+    #[repr(C, packed)]
+    pub struct IPV6_ADDRESS_EX {
+        pub sin6_port: USHORT,
+        pub sin6_flowinfo: ULONG,
+        pub sin6_addr: [USHORT; 8],
+        pub sin6_scope_id: ULONG,
+    }
+
+    #[repr(C)]
+    pub struct ICMPV6_ECHO_REPLY {
+        pub address: IPV6_ADDRESS_EX,
+        pub status: ULONG,
+        pub round_trip_time: ULONG,
+    }
+
     #[link(name = "iphlpapi")]
     extern "system" {
         pub fn IcmpCreateFile() -> HANDLE;
+        pub fn Icmp6CreateFile() -> HANDLE;
         pub fn IcmpCloseHandle(handle: HANDLE) -> BOOL;
         pub fn IcmpSendEcho(
             icmp_handle: HANDLE,
@@ -62,6 +91,20 @@ mod ffi {
             reply_size: DWORD,
             timeout: DWORD,
         ) -> DWORD;
+        pub fn Icmp6SendEcho2(
+            icmp_handle: HANDLE,
+            event: HANDLE,
+            apc_routine: *mut c_void,
+            apc_context: *mut c_void,
+            source_address: *const SOCKADDR_IN6,
+            destination_address: *const SOCKADDR_IN6,
+            request_data: *const c_void,
+            request_size: USHORT,
+            request_options: *mut IP_OPTION_INFORMATION,
+            reply_buffer: *mut c_void,
+            reply_size: DWORD,
+            timeout: DWORD,
+        ) -> DWORD;
     }
 
     #[link(name = "kernel32")]
@@ -76,21 +119,15 @@ pub async fn send_icmp_probe(
     ttl: u8,
     packet_size: u16,
     timeout_ms: u64,
+    _flow_id: Option<u16>,
 ) -> ProbeResult {
-    let IpAddr::V4(dest_v4) = dest else {
-        // IPv6 not supported via IcmpSendEcho (would need Icmp6SendEcho2)
-        return ProbeResult {
-            hop_number: ttl,
-            responding_ip: None,
-            rtt_us: None,
-            timed_out: true,
-            ttl_received: None,
-        };
-    };
+    // IcmpSendEcho picks the identifier and sequence number itself, so the
+    // checksum cannot be pinned here and flow-stable ICMP is best effort.
 
     // Run blocking Windows API call in spawn_blocking
-    tokio::task::spawn_blocking(move || {
-        send_icmp_probe_win(dest_v4, ttl, packet_size, timeout_ms)
+    tokio::task::spawn_blocking(move || match dest {
+        IpAddr::V4(dest_v4) => send_icmp_probe_win(dest_v4, ttl, packet_size, timeout_ms),
+        IpAddr::V6(dest_v6) => send_icmp6_probe_win(dest_v6, ttl, packet_size, timeout_ms),
     })
     .await
     .unwrap_or(ProbeResult {
@@ -99,6 +136,8 @@ pub async fn send_icmp_probe(
         rtt_us: None,
         timed_out: true,
         ttl_received: None,
+        tcp_reply: None,
+        mpls_labels: Vec::new(),
     })
 }
 
@@ -118,6 +157,8 @@ fn send_icmp_probe_win(
         rtt_us: None,
         timed_out: true,
         ttl_received: None,
+        tcp_reply: None,
+        mpls_labels: Vec::new(),
     };
 
     unsafe {
@@ -200,16 +241,22 @@ fn send_icmp_probe_win(
                         rtt_us: Some((reply.round_trip_time as u32) * 1000), // ms to us
                         timed_out: false,
                         ttl_received: Some(reply.options.ttl),
+                        tcp_reply: None,
+                        mpls_labels: Vec::new(),
                     }
                 }
                 IP_TTL_EXPIRED_TRANSIT => {
-                    // Intermediate router responded
+                    // Intermediate router responded. The ICMP API hands back
+                    // only the status and the router's address, not the message
+                    // body, so RFC 4950 label stacks cannot be read here.
                     ProbeResult {
                         hop_number: ttl,
                         responding_ip: Some(IpAddr::V4(reply_ip)),
                         rtt_us: Some((reply.round_trip_time as u32) * 1000), // ms to us
                         timed_out: false,
                         ttl_received: None,
+                        tcp_reply: None,
+                        mpls_labels: Vec::new(),
                     }
                 }
                 IP_DEST_NET_UNREACHABLE
@@ -223,6 +270,8 @@ fn send_icmp_probe_win(
                         rtt_us: Some((reply.round_trip_time as u32) * 1000),
                         timed_out: false,
                         ttl_received: None,
+                        tcp_reply: None,
+                        mpls_labels: Vec::new(),
                     }
                 }
                 11010 => {
@@ -248,13 +297,156 @@ fn send_icmp_probe_win(
     }
 }
 
-#[cfg(not(windows))]
+#[cfg(windows)]
+fn send_icmp6_probe_win(
+    dest: Ipv6Addr,
+    ttl: u8,
+    packet_size: u16,
+    timeout_ms: u64,
+) -> ProbeResult {
+    use ffi::*;
+    use std::ffi::c_void;
+
+    let fail = ProbeResult {
+        hop_number: ttl,
+        responding_ip: None,
+        rtt_us: None,
+        timed_out: true,
+        ttl_received: None,
+        tcp_reply: None,
+        mpls_labels: Vec::new(),
+    };
+
+    unsafe {
+        let handle = Icmp6CreateFile();
+        if handle == INVALID_HANDLE_VALUE || handle.is_null() {
+            let err = GetLastError();
+            tracing::warn!(error_code = err, "Icmp6CreateFile failed");
+            return fail;
+        }
+
+        // Let the stack pick the source address
+        let source = SOCKADDR_IN6 {
+            sin6_family: AF_INET6,
+            sin6_port: 0,
+            sin6_flowinfo: 0,
+            sin6_addr: [0; 16],
+            sin6_scope_id: 0,
+        };
+        let destination = SOCKADDR_IN6 {
+            sin6_family: AF_INET6,
+            sin6_port: 0,
+            sin6_flowinfo: 0,
+            sin6_addr: dest.octets(),
+            sin6_scope_id: 0,
+        };
+
+        let payload_size = (packet_size as usize).saturating_sub(8).max(1);
+        let request_data = vec![0x41u8; payload_size];
+
+        // The TTL field carries the IPv6 hop limit
+        let mut options = IP_OPTION_INFORMATION {
+            ttl,
+            tos: 0,
+            flags: 0,
+            options_size: 0,
+            options_data: std::ptr::null_mut(),
+        };
+
+        // Reply buffer: ICMPV6_ECHO_REPLY + data + 8 bytes of ICMP error + IO_STATUS_BLOCK
+        let reply_size = std::mem::size_of::<ICMPV6_ECHO_REPLY>() + payload_size + 8 + 16;
+        let mut reply_buffer = vec![0u8; reply_size];
+
+        let num_replies = Icmp6SendEcho2(
+            handle,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &source,
+            &destination,
+            request_data.as_ptr() as *const c_void,
+            payload_size as USHORT,
+            &mut options,
+            reply_buffer.as_mut_ptr() as *mut c_void,
+            reply_size as DWORD,
+            timeout_ms as DWORD,
+        );
+
+        if num_replies == 0 {
+            let err = GetLastError();
+            if err != 11010 {
+                tracing::debug!(ttl = ttl, dest = %dest, error_code = err, "Icmp6SendEcho2 failed");
+            }
+        }
+
+        // Like IcmpSendEcho, error statuses still fill the reply buffer.
+        let reply = std::ptr::read_unaligned(reply_buffer.as_ptr() as *const ICMPV6_ECHO_REPLY);
+        let status = reply.status;
+        let rtt_us = Some(reply.round_trip_time * 1000);
+
+        let result = if num_replies > 0 || status != 0 {
+            // Address words are in network byte order
+            let words = reply.address.sin6_addr;
+            let reply_ip = Ipv6Addr::from(words.map(u16::from_be));
+
+            match status {
+                IP_SUCCESS => ProbeResult {
+                    hop_number: ttl,
+                    responding_ip: Some(IpAddr::V6(reply_ip)),
+                    rtt_us,
+                    timed_out: false,
+                    ttl_received: None,
+                    tcp_reply: None,
+                    mpls_labels: Vec::new(),
+                },
+                IP_TTL_EXPIRED_TRANSIT
+                | IP_DEST_NET_UNREACHABLE
+                | IP_DEST_HOST_UNREACHABLE
+                | IP_DEST_PROT_UNREACHABLE
+                | IP_DEST_PORT_UNREACHABLE => ProbeResult {
+                    hop_number: ttl,
+                    responding_ip: Some(IpAddr::V6(reply_ip)),
+                    rtt_us,
+                    timed_out: false,
+                    ttl_received: None,
+                    tcp_reply: None,
+                    mpls_labels: Vec::new(),
+                },
+                11010 => fail,
+                _ => {
+                    tracing::debug!(status = status, ttl = ttl, reply_ip = %reply_ip, "ICMPv6 reply with status");
+                    fail
+                }
+            }
+        } else {
+            fail
+        };
+
+        IcmpCloseHandle(handle);
+        result
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub async fn send_icmp_probe(
+    dest: IpAddr,
+    ttl: u8,
+    packet_size: u16,
+    timeout_ms: u64,
+    flow_id: Option<u16>,
+) -> ProbeResult {
+    // Shared-socket engine with reply demultiplexing
+    super::icmp_linux::send_icmp_probe(dest, ttl, packet_size, timeout_ms, flow_id).await
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
 pub async fn send_icmp_probe(
     dest: IpAddr,
     ttl: u8,
     packet_size: u16,
     timeout_ms: u64,
+    flow_id: Option<u16>,
 ) -> ProbeResult {
-    // Fall back to raw socket implementation on non-Windows
-    super::icmp::send_icmp_probe(dest, ttl, packet_size, timeout_ms).await
+    // Fall back to raw socket implementation on other platforms
+    super::icmp::send_icmp_probe(dest, ttl, packet_size, timeout_ms, flow_id).await
 }
@@ -0,0 +1,204 @@
+//! Linux socket helpers shared by the probe engines: socket options that
+//! socket2 does not expose, and `recvmsg` with the ancillary data we need to
+//! read ICMP errors from a socket's error queue.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+use socket2::Socket;
+
+/// One datagram read with `recvmsg`, plus the ancillary data we care about.
+pub(super) struct RawMessage {
+    pub data: Vec<u8>,
+    /// Sender of the datagram, or the original destination for error-queue entries.
+    pub peer: Option<SocketAddr>,
+    /// `IP_TTL` / `IPV6_HOPLIMIT` control message.
+    pub hop_limit: Option<u8>,
+    /// ICMP type, code and offender address from an `IP_RECVERR` / `IPV6_RECVERR` entry.
+    pub error: Option<IcmpError>,
+    /// MTU from a locally generated `EMSGSIZE` error-queue entry.
+    pub local_mtu: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct IcmpError {
+    pub icmp_type: u8,
+    pub code: u8,
+    pub offender: IpAddr,
+    /// `ee_info`: the next-hop MTU for Fragmentation Needed / Packet Too Big.
+    pub info: u32,
+}
+
+pub(super) fn set_int_opt(
+    socket: &Socket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> std::io::Result<()> {
+    // SAFETY: value outlives the call and the length matches its type.
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Ask the kernel to queue ICMP errors for this socket on its error queue.
+pub(super) fn enable_recverr(socket: &Socket, ipv6: bool) -> std::io::Result<()> {
+    if ipv6 {
+        set_int_opt(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1)
+    } else {
+        set_int_opt(socket, libc::IPPROTO_IP, libc::IP_RECVERR, 1)
+    }
+}
+
+/// Wait until the socket is readable or has a pending error.
+/// Returns `(readable, error_pending)`; both false on timeout.
+pub(super) fn poll_socket(fd: libc::c_int, timeout: Option<Duration>) -> std::io::Result<(bool, bool)> {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as libc::c_int);
+    // SAFETY: pfd is a valid pollfd for the duration of the call.
+    let rc = unsafe { libc::poll(&mut pfd, 1, timeout_ms) };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((pfd.revents & libc::POLLIN != 0, pfd.revents & libc::POLLERR != 0))
+}
+
+/// Wait until any of `fds` is readable. Returns one flag per fd; all false on timeout.
+pub(super) fn poll_readable(fds: &[libc::c_int], timeout: Duration) -> std::io::Result<Vec<bool>> {
+    let mut pfds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as libc::c_int;
+    // SAFETY: pfds is a valid pollfd array for the duration of the call.
+    let rc = unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, timeout_ms) };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(pfds.iter().map(|p| p.revents & libc::POLLIN != 0).collect())
+}
+
+/// Wait for a non-blocking `connect()` to finish, successfully or not.
+/// Returns false on timeout.
+pub(super) fn poll_connected(fd: libc::c_int, timeout: Duration) -> std::io::Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLOUT,
+        revents: 0,
+    };
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as libc::c_int;
+    // SAFETY: pfd is a valid pollfd for the duration of the call.
+    let rc = unsafe { libc::poll(&mut pfd, 1, timeout_ms) };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(pfd.revents & (libc::POLLOUT | libc::POLLERR | libc::POLLHUP) != 0)
+}
+
+/// Read one datagram (or one error-queue entry) without blocking.
+/// Returns None once nothing is left to read.
+pub(super) fn recv_message(fd: libc::c_int, errqueue: bool) -> Option<RawMessage> {
+    let mut data = [0u8; 1500];
+    let mut control = [0u8; 512];
+    // SAFETY: all-zero is a valid sockaddr_storage / msghdr.
+    let mut name: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut name as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+
+    let flags = libc::MSG_DONTWAIT | if errqueue { libc::MSG_ERRQUEUE } else { 0 };
+    // SAFETY: msg points at buffers that live for the duration of the call.
+    let n = unsafe { libc::recvmsg(fd, &mut msg, flags) };
+    if n < 0 {
+        return None;
+    }
+
+    let mut hop_limit = None;
+    let mut error = None;
+    let mut local_mtu = None;
+    // SAFETY: the CMSG_* macros walk the control buffer recvmsg just filled, and
+    // the sockaddr pointers come from the kernel with the family they claim.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let hdr = &*cmsg;
+            match (hdr.cmsg_level, hdr.cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_TTL) | (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                    hop_limit = Some(*(libc::CMSG_DATA(cmsg) as *const libc::c_int) as u8);
+                }
+                (libc::IPPROTO_IP, libc::IP_RECVERR) | (libc::IPPROTO_IPV6, libc::IPV6_RECVERR) => {
+                    let ee = libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err;
+                    let origin = (*ee).ee_origin;
+                    if origin == libc::SO_EE_ORIGIN_ICMP || origin == libc::SO_EE_ORIGIN_ICMP6 {
+                        let offender = libc::SO_EE_OFFENDER(ee) as *const libc::sockaddr_storage;
+                        if let Some(source) = sockaddr_to_std(&*offender) {
+                            error = Some(IcmpError {
+                                icmp_type: (*ee).ee_type,
+                                code: (*ee).ee_code,
+                                offender: source.ip(),
+                                info: (*ee).ee_info,
+                            });
+                        }
+                    } else if origin == libc::SO_EE_ORIGIN_LOCAL && (*ee).ee_errno == libc::EMSGSIZE as u32 {
+                        local_mtu = Some((*ee).ee_info);
+                    }
+                }
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Some(RawMessage {
+        data: data[..n as usize].to_vec(),
+        peer: sockaddr_to_std(&name),
+        hop_limit,
+        error,
+        local_mtu,
+    })
+}
+
+fn sockaddr_to_std(addr: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET => {
+            // SAFETY: ss_family says this storage holds a sockaddr_in.
+            let sin = unsafe { &*(addr as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            Some(SocketAddr::new(IpAddr::V4(ip), u16::from_be(sin.sin_port)))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: ss_family says this storage holds a sockaddr_in6.
+            let sin6 = unsafe { &*(addr as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            Some(SocketAddr::new(IpAddr::V6(ip), u16::from_be(sin6.sin6_port)))
+        }
+        _ => None,
+    }
+}
@@ -1,32 +1,141 @@
+pub mod budget;
 pub mod engine;
+// Raw-socket prober, only used as the ICMP backend on non-Linux Unixes.
+#[cfg_attr(any(windows, target_os = "linux"), allow(dead_code))]
 pub mod icmp;
+#[cfg(target_os = "linux")]
+pub mod icmp_linux;
+#[cfg(target_os = "linux")]
+mod linux;
 pub mod icmp_win;
+pub mod mpls;
+pub mod multipath;
+pub mod pmtu;
 pub mod tcp;
 pub mod udp;
 
+use std::ops::RangeInclusive;
+
 use nm_common::protocol::ProbeMethod;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ProbeResult {
     pub hop_number: u8,
     pub responding_ip: Option<std::net::IpAddr>,
     pub rtt_us: Option<u32>,
     pub timed_out: bool,
     pub ttl_received: Option<u8>,
+    pub tcp_reply: Option<nm_common::protocol::TcpReply>,
+    pub mpls_labels: Vec<nm_common::protocol::MplsLabel>,
 }
 
-/// Send a single probe with the given TTL using the specified method.
-pub async fn send_probe(
-    method: ProbeMethod,
+impl ProbeResult {
+    /// A probe that got no answer.
+    pub fn lost(hop_number: u8) -> Self {
+        Self {
+            hop_number,
+            responding_ip: None,
+            rtt_us: None,
+            timed_out: true,
+            ttl_received: None,
+            tcp_reply: None,
+            mpls_labels: Vec::new(),
+        }
+    }
+}
+
+/// Set the outgoing TTL (IPv4) or unicast hop limit (IPv6) on a probe socket.
+pub(crate) fn set_hop_limit(
+    socket: &socket2::Socket,
     dest: std::net::IpAddr,
     ttl: u8,
+) -> std::io::Result<()> {
+    match dest {
+        std::net::IpAddr::V4(_) => socket.set_ttl(ttl as u32),
+        std::net::IpAddr::V6(_) => socket.set_unicast_hops_v6(ttl as u32),
+    }
+}
+
+/// Stable per-target flow id for flow-stable probing.
+pub(crate) fn flow_id_for(target_id: uuid::Uuid) -> u16 {
+    let bytes = target_id.as_bytes();
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+/// Source port of a flow's UDP and TCP probes, inside the IANA dynamic range.
+pub(crate) fn flow_source_port(flow_id: u16) -> u16 {
+    49152 + flow_id % 16384
+}
+
+/// Probe every TTL in `ttls` using the specified method and return one
+/// result per TTL, in order.
+///
+/// `flow_id` selects flow-stable (Paris traceroute) probing: every probe of the
+/// round carries the same flow identifier (ports for UDP/TCP, checksum for
+/// ICMP), chosen from the id, so per-flow load balancers route them alike.
+///
+/// With a privileged helper running, the round is probed by the helper.
+pub async fn send_round(
+    method: ProbeMethod,
+    dest: std::net::IpAddr,
+    ttls: RangeInclusive<u8>,
+    packet_size: u16,
+    timeout_ms: u64,
+    port: Option<u16>,
+    flow_id: Option<u16>,
+) -> Vec<ProbeResult> {
+    #[cfg(target_os = "linux")]
+    if let Some(helper) = crate::privsep::client() {
+        return helper
+            .send_round(method, dest, ttls, packet_size, timeout_ms, port, flow_id)
+            .await;
+    }
+    send_round_local(method, dest, ttls, packet_size, timeout_ms, port, flow_id).await
+}
+
+/// One ICMP Echo Request with the given TTL, through the helper if there is one.
+pub async fn send_echo(dest: std::net::IpAddr, ttl: u8, packet_size: u16, timeout_ms: u64) -> ProbeResult {
+    #[cfg(target_os = "linux")]
+    if let Some(helper) = crate::privsep::client() {
+        return helper.send_echo(dest, ttl, packet_size, timeout_ms).await;
+    }
+    icmp_win::send_icmp_probe(dest, ttl, packet_size, timeout_ms, None).await
+}
+
+/// `send_round` in this process.
+pub(crate) async fn send_round_local(
+    method: ProbeMethod,
+    dest: std::net::IpAddr,
+    ttls: RangeInclusive<u8>,
     packet_size: u16,
     timeout_ms: u64,
     port: Option<u16>,
-) -> ProbeResult {
+    flow_id: Option<u16>,
+) -> Vec<ProbeResult> {
     match method {
-        ProbeMethod::Icmp => icmp_win::send_icmp_probe(dest, ttl, packet_size, timeout_ms).await,
-        ProbeMethod::Tcp => tcp::send_tcp_probe(dest, ttl, port.unwrap_or(80), timeout_ms).await,
-        ProbeMethod::Udp => udp::send_udp_probe(dest, ttl, timeout_ms).await,
+        ProbeMethod::Icmp => {
+            let mut futures = Vec::with_capacity(ttls.len());
+            for ttl in ttls.clone() {
+                futures.push(tokio::spawn(icmp_win::send_icmp_probe(
+                    dest,
+                    ttl,
+                    packet_size,
+                    timeout_ms,
+                    flow_id,
+                )));
+            }
+            let mut results = Vec::with_capacity(ttls.len());
+            for (ttl, future) in ttls.zip(futures) {
+                results.push(future.await.unwrap_or_else(|_| ProbeResult::lost(ttl)));
+            }
+            results
+        }
+        ProbeMethod::Tcp => {
+            tcp::send_tcp_round(dest, ttls, port.unwrap_or(80), timeout_ms, flow_id).await
+        }
+        ProbeMethod::Udp => {
+            udp::send_udp_round(dest, ttls, packet_size, timeout_ms, port, flow_id).await
+        }
     }
 }
@@ -0,0 +1,94 @@
+//! MPLS label stacks from ICMP extension objects (RFC 4884 / RFC 4950).
+//!
+//! Routers inside an MPLS tunnel that send Time Exceeded append the label
+//! stack the expired packet carried, so a hop that looks slow can be traced to
+//! the hidden LSP behind it.
+
+use nm_common::protocol::MplsLabel;
+
+/// RFC 4884 says the quoted datagram is zero-padded to 128 bytes when older
+/// (RFC 4950-only) routers leave the length field at zero.
+const LEGACY_ORIGINAL_DATAGRAM_LEN: usize = 128;
+const EXTENSION_VERSION: u8 = 2;
+const CLASS_MPLS_LABEL_STACK: u8 = 1;
+const CTYPE_INCOMING_STACK: u8 = 1;
+
+/// Label stack from a Time Exceeded or Destination Unreachable message, given
+/// the ICMP message itself (outer IP header already stripped). Returns an
+/// empty stack when there is no valid MPLS extension.
+pub(super) fn parse_label_stack(icmp: &[u8], ipv6: bool) -> Vec<MplsLabel> {
+    if icmp.len() < 8 {
+        return Vec::new();
+    }
+    // RFC 4884 length of the original datagram: 32-bit words for ICMPv4
+    // (byte 5), 64-bit words for ICMPv6 (byte 4).
+    let quoted_len = if ipv6 { icmp[4] as usize * 8 } else { icmp[5] as usize * 4 };
+    let offset = 8 + if quoted_len > 0 { quoted_len } else { LEGACY_ORIGINAL_DATAGRAM_LEN };
+
+    icmp.get(offset..).and_then(parse_extension).unwrap_or_default()
+}
+
+fn parse_extension(ext: &[u8]) -> Option<Vec<MplsLabel>> {
+    if ext.len() < 4 || ext[0] >> 4 != EXTENSION_VERSION {
+        return None;
+    }
+    // A non-zero checksum must verify; zero means the sender did not compute one.
+    if u16::from_be_bytes([ext[2], ext[3]]) != 0 && super::icmp::icmp_checksum(ext) != 0 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut objects = &ext[4..];
+    while objects.len() >= 4 {
+        let len = u16::from_be_bytes([objects[0], objects[1]]) as usize;
+        if len < 4 || len > objects.len() {
+            break;
+        }
+        if objects[2] == CLASS_MPLS_LABEL_STACK && objects[3] == CTYPE_INCOMING_STACK {
+            labels.extend(objects[4..len].chunks_exact(4).map(|entry| {
+                let word = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                MplsLabel {
+                    label: word >> 12,
+                    traffic_class: ((word >> 9) & 0x7) as u8,
+                    bottom_of_stack: word & 0x100 != 0,
+                    ttl: word as u8,
+                }
+            }));
+        }
+        objects = &objects[len..];
+    }
+    Some(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rfc4950_label_stack() {
+        // Time Exceeded with a 128-byte quote (length field = 32 words) and
+        // one MPLS object carrying two labels.
+        let mut icmp = vec![11, 0, 0, 0, 0, 32, 0, 0];
+        icmp.extend_from_slice(&[0u8; 128]);
+        let mut ext = vec![0x20, 0, 0, 0, 0, 12, CLASS_MPLS_LABEL_STACK, CTYPE_INCOMING_STACK];
+        ext.extend_from_slice(&((16004u32 << 12) | (5 << 9) | 1).to_be_bytes());
+        ext.extend_from_slice(&((24017u32 << 12) | 0x100 | 254).to_be_bytes());
+        let checksum = super::super::icmp::icmp_checksum(&ext);
+        ext[2..4].copy_from_slice(&checksum.to_be_bytes());
+        icmp.extend_from_slice(&ext);
+
+        let labels = parse_label_stack(&icmp, false);
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[0].label, 16004);
+        assert_eq!(labels[0].traffic_class, 5);
+        assert_eq!(labels[0].ttl, 1);
+        assert!(!labels[0].bottom_of_stack);
+        assert_eq!(labels[1].label, 24017);
+        assert!(labels[1].bottom_of_stack);
+
+        // Corrupt the object: checksum no longer verifies.
+        let last = icmp.len() - 1;
+        icmp[last] ^= 0xFF;
+        assert!(parse_label_stack(&icmp, false).is_empty());
+    }
+}
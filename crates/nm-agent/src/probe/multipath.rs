@@ -0,0 +1,164 @@
+//! Multipath discovery using the Multipath Detection Algorithm (MDA).
+//!
+//! Each flow is a full flow-stable round with its own flow id, so per-flow
+//! load balancers send it down one path. Flows are added until, at every TTL,
+//! enough of them have been sent to rule out an interface we have not seen yet
+//! with 95% confidence. Because every flow covers every TTL, consecutive hops
+//! of the same flow also give us the links between interfaces.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+
+use chrono::Utc;
+use nm_common::protocol::{MultipathDiscoveryReport, MultipathHop, MultipathLink, TargetConfig};
+use uuid::Uuid;
+
+use super::budget::{ProbeBudget, RoundProbe};
+
+/// Flows needed to rule out a (k+1)-th interface after seeing k, at 95%
+/// confidence (Veitch et al., "Failure Control in Multipath Route Tracing").
+const STOPPING_POINTS: [u16; 16] = [6, 11, 16, 21, 27, 33, 38, 44, 51, 57, 63, 70, 76, 83, 90, 96];
+
+/// Flows probed concurrently.
+const FLOW_BATCH: u16 = 8;
+
+/// Each flow takes its probes' permits from `budget` like a regular round.
+pub async fn discover(
+    target: &TargetConfig,
+    session_id: Uuid,
+    dest_ip: IpAddr,
+    max_ttl: u8,
+    timeout_ms: u64,
+    budget: &ProbeBudget,
+) -> MultipathDiscoveryReport {
+    let base_flow = super::flow_id_for(target.target_id);
+    let round = super::engine::round_probe(target, dest_ip, timeout_ms);
+    let max_flows = STOPPING_POINTS[STOPPING_POINTS.len() - 1];
+
+    // paths[flow][ttl - 1] = interface that answered
+    let mut paths: Vec<Vec<Option<IpAddr>>> = Vec::new();
+    loop {
+        let probed = paths.len() as u16;
+        let needed = flows_needed(&paths).min(max_flows);
+        if probed >= needed {
+            break;
+        }
+
+        // Offset by one so discovery never shares a flow with the regular rounds.
+        let batch = (probed..needed.min(probed + FLOW_BATCH)).map(|f| {
+            let flow = RoundProbe { flow_id: Some(base_flow.wrapping_add(1 + f)), ..round };
+            budget.send_round(flow, max_ttl, None)
+        });
+        for results in futures_util::future::join_all(batch).await {
+            paths.push(results.into_iter().map(|r| r.responding_ip).collect());
+        }
+    }
+
+    let (hops, links) = build_graph(&paths, dest_ip);
+    tracing::info!(
+        target = %target.address,
+        flows = paths.len(),
+        max_width = hops.iter().map(|h| h.interfaces.len()).max().unwrap_or(0),
+        "Multipath discovery complete"
+    );
+
+    MultipathDiscoveryReport {
+        target_id: target.target_id,
+        session_id,
+        discovered_at: Utc::now(),
+        flows_probed: paths.len() as u16,
+        hops,
+        links,
+    }
+}
+
+/// Flows required before the widest hop seen so far is fully enumerated.
+fn flows_needed(paths: &[Vec<Option<IpAddr>>]) -> u16 {
+    let ttls = paths.iter().map(Vec::len).max().unwrap_or(0);
+    (0..ttls)
+        .map(|i| {
+            let seen: BTreeSet<IpAddr> = paths.iter().filter_map(|p| p.get(i).copied().flatten()).collect();
+            STOPPING_POINTS[seen.len().clamp(1, STOPPING_POINTS.len()) - 1]
+        })
+        .max()
+        .unwrap_or(STOPPING_POINTS[0])
+}
+
+/// Collapse per-flow paths into interfaces per hop and links between hops,
+/// stopping at the first hop where only the destination answered.
+fn build_graph(paths: &[Vec<Option<IpAddr>>], dest_ip: IpAddr) -> (Vec<MultipathHop>, Vec<MultipathLink>) {
+    let mut interfaces: BTreeMap<u8, BTreeSet<IpAddr>> = BTreeMap::new();
+    let mut links: BTreeSet<(u8, IpAddr, IpAddr)> = BTreeSet::new();
+
+    for path in paths {
+        for (i, ip) in path.iter().enumerate() {
+            let hop_number = (i + 1) as u8;
+            if let Some(ip) = ip {
+                interfaces.entry(hop_number).or_default().insert(*ip);
+                if let Some(Some(next)) = path.get(i + 1) {
+                    links.insert((hop_number, *ip, *next));
+                }
+            }
+        }
+    }
+
+    let last_hop = interfaces
+        .iter()
+        .find(|(_, ips)| ips.len() == 1 && ips.contains(&dest_ip))
+        .map(|(hop, _)| *hop)
+        .unwrap_or(u8::MAX);
+
+    let hops = interfaces
+        .into_iter()
+        .filter(|(hop, _)| *hop <= last_hop)
+        .map(|(hop_number, ips)| MultipathHop {
+            hop_number,
+            interfaces: ips.iter().map(|ip| ip.to_string()).collect(),
+        })
+        .collect();
+    let links = links
+        .into_iter()
+        .filter(|(hop, _, _)| *hop < last_hop)
+        .map(|(hop_number, from, to)| MultipathLink {
+            hop_number,
+            from_ip: from.to_string(),
+            to_ip: to.to_string(),
+        })
+        .collect();
+
+    (hops, links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn stopping_point_follows_widest_hop() {
+        assert_eq!(flows_needed(&[]), 6);
+        let paths = vec![
+            vec![ip("10.0.0.1"), ip("10.1.0.1")],
+            vec![ip("10.0.0.1"), ip("10.1.0.2")],
+            vec![ip("10.0.0.1"), None],
+        ];
+        assert_eq!(flows_needed(&paths), 11);
+    }
+
+    #[test]
+    fn builds_diamond_and_stops_at_destination() {
+        let dest = "192.0.2.9";
+        let paths = vec![
+            vec![ip("10.0.0.1"), ip("10.1.0.1"), ip(dest), ip(dest)],
+            vec![ip("10.0.0.1"), ip("10.1.0.2"), ip(dest), ip(dest)],
+        ];
+        let (hops, links) = build_graph(&paths, dest.parse().unwrap());
+        assert_eq!(hops.len(), 3);
+        assert_eq!(hops[1].interfaces, vec!["10.1.0.1", "10.1.0.2"]);
+        assert_eq!(links.len(), 4);
+        assert!(links.iter().all(|l| l.hop_number < 3));
+    }
+}
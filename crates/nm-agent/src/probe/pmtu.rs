@@ -0,0 +1,334 @@
+//! Path MTU discovery, tracepath style.
+//!
+//! UDP probes go out with Don't Fragment set, one TTL at a time, at the
+//! current MTU estimate. Time Exceeded means a packet of that size reached
+//! the hop; Fragmentation Needed / Packet Too Big lowers the estimate to the
+//! MTU the router reports and the TTL is probed again. A hop that answers a
+//! minimum-size probe but never a large one is a black hole: it drops
+//! oversized packets without telling anyone, so the largest size that still
+//! gets through is found by binary search.
+//!
+//! Only Linux gives an unprivileged UDP socket both DF probing past the cached
+//! PMTU (`IP_PMTUDISC_PROBE`) and the reported MTU (`IP_RECVERR`); elsewhere
+//! discovery is skipped.
+
+use std::net::IpAddr;
+
+use nm_common::protocol::{HopMtu, PathMtuReport, TargetConfig};
+use uuid::Uuid;
+
+/// What one probe of a given size at a given TTL ran into.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Answer {
+    /// Time Exceeded: the packet fit as far as this hop.
+    Hop(IpAddr),
+    /// Port Unreachable or any other end of the path.
+    End(IpAddr),
+    /// Fragmentation Needed / Packet Too Big with the next-hop MTU.
+    TooBig(u16),
+    Silent,
+}
+
+pub async fn discover(
+    target: &TargetConfig,
+    session_id: Uuid,
+    dest_ip: IpAddr,
+    max_ttl: u8,
+    timeout_ms: u64,
+) -> Option<PathMtuReport> {
+    #[cfg(target_os = "linux")]
+    {
+        let walked = tokio::task::spawn_blocking(move || linux::walk_path(dest_ip, max_ttl, timeout_ms))
+            .await
+            .ok()?;
+        let walked = match walked {
+            Ok(w) => w,
+            Err(e) => {
+                tracing::warn!(target = %target.address, error = %e, "Path MTU discovery failed");
+                return None;
+            }
+        };
+
+        tracing::info!(
+            target = %target.address,
+            path_mtu = walked.path_mtu,
+            drop_hop = ?walked.mtu_drop_hop,
+            black_holes = walked.black_hole_hops.len(),
+            "Path MTU discovery complete"
+        );
+
+        Some(PathMtuReport {
+            target_id: target.target_id,
+            session_id,
+            discovered_at: chrono::Utc::now(),
+            path_mtu: walked.path_mtu,
+            reached_destination: walked.reached_destination,
+            mtu_drop_hop: walked.mtu_drop_hop,
+            black_hole_hops: walked.black_hole_hops,
+            hops: walked.hops,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (session_id, dest_ip, max_ttl, timeout_ms);
+        tracing::debug!(target = %target.address, "Path MTU discovery is only supported on Linux");
+        None
+    }
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct Walk {
+    path_mtu: u16,
+    reached_destination: bool,
+    mtu_drop_hop: Option<u8>,
+    black_hole_hops: Vec<u8>,
+    hops: Vec<HopMtu>,
+}
+
+/// Walk the path hop by hop with `probe(ttl, size)`, starting at `local_mtu`
+/// and never going below `min_mtu`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn walk(
+    mut probe: impl FnMut(u8, u16) -> Answer,
+    dest: IpAddr,
+    max_ttl: u8,
+    local_mtu: u16,
+    min_mtu: u16,
+) -> Walk {
+    let mut mtu = local_mtu;
+    let mut walked = Walk {
+        path_mtu: local_mtu,
+        reached_destination: false,
+        mtu_drop_hop: None,
+        black_hole_hops: Vec::new(),
+        hops: Vec::new(),
+    };
+
+    for ttl in 1..=max_ttl {
+        let mut answer = probe(ttl, mtu);
+        while let Answer::TooBig(reported) = answer {
+            if reported < min_mtu || reported >= mtu {
+                // Nonsense MTU (or an old router reporting 0): find it ourselves.
+                answer = Answer::Silent;
+                break;
+            }
+            mtu = reported;
+            walked.mtu_drop_hop.get_or_insert(ttl);
+            answer = probe(ttl, mtu);
+        }
+
+        if answer == Answer::Silent && mtu > min_mtu {
+            // One lost probe or a rate-limited ICMP is not a black hole, so
+            // retry before trying a small packet.
+            answer = probe(ttl, mtu);
+            if answer == Answer::Silent {
+                let small = probe(ttl, min_mtu);
+                if !matches!(small, Answer::Silent | Answer::TooBig(_)) {
+                    // Largest size that gets through: `passes` does, `fails` does not.
+                    let (mut passes, mut fails) = (min_mtu, mtu);
+                    answer = small;
+                    while fails - passes > 1 {
+                        let size = passes + (fails - passes) / 2;
+                        match probe(ttl, size) {
+                            Answer::Silent | Answer::TooBig(_) => fails = size,
+                            a => {
+                                passes = size;
+                                answer = a;
+                            }
+                        }
+                    }
+                    mtu = passes;
+                    walked.black_hole_hops.push(ttl);
+                    walked.mtu_drop_hop.get_or_insert(ttl);
+                }
+            }
+        }
+
+        let (ip_address, end) = match answer {
+            Answer::Hop(ip) => (Some(ip), false),
+            Answer::End(ip) => (Some(ip), true),
+            Answer::TooBig(_) | Answer::Silent => (None, false),
+        };
+        walked.hops.push(HopMtu {
+            hop_number: ttl,
+            ip_address: ip_address.map(|ip| ip.to_string()),
+            mtu,
+        });
+        if end {
+            walked.reached_destination = ip_address == Some(dest);
+            break;
+        }
+    }
+
+    walked.path_mtu = mtu;
+    walked
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::net::{IpAddr, SocketAddr};
+    use std::os::fd::AsRawFd;
+    use std::time::{Duration, Instant};
+
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    use super::super::linux::{enable_recverr, poll_socket, recv_message, set_int_opt};
+    use super::{walk, Answer, Walk};
+
+    /// Destination ports cycle through this range (tracepath's) so late
+    /// errors for an earlier probe are not mistaken for the current one.
+    const PROBE_BASE_PORT: u16 = 44444;
+    const PROBE_PORT_RANGE: u16 = 1000;
+
+    pub(super) fn walk_path(dest: IpAddr, max_ttl: u8, timeout_ms: u64) -> std::io::Result<Walk> {
+        let ipv6 = dest.is_ipv6();
+        let domain = if ipv6 { Domain::IPV6 } else { Domain::IPV4 };
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        enable_recverr(&socket, ipv6)?;
+        // DF on every packet, without the kernel clamping sends to its own
+        // cached PMTU for the destination.
+        if ipv6 {
+            set_int_opt(&socket, libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_PROBE)?;
+        } else {
+            set_int_opt(&socket, libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_PROBE)?;
+        }
+
+        let mut prober = Prober {
+            socket,
+            dest,
+            seq: 0,
+            timeout: Duration::from_millis(timeout_ms),
+        };
+        let local_mtu = prober.local_mtu();
+        let min_mtu = if ipv6 { 1280 } else { 576 };
+        Ok(walk(|ttl, size| prober.probe(ttl, size), dest, max_ttl, local_mtu, min_mtu))
+    }
+
+    struct Prober {
+        socket: Socket,
+        dest: IpAddr,
+        seq: u16,
+        timeout: Duration,
+    }
+
+    impl Prober {
+        fn header_len(&self) -> u16 {
+            if self.dest.is_ipv6() { 48 } else { 28 }
+        }
+
+        /// MTU of the outgoing interface: the kernel rejects an oversized send
+        /// and queues the limit it applied.
+        fn local_mtu(&mut self) -> u16 {
+            match self.probe(1, u16::MAX) {
+                Answer::TooBig(mtu) => mtu,
+                // Loopback and other 64K-MTU interfaces accept the largest datagram.
+                _ => u16::MAX,
+            }
+        }
+
+        fn probe(&mut self, ttl: u8, size: u16) -> Answer {
+            self.seq = self.seq.wrapping_add(1);
+            let port = PROBE_BASE_PORT + self.seq % PROBE_PORT_RANGE;
+            if super::super::set_hop_limit(&self.socket, self.dest, ttl).is_err() {
+                return Answer::Silent;
+            }
+
+            let payload = vec![0u8; size.saturating_sub(self.header_len()) as usize];
+            let addr = SocketAddr::new(self.dest, port);
+            let fd = self.socket.as_raw_fd();
+            // A stale ICMP error from the previous probe is reported (and
+            // cleared) by the next send instead of sending.
+            let mut sent = false;
+            for _ in 0..2 {
+                match self.socket.send_to(&payload, &addr.into()) {
+                    Ok(_) => {
+                        sent = true;
+                        break;
+                    }
+                    Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => {
+                        let mtu = std::iter::from_fn(|| recv_message(fd, true)).find_map(|m| m.local_mtu);
+                        return Answer::TooBig(mtu.map_or(size - 1, |m| m.min(u16::MAX as u32) as u16));
+                    }
+                    Err(e) => tracing::trace!(dest = %self.dest, ttl = ttl, error = %e, "PMTU probe send failed"),
+                }
+            }
+            if !sent {
+                return Answer::Silent;
+            }
+
+            let deadline = Instant::now() + self.timeout;
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Answer::Silent;
+                }
+                match poll_socket(fd, Some(remaining)) {
+                    Ok((_, true)) => {}
+                    Ok((true, false)) => {
+                        // A UDP service actually answered on the destination.
+                        while let Some(msg) = recv_message(fd, false) {
+                            if msg.peer.is_some_and(|p| p.ip() == self.dest) {
+                                return Answer::End(self.dest);
+                            }
+                        }
+                        continue;
+                    }
+                    Ok((false, false)) => return Answer::Silent,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(_) => return Answer::Silent,
+                }
+
+                while let Some(msg) = recv_message(fd, true) {
+                    let Some(error) = msg.error else {
+                        continue;
+                    };
+                    if msg.peer.map(|p| p.port()) != Some(port) {
+                        continue;
+                    }
+                    return classify(self.dest.is_ipv6(), error.icmp_type, error.code, error.offender, error.info);
+                }
+            }
+        }
+    }
+
+    fn classify(ipv6: bool, icmp_type: u8, code: u8, offender: IpAddr, info: u32) -> Answer {
+        let mtu = info.min(u16::MAX as u32) as u16;
+        match (ipv6, icmp_type, code) {
+            (false, 11, _) | (true, 3, _) => Answer::Hop(offender),
+            (false, 3, 4) | (true, 2, _) => Answer::TooBig(mtu),
+            _ => Answer::End(offender),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_drop_hop_and_black_hole() {
+        let dest: IpAddr = "192.0.2.9".parse().unwrap();
+        let router = |n: u8| IpAddr::from([10, 0, 0, n]);
+        // Hop 2 reports a 1400-byte link; hop 3 silently drops anything over 1380.
+        let probe = |ttl: u8, size: u16| match ttl {
+            1 => Answer::Hop(router(1)),
+            _ if size > 1400 => Answer::TooBig(1400),
+            2 => Answer::Hop(router(2)),
+            _ if size > 1380 => Answer::Silent,
+            3 => Answer::Hop(router(3)),
+            _ => Answer::End(dest),
+        };
+
+        let walked = walk(probe, dest, 30, 1500, 576);
+        assert_eq!(walked.path_mtu, 1380);
+        assert!(walked.reached_destination);
+        assert_eq!(walked.mtu_drop_hop, Some(2));
+        assert_eq!(walked.black_hole_hops, vec![3]);
+        assert_eq!(walked.hops.len(), 4);
+        assert_eq!(walked.hops[0].mtu, 1500);
+        assert_eq!(walked.hops[1].mtu, 1400);
+        assert_eq!(walked.hops[2].ip_address.as_deref(), Some("10.0.0.3"));
+    }
+}
@@ -1,38 +1,99 @@
+//! TCP SYN traceroute.
+//!
+//! On Linux with CAP_NET_RAW the agent sends half-open SYN probes from a raw
+//! socket: intermediate hops are learned from the ICMP Time Exceeded they send
+//! back, and the destination answers with a SYN-ACK (port open) or a RST (port
+//! closed). The kernel resets the SYN-ACK itself since no socket owns the
+//! connection, so the handshake never completes.
+//!
+//! Without raw sockets each probe is a non-blocking `connect()` with a limited
+//! TTL. On Linux the ICMP error that aborts the connect is read back from the
+//! socket error queue, which still identifies the hop; elsewhere only the
+//! destination's answer can be observed.
+
 use std::net::IpAddr;
+use std::ops::RangeInclusive;
 
 use super::ProbeResult;
 
-pub async fn send_tcp_probe(
+/// Probe every TTL in `ttls` for one round.
+///
+/// With a flow id every probe uses the same source port, so the whole round
+/// shares one 5-tuple and probes are told apart by their sequence numbers.
+/// The `connect()` fallback can only have one attempt in flight per 4-tuple,
+/// so there flow-mode probes go out one TTL at a time.
+pub async fn send_tcp_round(
+    dest: IpAddr,
+    ttls: RangeInclusive<u8>,
+    port: u16,
+    timeout_ms: u64,
+    flow_id: Option<u16>,
+) -> Vec<ProbeResult> {
+    #[cfg(target_os = "linux")]
+    {
+        let round_ttls = ttls.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            linux::send_syn_round_sync(dest, round_ttls, port, timeout_ms, flow_id)
+        })
+        .await;
+        if let Ok(Some(results)) = result {
+            return results;
+        }
+    }
+
+    let Some(flow_id) = flow_id else {
+        let mut futures = Vec::with_capacity(ttls.len());
+        for ttl in ttls.clone() {
+            futures.push(tokio::spawn(send_connect_probe(dest, ttl, port, timeout_ms, None)));
+        }
+        let mut results = Vec::with_capacity(ttls.len());
+        for (ttl, future) in ttls.zip(futures) {
+            results.push(future.await.unwrap_or_else(|_| lost(ttl)));
+        }
+        return results;
+    };
+
+    let source_port = super::flow_source_port(flow_id);
+    let mut results = Vec::with_capacity(ttls.len());
+    for ttl in ttls {
+        results.push(send_connect_probe(dest, ttl, port, timeout_ms, Some(source_port)).await);
+    }
+    results
+}
+
+fn lost(ttl: u8) -> ProbeResult {
+    ProbeResult {
+        hop_number: ttl,
+        responding_ip: None,
+        rtt_us: None,
+        timed_out: true,
+        ttl_received: None,
+        tcp_reply: None,
+        mpls_labels: Vec::new(),
+    }
+}
+
+async fn send_connect_probe(
     dest: IpAddr,
     ttl: u8,
     port: u16,
     timeout_ms: u64,
+    source_port: Option<u16>,
 ) -> ProbeResult {
-    // TCP probe: attempt a TCP connect with limited TTL
-    // Intermediate hops return ICMP Time Exceeded
-    // Destination returns SYN-ACK or RST
     let result = tokio::task::spawn_blocking(move || {
-        send_tcp_probe_sync(dest, ttl, port, timeout_ms)
+        send_connect_probe_sync(dest, ttl, port, timeout_ms, source_port)
     })
     .await;
 
-    match result {
-        Ok(r) => r,
-        Err(_) => ProbeResult {
-            hop_number: ttl,
-            responding_ip: None,
-            rtt_us: None,
-            timed_out: true,
-            ttl_received: None,
-        },
-    }
+    result.unwrap_or_else(|_| lost(ttl))
 }
 
-fn send_tcp_probe_sync(
+fn send_connect_probe_sync(
     dest: IpAddr,
     ttl: u8,
     port: u16,
     timeout_ms: u64,
+    source_port: Option<u16>,
 ) -> ProbeResult {
     use std::net::SocketAddr;
     use std::time::{Duration, Instant};
@@ -45,64 +106,521 @@ fn send_tcp_probe_sync(
 
     let socket = match Socket::new(domain, Type::STREAM, None) {
         Ok(s) => s,
-        Err(_) => {
-            return ProbeResult {
-                hop_number: ttl,
-                responding_ip: None,
-                rtt_us: None,
-                timed_out: true,
-                ttl_received: None,
-            };
-        }
+        Err(_) => return lost(ttl),
     };
 
-    let _ = socket.set_ttl(ttl as u32);
-    socket.set_nonblocking(true).ok();
+    if let Some(source_port) = source_port {
+        // The previous probe of the flow closed in SYN-SENT, so the port is free
+        // again; SO_REUSEADDR covers a straggling close.
+        let unspecified: IpAddr = match dest {
+            IpAddr::V4(_) => std::net::Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => std::net::Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let _ = socket.set_reuse_address(true);
+        if let Err(e) = socket.bind(&SocketAddr::new(unspecified, source_port).into()) {
+            tracing::debug!(port = source_port, error = %e, "Could not bind flow source port");
+        }
+    }
 
+    let _ = super::set_hop_limit(&socket, dest, ttl);
     let addr = SocketAddr::new(dest, port);
-    let start = Instant::now();
-
-    // Attempt connect (non-blocking)
-    let _ = socket.connect(&addr.into());
-
-    // Poll for completion
     let timeout = Duration::from_millis(timeout_ms);
-    let _ = socket.set_read_timeout(Some(timeout));
-
-    // Use a simple poll: try connect_timeout equivalent
-    std::thread::sleep(Duration::from_millis(timeout_ms.min(100)));
+    let start = Instant::now();
 
-    let rtt = start.elapsed();
+    #[cfg(target_os = "linux")]
+    let outcome = linux::connect_with_errqueue(&socket, dest, addr, timeout);
+    #[cfg(not(target_os = "linux"))]
+    let outcome = {
+        use nm_common::protocol::TcpReply;
+        match socket.connect_timeout(&addr.into(), timeout) {
+            Ok(()) => Some((dest, Some(TcpReply::SynAck))),
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => Some((dest, Some(TcpReply::Rst))),
+            Err(_) => None,
+        }
+    };
 
-    // Check if connection succeeded or got a response
-    match socket.peer_addr() {
-        Ok(_) => ProbeResult {
+    match outcome {
+        Some((responding_ip, tcp_reply)) => ProbeResult {
             hop_number: ttl,
-            responding_ip: Some(dest),
-            rtt_us: Some(rtt.as_micros() as u32),
+            responding_ip: Some(responding_ip),
+            rtt_us: Some(start.elapsed().as_micros() as u32),
             timed_out: false,
             ttl_received: None,
+            tcp_reply,
+            mpls_labels: Vec::new(),
         },
-        Err(_) => {
-            // Connection failed - could be TTL exceeded or timeout
-            if rtt < timeout {
-                // Got a quick rejection - likely ICMP response
-                ProbeResult {
+        None => lost(ttl),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+    use std::ops::RangeInclusive;
+    use std::os::fd::AsRawFd;
+    use std::sync::Once;
+    use std::time::{Duration, Instant};
+
+    use nm_common::protocol::{MplsLabel, TcpReply};
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    use super::super::icmp::icmp_checksum;
+    use super::super::icmp_linux::parse_ipv4_header;
+    use super::super::linux::{enable_recverr, poll_connected, poll_readable, recv_message, set_int_opt};
+    use super::super::mpls::parse_label_stack;
+    use super::super::{flow_source_port, ProbeResult};
+    use super::lost;
+
+    const IPPROTO_TCP: u8 = 6;
+    const ICMP_DEST_UNREACHABLE: u8 = 3;
+    const ICMP_TIME_EXCEEDED: u8 = 11;
+    const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+    const ICMPV6_TIME_EXCEEDED: u8 = 3;
+
+    const TCP_SYN: u8 = 0x02;
+    const TCP_RST: u8 = 0x04;
+    const TCP_ACK: u8 = 0x10;
+    const SYN_MSS: u16 = 1460;
+
+    static RAW_UNAVAILABLE: Once = Once::new();
+
+    /// The TCP header fields an ICMP error quotes back from one of our SYNs.
+    #[derive(Debug, PartialEq)]
+    struct QuotedSyn {
+        dest: IpAddr,
+        source_port: u16,
+        dest_port: u16,
+        seq: u32,
+    }
+
+    /// An ICMP error answering one of our SYNs.
+    struct IcmpAnswer {
+        offender: IpAddr,
+        /// TTL / hop limit of the ICMP error itself.
+        ttl: Option<u8>,
+        quote: QuotedSyn,
+        mpls_labels: Vec<MplsLabel>,
+    }
+
+    /// A TCP segment received from the destination.
+    #[derive(Debug)]
+    struct Segment {
+        source: IpAddr,
+        source_port: u16,
+        dest_port: u16,
+        ack: u32,
+        flags: u8,
+        ttl: Option<u8>,
+    }
+
+    /// Send a half-open SYN for every TTL from a raw socket and collect the
+    /// answers. Probe `ttl` carries sequence number `base_seq + ttl`, which
+    /// comes back in the quoted header of an ICMP error or as `ack - 1` from
+    /// the destination. Returns None when raw sockets are not available.
+    pub(super) fn send_syn_round_sync(
+        dest: IpAddr,
+        ttls: RangeInclusive<u8>,
+        port: u16,
+        timeout_ms: u64,
+        flow_id: Option<u16>,
+    ) -> Option<Vec<ProbeResult>> {
+        let (tcp, icmp) = match open_raw_sockets(dest) {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                RAW_UNAVAILABLE.call_once(|| {
+                    tracing::warn!(
+                        error = %e,
+                        "Raw sockets unavailable, TCP probes fall back to connect() without ttl_received"
+                    );
+                });
+                return None;
+            }
+        };
+        let source = match local_address(dest, port) {
+            Ok(ip) => ip,
+            Err(e) => {
+                tracing::debug!(dest = %dest, error = %e, "No route for TCP probe");
+                return None;
+            }
+        };
+
+        let base_seq = initial_sequence();
+        // Classic mode moves the source port with the TTL, like classic UDP
+        // traceroute moves the destination port.
+        let classic_base = 49152 + (base_seq >> 16) as u16 % (16384 - 256);
+        let probe_port = |ttl: u8| match flow_id {
+            Some(flow_id) => flow_source_port(flow_id),
+            None => classic_base + ttl as u16,
+        };
+
+        // Indexed by ttl - 1; the TTLs below the batch are dropped at the end
+        let max_ttl = *ttls.end();
+        let mut results: Vec<ProbeResult> = (1..=max_ttl).map(lost).collect();
+        let mut sent_at: Vec<Option<Instant>> = vec![None; max_ttl as usize];
+        let target = SocketAddr::new(dest, 0);
+        for ttl in ttls.clone() {
+            let syn = build_syn(source, dest, probe_port(ttl), port, base_seq.wrapping_add(ttl as u32));
+            if super::super::set_hop_limit(&tcp, dest, ttl).is_err() {
+                continue;
+            }
+            let now = Instant::now();
+            match tcp.send_to(&syn, &target.into()) {
+                Ok(_) => sent_at[ttl as usize - 1] = Some(now),
+                Err(e) => tracing::trace!(dest = %dest, ttl = ttl, error = %e, "TCP SYN send failed"),
+            }
+        }
+
+        let fds = [tcp.as_raw_fd(), icmp.as_raw_fd()];
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+        let mut outstanding = sent_at.iter().filter(|s| s.is_some()).count();
+
+        while outstanding > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let readable = match poll_readable(&fds, remaining) {
+                Ok(r) => r,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if !readable.iter().any(|r| *r) {
+                break;
+            }
+
+            while let Some(msg) = recv_message(fds[1], false) {
+                let received_at = Instant::now();
+                let parsed = match dest {
+                    IpAddr::V4(_) => parse_icmp_v4(&msg.data),
+                    IpAddr::V6(_) => msg.peer.and_then(|p| parse_icmp_v6(&msg.data, p.ip(), msg.hop_limit)),
+                };
+                let Some(error) = parsed else {
+                    continue;
+                };
+                let quote = &error.quote;
+                if quote.dest != dest || quote.dest_port != port {
+                    continue;
+                }
+                let Some(ttl) = probe_ttl(quote.seq.wrapping_sub(base_seq), &ttls) else {
+                    continue;
+                };
+                if quote.source_port != probe_port(ttl) {
+                    continue;
+                }
+                let answer = ProbeResult {
                     hop_number: ttl,
-                    responding_ip: Some(dest),
-                    rtt_us: Some(rtt.as_micros() as u32),
+                    responding_ip: Some(error.offender),
+                    rtt_us: None,
                     timed_out: false,
-                    ttl_received: None,
+                    ttl_received: error.ttl,
+                    tcp_reply: None,
+                    mpls_labels: error.mpls_labels,
+                };
+                if record(&mut results, &sent_at, received_at, answer) {
+                    outstanding -= 1;
+                }
+            }
+
+            while let Some(msg) = recv_message(fds[0], false) {
+                let received_at = Instant::now();
+                let parsed = match dest {
+                    IpAddr::V4(_) => parse_tcp_v4(&msg.data),
+                    IpAddr::V6(_) => msg.peer.and_then(|p| parse_tcp_v6(&msg.data, p.ip(), msg.hop_limit)),
+                };
+                let Some(segment) = parsed else {
+                    continue;
+                };
+                if segment.source != dest || segment.source_port != port {
+                    continue;
                 }
-            } else {
-                ProbeResult {
+                let reply = if segment.flags & TCP_RST != 0 {
+                    TcpReply::Rst
+                } else if segment.flags & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK {
+                    TcpReply::SynAck
+                } else {
+                    continue;
+                };
+                let Some(ttl) = probe_ttl(segment.ack.wrapping_sub(1).wrapping_sub(base_seq), &ttls) else {
+                    continue;
+                };
+                if segment.dest_port != probe_port(ttl) {
+                    continue;
+                }
+                let answer = ProbeResult {
                     hop_number: ttl,
-                    responding_ip: None,
+                    responding_ip: Some(dest),
                     rtt_us: None,
-                    timed_out: true,
-                    ttl_received: None,
+                    timed_out: false,
+                    ttl_received: segment.ttl,
+                    tcp_reply: Some(reply),
+                    mpls_labels: Vec::new(),
+                };
+                if record(&mut results, &sent_at, received_at, answer) {
+                    outstanding -= 1;
                 }
             }
         }
+
+        Some(results.split_off(*ttls.start() as usize - 1))
+    }
+
+    /// Store the answer for its TTL, with the RTT filled in, unless that TTL
+    /// already has one. Returns true if it was stored.
+    fn record(
+        results: &mut [ProbeResult],
+        sent_at: &[Option<Instant>],
+        received_at: Instant,
+        mut answer: ProbeResult,
+    ) -> bool {
+        let index = answer.hop_number as usize - 1;
+        let Some(sent) = sent_at[index] else {
+            return false;
+        };
+        if !results[index].timed_out {
+            return false;
+        }
+        answer.rtt_us = Some(received_at.saturating_duration_since(sent).as_micros() as u32);
+        results[index] = answer;
+        true
+    }
+
+    fn probe_ttl(offset: u32, ttls: &RangeInclusive<u8>) -> Option<u8> {
+        u8::try_from(offset).ok().filter(|t| ttls.contains(t))
+    }
+
+    fn open_raw_sockets(dest: IpAddr) -> std::io::Result<(Socket, Socket)> {
+        let (domain, icmp_protocol) = match dest {
+            IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+            IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+        };
+        let tcp = Socket::new(domain, Type::RAW, Some(Protocol::TCP))?;
+        let icmp = Socket::new(domain, Type::RAW, Some(icmp_protocol))?;
+        if dest.is_ipv6() {
+            // IPv6 raw sockets strip the header, so the hop limit has to come
+            // from ancillary data.
+            set_int_opt(&tcp, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+            set_int_opt(&icmp, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+        }
+        Ok((tcp, icmp))
+    }
+
+    /// Source address the kernel will use towards `dest`, needed for the TCP
+    /// checksum pseudo-header.
+    fn local_address(dest: IpAddr, port: u16) -> std::io::Result<IpAddr> {
+        let unspecified: IpAddr = match dest {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+        socket.connect(SocketAddr::new(dest, port))?;
+        Ok(socket.local_addr()?.ip())
+    }
+
+    fn initial_sequence() -> u32 {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos() ^ (d.as_secs() as u32).rotate_left(16));
+        nanos ^ std::process::id().rotate_left(8)
+    }
+
+    /// Build a SYN segment with an MSS option, checksum included.
+    fn build_syn(source: IpAddr, dest: IpAddr, source_port: u16, dest_port: u16, seq: u32) -> Vec<u8> {
+        let mut segment = vec![0u8; 24];
+        segment[0..2].copy_from_slice(&source_port.to_be_bytes());
+        segment[2..4].copy_from_slice(&dest_port.to_be_bytes());
+        segment[4..8].copy_from_slice(&seq.to_be_bytes());
+        segment[12] = 6 << 4; // data offset: 6 words
+        segment[13] = TCP_SYN;
+        segment[14..16].copy_from_slice(&64240u16.to_be_bytes());
+        segment[20..24].copy_from_slice(&[2, 4, (SYN_MSS >> 8) as u8, SYN_MSS as u8]);
+        let checksum = tcp_checksum(source, dest, &segment);
+        segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+        segment
+    }
+
+    fn tcp_checksum(source: IpAddr, dest: IpAddr, segment: &[u8]) -> u16 {
+        let mut buf = Vec::with_capacity(40 + segment.len());
+        match (source, dest) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => {
+                buf.extend_from_slice(&s.octets());
+                buf.extend_from_slice(&d.octets());
+                buf.extend_from_slice(&[0, IPPROTO_TCP]);
+                buf.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+            }
+            (IpAddr::V6(s), IpAddr::V6(d)) => {
+                buf.extend_from_slice(&s.octets());
+                buf.extend_from_slice(&d.octets());
+                buf.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+                buf.extend_from_slice(&[0, 0, 0, IPPROTO_TCP]);
+            }
+            _ => return 0,
+        }
+        buf.extend_from_slice(segment);
+        icmp_checksum(&buf)
+    }
+
+    fn quoted_syn(dest: IpAddr, tcp: &[u8]) -> Option<QuotedSyn> {
+        // ICMP errors quote at least the first 8 bytes: ports and sequence number.
+        let tcp = tcp.get(..8)?;
+        Some(QuotedSyn {
+            dest,
+            source_port: u16::from_be_bytes([tcp[0], tcp[1]]),
+            dest_port: u16::from_be_bytes([tcp[2], tcp[3]]),
+            seq: u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]),
+        })
+    }
+
+    /// Parse an ICMPv4 error quoting a TCP segment (IPv4 header included).
+    fn parse_icmp_v4(data: &[u8]) -> Option<IcmpAnswer> {
+        let (header_len, ttl, source) = parse_ipv4_header(data)?;
+        let icmp = data.get(header_len..)?;
+        if icmp.len() < 8 || !matches!(icmp[0], ICMP_TIME_EXCEEDED | ICMP_DEST_UNREACHABLE) {
+            return None;
+        }
+        let inner = &icmp[8..];
+        let (inner_header_len, _, _) = parse_ipv4_header(inner)?;
+        if inner[9] != IPPROTO_TCP {
+            return None;
+        }
+        let dest = Ipv4Addr::new(inner[16], inner[17], inner[18], inner[19]);
+        let quote = quoted_syn(IpAddr::V4(dest), inner.get(inner_header_len..)?)?;
+        Some(IcmpAnswer {
+            offender: IpAddr::V4(source),
+            ttl: Some(ttl),
+            quote,
+            mpls_labels: parse_label_stack(icmp, false),
+        })
+    }
+
+    /// Parse an ICMPv6 error quoting a TCP segment. The kernel strips the
+    /// outer IPv6 header, so the offender and hop limit come from `recvmsg`.
+    fn parse_icmp_v6(icmp: &[u8], source: IpAddr, hop_limit: Option<u8>) -> Option<IcmpAnswer> {
+        if icmp.len() < 8 || !matches!(icmp[0], ICMPV6_TIME_EXCEEDED | ICMPV6_DEST_UNREACHABLE) {
+            return None;
+        }
+        let inner = &icmp[8..];
+        if inner.len() < 48 || inner[0] >> 4 != 6 || inner[6] != IPPROTO_TCP {
+            return None;
+        }
+        let mut dest = [0u8; 16];
+        dest.copy_from_slice(&inner[24..40]);
+        let quote = quoted_syn(IpAddr::V6(Ipv6Addr::from(dest)), &inner[40..])?;
+        Some(IcmpAnswer {
+            offender: source,
+            ttl: hop_limit,
+            quote,
+            mpls_labels: parse_label_stack(icmp, true),
+        })
+    }
+
+    fn segment(source: IpAddr, tcp: &[u8], ttl: Option<u8>) -> Option<Segment> {
+        if tcp.len() < 20 {
+            return None;
+        }
+        Some(Segment {
+            source,
+            source_port: u16::from_be_bytes([tcp[0], tcp[1]]),
+            dest_port: u16::from_be_bytes([tcp[2], tcp[3]]),
+            ack: u32::from_be_bytes([tcp[8], tcp[9], tcp[10], tcp[11]]),
+            flags: tcp[13],
+            ttl,
+        })
+    }
+
+    fn parse_tcp_v4(data: &[u8]) -> Option<Segment> {
+        let (header_len, ttl, source) = parse_ipv4_header(data)?;
+        segment(IpAddr::V4(source), data.get(header_len..)?, Some(ttl))
+    }
+
+    fn parse_tcp_v6(tcp: &[u8], source: IpAddr, hop_limit: Option<u8>) -> Option<Segment> {
+        segment(source, tcp, hop_limit)
+    }
+
+    /// `connect()` fallback: wait for the attempt to finish and read the ICMP
+    /// error that aborted it, if any, from the socket error queue.
+    pub(super) fn connect_with_errqueue(
+        socket: &Socket,
+        dest: IpAddr,
+        addr: SocketAddr,
+        timeout: Duration,
+    ) -> Option<(IpAddr, Option<TcpReply>)> {
+        // Without IP_RECVERR the kernel drops the error details and a hop
+        // looks like a plain timeout.
+        enable_recverr(socket, dest.is_ipv6()).ok()?;
+        socket.set_nonblocking(true).ok()?;
+        match socket.connect(&addr.into()) {
+            Ok(()) => return Some((dest, Some(TcpReply::SynAck))),
+            Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                return Some((dest, Some(TcpReply::Rst)));
+            }
+            Err(_) => return None,
+        }
+
+        let fd = socket.as_raw_fd();
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match poll_connected(fd, remaining) {
+                Ok(true) => break,
+                Ok(false) => return None,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted && !remaining.is_zero() => continue,
+                Err(_) => return None,
+            }
+        }
+
+        if let Some(error) = std::iter::from_fn(|| recv_message(fd, true)).find_map(|m| m.error) {
+            return Some((error.offender, None));
+        }
+        match socket.take_error() {
+            Ok(None) => Some((dest, Some(TcpReply::SynAck))),
+            Ok(Some(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => Some((dest, Some(TcpReply::Rst))),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn syn_checksum_verifies() {
+            let src: IpAddr = "2001:db8::1".parse().unwrap();
+            let dst: IpAddr = "2001:db8::2".parse().unwrap();
+            let syn = build_syn(src, dst, 50000, 443, 0xDEAD_BEEF);
+            assert_eq!(tcp_checksum(src, dst, &syn), 0);
+        }
+
+        #[test]
+        fn parses_time_exceeded_quoting_syn() {
+            let src = Ipv4Addr::new(10, 0, 0, 2);
+            let dst = Ipv4Addr::new(192, 0, 2, 9);
+            let router = Ipv4Addr::new(10, 0, 0, 1);
+            let syn = build_syn(src.into(), dst.into(), 50001, 443, 7);
+
+            let mut inner = vec![0x45, 0, 0, 44, 0, 0, 0, 0, 1, IPPROTO_TCP, 0, 0];
+            inner.extend_from_slice(&src.octets());
+            inner.extend_from_slice(&dst.octets());
+            inner.extend_from_slice(&syn[..8]);
+            let mut packet = vec![0x45, 0, 0, 56, 0, 0, 0, 0, 254, 1, 0, 0];
+            packet.extend_from_slice(&router.octets());
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&[ICMP_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0]);
+            packet.extend_from_slice(&inner);
+
+            let answer = parse_icmp_v4(&packet).unwrap();
+            assert_eq!(answer.offender, IpAddr::V4(router));
+            assert_eq!(answer.ttl, Some(254));
+            assert!(answer.mpls_labels.is_empty());
+            assert_eq!(
+                answer.quote,
+                QuotedSyn {
+                    dest: dst.into(),
+                    source_port: 50001,
+                    dest_port: 443,
+                    seq: 7
+                }
+            );
+        }
     }
 }
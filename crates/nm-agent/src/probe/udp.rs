@@ -1,32 +1,103 @@
 use std::net::IpAddr;
+use std::ops::RangeInclusive;
 
 use super::ProbeResult;
 
-pub async fn send_udp_probe(
+/// First destination port of the classic traceroute range (33434 + ttl - 1).
+const TRACEROUTE_BASE_PORT: u16 = 33434;
+
+/// Probe every TTL in `ttls` for one round.
+///
+/// Classic mode walks the traceroute port range, one destination port per TTL.
+/// With a flow id, every probe of the round shares one source port (derived
+/// from the flow id) and one destination port, so per-flow load balancers keep
+/// them on a single path.
+pub async fn send_udp_round(
+    dest: IpAddr,
+    ttls: RangeInclusive<u8>,
+    packet_size: u16,
+    timeout_ms: u64,
+    port: Option<u16>,
+    flow_id: Option<u16>,
+) -> Vec<ProbeResult> {
+    #[cfg(target_os = "linux")]
+    {
+        let round_ttls = ttls.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            linux::send_udp_round_sync(dest, round_ttls, packet_size, timeout_ms, port, flow_id)
+        })
+        .await;
+        result.unwrap_or_else(|_| ttls.map(lost).collect())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        // Without an error queue each probe needs its own socket to see its ICMP
+        // error, so the source port cannot be shared; only the destination port
+        // stays fixed in flow mode.
+        let mut futures = Vec::with_capacity(ttls.len());
+        for ttl in ttls.clone() {
+            let dest_port = destination_port(ttl, port, flow_id);
+            futures.push(tokio::spawn(send_udp_probe(dest, ttl, dest_port, packet_size, timeout_ms)));
+        }
+        let mut results = Vec::with_capacity(ttls.len());
+        for (ttl, future) in ttls.zip(futures) {
+            results.push(future.await.unwrap_or_else(|_| lost(ttl)));
+        }
+        results
+    }
+}
+
+fn destination_port(ttl: u8, port: Option<u16>, flow_id: Option<u16>) -> u16 {
+    match flow_id {
+        Some(_) => port.unwrap_or(TRACEROUTE_BASE_PORT),
+        None => TRACEROUTE_BASE_PORT.wrapping_add(ttl as u16 - 1),
+    }
+}
+
+/// Payload for one probe: the first byte carries the TTL so the quoted copy
+/// in an ICMP error tells us which probe it answers.
+fn probe_payload(ttl: u8, packet_size: u16, ipv6: bool) -> Vec<u8> {
+    let headers = if ipv6 { 48 } else { 28 };
+    let mut payload = vec![0u8; (packet_size as usize).saturating_sub(headers).max(2)];
+    payload[0] = ttl;
+    payload
+}
+
+fn lost(ttl: u8) -> ProbeResult {
+    ProbeResult {
+        hop_number: ttl,
+        responding_ip: None,
+        rtt_us: None,
+        timed_out: true,
+        ttl_received: None,
+        tcp_reply: None,
+        mpls_labels: Vec::new(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn send_udp_probe(
     dest: IpAddr,
     ttl: u8,
+    dest_port: u16,
+    packet_size: u16,
     timeout_ms: u64,
 ) -> ProbeResult {
     let result = tokio::task::spawn_blocking(move || {
-        send_udp_probe_sync(dest, ttl, timeout_ms)
+        send_udp_probe_sync(dest, ttl, dest_port, packet_size, timeout_ms)
     })
     .await;
 
-    match result {
-        Ok(r) => r,
-        Err(_) => ProbeResult {
-            hop_number: ttl,
-            responding_ip: None,
-            rtt_us: None,
-            timed_out: true,
-            ttl_received: None,
-        },
-    }
+    result.unwrap_or_else(|_| lost(ttl))
 }
 
+#[cfg(not(target_os = "linux"))]
 fn send_udp_probe_sync(
     dest: IpAddr,
     ttl: u8,
+    dest_port: u16,
+    packet_size: u16,
     timeout_ms: u64,
 ) -> ProbeResult {
     use std::mem::MaybeUninit;
@@ -42,35 +113,18 @@ fn send_udp_probe_sync(
     // Create UDP socket
     let socket = match Socket::new(domain, Type::DGRAM, Some(Protocol::UDP)) {
         Ok(s) => s,
-        Err(_) => {
-            return ProbeResult {
-                hop_number: ttl,
-                responding_ip: None,
-                rtt_us: None,
-                timed_out: true,
-                ttl_received: None,
-            };
-        }
+        Err(_) => return lost(ttl),
     };
 
-    let _ = socket.set_ttl(ttl as u32);
+    let _ = super::set_hop_limit(&socket, dest, ttl);
     let _ = socket.set_read_timeout(Some(Duration::from_millis(timeout_ms)));
 
-    // Standard traceroute UDP port range: 33434 + (ttl - 1)
-    let port = 33434u16.wrapping_add(ttl as u16 - 1);
-    let addr = SocketAddr::new(dest, port);
-
-    let payload = vec![0u8; 32];
+    let addr = SocketAddr::new(dest, dest_port);
+    let payload = probe_payload(ttl, packet_size, dest.is_ipv6());
     let start = Instant::now();
 
     if socket.send_to(&payload, &addr.into()).is_err() {
-        return ProbeResult {
-            hop_number: ttl,
-            responding_ip: None,
-            rtt_us: None,
-            timed_out: true,
-            ttl_received: None,
-        };
+        return lost(ttl);
     }
 
     // Try to receive ICMP error via the UDP socket
@@ -86,14 +140,163 @@ fn send_udp_probe_sync(
                 rtt_us: Some(rtt.as_micros() as u32),
                 timed_out: false,
                 ttl_received: None,
+                tcp_reply: None,
+                mpls_labels: Vec::new(),
             }
         }
-        Err(_) => ProbeResult {
-            hop_number: ttl,
-            responding_ip: None,
-            rtt_us: None,
-            timed_out: true,
-            ttl_received: None,
-        },
+        Err(_) => lost(ttl),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::net::{IpAddr, SocketAddr};
+    use std::ops::RangeInclusive;
+    use std::os::fd::AsRawFd;
+    use std::time::{Duration, Instant};
+
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    use super::super::linux::{enable_recverr, poll_socket, recv_message};
+    use super::super::{flow_source_port, ProbeResult};
+    use super::{destination_port, lost, probe_payload, TRACEROUTE_BASE_PORT};
+
+    /// Send every probe of the round from one socket and read the ICMP errors
+    /// back from its error queue. Each error is matched to its probe by the
+    /// destination port (classic mode) or by the TTL byte in the quoted
+    /// payload (flow mode, where all probes share one port pair).
+    pub(super) fn send_udp_round_sync(
+        dest: IpAddr,
+        ttls: RangeInclusive<u8>,
+        packet_size: u16,
+        timeout_ms: u64,
+        port: Option<u16>,
+        flow_id: Option<u16>,
+    ) -> Vec<ProbeResult> {
+        // Indexed by ttl - 1; the TTLs below the batch are dropped at the end
+        let first_index = *ttls.start() as usize - 1;
+        let max_ttl = *ttls.end();
+        let mut results: Vec<ProbeResult> = (1..=max_ttl).map(lost).collect();
+
+        let socket = match open_socket(dest, port, flow_id) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::debug!(dest = %dest, error = %e, "Failed to open UDP probe socket");
+                return results.split_off(first_index);
+            }
+        };
+
+        let mut sent_at: Vec<Option<Instant>> = vec![None; max_ttl as usize];
+        for ttl in ttls.clone() {
+            let addr = SocketAddr::new(dest, destination_port(ttl, port, flow_id));
+            let payload = probe_payload(ttl, packet_size, dest.is_ipv6());
+            if super::super::set_hop_limit(&socket, dest, ttl).is_err() {
+                continue;
+            }
+            // An ICMP error for an earlier probe also sets the socket's pending
+            // error, which the next send reports (and clears) instead of sending.
+            let mut sent = None;
+            for _ in 0..2 {
+                let now = Instant::now();
+                match socket.send_to(&payload, &addr.into()) {
+                    Ok(_) => {
+                        sent = Some(now);
+                        break;
+                    }
+                    Err(e) => tracing::trace!(dest = %dest, ttl = ttl, error = %e, "UDP send failed"),
+                }
+            }
+            sent_at[ttl as usize - 1] = sent;
+        }
+
+        let fd = socket.as_raw_fd();
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+        let mut outstanding = sent_at.iter().filter(|s| s.is_some()).count();
+
+        while outstanding > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            match poll_socket(fd, Some(remaining)) {
+                Ok((_, true)) => {}
+                Ok((true, false)) => {
+                    // Stray data from the target port; discard it.
+                    while recv_message(fd, false).is_some() {}
+                    continue;
+                }
+                Ok((false, false)) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+
+            while let Some(msg) = recv_message(fd, true) {
+                let received_at = Instant::now();
+                let Some(error) = msg.error else {
+                    continue;
+                };
+
+                let ttl = match flow_id {
+                    Some(_) => msg.data.first().copied(),
+                    None => msg.peer.and_then(|p| {
+                        let offset = p.port().checked_sub(TRACEROUTE_BASE_PORT)?;
+                        u8::try_from(offset + 1).ok()
+                    }),
+                };
+                let Some(ttl) = ttl.filter(|t| ttls.contains(t)) else {
+                    continue;
+                };
+
+                let slot = &mut results[ttl as usize - 1];
+                let Some(sent) = sent_at[ttl as usize - 1] else {
+                    continue;
+                };
+                if !slot.timed_out {
+                    continue;
+                }
+
+                tracing::trace!(ttl = ttl, icmp_type = error.icmp_type, code = error.code, "UDP probe answered");
+                *slot = ProbeResult {
+                    hop_number: ttl,
+                    responding_ip: Some(error.offender),
+                    rtt_us: Some(received_at.saturating_duration_since(sent).as_micros() as u32),
+                    timed_out: false,
+                    ttl_received: None,
+                    tcp_reply: None,
+                    mpls_labels: Vec::new(),
+                };
+                outstanding -= 1;
+            }
+        }
+
+        results.split_off(first_index)
+    }
+
+    fn open_socket(dest: IpAddr, port: Option<u16>, flow_id: Option<u16>) -> std::io::Result<Socket> {
+        let domain = match dest {
+            IpAddr::V4(_) => Domain::IPV4,
+            IpAddr::V6(_) => Domain::IPV6,
+        };
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        enable_recverr(&socket, dest.is_ipv6())?;
+
+        if let Some(flow_id) = flow_id {
+            // Targets whose flow ids collide share a source port. Connecting
+            // gives each socket its own 4-tuple, so the kernel still hands
+            // every ICMP error to the right one.
+            socket.set_reuse_address(true)?;
+            let unspecified: IpAddr = match dest {
+                IpAddr::V4(_) => std::net::Ipv4Addr::UNSPECIFIED.into(),
+                IpAddr::V6(_) => std::net::Ipv6Addr::UNSPECIFIED.into(),
+            };
+            let local = SocketAddr::new(unspecified, flow_source_port(flow_id));
+            if let Err(e) = socket.bind(&local.into()) {
+                tracing::debug!(port = local.port(), error = %e, "Could not bind flow source port");
+            }
+            let remote = SocketAddr::new(dest, destination_port(1, port, Some(flow_id)));
+            socket.connect(&remote.into())?;
+        }
+
+        Ok(socket)
     }
 }
@@ -1,18 +1,23 @@
 use std::net::IpAddr;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use lru::LruCache;
 
+/// Reverse DNS with an LRU cache. Failed lookups are cached too, so an
+/// address without a PTR record is not queried again until `ttl` passes.
 pub struct DnsResolver {
-    cache: Mutex<LruCache<IpAddr, Option<String>>>,
+    cache: Mutex<LruCache<IpAddr, (Option<String>, Instant)>>,
+    ttl: Duration,
 }
 
 impl DnsResolver {
-    pub fn new(capacity: usize) -> Self {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
         Self {
             cache: Mutex::new(LruCache::new(
                 std::num::NonZeroUsize::new(capacity).unwrap_or(std::num::NonZeroUsize::new(1024).unwrap()),
             )),
+            ttl,
         }
     }
 
@@ -20,8 +25,10 @@ impl DnsResolver {
         // Check cache first
         {
             let mut cache = self.cache.lock().unwrap();
-            if let Some(cached) = cache.get(&ip) {
-                return cached.clone();
+            if let Some((cached, resolved_at)) = cache.get(&ip) {
+                if resolved_at.elapsed() < self.ttl {
+                    return cached.clone();
+                }
             }
         }
 
@@ -31,12 +38,14 @@ impl DnsResolver {
         })
         .await
         .ok()
-        .flatten();
+        .flatten()
+        // Without a PTR record some resolvers hand back the address itself
+        .filter(|name| name.parse::<IpAddr>().is_err());
 
         // Cache the result
         {
             let mut cache = self.cache.lock().unwrap();
-            cache.put(ip, result.clone());
+            cache.put(ip, (result.clone(), Instant::now()));
         }
 
         result
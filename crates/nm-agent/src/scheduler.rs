@@ -1,158 +1,732 @@
 use std::collections::HashMap;
 use std::net::IpAddr;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use nm_common::config::AgentConfig;
-use nm_common::protocol::{TargetConfig, WsEnvelope, WsPayload};
-use tokio::sync::mpsc;
+use nm_common::protocol::{
+    AckResponse, AddressFamily, AgentConfigUpdate, AgentStatus, AgentStatusReport, HopSample, TargetConfig,
+    WsEnvelope, WsPayload,
+};
+use chrono::{DateTime, Utc};
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit};
+use tokio::time::Instant;
 use uuid::Uuid;
 
+use crate::enrichment::HopSighting;
 use crate::probe;
+use crate::probe::budget::ProbeBudget;
+
+/// Re-run multipath discovery every this many rounds (~15 min at 2.5 s).
+const MULTIPATH_REDISCOVERY_ROUNDS: u64 = 360;
+
+/// Re-measure the path MTU every this many rounds (~15 min at 2.5 s).
+const PMTU_REDISCOVERY_ROUNDS: u64 = 360;
+
+/// How often skipped rounds are tallied into an overload report.
+const OVERLOAD_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
 pub enum TargetCommand {
     Add(TargetConfig),
     Remove(Vec<Uuid>),
+    /// Change the probe settings of a running target in place. `msg_id` is the
+    /// server's ConfigUpdate message, acknowledged once applied.
+    Update { msg_id: Uuid, update: AgentConfigUpdate },
 }
 
 struct TargetState {
     config: TargetConfig,
     session_id: Uuid,
-    round_counter: u64,
+    /// Last round number used in the session, shared with the target's
+    /// handle so a reassignment can continue from it.
+    round_counter: Arc<AtomicU64>,
+    /// Round number the task started after; its first round runs discovery.
+    start_round: u64,
     dest_ip: Option<IpAddr>,
     known_hops: u8,
-    last_probe_time: Option<Instant>,
+    /// RTT of each hop's latest reply, for jitter
+    last_rtts: HashMap<u8, u32>,
+    multipath_task: Option<tokio::task::JoinHandle<()>>,
+    pmtu_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Drop for TargetState {
+    fn drop(&mut self) {
+        // Discovery runs beside the rounds; it must not outlive the target.
+        for task in [self.multipath_task.take(), self.pmtu_task.take()].into_iter().flatten() {
+            task.abort();
+        }
+    }
+}
+
+/// Scheduler counters reported in heartbeats.
+#[derive(Default)]
+pub struct SchedulerStats {
+    /// Rounds currently waiting for probe permits.
+    pub queued_rounds: AtomicU32,
+    /// Rounds dropped because they could not start in their slot, since startup.
+    pub skipped_rounds: AtomicU64,
+}
+
+/// Counts a round as queued for as long as it waits for permits.
+struct QueuedRound<'a>(&'a AtomicU32);
+
+impl<'a> QueuedRound<'a> {
+    fn new(queued: &'a AtomicU32) -> Self {
+        queued.fetch_add(1, Ordering::Relaxed);
+        Self(queued)
+    }
+}
+
+impl Drop for QueuedRound<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A running target: its timer task and the channel that feeds it updates.
+struct TargetHandle {
+    task: tokio::task::JoinHandle<()>,
+    update_tx: mpsc::Sender<(Uuid, AgentConfigUpdate)>,
+    session_id: Uuid,
+    round_counter: Arc<AtomicU64>,
+}
+
+/// What every target task shares.
+#[derive(Clone)]
+struct RoundContext {
+    outgoing_tx: mpsc::Sender<WsEnvelope>,
+    /// One permit per probe in flight, `max_concurrent_probes` in total.
+    probes: ProbeBudget,
+    stats: Arc<SchedulerStats>,
+    /// Answering hops, for reverse DNS and IP database lookups.
+    sighting_tx: mpsc::Sender<HopSighting>,
+    timeout_ms: Arc<AtomicU64>,
 }
 
+/// Each target runs its own timer task, so a slow or timed-out round only
+/// delays that target. Rounds start on a fixed grid (start + n * interval)
+/// rather than "interval after the last round ended", which keeps `sent_at`
+/// from drifting. A round that cannot get its probe permits before its next
+/// slot, or that overruns into later slots, is skipped and counted; the
+/// count is reported to the server as a degraded status.
 pub async fn run(
-    config: AgentConfig,
+    mut config_rx: watch::Receiver<AgentConfig>,
     mut target_rx: mpsc::Receiver<TargetCommand>,
     outgoing_tx: mpsc::Sender<WsEnvelope>,
+    stats: Arc<SchedulerStats>,
+    sighting_tx: mpsc::Sender<HopSighting>,
 ) {
-    let mut targets: HashMap<Uuid, TargetState> = HashMap::new();
-    let timeout_ms = config.default_timeout_ms;
+    let config = config_rx.borrow_and_update().clone();
+    let ctx = RoundContext {
+        outgoing_tx: outgoing_tx.clone(),
+        probes: ProbeBudget::new(probe_limit_of(&config)),
+        stats,
+        sighting_tx,
+        timeout_ms: Arc::new(AtomicU64::new(config.probe.default_timeout_ms)),
+    };
+    let agent_id: Uuid = config.agent_id.parse().unwrap_or_default();
+
+    let mut targets: HashMap<Uuid, TargetHandle> = HashMap::new();
+    let mut overload_check = tokio::time::interval(OVERLOAD_CHECK_INTERVAL);
+    overload_check.reset();
+    let mut overloaded = false;
+    let mut skipped_total = 0;
 
     loop {
-        // Check for target commands (non-blocking drain)
-        while let Ok(cmd) = target_rx.try_recv() {
-            match cmd {
-                TargetCommand::Add(target_config) => {
-                    let target_id = target_config.target_id;
-                    let session_id = target_config.session_id;
-                    tracing::info!(
-                        target_id = %target_id,
-                        session_id = %session_id,
-                        address = %target_config.address,
-                        "New target assigned"
-                    );
+        tokio::select! {
+            cmd = target_rx.recv() => {
+                let Some(cmd) = cmd else {
+                    break;
+                };
+                match cmd {
+                    TargetCommand::Add(target_config) => {
+                        let target_id = target_config.target_id;
+                        tracing::info!(
+                            target_id = %target_id,
+                            session_id = %target_config.session_id,
+                            address = %target_config.address,
+                            "New target assigned"
+                        );
 
-                    // Resolve destination IP
-                    let dest_ip = resolve_target(&target_config.address).await;
-
-                    targets.insert(target_id, TargetState {
-                        config: target_config,
-                        session_id,
-                        round_counter: 0,
-                        dest_ip,
-                        known_hops: 30,
-                        last_probe_time: None,
-                    });
-                }
-                TargetCommand::Remove(target_ids) => {
-                    for id in &target_ids {
-                        if targets.remove(id).is_some() {
-                            tracing::info!(target_id = %id, "Target removed");
+                        let old = targets.remove(&target_id);
+                        if let Some(old) = &old {
+                            old.task.abort();
                         }
+                        let session_id = target_config.session_id;
+                        let round_counter = Arc::new(AtomicU64::new(rounds_used(
+                            &target_config,
+                            old.map(|h| (h.session_id, h.round_counter.load(Ordering::Relaxed))),
+                        )));
+                        let (update_tx, update_rx) = mpsc::channel(8);
+                        let task = tokio::spawn(run_target(target_config, round_counter.clone(), update_rx, ctx.clone()));
+                        targets.insert(target_id, TargetHandle { task, update_tx, session_id, round_counter });
+                    }
+                    TargetCommand::Update { msg_id, update } => {
+                        let target_id = update.target_id;
+                        let sent = match targets.get(&target_id) {
+                            Some(handle) => handle.update_tx.send((msg_id, update)).await.is_ok(),
+                            None => false,
+                        };
+                        if !sent {
+                            let error = "target is not running on this agent".to_string();
+                            tracing::warn!(target_id = %target_id, error = %error, "Config update rejected");
+                            send_ack(&outgoing_tx, msg_id, Err(error)).await;
+                        }
+                    }
+                    TargetCommand::Remove(target_ids) => {
+                        for id in &target_ids {
+                            if let Some(handle) = targets.remove(id) {
+                                handle.task.abort();
+                                tracing::info!(target_id = %id, "Target removed");
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(()) = config_rx.changed() => {
+                let config = config_rx.borrow_and_update().clone();
+                apply_probe_config(&config, &ctx);
+            }
+            _ = overload_check.tick() => {
+                let probe_limit = ctx.probes.limit();
+                let total = ctx.stats.skipped_rounds.load(Ordering::Relaxed);
+                let skipped = total - std::mem::replace(&mut skipped_total, total);
+                let report = if skipped > 0 {
+                    tracing::warn!(
+                        skipped_rounds = skipped,
+                        targets = targets.len(),
+                        max_concurrent_probes = probe_limit,
+                        "Scheduler overloaded, probe rounds skipped"
+                    );
+                    overloaded = true;
+                    Some(AgentStatusReport {
+                        agent_id,
+                        status: AgentStatus::Degraded,
+                        message: Some(format!(
+                            "scheduler overloaded: {} probe rounds skipped in the last {}s ({} targets, max_concurrent_probes = {})",
+                            skipped,
+                            OVERLOAD_CHECK_INTERVAL.as_secs(),
+                            targets.len(),
+                            probe_limit
+                        )),
+                    })
+                } else if overloaded {
+                    tracing::info!("Scheduler caught up");
+                    overloaded = false;
+                    Some(AgentStatusReport {
+                        agent_id,
+                        status: AgentStatus::Running,
+                        message: None,
+                    })
+                } else {
+                    None
+                };
+                if let Some(report) = report {
+                    let envelope = WsEnvelope::new(WsPayload::AgentStatus(report));
+                    if outgoing_tx.send(envelope).await.is_err() {
+                        tracing::warn!("Failed to queue agent status (connection down?)");
                     }
                 }
             }
         }
+    }
+
+    for handle in targets.into_values() {
+        handle.task.abort();
+    }
+}
+
+fn probe_limit_of(config: &AgentConfig) -> u32 {
+    config.probe.max_concurrent_probes.min(u32::MAX as usize) as u32
+}
+
+/// Apply reloaded `[probe]` settings to the running scheduler. Rounds already
+/// in flight finish with the values they started with.
+fn apply_probe_config(config: &AgentConfig, ctx: &RoundContext) {
+    let old_timeout = ctx.timeout_ms.swap(config.probe.default_timeout_ms, Ordering::Relaxed);
+    let limit_changed = ctx.probes.set_limit(probe_limit_of(config));
+    if old_timeout == config.probe.default_timeout_ms && !limit_changed {
+        return;
+    }
+    tracing::info!(
+        timeout_ms = config.probe.default_timeout_ms,
+        max_concurrent_probes = ctx.probes.limit(),
+        "Probe settings updated"
+    );
+}
 
-        if targets.is_empty() {
-            // No targets yet, wait a bit then check again
-            tokio::time::sleep(Duration::from_millis(500)).await;
-            continue;
+/// Round numbers a newly assigned target has to continue after: those the
+/// server has seen, and those the task it replaces (session, last round)
+/// sent in the same session but the server may not have stored yet.
+fn rounds_used(config: &TargetConfig, replaced: Option<(Uuid, u64)>) -> u64 {
+    let running = replaced
+        .filter(|(session_id, _)| *session_id == config.session_id)
+        .map_or(0, |(_, round)| round);
+    running.max(config.first_round.saturating_sub(1))
+}
+
+/// Timer loop of one target.
+async fn run_target(
+    config: TargetConfig,
+    round_counter: Arc<AtomicU64>,
+    mut update_rx: mpsc::Receiver<(Uuid, AgentConfigUpdate)>,
+    ctx: RoundContext,
+) {
+    let mut state = TargetState {
+        session_id: config.session_id,
+        dest_ip: resolve_target(&config.address, config.address_family).await,
+        config,
+        start_round: round_counter.load(Ordering::Relaxed),
+        round_counter,
+        known_hops: 30,
+        last_rtts: HashMap::new(),
+        multipath_task: None,
+        pmtu_task: None,
+    };
+
+    let mut interval = interval_of(&state.config);
+    let mut next_slot = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_slot) => {}
+            update = update_rx.recv() => {
+                let Some((msg_id, update)) = update else {
+                    return;
+                };
+                let result = apply_update(&mut state, &update);
+                match &result {
+                    Ok(()) => tracing::info!(target_id = %update.target_id, "Config update applied"),
+                    Err(e) => tracing::warn!(target_id = %update.target_id, error = %e, "Config update rejected"),
+                }
+                send_ack(&ctx.outgoing_tx, msg_id, result).await;
+
+                let new_interval = interval_of(&state.config);
+                if new_interval != interval {
+                    // Keep the grid's phase but don't wait out a longer old interval.
+                    interval = new_interval;
+                    next_slot = next_slot.min(Instant::now() + interval);
+                }
+                continue;
+            }
         }
 
-        // Run probe rounds for targets that are due
-        let now = Instant::now();
-        for state in targets.values_mut() {
-            // Check if this target is due for a probe
-            if let Some(last) = state.last_probe_time {
-                let interval = Duration::from_millis(state.config.interval_ms as u64);
-                if now.duration_since(last) < interval {
-                    continue;
+        let slot = next_slot;
+        let sent_at = wall_clock(slot);
+        next_slot = slot + interval;
+
+        match state.dest_ip {
+            Some(dest_ip) => {
+                let ttl_count = probe::engine::round_ttl_count(&state.config, state.known_hops);
+                let wanted = ctx.probes.first_batch(ttl_count);
+                match acquire_for_slot(&ctx.probes, wanted, next_slot, &ctx.stats).await {
+                    Some(permits) => execute_round(&mut state, dest_ip, sent_at, permits, &ctx).await,
+                    None => tracing::debug!(target = %state.config.address, "No probe capacity, round skipped"),
                 }
             }
+            None => {
+                state.dest_ip = resolve_target(&state.config.address, state.config.address_family).await;
+            }
+        }
 
-            let Some(dest_ip) = state.dest_ip else {
-                // Try to resolve again
-                state.dest_ip = resolve_target(&state.config.address).await;
-                continue;
-            };
+        // A round that ran past its slot(s) gives those slots up rather than
+        // firing them back to back.
+        let missed = skip_missed_slots(&mut next_slot, interval, Instant::now());
+        if missed > 0 && state.dest_ip.is_some() {
+            ctx.stats.skipped_rounds.fetch_add(missed as u64, Ordering::Relaxed);
+            tracing::debug!(target = %state.config.address, missed = missed, "Round overran its interval");
+        }
+    }
+}
+
+fn interval_of(config: &TargetConfig) -> Duration {
+    Duration::from_millis(config.interval_ms.max(1) as u64)
+}
+
+/// Wall-clock time of `slot`, which may have passed already.
+fn wall_clock(slot: Instant) -> DateTime<Utc> {
+    let since = Instant::now().saturating_duration_since(slot);
+    Utc::now() - chrono::Duration::from_std(since).unwrap_or_default()
+}
 
-            state.round_counter += 1;
-            state.last_probe_time = Some(now);
-            let round = state.round_counter;
-
-            tracing::debug!(
-                target = %state.config.address,
-                round = round,
-                "Executing probe round"
-            );
-
-            let report = probe::engine::execute_round(
-                &state.config,
-                state.session_id,
-                round,
-                dest_ip,
-                state.known_hops,
-                timeout_ms,
-            )
-            .await;
-
-            // Update known_hops based on actual responses
-            if let Some(last_responding) = report.hops.iter()
-                .rev()
-                .find(|h| h.ip_address.is_some())
-            {
-                state.known_hops = last_responding.hop_number;
+/// Permits for the first batch of a round, waited for no longer than until
+/// the next slot: a round that starts late would just crowd out the next
+/// one. A round that gets none is counted as skipped.
+async fn acquire_for_slot(
+    probes: &ProbeBudget,
+    wanted: u32,
+    next_slot: Instant,
+    stats: &SchedulerStats,
+) -> Option<OwnedSemaphorePermit> {
+    let queued = QueuedRound::new(&stats.queued_rounds);
+    let acquired = tokio::time::timeout_at(next_slot, probes.acquire(wanted)).await;
+    drop(queued);
+    match acquired {
+        Ok(permits) => permits,
+        Err(_) => {
+            stats.skipped_rounds.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// Move `next_slot` past `now` if the last round ran into it, and return how
+/// many slots were given up.
+fn skip_missed_slots(next_slot: &mut Instant, interval: Duration, now: Instant) -> u32 {
+    if *next_slot > now {
+        return 0;
+    }
+    let missed = ((now - *next_slot).as_nanos() / interval.as_nanos()) as u32 + 1;
+    *next_slot += interval * missed;
+    missed
+}
+
+async fn execute_round(
+    state: &mut TargetState,
+    dest_ip: IpAddr,
+    sent_at: DateTime<Utc>,
+    permits: OwnedSemaphorePermit,
+    ctx: &RoundContext,
+) {
+    let round = state.round_counter.fetch_add(1, Ordering::Relaxed) + 1;
+    let timeout_ms = ctx.timeout_ms.load(Ordering::Relaxed);
+
+    tracing::debug!(
+        target = %state.config.address,
+        round = round,
+        "Executing probe round"
+    );
+
+    // Rounds wider than max_concurrent_probes go out in batches
+    let max_ttl = probe::engine::round_ttl_count(&state.config, state.known_hops);
+    let probe = probe::engine::round_probe(&state.config, dest_ip, timeout_ms);
+    let results = ctx.probes.send_round(probe, max_ttl, Some(permits)).await;
+    let mut report = probe::engine::round_report(&state.config, state.session_id, round, sent_at, results);
+    fill_jitter(&mut report.hops, &mut state.last_rtts);
+
+    // Update known_hops based on actual responses
+    if let Some(last_responding) = report.hops.iter()
+        .rev()
+        .find(|h| h.ip_address.is_some())
+    {
+        state.known_hops = last_responding.hop_number;
+    }
+
+    // Log summary
+    let responding = report.hops.iter().filter(|h| !h.is_lost).count();
+    let total = report.hops.len();
+    tracing::info!(
+        target = %state.config.address,
+        round = round,
+        "Probe round complete: {}/{} hops responded",
+        responding,
+        total
+    );
+
+    // A full enrichment queue just means these hops are offered again next round
+    for hop in &report.hops {
+        if let Some(ip) = hop.ip_address.as_deref().and_then(|ip| ip.parse().ok()) {
+            let _ = ctx.sighting_tx.try_send(HopSighting {
+                session_id: state.session_id,
+                hop_number: hop.hop_number,
+                ip,
+            });
+        }
+    }
+
+    // Send to server
+    let envelope = WsEnvelope::new(WsPayload::TraceRound(report));
+    if ctx.outgoing_tx.send(envelope).await.is_err() {
+        tracing::warn!("Failed to queue trace report (connection down?)");
+    }
+
+    // Multipath discovery takes many rounds' worth of probes, so it
+    // runs beside the regular rounds rather than delaying them.
+    let discovery_due = round == state.start_round + 1 || round.is_multiple_of(MULTIPATH_REDISCOVERY_ROUNDS);
+    let discovery_idle = state.multipath_task.as_ref().is_none_or(|t| t.is_finished());
+    if state.config.multipath_discovery && discovery_due && discovery_idle {
+        let config = state.config.clone();
+        let session_id = state.session_id;
+        let max_ttl = state.known_hops.min(config.max_hops);
+        let outgoing_tx = ctx.outgoing_tx.clone();
+        let probes = ctx.probes.clone();
+        state.multipath_task = Some(tokio::spawn(async move {
+            let report = probe::multipath::discover(&config, session_id, dest_ip, max_ttl, timeout_ms, &probes).await;
+            let envelope = WsEnvelope::new(WsPayload::MultipathDiscovery(report));
+            if outgoing_tx.send(envelope).await.is_err() {
+                tracing::warn!("Failed to queue multipath report (connection down?)");
             }
+        }));
+    }
 
-            // Log summary
-            let responding = report.hops.iter().filter(|h| !h.is_lost).count();
-            let total = report.hops.len();
-            tracing::info!(
-                target = %state.config.address,
-                round = round,
-                "Probe round complete: {}/{} hops responded",
-                responding,
-                total
-            );
-
-            // Send to server
-            let envelope = WsEnvelope::new(WsPayload::TraceRound(report));
+    let pmtu_due = round == state.start_round + 1 || round.is_multiple_of(PMTU_REDISCOVERY_ROUNDS);
+    let pmtu_idle = state.pmtu_task.as_ref().is_none_or(|t| t.is_finished());
+    if state.config.pmtu_discovery && pmtu_due && pmtu_idle {
+        let config = state.config.clone();
+        let session_id = state.session_id;
+        let max_ttl = state.known_hops.min(config.max_hops);
+        let outgoing_tx = ctx.outgoing_tx.clone();
+        let probes = ctx.probes.clone();
+        state.pmtu_task = Some(tokio::spawn(async move {
+            // The walk has one probe in flight at a time
+            let Some(permit) = probes.acquire(1).await else {
+                return;
+            };
+            let report = probe::pmtu::discover(&config, session_id, dest_ip, max_ttl, timeout_ms).await;
+            drop(permit);
+            let Some(report) = report else {
+                return;
+            };
+            let envelope = WsEnvelope::new(WsPayload::PathMtuDiscovery(report));
             if outgoing_tx.send(envelope).await.is_err() {
-                tracing::warn!("Failed to queue trace report (connection down?)");
+                tracing::warn!("Failed to queue path MTU report (connection down?)");
             }
+        }));
+    }
+}
+
+async fn send_ack(outgoing_tx: &mpsc::Sender<WsEnvelope>, msg_id: Uuid, result: Result<(), String>) {
+    let ack = WsEnvelope::new(WsPayload::AckResponse(AckResponse {
+        ack_msg_id: msg_id,
+        success: result.is_ok(),
+        error: result.err(),
+    }));
+    if outgoing_tx.send(ack).await.is_err() {
+        tracing::warn!("Failed to queue config ack (connection down?)");
+    }
+}
+
+/// Set each answering hop's jitter against its previous reply, here rather
+/// than on the server so rounds spooled while offline get it too.
+fn fill_jitter(hops: &mut [HopSample], last_rtts: &mut HashMap<u8, u32>) {
+    for hop in hops {
+        let Some(rtt) = hop.rtt_us else { continue };
+        if let Some(prev) = last_rtts.insert(hop.hop_number, rtt) {
+            hop.jitter_us = Some(rtt.abs_diff(prev));
         }
+    }
+}
 
-        // Sleep for a short tick interval to check timing
-        tokio::time::sleep(Duration::from_millis(100)).await;
+/// Apply a config update to a running target. The session and round counter
+/// carry on, so the server sees one continuous session.
+fn apply_update(state: &mut TargetState, update: &AgentConfigUpdate) -> Result<(), String> {
+    if update.interval_ms == Some(0) {
+        return Err("interval_ms must be positive".to_string());
     }
+    if update.max_hops == Some(0) {
+        return Err("max_hops must be positive".to_string());
+    }
+
+    let config = &mut state.config;
+    if let Some(interval_ms) = update.interval_ms {
+        config.interval_ms = interval_ms;
+    }
+    if let Some(packet_size) = update.packet_size {
+        config.packet_size = packet_size;
+    }
+    if let Some(probe_port) = update.probe_port {
+        config.probe_port = Some(probe_port);
+    }
+    if let Some(max_hops) = update.max_hops {
+        config.max_hops = max_hops;
+    }
+    if let Some(method) = update.probe_method {
+        if method != config.probe_method {
+            // Another method may get further (or less far), so rediscover the path length.
+            config.probe_method = method;
+            state.known_hops = config.max_hops;
+        }
+    }
+    state.known_hops = state.known_hops.min(config.max_hops);
+    Ok(())
 }
 
-async fn resolve_target(address: &str) -> Option<IpAddr> {
+async fn resolve_target(address: &str, family: AddressFamily) -> Option<IpAddr> {
+    let wanted = |ip: &IpAddr| match family {
+        AddressFamily::Auto => true,
+        AddressFamily::Ipv4 => ip.is_ipv4(),
+        AddressFamily::Ipv6 => ip.is_ipv6(),
+    };
+
     // Try parsing as IP first
     if let Ok(ip) = address.parse::<IpAddr>() {
+        if !wanted(&ip) {
+            tracing::warn!(address = %address, family = %family, "Target address does not match its address family");
+            return None;
+        }
         return Some(ip);
     }
 
     // DNS resolution
     match tokio::net::lookup_host(format!("{}:0", address)).await {
-        Ok(mut addrs) => addrs.next().map(|a| a.ip()),
+        Ok(addrs) => {
+            let ip = addrs.map(|a| a.ip()).find(|ip| wanted(ip));
+            if ip.is_none() {
+                tracing::warn!(address = %address, family = %family, "No address of the requested family");
+            }
+            ip
+        }
         Err(e) => {
             tracing::warn!(address = %address, error = %e, "DNS resolution failed");
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nm_common::protocol::ProbeMethod;
+
+    #[test]
+    fn update_keeps_session_and_clamps_hops() {
+        let session_id = Uuid::new_v4();
+        let mut state = TargetState {
+            config: TargetConfig {
+                target_id: Uuid::new_v4(),
+                session_id,
+                address: "192.0.2.1".into(),
+                probe_method: ProbeMethod::Icmp,
+                probe_port: None,
+                packet_size: 64,
+                interval_ms: 1000,
+                max_hops: 30,
+                address_family: AddressFamily::Auto,
+                flow_stable: false,
+                multipath_discovery: false,
+                pmtu_discovery: false,
+                first_round: 0,
+            },
+            session_id,
+            round_counter: Arc::new(AtomicU64::new(42)),
+            start_round: 0,
+            dest_ip: None,
+            known_hops: 12,
+            last_rtts: HashMap::new(),
+            multipath_task: None,
+            pmtu_task: None,
+        };
+        let mut update = AgentConfigUpdate {
+            target_id: state.config.target_id,
+            interval_ms: Some(500),
+            packet_size: None,
+            probe_method: None,
+            max_hops: Some(8),
+            probe_port: None,
+        };
+
+        apply_update(&mut state, &update).unwrap();
+        assert_eq!(state.config.interval_ms, 500);
+        assert_eq!(state.config.packet_size, 64);
+        assert_eq!(state.known_hops, 8);
+        assert_eq!(state.round_counter.load(Ordering::Relaxed), 42);
+        assert_eq!(state.session_id, session_id);
+
+        update.interval_ms = Some(0);
+        assert!(apply_update(&mut state, &update).is_err());
+        assert_eq!(state.config.interval_ms, 500);
+    }
+
+    #[test]
+    fn reassigned_session_continues_its_round_numbers() {
+        let session_id = Uuid::new_v4();
+        let mut config = TargetConfig {
+            target_id: Uuid::new_v4(),
+            session_id,
+            address: "192.0.2.1".into(),
+            probe_method: ProbeMethod::Icmp,
+            probe_port: None,
+            packet_size: 64,
+            interval_ms: 1000,
+            max_hops: 30,
+            address_family: AddressFamily::Auto,
+            flow_stable: false,
+            multipath_discovery: false,
+            pmtu_discovery: false,
+            first_round: 0,
+        };
+        assert_eq!(rounds_used(&config, None), 0);
+
+        config.first_round = 101;
+        assert_eq!(rounds_used(&config, None), 100);
+        // Rounds sent while disconnected are ahead of what the server stored
+        assert_eq!(rounds_used(&config, Some((session_id, 130))), 130);
+        assert_eq!(rounds_used(&config, Some((session_id, 50))), 100);
+        // A new session does not inherit the old one's numbers
+        assert_eq!(rounds_used(&config, Some((Uuid::new_v4(), 130))), 100);
+    }
+
+    #[test]
+    fn jitter_is_against_the_hops_previous_reply() {
+        let hop = |hop_number: u8, rtt_us: Option<u32>| HopSample {
+            hop_number,
+            ip_address: None,
+            rtt_us,
+            is_lost: rtt_us.is_none(),
+            ttl_received: None,
+            tcp_reply: None,
+            mpls_labels: vec![],
+            jitter_us: None,
+        };
+        let mut last_rtts = HashMap::new();
+
+        let mut first = vec![hop(1, Some(1000)), hop(2, None)];
+        fill_jitter(&mut first, &mut last_rtts);
+        assert_eq!(first[0].jitter_us, None);
+
+        let mut second = vec![hop(1, Some(1300)), hop(2, Some(5000))];
+        fill_jitter(&mut second, &mut last_rtts);
+        assert_eq!(second[0].jitter_us, Some(300));
+        assert_eq!(second[1].jitter_us, None);
+
+        // A lost probe leaves the previous reply in place
+        let mut third = vec![hop(1, None), hop(2, Some(4200))];
+        fill_jitter(&mut third, &mut last_rtts);
+        assert_eq!(third[0].jitter_us, None);
+        assert_eq!(third[1].jitter_us, Some(800));
+
+        let mut fourth = vec![hop(1, Some(1100))];
+        fill_jitter(&mut fourth, &mut last_rtts);
+        assert_eq!(fourth[0].jitter_us, Some(200));
+    }
+
+    #[test]
+    fn overrun_gives_up_the_missed_slots() {
+        let interval = Duration::from_secs(1);
+        let start = Instant::now();
+
+        let mut next_slot = start + interval;
+        assert_eq!(skip_missed_slots(&mut next_slot, interval, start + Duration::from_millis(900)), 0);
+        assert_eq!(next_slot, start + interval);
+
+        // A round that ends 2.5 s past its slot misses the slots at 1 s and 2 s
+        assert_eq!(skip_missed_slots(&mut next_slot, interval, start + Duration::from_millis(2500)), 2);
+        assert_eq!(next_slot, start + Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn round_without_permits_by_the_next_slot_is_skipped() {
+        let stats = SchedulerStats::default();
+        let probes = ProbeBudget::new(20);
+        let busy = probes.acquire(20).await.unwrap();
+
+        let next_slot = Instant::now() + Duration::from_secs(1);
+        assert!(acquire_for_slot(&probes, 20, next_slot, &stats).await.is_none());
+        assert_eq!(Instant::now(), next_slot);
+        assert_eq!(stats.skipped_rounds.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.queued_rounds.load(Ordering::Relaxed), 0);
+
+        // Permits freed before the slot ends let the round start
+        let next_slot = Instant::now() + Duration::from_secs(1);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            drop(busy);
+        });
+        let permits = acquire_for_slot(&probes, 20, next_slot, &stats).await.unwrap();
+        assert_eq!(permits.num_permits(), 20);
+        assert!(Instant::now() < next_slot);
+        assert_eq!(stats.skipped_rounds.load(Ordering::Relaxed), 1);
+    }
+}
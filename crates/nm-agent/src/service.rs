@@ -67,27 +67,16 @@ fn run_service_inner() -> anyhow::Result<()> {
         let config_path = format!("{}\\{}", INSTALL_DIR, CONFIG_FILENAME);
         let config = crate::config::load(&config_path)?;
 
-        // Set up logging to file in the install directory
+        // Log to the install directory unless the config names a file
         let log_path = format!("{}\\nm-agent.log", INSTALL_DIR);
-        let file = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&log_path)?;
-
-        tracing_subscriber::fmt()
-            .with_env_filter(
-                tracing_subscriber::EnvFilter::try_from_default_env()
-                    .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&config.log_level)),
-            )
-            .with_writer(std::sync::Mutex::new(file))
-            .init();
+        let log_handle = crate::init_logging(&config.logging, Some(&log_path))?;
 
         tracing::info!("Network Master Agent service starting");
 
         // Clean up old binaries from OTA updates
         crate::updater::cleanup_old_binaries();
 
-        crate::run_agent(config, shutdown_rx).await
+        crate::run_agent(config, config_path, log_handle, shutdown_rx).await
     })?;
 
     status_handle.set_service_status(ServiceStatus {
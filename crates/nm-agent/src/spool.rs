@@ -0,0 +1,302 @@
+//! On-disk store-and-forward buffer for trace data.
+//!
+//! While the server is unreachable, data messages are appended to a spool
+//! file as length-prefixed msgpack `WsEnvelope`s. After the next successful
+//! authentication they are replayed in order, in batches the server
+//! acknowledges; each acknowledged batch advances a committed offset kept in a
+//! sidecar file, so an agent restart mid-replay resumes where it stopped.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use nm_common::protocol::{WsEnvelope, WsPayload};
+use uuid::Uuid;
+
+/// Frame header: big-endian length of the msgpack body that follows.
+const FRAME_HEADER_LEN: u64 = 4;
+
+pub struct Spool {
+    path: PathBuf,
+    offset_path: PathBuf,
+    file: File,
+    /// Bytes in the spool file (always ends on a frame boundary).
+    len: u64,
+    /// Bytes already acknowledged by the server.
+    committed: u64,
+    max_bytes: u64,
+    /// Messages refused since the last `take_dropped` because the spool was full.
+    dropped: u64,
+    /// Highest trace round spooled per session, including those from a
+    /// previous run, so resumed sessions do not reuse their numbers.
+    last_rounds: HashMap<Uuid, u64>,
+}
+
+/// Only measurement data and what is learned about it is worth keeping;
+/// heartbeats, statuses and acks are stale by the time the connection comes back.
+pub fn is_spoolable(payload: &WsPayload) -> bool {
+    matches!(
+        payload,
+        WsPayload::TraceRound(_)
+            | WsPayload::RouteDiscovery(_)
+            | WsPayload::MultipathDiscovery(_)
+            | WsPayload::PathMtuDiscovery(_)
+            | WsPayload::HopMetadata(_)
+            | WsPayload::NetworkDiscovery(_)
+    )
+}
+
+impl Spool {
+    pub fn open(path: impl AsRef<Path>, max_bytes: u64) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut offset_path = path.clone().into_os_string();
+        offset_path.push(".offset");
+        let offset_path = PathBuf::from(offset_path);
+
+        let file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+        let committed = std::fs::read_to_string(&offset_path)
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(0);
+
+        let mut spool = Self {
+            path,
+            offset_path,
+            file,
+            len: 0,
+            committed,
+            max_bytes,
+            dropped: 0,
+            last_rounds: HashMap::new(),
+        };
+
+        // A crash mid-append leaves a partial frame at the end; cut it off so
+        // new frames start on a boundary.
+        let complete = spool.complete_len()?;
+        if complete != spool.file.metadata()?.len() {
+            tracing::warn!(path = %spool.path.display(), "Truncating partial frame at end of spool");
+            spool.file.set_len(complete)?;
+        }
+        spool.len = complete;
+        if spool.committed > spool.len {
+            spool.committed = spool.len;
+        }
+        if !spool.is_empty() {
+            spool.scan_rounds()?;
+            tracing::info!(
+                path = %spool.path.display(),
+                pending_bytes = spool.len - spool.committed,
+                "Spool has data from a previous run"
+            );
+        }
+        Ok(spool)
+    }
+
+    /// True when everything written has been acknowledged.
+    pub fn is_empty(&self) -> bool {
+        self.committed >= self.len
+    }
+
+    /// Append one message. Returns false (and counts it) when the spool is full.
+    pub fn push(&mut self, envelope: &WsEnvelope) -> bool {
+        let body = match rmp_serde::to_vec(envelope) {
+            Ok(b) => b,
+            Err(e) => {
+                tracing::error!("Failed to serialize spooled message: {e}");
+                return false;
+            }
+        };
+        let frame_len = FRAME_HEADER_LEN + body.len() as u64;
+        if self.len + frame_len > self.max_bytes {
+            if self.dropped == 0 {
+                tracing::warn!(
+                    path = %self.path.display(),
+                    max_bytes = self.max_bytes,
+                    "Spool full, dropping new trace data until the server is back"
+                );
+            }
+            self.dropped += 1;
+            return false;
+        }
+
+        let mut frame = Vec::with_capacity(frame_len as usize);
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&body);
+        if let Err(e) = self.file.write_all(&frame).and_then(|_| self.file.flush()) {
+            tracing::error!(path = %self.path.display(), error = %e, "Failed to write to spool");
+            // Drop whatever part of the frame made it to disk.
+            let _ = self.file.set_len(self.len);
+            return false;
+        }
+        self.len += frame_len;
+        if let WsPayload::TraceRound(report) = &envelope.payload {
+            self.note_round(report.session_id, report.round_number);
+        }
+        true
+    }
+
+    /// First round number of `session_id` not yet in the spool (0 if none is).
+    pub fn next_round(&self, session_id: Uuid) -> u64 {
+        self.last_rounds.get(&session_id).map_or(0, |round| round + 1)
+    }
+
+    fn note_round(&mut self, session_id: Uuid, round_number: u64) {
+        let last = self.last_rounds.entry(session_id).or_default();
+        *last = (*last).max(round_number);
+    }
+
+    /// Record the rounds of unacknowledged messages left by a previous run.
+    fn scan_rounds(&mut self) -> io::Result<()> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(self.committed))?;
+        let mut offset = self.committed;
+        while offset < self.len {
+            let body = read_frame(&mut reader)?;
+            offset += FRAME_HEADER_LEN + body.len() as u64;
+            if let Ok(WsEnvelope { payload: WsPayload::TraceRound(report), .. }) = rmp_serde::from_slice::<WsEnvelope>(&body) {
+                self.note_round(report.session_id, report.round_number);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read up to `max_messages` unacknowledged messages. Returns them with
+    /// the offset to `commit` once the server has acknowledged them.
+    pub fn read_batch(&self, max_messages: usize) -> io::Result<(Vec<WsEnvelope>, u64)> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(self.committed))?;
+
+        let mut messages = Vec::new();
+        let mut offset = self.committed;
+        while offset < self.len && messages.len() < max_messages {
+            let body = read_frame(&mut reader)?;
+            offset += FRAME_HEADER_LEN + body.len() as u64;
+            match rmp_serde::from_slice::<WsEnvelope>(&body) {
+                Ok(envelope) => messages.push(envelope),
+                // Written by an older agent with a different protocol; skip it.
+                Err(e) => tracing::warn!(error = %e, "Skipping unreadable spool frame"),
+            }
+        }
+        Ok((messages, offset))
+    }
+
+    /// Mark everything before `offset` as delivered. Once the whole spool is
+    /// delivered the file is emptied.
+    pub fn commit(&mut self, offset: u64) -> io::Result<()> {
+        self.committed = offset.min(self.len);
+        if self.is_empty() {
+            self.file.set_len(0)?;
+            self.len = 0;
+            self.committed = 0;
+            match std::fs::remove_file(&self.offset_path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            return Ok(());
+        }
+        std::fs::write(&self.offset_path, self.committed.to_string())
+    }
+
+    /// Change the size limit; data already spooled is kept even if it is over it.
+    pub fn set_max_bytes(&mut self, max_bytes: u64) {
+        self.max_bytes = max_bytes;
+    }
+
+    /// Number of messages dropped since the last call, resetting the count.
+    pub fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// Length of the file up to the end of its last complete frame.
+    fn complete_len(&self) -> io::Result<u64> {
+        let total = self.file.metadata()?.len();
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut offset = 0;
+        loop {
+            let mut header = [0u8; FRAME_HEADER_LEN as usize];
+            if offset + FRAME_HEADER_LEN > total || reader.read_exact(&mut header).is_err() {
+                return Ok(offset);
+            }
+            let frame_end = offset + FRAME_HEADER_LEN + u32::from_be_bytes(header) as u64;
+            if frame_end > total {
+                return Ok(offset);
+            }
+            reader.seek_relative(u32::from_be_bytes(header) as i64)?;
+            offset = frame_end;
+        }
+    }
+}
+
+fn read_frame(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut header = [0u8; FRAME_HEADER_LEN as usize];
+    reader.read_exact(&mut header)?;
+    let mut body = vec![0u8; u32::from_be_bytes(header) as usize];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nm_common::protocol::TraceRoundReport;
+    use uuid::Uuid;
+
+    fn round(n: u64) -> WsEnvelope {
+        WsEnvelope::new(WsPayload::TraceRound(TraceRoundReport {
+            target_id: Uuid::nil(),
+            session_id: Uuid::nil(),
+            round_number: n,
+            sent_at: chrono::Utc::now(),
+            hops: Vec::new(),
+            probe_method: None,
+            packet_size: None,
+        }))
+    }
+
+    fn round_number(envelope: &WsEnvelope) -> u64 {
+        match &envelope.payload {
+            WsPayload::TraceRound(r) => r.round_number,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn replays_in_order_across_restart() {
+        let dir = std::env::temp_dir().join(format!("nm-spool-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("agent.spool");
+
+        let mut spool = Spool::open(&path, 1 << 20).unwrap();
+        for n in 1..=5 {
+            assert!(spool.push(&round(n)));
+        }
+        let (batch, offset) = spool.read_batch(2).unwrap();
+        assert_eq!(batch.iter().map(round_number).collect::<Vec<_>>(), vec![1, 2]);
+        spool.commit(offset).unwrap();
+        drop(spool);
+
+        // Simulate a crash in the middle of an append.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 1]).unwrap();
+        drop(file);
+
+        let mut spool = Spool::open(&path, 1 << 20).unwrap();
+        assert_eq!(spool.next_round(Uuid::nil()), 6);
+        assert_eq!(spool.next_round(Uuid::new_v4()), 0);
+        let (batch, offset) = spool.read_batch(10).unwrap();
+        assert_eq!(batch.iter().map(round_number).collect::<Vec<_>>(), vec![3, 4, 5]);
+        spool.commit(offset).unwrap();
+        assert!(spool.is_empty());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+        // Bounded: frames past max_bytes are refused and counted.
+        let frame_len = FRAME_HEADER_LEN + rmp_serde::to_vec(&round(6)).unwrap().len() as u64;
+        let mut spool = Spool::open(&path, frame_len + 10).unwrap();
+        assert!(spool.push(&round(6)));
+        assert!(!spool.push(&round(7)));
+        assert_eq!(spool.take_dropped(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
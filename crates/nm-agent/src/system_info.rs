@@ -1,18 +1,33 @@
-use sysinfo::System;
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
 
+/// Host and agent-process metrics reported in heartbeats.
 pub struct SystemInfo {
     sys: System,
+    pid: Option<Pid>,
 }
 
 impl SystemInfo {
     pub fn new() -> Self {
-        Self {
-            sys: System::new_all(),
-        }
+        let mut info = Self {
+            sys: System::new(),
+            pid: sysinfo::get_current_pid().ok(),
+        };
+        info.refresh();
+        info
     }
 
+    /// CPU usage is measured between two refreshes, so the first reading
+    /// after `new` is 0.
     pub fn refresh(&mut self) {
-        self.sys.refresh_all();
+        self.sys.refresh_cpu_usage();
+        self.sys.refresh_memory();
+        if let Some(pid) = self.pid {
+            self.sys.refresh_processes_specifics(
+                ProcessesToUpdate::Some(&[pid]),
+                false,
+                ProcessRefreshKind::nothing().with_memory(),
+            );
+        }
     }
 
     pub fn cpu_usage(&self) -> f32 {
@@ -23,6 +38,27 @@ impl SystemInfo {
         (self.sys.used_memory() / 1_048_576) as u32
     }
 
+    pub fn memory_total_mb(&self) -> u32 {
+        (self.sys.total_memory() / 1_048_576) as u32
+    }
+
+    /// Resident memory of this process.
+    pub fn agent_rss_mb(&self) -> u32 {
+        self.pid
+            .and_then(|pid| self.sys.process(pid))
+            .map(|p| (p.memory() / 1_048_576) as u32)
+            .unwrap_or(0)
+    }
+
+    /// Windows has no load average (sysinfo reports zeros there).
+    pub fn load_avg_1m() -> Option<f32> {
+        if cfg!(windows) {
+            None
+        } else {
+            Some(System::load_average().one as f32)
+        }
+    }
+
     pub fn hostname() -> String {
         System::host_name().unwrap_or_else(|| "unknown".to_string())
     }
@@ -0,0 +1,135 @@
+//! TLS settings for the server connection: the client certificate for the
+//! server's mTLS listener and how the server's certificate is verified.
+
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use nm_common::config::AgentTlsConfig;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, DigitallySignedStruct, SignatureScheme};
+use sha2::{Digest, Sha256};
+use tokio_tungstenite::Connector;
+
+/// Connector for `config`, or None when nothing is configured and the
+/// default (web roots, no client certificate) applies.
+pub fn connector(config: &AgentTlsConfig) -> Result<Option<Connector>> {
+    if *config == AgentTlsConfig::default() {
+        return Ok(None);
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+
+    let builder = if let Some(fingerprint) = &config.server_fingerprint {
+        let pinned = PinnedServerCert {
+            sha256: parse_fingerprint(fingerprint)?,
+            provider,
+        };
+        builder.dangerous().with_custom_certificate_verifier(Arc::new(pinned))
+    } else {
+        let mut roots = rustls::RootCertStore::empty();
+        match &config.ca_cert {
+            Some(path) => {
+                for cert in CertificateDer::pem_file_iter(path).with_context(|| format!("Failed to read {}", path))? {
+                    roots.add(cert.with_context(|| format!("Invalid certificate in {}", path))?)?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        builder.with_root_certificates(roots)
+    };
+
+    let tls = match (&config.client_cert, &config.client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let certs = CertificateDer::pem_file_iter(cert_path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .with_context(|| format!("Failed to read client certificate {}", cert_path))?;
+            let key = PrivateKeyDer::from_pem_file(key_path)
+                .with_context(|| format!("Failed to read client key {}", key_path))?;
+            builder.with_client_auth_cert(certs, key).context("Client certificate does not match its key")?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => bail!("tls.client_cert and tls.client_key must be set together"),
+    };
+
+    Ok(Some(Connector::Rustls(Arc::new(tls))))
+}
+
+/// Hex SHA-256, with or without colons between the bytes.
+pub(crate) fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32]> {
+    let digits: String = fingerprint.chars().filter(|c| *c != ':').collect();
+    hex::decode(&digits)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .with_context(|| format!("tls.server_fingerprint is not a hex SHA-256: {}", fingerprint))
+}
+
+/// Accepts exactly one server certificate, whoever issued it and whatever
+/// names it has. The handshake signature is still checked, so the server
+/// must hold that certificate's key.
+#[derive(Debug)]
+struct PinnedServerCert {
+    sha256: [u8; 32],
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedServerCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Sha256::digest(end_entity).as_slice() == self.sha256 {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_accepts_colons_and_case() {
+        let plain = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let colons = plain
+            .to_uppercase()
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(parse_fingerprint(plain).unwrap(), parse_fingerprint(&colons).unwrap());
+        assert!(parse_fingerprint("9f86d0").is_err());
+    }
+}
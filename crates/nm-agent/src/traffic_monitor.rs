@@ -7,24 +7,43 @@ use nm_common::protocol::{
     ConnectionEntry, ConnectionProtocol, ProcessNetworkEntry, ProcessTrafficReport, WsEnvelope,
     WsPayload,
 };
-use tokio::sync::mpsc;
+use nm_common::config::AgentConfig;
+use tokio::sync::{mpsc, watch};
 use uuid::Uuid;
 
-const POLL_INTERVAL_MS: u32 = 5000;
+/// Shortest poll interval accepted from the config.
+const MIN_POLL_INTERVAL_MS: u32 = 1000;
 
 /// Run the traffic monitor loop, sending reports via `outgoing_tx`.
-pub async fn run(agent_id: Uuid, outgoing_tx: mpsc::Sender<WsEnvelope>) {
+/// `[traffic]` settings are re-read before every poll.
+pub async fn run(
+    agent_id: Uuid,
+    mut config_rx: watch::Receiver<AgentConfig>,
+    outgoing_tx: mpsc::Sender<WsEnvelope>,
+) {
     tracing::info!("Traffic monitor starting");
 
     let mut monitor = TrafficMonitor::new();
 
     loop {
-        tokio::time::sleep(Duration::from_millis(POLL_INTERVAL_MS as u64)).await;
+        let traffic = config_rx.borrow_and_update().traffic.clone();
+        if !traffic.enabled {
+            tracing::info!("Traffic monitoring disabled");
+            if config_rx.changed().await.is_err() {
+                break;
+            }
+            // Byte counters moved on while disabled; start from a new baseline.
+            monitor = TrafficMonitor::new();
+            continue;
+        }
+        let interval_ms = traffic.poll_interval_ms.max(MIN_POLL_INTERVAL_MS);
+
+        tokio::time::sleep(Duration::from_millis(interval_ms as u64)).await;
 
         let report = match tokio::task::spawn_blocking({
             let mut mon = std::mem::replace(&mut monitor, TrafficMonitor::new());
             move || {
-                let result = mon.poll(agent_id);
+                let result = mon.poll(agent_id, interval_ms);
                 (mon, result)
             }
         })
@@ -58,6 +77,44 @@ pub async fn run(agent_id: Uuid, outgoing_tx: mpsc::Sender<WsEnvelope>) {
     }
 }
 
+/// Group connections into per-process entries, busiest process first.
+/// Connections without an owning process (pid 0) are dropped.
+fn build_process_entries(
+    pid_map: HashMap<u32, Vec<ConnectionEntry>>,
+    mut resolve_process: impl FnMut(u32) -> (String, Option<String>),
+) -> Vec<ProcessNetworkEntry> {
+    let mut processes = Vec::new();
+    for (pid, connections) in pid_map {
+        if pid == 0 {
+            continue; // Skip System Idle Process / unowned sockets
+        }
+
+        let (process_name, exe_path) = resolve_process(pid);
+
+        let total_bytes_in: u64 = connections.iter().map(|c| c.bytes_in).sum();
+        let total_bytes_out: u64 = connections.iter().map(|c| c.bytes_out).sum();
+        let active_connection_count = connections.len() as u32;
+
+        processes.push(ProcessNetworkEntry {
+            pid,
+            process_name,
+            exe_path,
+            connections,
+            total_bytes_in,
+            total_bytes_out,
+            active_connection_count,
+        });
+    }
+
+    // Sort by total bandwidth descending
+    processes.sort_by(|a, b| {
+        let a_total = a.total_bytes_in + a.total_bytes_out;
+        let b_total = b.total_bytes_in + b.total_bytes_out;
+        b_total.cmp(&a_total)
+    });
+    processes
+}
+
 // ─── Platform-specific implementation ────────────────────
 
 #[cfg(windows)]
@@ -135,7 +192,7 @@ mod platform {
             }
         }
 
-        pub fn poll(&mut self, agent_id: Uuid) -> anyhow::Result<ProcessTrafficReport> {
+        pub fn poll(&mut self, agent_id: Uuid, interval_ms: u32) -> anyhow::Result<ProcessTrafficReport> {
             // Refresh process list (names only, minimal overhead)
             self.system.refresh_processes_specifics(
                 sysinfo::ProcessesToUpdate::All,
@@ -202,40 +259,12 @@ mod platform {
             }
 
             // Build per-process entries
-            let mut processes = Vec::new();
-            for (pid, connections) in pid_map {
-                if pid == 0 {
-                    continue; // Skip System Idle Process
-                }
-
-                let (process_name, exe_path) = self.resolve_process(pid);
-
-                let total_bytes_in: u64 = connections.iter().map(|c| c.bytes_in).sum();
-                let total_bytes_out: u64 = connections.iter().map(|c| c.bytes_out).sum();
-                let active_connection_count = connections.len() as u32;
-
-                processes.push(ProcessNetworkEntry {
-                    pid,
-                    process_name,
-                    exe_path,
-                    connections,
-                    total_bytes_in,
-                    total_bytes_out,
-                    active_connection_count,
-                });
-            }
-
-            // Sort by total bandwidth descending
-            processes.sort_by(|a, b| {
-                let a_total = a.total_bytes_in + a.total_bytes_out;
-                let b_total = b.total_bytes_in + b.total_bytes_out;
-                b_total.cmp(&a_total)
-            });
+            let processes = build_process_entries(pid_map, |pid| self.resolve_process(pid));
 
             Ok(ProcessTrafficReport {
                 agent_id,
                 captured_at: Utc::now(),
-                interval_ms: POLL_INTERVAL_MS,
+                interval_ms,
                 processes,
             })
         }
@@ -402,7 +431,421 @@ mod platform {
     }
 }
 
-#[cfg(not(windows))]
+#[cfg(target_os = "linux")]
+mod platform {
+    //! Sockets come from `/proc/net/{tcp,tcp6,udp,udp6}` and are tied to
+    //! processes through the `socket:[inode]` links in `/proc/<pid>/fd`.
+    //! TCP byte counters come from netlink `sock_diag` (`tcp_info`
+    //! bytes_received / bytes_acked), keyed by the same inode. UDP sockets
+    //! have no per-socket counters, so they report 0 bytes like on Windows.
+
+    use super::*;
+    use std::io;
+    use std::net::{IpAddr, Ipv6Addr};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use sysinfo::{Pid, ProcessRefreshKind, RefreshKind, System};
+
+    // TCP states as numbered in /proc/net/tcp (include/net/tcp_states.h)
+    const TCP_ESTABLISHED: u8 = 1;
+    const TCP_SYN_SENT: u8 = 2;
+    const TCP_SYN_RECV: u8 = 3;
+    const TCP_FIN_WAIT1: u8 = 4;
+    const TCP_FIN_WAIT2: u8 = 5;
+    const TCP_TIME_WAIT: u8 = 6;
+    const TCP_CLOSE: u8 = 7;
+    const TCP_CLOSE_WAIT: u8 = 8;
+    const TCP_LAST_ACK: u8 = 9;
+    const TCP_LISTEN: u8 = 10;
+    const TCP_CLOSING: u8 = 11;
+
+    /// Same names the Windows MIB_TCP_STATE mapping reports.
+    fn tcp_state_str(state: u8) -> &'static str {
+        match state {
+            TCP_ESTABLISHED => "ESTABLISHED",
+            TCP_SYN_SENT => "SYN_SENT",
+            TCP_SYN_RECV => "SYN_RCVD",
+            TCP_FIN_WAIT1 => "FIN_WAIT1",
+            TCP_FIN_WAIT2 => "FIN_WAIT2",
+            TCP_TIME_WAIT => "TIME_WAIT",
+            TCP_CLOSE => "CLOSED",
+            TCP_CLOSE_WAIT => "CLOSE_WAIT",
+            TCP_LAST_ACK => "LAST_ACK",
+            TCP_LISTEN => "LISTEN",
+            TCP_CLOSING => "CLOSING",
+            _ => "UNKNOWN",
+        }
+    }
+
+    // sock_diag (linux/sock_diag.h, linux/inet_diag.h)
+    const SOCK_DIAG_BY_FAMILY: u16 = 20;
+    const INET_DIAG_INFO: u16 = 2;
+    const NLMSG_HDR_LEN: usize = 16;
+    const INET_DIAG_REQ_V2_LEN: usize = 56;
+    const INET_DIAG_MSG_LEN: usize = 72;
+    const INET_DIAG_MSG_INODE: usize = 68;
+    // struct tcp_info offsets (kernel 4.1+)
+    const TCPI_BYTES_ACKED: usize = 120;
+    const TCPI_BYTES_RECEIVED: usize = 128;
+
+    pub struct TrafficMonitor {
+        system: System,
+        /// Cumulative (bytes_received, bytes_acked) per socket inode at the last poll.
+        prev_counters: HashMap<u64, (u64, u64)>,
+        /// Set after the first poll, which only records a baseline.
+        primed: bool,
+        sock_diag_warned: bool,
+    }
+
+    struct ProcSocket {
+        protocol: ConnectionProtocol,
+        local: IpAddr,
+        local_port: u16,
+        remote: IpAddr,
+        remote_port: u16,
+        state: u8,
+        inode: u64,
+    }
+
+    impl TrafficMonitor {
+        pub fn new() -> Self {
+            Self {
+                system: System::new_with_specifics(
+                    RefreshKind::nothing().with_processes(ProcessRefreshKind::nothing()),
+                ),
+                prev_counters: HashMap::new(),
+                primed: false,
+                sock_diag_warned: false,
+            }
+        }
+
+        pub fn poll(&mut self, agent_id: Uuid, interval_ms: u32) -> anyhow::Result<ProcessTrafficReport> {
+            // Refresh process list (names only, minimal overhead)
+            self.system.refresh_processes_specifics(
+                sysinfo::ProcessesToUpdate::All,
+                true,
+                ProcessRefreshKind::nothing(),
+            );
+
+            let mut sockets = Vec::new();
+            for (file, protocol) in [
+                ("/proc/net/tcp", ConnectionProtocol::Tcp),
+                ("/proc/net/tcp6", ConnectionProtocol::Tcp),
+                ("/proc/net/udp", ConnectionProtocol::Udp),
+                ("/proc/net/udp6", ConnectionProtocol::Udp),
+            ] {
+                // tcp6/udp6 are missing when IPv6 is disabled
+                if let Ok(content) = std::fs::read_to_string(file) {
+                    sockets.extend(parse_proc_net(&content, protocol));
+                }
+            }
+
+            let owners = socket_owners();
+            let counters = match tcp_counters() {
+                Ok(c) => c,
+                Err(e) => {
+                    if !self.sock_diag_warned {
+                        tracing::warn!("sock_diag unavailable, TCP byte counters will be 0: {e}");
+                        self.sock_diag_warned = true;
+                    }
+                    HashMap::new()
+                }
+            };
+
+            // Group connections by PID
+            let mut pid_map: HashMap<u32, Vec<ConnectionEntry>> = HashMap::new();
+
+            for sock in &sockets {
+                // Skip LISTEN sockets
+                if sock.protocol == ConnectionProtocol::Tcp && sock.state == TCP_LISTEN {
+                    continue;
+                }
+
+                // Bytes since the last poll. A socket first seen after the
+                // baseline poll was opened during the interval, so all of its
+                // bytes are new.
+                let (bytes_in, bytes_out) = match counters.get(&sock.inode) {
+                    Some(&(received, acked)) => {
+                        let (prev_received, prev_acked) = match self.prev_counters.get(&sock.inode) {
+                            Some(&prev) => prev,
+                            None if self.primed => (0, 0),
+                            None => (received, acked),
+                        };
+                        (received.saturating_sub(prev_received), acked.saturating_sub(prev_acked))
+                    }
+                    None => (0, 0),
+                };
+
+                let entry = ConnectionEntry {
+                    protocol: sock.protocol,
+                    local_addr: sock.local.to_string(),
+                    local_port: sock.local_port,
+                    remote_addr: sock.remote.to_string(),
+                    remote_port: sock.remote_port,
+                    state: (sock.protocol == ConnectionProtocol::Tcp)
+                        .then(|| tcp_state_str(sock.state).to_string()),
+                    bytes_in,
+                    bytes_out,
+                };
+
+                // TIME_WAIT and other orphaned sockets have no owner
+                let pid = owners.get(&sock.inode).copied().unwrap_or(0);
+                pid_map.entry(pid).or_default().push(entry);
+            }
+
+            self.prev_counters = counters;
+            self.primed = true;
+
+            // Build per-process entries
+            let processes = build_process_entries(pid_map, |pid| self.resolve_process(pid));
+
+            Ok(ProcessTrafficReport {
+                agent_id,
+                captured_at: Utc::now(),
+                interval_ms,
+                processes,
+            })
+        }
+
+        fn resolve_process(&self, pid: u32) -> (String, Option<String>) {
+            if let Some(process) = self.system.process(Pid::from_u32(pid)) {
+                let name = process.name().to_string_lossy().to_string();
+                let exe = process.exe().map(|p| p.to_string_lossy().to_string());
+                (name, exe)
+            } else {
+                (format!("PID {pid}"), None)
+            }
+        }
+    }
+
+    /// Parse one of the /proc/net socket tables.
+    fn parse_proc_net(content: &str, protocol: ConnectionProtocol) -> Vec<ProcSocket> {
+        content
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let (local, local_port) = parse_endpoint(fields.get(1)?)?;
+                let (remote, remote_port) = parse_endpoint(fields.get(2)?)?;
+                Some(ProcSocket {
+                    protocol,
+                    local,
+                    local_port,
+                    remote,
+                    remote_port,
+                    state: u8::from_str_radix(fields.get(3)?, 16).ok()?,
+                    inode: fields.get(9)?.parse().ok()?,
+                })
+            })
+            .collect()
+    }
+
+    /// `0100007F:0035` style endpoint. The address is printed as 32-bit words
+    /// in host byte order; the port as a plain number.
+    fn parse_endpoint(field: &str) -> Option<(IpAddr, u16)> {
+        let (addr, port) = field.split_once(':')?;
+        let port = u16::from_str_radix(port, 16).ok()?;
+        let word = |i: usize| u32::from_str_radix(addr.get(i * 8..i * 8 + 8)?, 16).ok().map(u32::to_ne_bytes);
+        let ip = match addr.len() {
+            8 => IpAddr::V4(Ipv4Addr::from(word(0)?)),
+            32 => {
+                let mut octets = [0u8; 16];
+                for i in 0..4 {
+                    octets[i * 4..i * 4 + 4].copy_from_slice(&word(i)?);
+                }
+                // Dual-stack sockets show IPv4 peers as ::ffff:a.b.c.d
+                IpAddr::V6(Ipv6Addr::from(octets)).to_canonical()
+            }
+            _ => return None,
+        };
+        Some((ip, port))
+    }
+
+    /// Socket inode -> owning pid, from the fd links of every process we may read.
+    fn socket_owners() -> HashMap<u64, u32> {
+        let mut owners = HashMap::new();
+        let Ok(procs) = std::fs::read_dir("/proc") else {
+            return owners;
+        };
+        for entry in procs.flatten() {
+            let Some(pid) = entry.file_name().to_str().and_then(|n| n.parse::<u32>().ok()) else {
+                continue;
+            };
+            // Other users' processes are unreadable without root; skip them
+            let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+                continue;
+            };
+            for fd in fds.flatten() {
+                let Ok(target) = std::fs::read_link(fd.path()) else {
+                    continue;
+                };
+                let inode = target
+                    .to_str()
+                    .and_then(|t| t.strip_prefix("socket:["))
+                    .and_then(|t| t.strip_suffix(']'))
+                    .and_then(|t| t.parse::<u64>().ok());
+                if let Some(inode) = inode {
+                    owners.entry(inode).or_insert(pid);
+                }
+            }
+        }
+        owners
+    }
+
+    /// Cumulative (bytes_received, bytes_acked) of every TCP socket, by inode.
+    fn tcp_counters() -> io::Result<HashMap<u64, (u64, u64)>> {
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_SOCK_DIAG) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut counters = HashMap::new();
+        for family in [libc::AF_INET, libc::AF_INET6] {
+            dump_tcp(&fd, family as u8, &mut counters)?;
+        }
+        Ok(counters)
+    }
+
+    fn dump_tcp(fd: &OwnedFd, family: u8, counters: &mut HashMap<u64, (u64, u64)>) -> io::Result<()> {
+        let mut req = [0u8; NLMSG_HDR_LEN + INET_DIAG_REQ_V2_LEN];
+        req[0..4].copy_from_slice(&((NLMSG_HDR_LEN + INET_DIAG_REQ_V2_LEN) as u32).to_ne_bytes());
+        req[4..6].copy_from_slice(&SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+        req[6..8].copy_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+        req[8..12].copy_from_slice(&1u32.to_ne_bytes());
+        // inet_diag_req_v2: family, protocol, ext, pad, states, id
+        req[16] = family;
+        req[17] = libc::IPPROTO_TCP as u8;
+        req[18] = 1 << (INET_DIAG_INFO - 1);
+        req[20..24].copy_from_slice(&u32::MAX.to_ne_bytes());
+
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let sent = unsafe {
+            libc::sendto(
+                fd.as_raw_fd(),
+                req.as_ptr() as *const libc::c_void,
+                req.len(),
+                0,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            let mut msgs = &buf[..n as usize];
+            while msgs.len() >= NLMSG_HDR_LEN {
+                let len = u32::from_ne_bytes([msgs[0], msgs[1], msgs[2], msgs[3]]) as usize;
+                let msg_type = u16::from_ne_bytes([msgs[4], msgs[5]]);
+                if len < NLMSG_HDR_LEN || len > msgs.len() {
+                    break;
+                }
+                match msg_type as libc::c_int {
+                    libc::NLMSG_DONE => return Ok(()),
+                    libc::NLMSG_ERROR => {
+                        let errno = msgs
+                            .get(16..20)
+                            .map_or(libc::EIO, |e| -i32::from_ne_bytes([e[0], e[1], e[2], e[3]]));
+                        return Err(io::Error::from_raw_os_error(errno));
+                    }
+                    _ => {
+                        if let Some((inode, bytes)) = parse_diag_msg(&msgs[NLMSG_HDR_LEN..len]) {
+                            counters.insert(inode, bytes);
+                        }
+                    }
+                }
+                msgs = &msgs[align4(len).min(msgs.len())..];
+            }
+        }
+    }
+
+    /// Inode and (bytes_received, bytes_acked) from one inet_diag_msg with
+    /// its attributes. None when the kernel's tcp_info has no byte counters.
+    fn parse_diag_msg(msg: &[u8]) -> Option<(u64, (u64, u64))> {
+        let inode = msg.get(INET_DIAG_MSG_INODE..INET_DIAG_MSG_INODE + 4)?;
+        let inode = u32::from_ne_bytes([inode[0], inode[1], inode[2], inode[3]]) as u64;
+
+        let mut attrs = msg.get(INET_DIAG_MSG_LEN..)?;
+        while attrs.len() >= 4 {
+            let len = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+            let attr_type = u16::from_ne_bytes([attrs[2], attrs[3]]);
+            if len < 4 || len > attrs.len() {
+                break;
+            }
+            if attr_type == INET_DIAG_INFO {
+                let info = &attrs[4..len];
+                let field = |offset: usize| {
+                    info.get(offset..offset + 8)
+                        .map(|b| u64::from_ne_bytes(b.try_into().unwrap()))
+                };
+                return Some((inode, (field(TCPI_BYTES_RECEIVED)?, field(TCPI_BYTES_ACKED)?)));
+            }
+            attrs = &attrs[align4(len).min(attrs.len())..];
+        }
+        None
+    }
+
+    fn align4(len: usize) -> usize {
+        (len + 3) & !3
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parses_proc_net_and_sock_diag() {
+            let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n   \
+                       0: 0100007F:1F90 0200007F:C350 01 00000000:00000000 00:00000000 00000000  1000        0 4242 1 0000000000000000 20 4 30 10 -1\n";
+            let sockets = parse_proc_net(tcp, ConnectionProtocol::Tcp);
+            assert_eq!(sockets.len(), 1);
+            assert_eq!(sockets[0].local, IpAddr::from([127, 0, 0, 1]));
+            assert_eq!(sockets[0].local_port, 8080);
+            assert_eq!(sockets[0].remote, IpAddr::from([127, 0, 0, 2]));
+            assert_eq!(sockets[0].remote_port, 50000);
+            assert_eq!(sockets[0].state, TCP_ESTABLISHED);
+            assert_eq!(sockets[0].inode, 4242);
+
+            // IPv4-mapped peer on a dual-stack socket
+            let tcp6 = "header\n   0: 00000000000000000000000000000000:0016 0000000000000000FFFF00000A00000A:D431 01 0:0 0:0 0 0 0 77\n";
+            let sockets = parse_proc_net(tcp6, ConnectionProtocol::Tcp);
+            assert_eq!(sockets[0].local, IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+            assert_eq!(sockets[0].local_port, 22);
+            assert_eq!(sockets[0].remote, IpAddr::from([10, 0, 0, 10]));
+
+            // inet_diag_msg with inode 4242 and an INET_DIAG_INFO attribute
+            let mut msg = vec![0u8; INET_DIAG_MSG_LEN];
+            msg[INET_DIAG_MSG_INODE..INET_DIAG_MSG_LEN].copy_from_slice(&4242u32.to_ne_bytes());
+            let mut info = vec![0u8; TCPI_BYTES_RECEIVED + 8];
+            info[TCPI_BYTES_ACKED..TCPI_BYTES_ACKED + 8].copy_from_slice(&1500u64.to_ne_bytes());
+            info[TCPI_BYTES_RECEIVED..TCPI_BYTES_RECEIVED + 8].copy_from_slice(&9000u64.to_ne_bytes());
+            msg.extend_from_slice(&(4 + info.len() as u16).to_ne_bytes());
+            msg.extend_from_slice(&INET_DIAG_INFO.to_ne_bytes());
+            msg.extend_from_slice(&info);
+            assert_eq!(parse_diag_msg(&msg), Some((4242, (9000, 1500))));
+
+            // Older kernels: tcp_info without byte counters
+            msg.truncate(INET_DIAG_MSG_LEN + 4 + 100);
+            msg[INET_DIAG_MSG_LEN..INET_DIAG_MSG_LEN + 2].copy_from_slice(&104u16.to_ne_bytes());
+            assert_eq!(parse_diag_msg(&msg), None);
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
 mod platform {
     use super::*;
 
@@ -413,11 +856,11 @@ mod platform {
             Self
         }
 
-        pub fn poll(&mut self, agent_id: Uuid) -> anyhow::Result<ProcessTrafficReport> {
+        pub fn poll(&mut self, agent_id: Uuid, interval_ms: u32) -> anyhow::Result<ProcessTrafficReport> {
             Ok(ProcessTrafficReport {
                 agent_id,
                 captured_at: Utc::now(),
-                interval_ms: POLL_INTERVAL_MS,
+                interval_ms,
                 processes: Vec::new(),
             })
         }
@@ -73,6 +73,16 @@ enum TargetAction {
         /// Target ID
         id: String,
     },
+    /// Stop collecting for a target, keeping its session open
+    Pause {
+        /// Target ID
+        id: String,
+    },
+    /// Continue collecting for a paused target
+    Resume {
+        /// Target ID
+        id: String,
+    },
 }
 
 #[tokio::main]
@@ -206,6 +216,22 @@ async fn main() -> Result<()> {
                     .await?;
                 println!("Target {} removed", id);
             }
+
+            TargetAction::Pause { id } => {
+                client
+                    .post(format!("{}/api/v1/targets/{}/pause", base_url, id))
+                    .send()
+                    .await?;
+                println!("Target {} paused", id);
+            }
+
+            TargetAction::Resume { id } => {
+                client
+                    .post(format!("{}/api/v1/targets/{}/resume", base_url, id))
+                    .send()
+                    .await?;
+                println!("Target {} resumed", id);
+            }
         },
     }
 
@@ -11,6 +11,36 @@ pub struct ServerConfig {
     pub agent_heartbeat_timeout_secs: u64,
     pub stats_aggregation_interval_secs: u64,
     pub static_dir: String,
+    /// `.mmdb` and RIR delegation files used to enrich hops and endpoints.
+    pub ip_intel_dir: String,
+    /// Second listener for `/ws/agent` that requires a client certificate
+    /// from the built-in CA. Disabled when unset.
+    pub agent_tls_listen_addr: Option<String>,
+    /// CA key and certificate, and the TLS listener's certificate.
+    pub agent_ca_dir: String,
+    /// DNS names and IPs put in the TLS listener's certificate.
+    pub agent_tls_hostnames: Vec<String>,
+    /// Trace rounds waiting for the sample writer before agents are held back.
+    pub ingest_queue_rounds: usize,
+    /// Samples written per batch; a fuller queue is flushed early.
+    pub ingest_batch_rows: usize,
+    /// Longest a queued round waits before its batch is flushed.
+    pub ingest_flush_interval_ms: u64,
+    /// Days raw samples are kept; 0 keeps them forever.
+    pub raw_retention_days: u32,
+    /// Days per-minute rollups are kept; 0 keeps them forever.
+    pub minute_rollup_retention_days: u32,
+    /// Days hourly rollups are kept; 0 keeps them forever.
+    pub hourly_rollup_retention_days: u32,
+    /// Days agent heartbeat metrics are kept; 0 keeps them forever.
+    pub agent_health_retention_days: u32,
+    /// Most recent samples per hop behind the live and alerting statistics.
+    pub hop_window_samples: usize,
+    /// Age of the oldest sample in a hop's statistics window.
+    pub hop_window_secs: u64,
+    /// Seconds without a live round before a session's statistics are dropped
+    /// from memory.
+    pub hop_stats_idle_secs: u64,
 }
 
 impl Default for ServerConfig {
@@ -26,21 +56,46 @@ impl Default for ServerConfig {
             agent_heartbeat_timeout_secs: 90,
             stats_aggregation_interval_secs: 300,
             static_dir: "./frontend/dist".to_string(),
+            ip_intel_dir: "data/ip-intel".to_string(),
+            agent_tls_listen_addr: None,
+            agent_ca_dir: "data/ca".to_string(),
+            agent_tls_hostnames: vec!["localhost".to_string()],
+            ingest_queue_rounds: 5000,
+            ingest_batch_rows: 10_000,
+            ingest_flush_interval_ms: 250,
+            raw_retention_days: 7,
+            minute_rollup_retention_days: 90,
+            hourly_rollup_retention_days: 0,
+            agent_health_retention_days: 30,
+            hop_window_samples: 100,
+            hop_window_secs: 60,
+            hop_stats_idle_secs: 600,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Agent configuration, read from `nm-agent.toml`. Unknown keys are
+/// rejected so a typo does not silently fall back to a default.
+///
+/// `server_url`, `agent_id`, `api_key`, `tls` and `spool.path` identify the
+/// agent and its state; changing them needs a restart. Everything else is applied
+/// while the agent runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct AgentConfig {
+    #[serde(alias = "url")]
     pub server_url: String,
     pub agent_id: String,
     pub api_key: String,
     pub reconnect_max_delay_secs: u64,
-    pub default_timeout_ms: u64,
-    pub max_concurrent_probes: usize,
     pub dns_cache_ttl_secs: u64,
-    pub log_level: String,
-    pub log_file: Option<String>,
+    pub probe: AgentProbeConfig,
+    pub traffic: AgentTrafficConfig,
+    pub logging: AgentLoggingConfig,
+    pub spool: AgentSpoolConfig,
+    pub enrichment: AgentEnrichmentConfig,
+    pub discovery: AgentDiscoveryConfig,
+    pub tls: AgentTlsConfig,
 }
 
 impl Default for AgentConfig {
@@ -50,11 +105,153 @@ impl Default for AgentConfig {
             agent_id: uuid::Uuid::new_v4().to_string(),
             api_key: String::new(),
             reconnect_max_delay_secs: 60,
+            dns_cache_ttl_secs: 300,
+            probe: AgentProbeConfig::default(),
+            traffic: AgentTrafficConfig::default(),
+            logging: AgentLoggingConfig::default(),
+            spool: AgentSpoolConfig::default(),
+            enrichment: AgentEnrichmentConfig::default(),
+            discovery: AgentDiscoveryConfig::default(),
+            tls: AgentTlsConfig::default(),
+        }
+    }
+}
+
+impl AgentConfig {
+    /// True when `other` differs in a setting that only takes effect on restart.
+    pub fn identity_differs(&self, other: &AgentConfig) -> bool {
+        self.server_url != other.server_url
+            || self.agent_id != other.agent_id
+            || self.api_key != other.api_key
+            || self.spool.path != other.spool.path
+            || self.probe.privileged_helper != other.probe.privileged_helper
+            || self.tls != other.tls
+    }
+}
+
+/// `[probe]`: defaults for every target's probe rounds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentProbeConfig {
+    pub default_timeout_ms: u64,
+    pub max_concurrent_probes: usize,
+    /// Linux: open raw sockets in a separate helper process that keeps only
+    /// `CAP_NET_RAW`, while the agent itself drops every capability.
+    pub privileged_helper: bool,
+}
+
+impl Default for AgentProbeConfig {
+    fn default() -> Self {
+        Self {
             default_timeout_ms: 2000,
             max_concurrent_probes: 100,
-            dns_cache_ttl_secs: 300,
-            log_level: "info".to_string(),
-            log_file: None,
+            privileged_helper: true,
+        }
+    }
+}
+
+/// `[traffic]`: per-process traffic monitoring.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentTrafficConfig {
+    pub enabled: bool,
+    pub poll_interval_ms: u32,
+}
+
+impl Default for AgentTrafficConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poll_interval_ms: 5000,
+        }
+    }
+}
+
+/// `[logging]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentLoggingConfig {
+    /// `tracing` filter directive, e.g. "info" or "nm_agent=debug". `RUST_LOG` wins when set.
+    pub level: String,
+    pub file: Option<String>,
+}
+
+impl Default for AgentLoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            file: None,
+        }
+    }
+}
+
+/// `[spool]`: on-disk buffer for trace data while the server is unreachable.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentSpoolConfig {
+    pub path: String,
+    pub max_mb: u64,
+}
+
+impl Default for AgentSpoolConfig {
+    fn default() -> Self {
+        Self {
+            path: "nm-agent.spool".to_string(),
+            max_mb: 64,
         }
     }
 }
+
+/// `[enrichment]`: reverse DNS, ASN and geolocation for hop addresses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentEnrichmentConfig {
+    pub enabled: bool,
+    /// MaxMind or IPinfo `.mmdb` files, e.g. an ASN and a city database.
+    /// When several have a value for the same field, the first one wins.
+    pub mmdb_files: Vec<String>,
+}
+
+impl Default for AgentEnrichmentConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            mmdb_files: Vec::new(),
+        }
+    }
+}
+
+/// `[discovery]`: local network scans requested by the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentDiscoveryConfig {
+    pub enabled: bool,
+    /// Most addresses pinged by one scan. A larger local subnet is narrowed
+    /// to the block around the agent's own address.
+    pub max_hosts: u32,
+}
+
+impl Default for AgentDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_hosts: 1024,
+        }
+    }
+}
+
+/// `[tls]`: client certificate and server verification for a `wss://`
+/// server URL. Without it the server is checked against the public web roots.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentTlsConfig {
+    /// PEM certificate and key issued by the server at registration.
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    /// PEM CA certificate to verify the server against instead of the web roots.
+    pub ca_cert: Option<String>,
+    /// SHA-256 of the server's certificate in hex (colons allowed). When set,
+    /// the server must present exactly this certificate and the CA and
+    /// hostname are not checked.
+    pub server_fingerprint: Option<String>,
+}
@@ -0,0 +1,169 @@
+use std::net::IpAddr;
+
+use serde_json::Value;
+
+/// Widths of the `hops` columns the values end up in.
+const MAX_COUNTRY_LEN: usize = 3;
+const MAX_NAME_LEN: usize = 255;
+
+/// What IP databases know about an address.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IpInfo {
+    pub asn: Option<u32>,
+    pub as_name: Option<String>,
+    /// ISO 3166 country code.
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+}
+
+impl IpInfo {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fill the fields still missing from one `.mmdb` record, decoded as JSON.
+    /// Understands the nested MaxMind GeoLite2/GeoIP2 layout and the flat
+    /// IPinfo layout; when several databases are merged, the first one wins.
+    pub fn merge_mmdb_record(&mut self, record: &Value) {
+        let asn = record
+            .get("autonomous_system_number")
+            .and_then(Value::as_u64)
+            .or_else(|| {
+                let asn = record.get("asn")?;
+                asn.as_u64().or_else(|| asn.as_str()?.trim_start_matches("AS").parse().ok())
+            })
+            .and_then(|asn| u32::try_from(asn).ok());
+        self.asn = self.asn.or(asn);
+
+        let as_name = text_at(record, &["autonomous_system_organization"]).or_else(|| text_at(record, &["as_name"]));
+        self.as_name = self.as_name.take().or(as_name.map(|s| truncate(s, MAX_NAME_LEN)));
+
+        // IPinfo's "country" is the full name; only ISO codes fit the column.
+        let country = text_at(record, &["country", "iso_code"])
+            .or_else(|| text_at(record, &["country_code"]))
+            .or_else(|| text_at(record, &["country"]))
+            .filter(|c| c.len() <= MAX_COUNTRY_LEN);
+        self.set_country(country);
+
+        let city = text_at(record, &["city", "names", "en"]).or_else(|| text_at(record, &["city"]));
+        self.city = self.city.take().or(city.map(|s| truncate(s, MAX_NAME_LEN)));
+
+        // Only take coordinates as a pair, from the same database.
+        if self.lat.is_none() && self.lon.is_none() {
+            let lat = number_at(record, &["location", "latitude"]).or_else(|| number_at(record, &["latitude"]));
+            let lon = number_at(record, &["location", "longitude"]).or_else(|| number_at(record, &["longitude"]));
+            if let (Some(lat), Some(lon)) = (lat, lon) {
+                self.lat = Some(lat);
+                self.lon = Some(lon);
+            }
+        }
+    }
+
+    /// Set the country unless one is already known.
+    pub fn set_country(&mut self, country: Option<&str>) {
+        self.country = self.country.take().or(country.map(str::to_string));
+    }
+}
+
+/// False for private, loopback, link-local and other addresses no public
+/// registry or resolver knows anything about.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_unspecified()
+                || v4.is_documentation()
+                || v4.is_multicast()
+                // 100.64.0.0/10, carrier-grade NAT
+                || (a == 100 && (b & 0xc0) == 64))
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // fc00::/7 unique local, fe80::/10 link-local
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+fn value_at<'a>(record: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(record, |value, key| value.get(key))
+}
+
+fn text_at<'a>(record: &'a Value, path: &[&str]) -> Option<&'a str> {
+    value_at(record, path)?.as_str().filter(|s| !s.is_empty())
+}
+
+/// IPinfo stores coordinates as strings.
+fn number_at(record: &Value, path: &[&str]) -> Option<f64> {
+    let value = value_at(record, path)?;
+    value.as_f64().or_else(|| value.as_str()?.parse().ok())
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    s.chars().take(max_chars).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merges_maxmind_and_ipinfo_records() {
+        let mut info = IpInfo::default();
+
+        // GeoLite2-ASN, then GeoLite2-City
+        info.merge_mmdb_record(&json!({
+            "autonomous_system_number": 15169,
+            "autonomous_system_organization": "GOOGLE",
+        }));
+        info.merge_mmdb_record(&json!({
+            "city": { "names": { "en": "Mountain View", "de": "Mountain View" } },
+            "country": { "iso_code": "US", "names": { "en": "United States" } },
+            "location": { "latitude": 37.386, "longitude": -122.0838 },
+        }));
+        // IPinfo: fills nothing that is already known
+        let ipinfo = json!({
+            "asn": "AS64500",
+            "as_name": "Example",
+            "country": "Germany",
+            "country_code": "DE",
+            "city": "Berlin",
+            "latitude": "52.52",
+            "longitude": "13.40",
+        });
+        info.merge_mmdb_record(&ipinfo);
+
+        assert_eq!(info.asn, Some(15169));
+        assert_eq!(info.as_name.as_deref(), Some("GOOGLE"));
+        assert_eq!(info.country.as_deref(), Some("US"));
+        assert_eq!(info.city.as_deref(), Some("Mountain View"));
+        assert_eq!((info.lat, info.lon), (Some(37.386), Some(-122.0838)));
+
+        let mut ipinfo_only = IpInfo::default();
+        ipinfo_only.merge_mmdb_record(&ipinfo);
+        assert_eq!(ipinfo_only.asn, Some(64500));
+        assert_eq!(ipinfo_only.country.as_deref(), Some("DE"));
+        assert_eq!((ipinfo_only.lat, ipinfo_only.lon), (Some(52.52), Some(13.40)));
+    }
+
+    #[test]
+    fn public_addresses() {
+        for ip in ["8.8.8.8", "2001:4860:4860::8888", "::ffff:1.1.1.1"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["10.1.2.3", "192.168.0.1", "100.64.1.1", "169.254.1.1", "fd00::1", "fe80::1", "::1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+}
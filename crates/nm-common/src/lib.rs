@@ -1,5 +1,6 @@
 pub mod config;
 pub mod crypto;
+pub mod ip_info;
 pub mod models;
 pub mod protocol;
 pub mod quality;
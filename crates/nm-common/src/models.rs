@@ -15,6 +15,11 @@ pub struct Agent {
     pub ip_address: Option<String>,
     pub is_online: bool,
     pub last_seen_at: Option<DateTime<Utc>>,
+    /// From the last handshake; 0 for agents that predate negotiation.
+    pub protocol_version: i32,
+    pub capabilities: Vec<String>,
+    /// Holds a client certificate, so it may only log in on the mTLS listener.
+    pub has_certificate: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -22,12 +27,36 @@ pub struct Agent {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateAgent {
     pub name: String,
+    /// PEM certificate signing request, signed when the server runs the mTLS
+    /// agent listener. The private key never leaves the agent.
+    #[serde(default)]
+    pub csr_pem: Option<String>,
+}
+
+/// Request for a client certificate for an existing agent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateRequest {
+    pub csr_pem: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentRegistration {
     pub agent: Agent,
     pub api_key: String,
+    /// Present when the server runs the mTLS agent listener.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<AgentCertificate>,
+}
+
+/// Client certificate for the mTLS `/ws/agent` listener; its subject CN is
+/// the agent id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentCertificate {
+    pub cert_pem: String,
+    pub ca_cert_pem: String,
+    /// SHA-256 of the listener's certificate, hex, for `tls.server_fingerprint`.
+    pub server_fingerprint: String,
+    pub tls_port: u16,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +64,20 @@ pub struct UpdateAgent {
     pub name: Option<String>,
 }
 
+/// Host metrics from one agent heartbeat.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AgentHealthSample {
+    pub recorded_at: DateTime<Utc>,
+    pub cpu_usage_pct: f32,
+    pub memory_used_mb: i32,
+    pub memory_total_mb: i32,
+    pub load_avg_1m: Option<f32>,
+    pub agent_rss_mb: i32,
+    pub active_targets: i32,
+    pub queued_rounds: i32,
+    pub skipped_rounds: i64,
+}
+
 // ─── Target ───────────────────────────────────────────────
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -49,7 +92,14 @@ pub struct Target {
     pub packet_size: i32,
     pub interval_ms: i32,
     pub max_hops: i32,
+    pub address_family: String,
+    pub flow_stable: bool,
+    pub multipath_discovery: bool,
+    pub pmtu_discovery: bool,
+    pub config_id: Option<Uuid>,
     pub is_active: bool,
+    /// Last time the agent acknowledged a live config update.
+    pub config_applied_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -67,6 +117,33 @@ pub struct CreateTarget {
     pub interval_ms: i32,
     #[serde(default = "default_max_hops")]
     pub max_hops: i32,
+    #[serde(default = "default_address_family")]
+    pub address_family: String,
+    #[serde(default)]
+    pub flow_stable: bool,
+    #[serde(default)]
+    pub multipath_discovery: bool,
+    #[serde(default)]
+    pub pmtu_discovery: bool,
+}
+
+impl CreateTarget {
+    /// A target for `address` with the default probe settings.
+    pub fn for_address(address: String, display_name: Option<String>) -> Self {
+        Self {
+            address,
+            display_name,
+            probe_method: default_probe_method(),
+            probe_port: None,
+            packet_size: default_packet_size(),
+            interval_ms: default_interval(),
+            max_hops: default_max_hops(),
+            address_family: default_address_family(),
+            flow_stable: false,
+            multipath_discovery: false,
+            pmtu_discovery: false,
+        }
+    }
 }
 
 fn default_probe_method() -> String {
@@ -81,6 +158,9 @@ fn default_interval() -> i32 {
 fn default_max_hops() -> i32 {
     30
 }
+fn default_address_family() -> String {
+    "auto".to_string()
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateTarget {
@@ -91,6 +171,10 @@ pub struct UpdateTarget {
     pub packet_size: Option<i32>,
     pub interval_ms: Option<i32>,
     pub max_hops: Option<i32>,
+    pub address_family: Option<String>,
+    pub flow_stable: Option<bool>,
+    pub multipath_discovery: Option<bool>,
+    pub pmtu_discovery: Option<bool>,
     pub is_active: Option<bool>,
 }
 
@@ -103,6 +187,8 @@ pub struct TraceSession {
     pub started_at: DateTime<Utc>,
     pub ended_at: Option<DateTime<Utc>>,
     pub sample_count: i64,
+    /// `active` or `paused` while open; `archived` or `will_delete` once ended.
+    pub state: String,
 }
 
 // ─── Hop ──────────────────────────────────────────────────
@@ -121,6 +207,8 @@ pub struct Hop {
     pub geo_lat: Option<f64>,
     pub geo_lon: Option<f64>,
     pub whois_data: Option<serde_json::Value>,
+    /// Latest MPLS label stack seen from this hop (`[MplsLabel]`).
+    pub mpls_labels: Option<serde_json::Value>,
     pub first_seen_at: DateTime<Utc>,
     pub last_seen_at: DateTime<Utc>,
 }
@@ -242,6 +330,33 @@ pub struct RouteSnapshot {
     pub route_hash: String,
 }
 
+/// Load-balanced hop graph from a multipath (MDA) discovery.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct MultipathSnapshot {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub captured_at: DateTime<Utc>,
+    pub flows_probed: i16,
+    pub max_width: i16,
+    pub hop_interfaces: serde_json::Value,
+    pub links: serde_json::Value,
+}
+
+/// Path MTU measurement, tied to the route snapshot that was current when it
+/// was stored.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PathMtuResult {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub route_snapshot_id: Option<Uuid>,
+    pub captured_at: DateTime<Utc>,
+    pub path_mtu: i32,
+    pub reached_destination: bool,
+    pub mtu_drop_hop: Option<i16>,
+    pub black_hole_hops: Vec<i16>,
+    pub hop_mtus: serde_json::Value,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RouteChange {
     pub id: Uuid,
@@ -264,6 +379,7 @@ pub struct TraceProfile {
     pub packet_size: i32,
     pub interval_ms: i32,
     pub max_hops: i32,
+    pub flow_stable: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -281,6 +397,8 @@ pub struct CreateTraceProfile {
     pub interval_ms: i32,
     #[serde(default = "default_max_hops")]
     pub max_hops: i32,
+    #[serde(default)]
+    pub flow_stable: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -292,6 +410,7 @@ pub struct UpdateTraceProfile {
     pub packet_size: Option<i32>,
     pub interval_ms: Option<i32>,
     pub max_hops: Option<i32>,
+    pub flow_stable: Option<bool>,
 }
 
 // ─── Share Token ─────────────────────────────────────────
@@ -518,3 +637,23 @@ pub struct DiscoveredDevice {
     pub discovered_at: DateTime<Utc>,
     pub last_seen_at: DateTime<Utc>,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StartDiscoveryScan {
+    /// Subnets in CIDR notation; empty scans the agent's own subnets.
+    #[serde(default)]
+    pub subnets: Vec<String>,
+}
+
+/// A discovery scan sent to an agent; finished once its report arrives.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveryScan {
+    pub scan_id: Uuid,
+    pub agent_id: Uuid,
+    pub requested_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// As requested until the report arrives, then the subnets swept.
+    pub subnets: Vec<String>,
+    pub device_count: Option<u32>,
+    pub error: Option<String>,
+}
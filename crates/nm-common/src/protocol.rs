@@ -29,16 +29,24 @@ pub enum WsPayload {
     Heartbeat(AgentHeartbeat),
     TraceRound(TraceRoundReport),
     RouteDiscovery(RouteDiscoveryReport),
+    MultipathDiscovery(MultipathDiscoveryReport),
+    PathMtuDiscovery(PathMtuReport),
     HopMetadata(HopMetadataUpdate),
     AgentStatus(AgentStatusReport),
     AckResponse(AckResponse),
 
+    // Agent -> Server (store-and-forward)
+    SpooledBatch(SpooledBatch),
+
     // Agent -> Server (OTA)
     UpdateProgress(UpdateProgressReport),
 
     // Agent -> Server (Traffic)
     ProcessTraffic(ProcessTrafficReport),
 
+    // Agent -> Server (Local network discovery)
+    NetworkDiscovery(NetworkDiscoveryReport),
+
     // Server -> Agent
     AuthResponse(AuthResponse),
     TargetAssignment(TargetAssignment),
@@ -46,6 +54,7 @@ pub enum WsPayload {
     ConfigUpdate(AgentConfigUpdate),
     ServerHeartbeat(ServerHeartbeat),
     UpdateCommand(UpdateCommand),
+    DiscoveryScan(DiscoveryScanCommand),
 
     // Server -> Frontend
     LiveTraceUpdate(LiveTraceUpdate),
@@ -57,6 +66,29 @@ pub enum WsPayload {
 
 // ─── Authentication ────────────────────────────────────────
 
+/// Revision of the agent/server protocol this build speaks. Peers that
+/// predate the handshake negotiation report 0.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Optional features a peer advertises in the handshake. A peer only sends
+/// messages or settings the other side listed; the ones an older peer
+/// cannot decode would otherwise fail its whole envelope.
+pub mod capability {
+    /// Agent: applies `ConfigUpdate` to running targets and acks it.
+    pub const CONFIG_UPDATE: &str = "supports_config_update";
+    /// Agent: probes `tcp` targets with raw SYNs and reports `tcp_reply`.
+    pub const TCP_SYN: &str = "supports_tcp_syn";
+    /// Agent: buffers data while disconnected and replays it in `SpooledBatch`.
+    /// Server: ingests and acknowledges `SpooledBatch`.
+    pub const SPOOL: &str = "supports_spool";
+    /// Agent: traces IPv6 targets and honours `TargetConfig::address_family`.
+    pub const IPV6: &str = "supports_ipv6";
+    /// Agent: runs `DiscoveryScan` commands.
+    pub const DISCOVERY_SCAN: &str = "supports_discovery_scan";
+    /// Agent: numbers rounds of a reassigned session from `TargetConfig::first_round`.
+    pub const SESSION_RESUME: &str = "supports_session_resume";
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthRequest {
     pub agent_id: Uuid,
@@ -64,6 +96,11 @@ pub struct AuthRequest {
     pub agent_version: String,
     pub hostname: String,
     pub os_info: String,
+    #[serde(default)]
+    pub protocol_version: u16,
+    /// Names from [`capability`].
+    #[serde(default)]
+    pub capabilities: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,7 +108,17 @@ pub struct AuthResponse {
     pub success: bool,
     pub error: Option<String>,
     pub session_token: Option<String>,
-    pub assigned_targets: Vec<TargetConfig>,
+    pub assigned_targets: Vec<AssignedTarget>,
+    /// Left out for agents at protocol version 0, whose decoder rejects
+    /// fields it does not know.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub protocol_version: u16,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
 }
 
 // ─── Target Management ────────────────────────────────────
@@ -86,6 +133,110 @@ pub struct TargetConfig {
     pub packet_size: u16,
     pub interval_ms: u32,
     pub max_hops: u8,
+    #[serde(default)]
+    pub address_family: AddressFamily,
+    /// Keep the flow identifier constant across TTLs (Paris traceroute).
+    #[serde(default)]
+    pub flow_stable: bool,
+    /// Periodically enumerate every load-balanced path (MDA).
+    #[serde(default)]
+    pub multipath_discovery: bool,
+    /// Periodically measure the path MTU with Don't Fragment probes.
+    #[serde(default)]
+    pub pmtu_discovery: bool,
+    /// Round number to continue a resumed session from. Only sent to agents
+    /// with `SESSION_RESUME`; 0 starts at round 1.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub first_round: u64,
+}
+
+/// `TargetConfig` as agents at protocol version 0 know it. rmp_serde writes
+/// structs as arrays and their decoder rejects one with more than these eight
+/// fields, so optional fields cannot simply be skipped for them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LegacyTargetConfig {
+    pub target_id: Uuid,
+    pub session_id: Uuid,
+    pub address: String,
+    pub probe_method: ProbeMethod,
+    pub probe_port: Option<u16>,
+    pub packet_size: u16,
+    pub interval_ms: u32,
+    pub max_hops: u8,
+}
+
+impl From<TargetConfig> for LegacyTargetConfig {
+    fn from(config: TargetConfig) -> Self {
+        Self {
+            target_id: config.target_id,
+            session_id: config.session_id,
+            address: config.address,
+            probe_method: config.probe_method,
+            probe_port: config.probe_port,
+            packet_size: config.packet_size,
+            interval_ms: config.interval_ms,
+            max_hops: config.max_hops,
+        }
+    }
+}
+
+/// A target sent to an agent, in the shape its protocol version decodes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AssignedTarget {
+    Current(TargetConfig),
+    Legacy(LegacyTargetConfig),
+}
+
+impl AssignedTarget {
+    pub fn for_version(config: TargetConfig, protocol_version: u16) -> Self {
+        if protocol_version == 0 {
+            AssignedTarget::Legacy(config.into())
+        } else {
+            AssignedTarget::Current(config)
+        }
+    }
+
+    pub fn into_config(self) -> TargetConfig {
+        match self {
+            AssignedTarget::Current(config) => config,
+            AssignedTarget::Legacy(config) => TargetConfig {
+                target_id: config.target_id,
+                session_id: config.session_id,
+                address: config.address,
+                probe_method: config.probe_method,
+                probe_port: config.probe_port,
+                packet_size: config.packet_size,
+                interval_ms: config.interval_ms,
+                max_hops: config.max_hops,
+                address_family: AddressFamily::Auto,
+                flow_stable: false,
+                multipath_discovery: false,
+                pmtu_discovery: false,
+                first_round: 0,
+            },
+        }
+    }
+}
+
+/// Which address family to trace over when a target name resolves to both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    #[default]
+    Auto,
+    Ipv4,
+    Ipv6,
+}
+
+impl std::fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressFamily::Auto => write!(f, "auto"),
+            AddressFamily::Ipv4 => write!(f, "ipv4"),
+            AddressFamily::Ipv6 => write!(f, "ipv6"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -108,7 +259,7 @@ impl std::fmt::Display for ProbeMethod {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TargetAssignment {
-    pub targets: Vec<TargetConfig>,
+    pub targets: Vec<AssignedTarget>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -123,6 +274,8 @@ pub struct AgentConfigUpdate {
     pub packet_size: Option<u16>,
     pub probe_method: Option<ProbeMethod>,
     pub max_hops: Option<u8>,
+    #[serde(default)]
+    pub probe_port: Option<u16>,
 }
 
 // ─── Trace Data (Hot Path) ────────────────────────────────
@@ -134,6 +287,11 @@ pub struct TraceRoundReport {
     pub round_number: u64,
     pub sent_at: DateTime<Utc>,
     pub hops: Vec<HopSample>,
+    /// Settings the round was probed with; None from agents that predate them.
+    #[serde(default)]
+    pub probe_method: Option<ProbeMethod>,
+    #[serde(default)]
+    pub packet_size: Option<u16>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -143,6 +301,35 @@ pub struct HopSample {
     pub rtt_us: Option<u32>,
     pub is_lost: bool,
     pub ttl_received: Option<u8>,
+    /// Destination's answer to a TCP SYN probe, for hops that reached it.
+    #[serde(default)]
+    pub tcp_reply: Option<TcpReply>,
+    /// MPLS label stack quoted in the hop's ICMP reply (RFC 4950), outermost first.
+    #[serde(default)]
+    pub mpls_labels: Vec<MplsLabel>,
+    /// |rtt - rtt of this hop's previous reply| in the session.
+    #[serde(default)]
+    pub jitter_us: Option<u32>,
+}
+
+/// One label stack entry from an ICMP MPLS extension object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MplsLabel {
+    pub label: u32,
+    /// Traffic class (formerly EXP) bits.
+    pub traffic_class: u8,
+    pub bottom_of_stack: bool,
+    pub ttl: u8,
+}
+
+/// How the destination answered a TCP SYN probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TcpReply {
+    /// SYN-ACK: the port is open.
+    SynAck,
+    /// RST: the host is up but the port is closed.
+    Rst,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -160,6 +347,57 @@ pub struct DiscoveredHop {
     pub hostname: Option<String>,
 }
 
+/// All interfaces seen at each TTL when the flow identifier is varied
+/// (Multipath Detection Algorithm), plus the links between them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipathDiscoveryReport {
+    pub target_id: Uuid,
+    pub session_id: Uuid,
+    pub discovered_at: DateTime<Utc>,
+    pub flows_probed: u16,
+    pub hops: Vec<MultipathHop>,
+    pub links: Vec<MultipathLink>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipathHop {
+    pub hop_number: u8,
+    pub interfaces: Vec<String>,
+}
+
+/// `from_ip` at `hop_number` forwarded at least one flow to `to_ip` at the next hop.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipathLink {
+    pub hop_number: u8,
+    pub from_ip: String,
+    pub to_ip: String,
+}
+
+/// Path MTU measured hop by hop with Don't Fragment probes (tracepath style).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathMtuReport {
+    pub target_id: Uuid,
+    pub session_id: Uuid,
+    pub discovered_at: DateTime<Utc>,
+    /// Largest packet (IP header included) that reached the last hop probed.
+    pub path_mtu: u16,
+    pub reached_destination: bool,
+    /// First hop at which the MTU drops below the local interface MTU.
+    pub mtu_drop_hop: Option<u8>,
+    /// Hops that silently dropped DF packets larger than their MTU instead of
+    /// answering with Fragmentation Needed / Packet Too Big.
+    pub black_hole_hops: Vec<u8>,
+    pub hops: Vec<HopMtu>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HopMtu {
+    pub hop_number: u8,
+    pub ip_address: Option<String>,
+    /// Largest packet known to reach this hop.
+    pub mtu: u16,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HopMetadataUpdate {
     pub session_id: Uuid,
@@ -182,7 +420,22 @@ pub struct AgentHeartbeat {
     pub active_target_count: u32,
     pub uptime_seconds: u64,
     pub cpu_usage_pct: f32,
+    /// Host memory in use.
     pub memory_usage_mb: u32,
+    #[serde(default)]
+    pub memory_total_mb: u32,
+    /// 1-minute load average; not available on Windows.
+    #[serde(default)]
+    pub load_avg_1m: Option<f32>,
+    /// Resident memory of the agent process itself.
+    #[serde(default)]
+    pub agent_rss_mb: u32,
+    /// Probe rounds waiting for probe capacity when the heartbeat was sent.
+    #[serde(default)]
+    pub queued_rounds: u32,
+    /// Probe rounds skipped since the previous heartbeat.
+    #[serde(default)]
+    pub skipped_rounds: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -215,6 +468,16 @@ pub struct AckResponse {
     pub error: Option<String>,
 }
 
+// ─── Store-and-forward ────────────────────────────────────
+
+/// Messages an agent buffered on disk while it was disconnected, replayed in
+/// their original order. The server answers with an `AckResponse` for the
+/// batch envelope's `msg_id` once every message has been processed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpooledBatch {
+    pub messages: Vec<WsEnvelope>,
+}
+
 // ─── Live Feed (Server -> Frontend) ──────────────────────
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -236,6 +499,8 @@ pub struct LiveHopData {
     pub is_lost: bool,
     pub jitter_us: Option<u32>,
     pub stats: HopRunningStats,
+    #[serde(default)]
+    pub tcp_reply: Option<TcpReply>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -371,6 +636,52 @@ pub struct RemoteEndpoint {
     pub protocol: ConnectionProtocol,
     pub bytes_in_per_sec: f64,
     pub bytes_out_per_sec: f64,
+    /// Filled in by the server from its IP intelligence databases.
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub asn: Option<u32>,
+    #[serde(default)]
+    pub as_name: Option<String>,
+    #[serde(default)]
+    pub country: Option<String>,
+}
+
+// ─── Local Network Discovery ─────────────────────────────
+
+/// Server -> Agent: sweep local subnets for live hosts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveryScanCommand {
+    pub scan_id: Uuid,
+    /// Subnets in CIDR notation; empty means every subnet the agent is
+    /// directly attached to.
+    #[serde(default)]
+    pub subnets: Vec<String>,
+}
+
+/// Agent -> Server: hosts found by one discovery scan
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkDiscoveryReport {
+    pub scan_id: Uuid,
+    pub agent_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// Subnets actually swept, in CIDR notation.
+    pub subnets: Vec<String>,
+    pub devices: Vec<DiscoveredHost>,
+    /// Set when the scan could not run, e.g. another scan was in progress.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveredHost {
+    pub ip_address: String,
+    /// From the ARP/NDP neighbor table, as aa:bb:cc:dd:ee:ff.
+    pub mac_address: Option<String>,
+    /// From DNS, mDNS or NetBIOS, in that order of preference.
+    pub hostname: Option<String>,
+    /// ICMP echo round trip; None when the host only answered ARP/NDP.
+    pub latency_us: Option<u32>,
 }
 
 // ─── Frontend WS Commands ────────────────────────────────
@@ -383,3 +694,172 @@ pub enum FrontendCommand {
     SubscribeTraffic { agent_ids: Vec<Uuid> },
     UnsubscribeTraffic { agent_ids: Vec<Uuid> },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `AuthRequest` as sent by agents before protocol negotiation.
+    #[derive(Serialize)]
+    struct LegacyAuthRequest {
+        agent_id: Uuid,
+        api_key: String,
+        agent_version: String,
+        hostname: String,
+        os_info: String,
+    }
+
+    #[derive(Deserialize)]
+    struct LegacyAuthResponse {
+        success: bool,
+        #[allow(dead_code)]
+        error: Option<String>,
+        #[allow(dead_code)]
+        session_token: Option<String>,
+        assigned_targets: Vec<BaselineTargetConfig>,
+    }
+
+    /// `TargetConfig` as decoded by agents before protocol negotiation.
+    #[derive(Deserialize)]
+    struct BaselineTargetConfig {
+        target_id: Uuid,
+        #[allow(dead_code)]
+        session_id: Uuid,
+        address: String,
+        probe_method: ProbeMethod,
+        #[allow(dead_code)]
+        probe_port: Option<u16>,
+        #[allow(dead_code)]
+        packet_size: u16,
+        interval_ms: u32,
+        max_hops: u8,
+    }
+
+    #[test]
+    fn legacy_auth_request_has_no_capabilities() {
+        let legacy = LegacyAuthRequest {
+            agent_id: Uuid::nil(),
+            api_key: "key".into(),
+            agent_version: "0.1.0".into(),
+            hostname: "host".into(),
+            os_info: "linux".into(),
+        };
+        let bytes = rmp_serde::to_vec(&legacy).unwrap();
+        let auth: AuthRequest = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(auth.protocol_version, 0);
+        assert!(auth.capabilities.is_empty());
+    }
+
+    #[test]
+    fn legacy_agent_decodes_version_zero_response() {
+        let config = TargetConfig {
+            target_id: Uuid::new_v4(),
+            session_id: Uuid::new_v4(),
+            address: "192.0.2.1".into(),
+            probe_method: ProbeMethod::Udp,
+            probe_port: Some(33434),
+            packet_size: 64,
+            interval_ms: 1000,
+            max_hops: 30,
+            address_family: AddressFamily::Ipv4,
+            flow_stable: true,
+            multipath_discovery: true,
+            pmtu_discovery: true,
+            first_round: 42,
+        };
+        let response = AuthResponse {
+            success: true,
+            error: None,
+            session_token: None,
+            assigned_targets: vec![AssignedTarget::for_version(config.clone(), 0)],
+            protocol_version: 0,
+            capabilities: vec![],
+        };
+        let bytes = rmp_serde::to_vec(&response).unwrap();
+        let legacy: LegacyAuthResponse = rmp_serde::from_slice(&bytes).unwrap();
+        assert!(legacy.success);
+        assert_eq!(legacy.assigned_targets.len(), 1);
+        let target = &legacy.assigned_targets[0];
+        assert_eq!(target.target_id, config.target_id);
+        assert_eq!(target.address, config.address);
+        assert_eq!(target.probe_method, ProbeMethod::Udp);
+        assert_eq!(target.interval_ms, 1000);
+        assert_eq!(target.max_hops, 30);
+
+        // The full config is more than a version 0 agent can decode
+        let current = AuthResponse {
+            assigned_targets: vec![AssignedTarget::for_version(config, PROTOCOL_VERSION)],
+            ..response
+        };
+        let bytes = rmp_serde::to_vec(&current).unwrap();
+        assert!(rmp_serde::from_slice::<LegacyAuthResponse>(&bytes).is_err());
+    }
+
+    #[test]
+    fn assigned_target_keeps_settings_for_current_agents() {
+        let config = TargetConfig {
+            target_id: Uuid::nil(),
+            session_id: Uuid::nil(),
+            address: "2001:db8::1".into(),
+            probe_method: ProbeMethod::Tcp,
+            probe_port: Some(443),
+            packet_size: 64,
+            interval_ms: 1000,
+            max_hops: 30,
+            address_family: AddressFamily::Ipv6,
+            flow_stable: true,
+            multipath_discovery: false,
+            pmtu_discovery: true,
+            first_round: 7,
+        };
+        let bytes = rmp_serde::to_vec(&AssignedTarget::for_version(config, PROTOCOL_VERSION)).unwrap();
+        let decoded = rmp_serde::from_slice::<AssignedTarget>(&bytes).unwrap().into_config();
+        assert_eq!(decoded.address_family, AddressFamily::Ipv6);
+        assert!(decoded.flow_stable && decoded.pmtu_discovery);
+        assert_eq!(decoded.first_round, 7);
+    }
+
+    #[test]
+    fn negotiated_response_round_trips() {
+        let response = AuthResponse {
+            success: true,
+            error: None,
+            session_token: None,
+            assigned_targets: vec![],
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: vec![capability::SPOOL.to_string()],
+        };
+        let bytes = rmp_serde::to_vec(&response).unwrap();
+        let decoded: AuthResponse = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(decoded.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(decoded.capabilities, vec![capability::SPOOL.to_string()]);
+    }
+
+    #[test]
+    fn first_round_is_only_sent_for_resumed_sessions() {
+        let mut config = TargetConfig {
+            target_id: Uuid::nil(),
+            session_id: Uuid::nil(),
+            address: "192.0.2.1".into(),
+            probe_method: ProbeMethod::Icmp,
+            probe_port: None,
+            packet_size: 64,
+            interval_ms: 1000,
+            max_hops: 30,
+            address_family: AddressFamily::Auto,
+            flow_stable: false,
+            multipath_discovery: false,
+            pmtu_discovery: false,
+            first_round: 0,
+        };
+        let fresh = rmp_serde::to_vec(&config).unwrap();
+        config.first_round = 42;
+        let resumed = rmp_serde::to_vec(&config).unwrap();
+        assert!(fresh.len() < resumed.len());
+
+        let decoded: TargetConfig = rmp_serde::from_slice(&fresh).unwrap();
+        assert_eq!(decoded.first_round, 0);
+        let decoded: TargetConfig = rmp_serde::from_slice(&resumed).unwrap();
+        assert_eq!(decoded.first_round, 42);
+    }
+}
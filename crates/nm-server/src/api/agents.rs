@@ -1,18 +1,25 @@
 use axum::{
     Json, Router,
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
-    routing::get,
+    routing::{get, post},
 };
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use serde_json::json;
 use uuid::Uuid;
 
-use nm_common::models::{Agent, AgentRegistration, CreateAgent};
+use nm_common::models::{
+    Agent, AgentCertificate, AgentHealthSample, AgentRegistration, CertificateRequest, CreateAgent,
+};
 use crate::state::AppState;
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/agents", get(list_agents).post(register_agent))
         .route("/agents/{id}", get(get_agent).delete(delete_agent))
+        .route("/agents/{id}/health", get(get_agent_health))
+        .route("/agents/{id}/certificate", post(issue_certificate))
 }
 
 async fn list_agents(State(state): State<AppState>) -> Result<Json<Vec<Agent>>, StatusCode> {
@@ -33,24 +40,73 @@ async fn get_agent(
         .ok_or(StatusCode::NOT_FOUND)
 }
 
+/// Register an agent. With a signing request in the body and the mTLS
+/// listener enabled, it also gets a client certificate.
 async fn register_agent(
     State(state): State<AppState>,
     Json(input): Json<CreateAgent>,
-) -> Result<(StatusCode, Json<AgentRegistration>), StatusCode> {
+) -> Result<(StatusCode, Json<AgentRegistration>), (StatusCode, Json<serde_json::Value>)> {
+    let internal = || (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": "Failed to register agent"})));
+    let signing = state.agent_ca.as_ref().zip(input.csr_pem.as_deref());
+
+    // Check the request first, so a bad one leaves no agent behind
+    if let Some((_, csr_pem)) = signing {
+        rcgen::CertificateSigningRequestParams::from_pem(csr_pem)
+            .map_err(|_| (StatusCode::BAD_REQUEST, Json(json!({"error": "Invalid certificate signing request"}))))?;
+    }
+
     let api_key = nm_common::crypto::generate_api_key();
-    let api_key_hash = bcrypt::hash(&api_key, bcrypt::DEFAULT_COST)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let api_key_hash = bcrypt::hash(&api_key, bcrypt::DEFAULT_COST).map_err(|_| internal())?;
 
-    let agent = crate::db::agents::create(&state.pool, &input, &api_key_hash)
+    let mut agent = crate::db::agents::create(&state.pool, &input, &api_key_hash)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|_| internal())?;
+
+    let certificate = match signing {
+        Some((ca, csr_pem)) => {
+            let certificate = ca.sign(agent.id, csr_pem).map_err(|e| {
+                tracing::error!(error = %e, "Failed to issue agent certificate");
+                internal()
+            })?;
+            crate::db::agents::set_has_certificate(&state.pool, agent.id)
+                .await
+                .map_err(|_| internal())?;
+            agent.has_certificate = true;
+            Some(certificate)
+        }
+        None => None,
+    };
 
     Ok((
         StatusCode::CREATED,
-        Json(AgentRegistration { agent, api_key }),
+        Json(AgentRegistration { agent, api_key, certificate }),
     ))
 }
 
+/// New client certificate for an existing agent and the key in its signing
+/// request, e.g. for an agent registered before the mTLS listener was
+/// enabled. Certificates issued earlier stay valid. From then on the agent
+/// can only log in on the mTLS listener.
+async fn issue_certificate(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(input): Json<CertificateRequest>,
+) -> Result<Json<AgentCertificate>, (StatusCode, Json<serde_json::Value>)> {
+    let Some(ca) = &state.agent_ca else {
+        return Err((StatusCode::CONFLICT, Json(json!({"error": "Agent mTLS listener is not enabled"}))));
+    };
+    let internal = |_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": "Failed to issue certificate"})));
+    crate::db::agents::get_by_id(&state.pool, id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, Json(json!({"error": "Agent not found"}))))?;
+    let certificate = ca
+        .sign(id, &input.csr_pem)
+        .map_err(|_| (StatusCode::BAD_REQUEST, Json(json!({"error": "Invalid certificate signing request"}))))?;
+    crate::db::agents::set_has_certificate(&state.pool, id).await.map_err(internal)?;
+    Ok(Json(certificate))
+}
+
 async fn delete_agent(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
@@ -60,3 +116,30 @@ async fn delete_agent(
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Widest range of heartbeat metrics returned at once.
+const MAX_HEALTH_RANGE_DAYS: i64 = 7;
+
+#[derive(Deserialize)]
+struct HealthQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+/// Heartbeat metrics of an agent, oldest first; defaults to the last 24 hours
+/// and spans at most `MAX_HEALTH_RANGE_DAYS`.
+async fn get_agent_health(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<HealthQuery>,
+) -> Result<Json<Vec<AgentHealthSample>>, StatusCode> {
+    let to = params.to.unwrap_or_else(Utc::now);
+    let from = params.from.unwrap_or(to - Duration::hours(24));
+    if from > to || to - from > Duration::days(MAX_HEALTH_RANGE_DAYS) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    crate::db::agent_health::list_range(&state.pool, id, from, to)
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use nm_common::models::{CreateTarget, DiscoveredDevice, DiscoveryScan, StartDiscoveryScan, Target};
+use nm_common::protocol::{capability, DiscoveryScanCommand, WsEnvelope, WsPayload};
+
+use crate::state::AppState;
+
+/// Scans are forgotten this long after they were requested.
+const SCAN_RETENTION: Duration = Duration::from_secs(24 * 3600);
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/agents/{agent_id}/discovery", post(start_scan))
+        .route("/agents/{agent_id}/devices", get(list_devices))
+        .route("/discovery/{scan_id}", get(get_scan))
+        .route("/devices/{id}/target", post(add_as_target))
+}
+
+/// POST /api/v1/agents/{agent_id}/discovery — Ask an online agent to sweep
+/// its local subnets (or the ones given); poll the returned scan for the result.
+async fn start_scan(
+    State(state): State<AppState>,
+    Path(agent_id): Path<Uuid>,
+    Json(input): Json<StartDiscoveryScan>,
+) -> Result<(StatusCode, Json<DiscoveryScan>), ApiError> {
+    if !state.agent_registry.is_online(&agent_id) {
+        return Err((StatusCode::CONFLICT, Json(json!({"error": "Agent is offline"}))));
+    }
+    if !state.agent_registry.supports(&agent_id, capability::DISCOVERY_SCAN) {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({"error": "Agent version does not support discovery scans"})),
+        ));
+    }
+
+    let retention = chrono::Duration::from_std(SCAN_RETENTION).unwrap_or_default();
+    state
+        .discovery_scans
+        .retain(|_, scan| Utc::now() - scan.requested_at < retention);
+
+    let scan = DiscoveryScan {
+        scan_id: Uuid::new_v4(),
+        agent_id,
+        requested_at: Utc::now(),
+        finished_at: None,
+        subnets: input.subnets.clone(),
+        device_count: None,
+        error: None,
+    };
+    state.discovery_scans.insert(scan.scan_id, scan.clone());
+
+    let envelope = WsEnvelope::new(WsPayload::DiscoveryScan(DiscoveryScanCommand {
+        scan_id: scan.scan_id,
+        subnets: input.subnets,
+    }));
+    if let Err(e) = state.agent_registry.send_to_agent(&agent_id, envelope).await {
+        state.discovery_scans.remove(&scan.scan_id);
+        tracing::warn!(agent_id = %agent_id, error = %e, "Failed to send discovery scan to agent");
+        return Err((StatusCode::BAD_GATEWAY, Json(json!({"error": "Agent unreachable"}))));
+    }
+
+    tracing::info!(agent_id = %agent_id, scan_id = %scan.scan_id, "Discovery scan requested");
+    Ok((StatusCode::ACCEPTED, Json(scan)))
+}
+
+/// GET /api/v1/discovery/{scan_id} — Progress of a scan
+async fn get_scan(
+    State(state): State<AppState>,
+    Path(scan_id): Path<Uuid>,
+) -> Result<Json<DiscoveryScan>, StatusCode> {
+    state
+        .discovery_scans
+        .get(&scan_id)
+        .map(|scan| Json(scan.clone()))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// GET /api/v1/agents/{agent_id}/devices — Everything the agent's scans have found
+async fn list_devices(
+    State(state): State<AppState>,
+    Path(agent_id): Path<Uuid>,
+) -> Result<Json<Vec<DiscoveredDevice>>, StatusCode> {
+    crate::db::devices::list_for_agent(&state.pool, agent_id)
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// POST /api/v1/devices/{id}/target — Trace a discovered device from the
+/// agent that found it, with the default probe settings
+async fn add_as_target(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<(StatusCode, Json<Target>), ApiError> {
+    let internal = |_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": "Database error"})));
+
+    let device = crate::db::devices::get_by_id(&state.pool, id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, Json(json!({"error": "Device not found"}))))?;
+
+    let existing = crate::db::targets::list_for_agent(&state.pool, device.agent_id)
+        .await
+        .map_err(internal)?;
+    if existing.iter().any(|t| t.address == device.ip_address) {
+        return Err((StatusCode::CONFLICT, Json(json!({"error": "Device is already a target"}))));
+    }
+
+    let input = CreateTarget::for_address(device.ip_address, device.hostname);
+    let target = crate::db::targets::create(&state.pool, device.agent_id, &input)
+        .await
+        .map_err(internal)?;
+    crate::engine::session_lifecycle::assign_to_agent(&state, &target).await;
+
+    Ok((StatusCode::CREATED, Json(target)))
+}
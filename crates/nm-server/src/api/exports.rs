@@ -36,10 +36,12 @@ async fn export_csv(
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    let mut csv = String::from("timestamp,hop_number,ip_address,hostname,rtt_us,is_lost,jitter_us\n");
+    let mut csv = String::from(
+        "timestamp,hop_number,ip_address,hostname,rtt_us,is_lost,jitter_us,probe_method,packet_size,ttl_sent\n",
+    );
     for row in &rows {
         csv.push_str(&format!(
-            "{},{},{},{},{},{},{}\n",
+            "{},{},{},{},{},{},{},{},{},{}\n",
             row.sent_at.to_rfc3339(),
             row.hop_number,
             row.ip_address.as_deref().unwrap_or(""),
@@ -47,6 +49,9 @@ async fn export_csv(
             row.rtt_us.map(|v| v.to_string()).unwrap_or_default(),
             row.is_lost,
             row.jitter_us.map(|v| v.to_string()).unwrap_or_default(),
+            row.probe_method,
+            row.packet_size,
+            row.ttl_sent,
         ));
     }
 
@@ -0,0 +1,81 @@
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use serde_json::json;
+
+use crate::engine::ip_intel::SourceFile;
+use crate::state::AppState;
+
+/// City databases run to a few hundred MB.
+const MAX_UPLOAD_BYTES: usize = 512 * 1024 * 1024;
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+/// Admin routes (admin role required)
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/admin/ip-intel",
+            get(list_sources)
+                .post(upload_source)
+                .layer(DefaultBodyLimit::max(MAX_UPLOAD_BYTES)),
+        )
+        .route("/admin/ip-intel/reload", post(reload_sources))
+}
+
+/// GET /api/v1/admin/ip-intel — Databases currently loaded
+async fn list_sources(State(state): State<AppState>) -> Json<Vec<SourceFile>> {
+    Json(state.ip_intel.files())
+}
+
+/// POST /api/v1/admin/ip-intel — Multipart upload of a `.mmdb` or RIR
+/// `delegated-*` file (field "file"); it replaces a file of the same name
+/// and is used right away.
+async fn upload_source(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Json<Vec<SourceFile>>, ApiError> {
+    let bad_request = |msg: String| (StatusCode::BAD_REQUEST, Json(json!({"error": msg})));
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| bad_request(e.to_string()))?
+    {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let name = field
+            .file_name()
+            .map(str::to_string)
+            .ok_or_else(|| bad_request("file name missing".to_string()))?;
+        let data = field.bytes().await.map_err(|e| bad_request(e.to_string()))?;
+
+        state
+            .ip_intel
+            .store(&name, data.to_vec())
+            .await
+            .map_err(|e| bad_request(format!("{e:#}")))?;
+        tracing::info!(file = %name, size = data.len(), "IP intelligence database uploaded");
+        return reload_sources(State(state)).await;
+    }
+
+    Err(bad_request("multipart field \"file\" missing".to_string()))
+}
+
+/// POST /api/v1/admin/ip-intel/reload — Re-read the database directory,
+/// e.g. after files were replaced on disk
+async fn reload_sources(State(state): State<AppState>) -> Result<Json<Vec<SourceFile>>, ApiError> {
+    let ip_intel = state.ip_intel.clone();
+    tokio::task::spawn_blocking(move || ip_intel.reload())
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": "Reload failed"}))))?
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("IP intelligence reload failed: {e:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": format!("{e:#}")})))
+        })
+}
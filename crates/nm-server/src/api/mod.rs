@@ -4,8 +4,10 @@ mod agents;
 mod alerts;
 mod auth_routes;
 mod dashboard;
+mod devices;
 mod download;
 mod exports;
+mod ip_intel;
 mod shares;
 mod targets;
 mod trace_profiles;
@@ -13,7 +15,7 @@ mod traces;
 mod traffic;
 mod update;
 
-use crate::auth::require_auth;
+use crate::auth::{require_admin, require_auth};
 use crate::state::AppState;
 
 pub fn router(state: AppState) -> Router<AppState> {
@@ -27,6 +29,7 @@ pub fn router(state: AppState) -> Router<AppState> {
         .merge(auth_routes::protected_router())
         .merge(agents::router())
         .merge(targets::router())
+        .merge(devices::router())
         .merge(traces::router())
         .merge(alerts::router())
         .merge(exports::router())
@@ -35,9 +38,14 @@ pub fn router(state: AppState) -> Router<AppState> {
         .merge(shares::router())
         .merge(traffic::router())
         .merge(update::router())
-        .route_layer(middleware::from_fn_with_state(state, require_auth));
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_auth));
 
-    public.merge(protected)
+    // Admin routes (require a JWT with the admin role)
+    let admin = Router::new()
+        .merge(ip_intel::router())
+        .route_layer(middleware::from_fn_with_state(state, require_admin));
+
+    public.merge(protected).merge(admin)
 }
 
 /// Public download routes — mounted at root level, not under /api/v1.
@@ -2,17 +2,28 @@ use axum::{
     Json, Router,
     extract::{Path, State},
     http::StatusCode,
-    routing::get,
+    routing::{get, post},
 };
+use std::time::{Duration, Instant};
+
 use uuid::Uuid;
 
 use nm_common::models::{CreateTarget, Target, UpdateTarget};
+use nm_common::models::TraceSession;
+use nm_common::protocol::{capability, AgentConfigUpdate, WsEnvelope, WsPayload};
+use crate::engine::session_lifecycle;
 use crate::state::AppState;
+use crate::ws::agent_handler::{assigned_probe_method, needs_ipv6};
+
+/// Unanswered config updates are forgotten after this long.
+const CONFIG_ACK_TIMEOUT: Duration = Duration::from_secs(300);
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/agents/{agent_id}/targets", get(list_targets).post(create_target))
         .route("/targets/{id}", get(get_target).put(update_target).delete(delete_target))
+        .route("/targets/{id}/pause", post(pause_target))
+        .route("/targets/{id}/resume", post(resume_target))
 }
 
 async fn list_targets(
@@ -41,11 +52,15 @@ async fn create_target(
     Path(agent_id): Path<Uuid>,
     Json(input): Json<CreateTarget>,
 ) -> Result<(StatusCode, Json<Target>), StatusCode> {
+    if !is_valid_address_family(&input.address_family) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
     let target = crate::db::targets::create(&state.pool, agent_id, &input)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    // TODO: Push TargetAssignment to agent via WS if online
+    session_lifecycle::assign_to_agent(&state, &target).await;
 
     Ok((StatusCode::CREATED, Json(target)))
 }
@@ -55,11 +70,132 @@ async fn update_target(
     Path(id): Path<Uuid>,
     Json(input): Json<UpdateTarget>,
 ) -> Result<Json<Target>, StatusCode> {
-    crate::db::targets::update(&state.pool, id, &input)
+    if let Some(family) = &input.address_family {
+        if !is_valid_address_family(family) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let before = crate::db::targets::get_by_id(&state.pool, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // Switching to IPv6 is refused up front for an agent that cannot probe it
+    if input.address.is_some() || input.address_family.is_some() {
+        let address = input.address.as_deref().unwrap_or(&before.address);
+        let family = input.address_family.as_deref().unwrap_or(&before.address_family);
+        if needs_ipv6(address, family) && !agent_supports_ipv6(&state, before.agent_id).await? {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    let target = crate::db::targets::update(&state.pool, id, &input)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if before.is_active && !target.is_active {
+        session_lifecycle::remove_from_agent(&state, &target).await;
+    } else if !before.is_active || needs_reassignment(&before, &target) {
+        // The agent only picks up a new address or probing mode from a full assignment
+        session_lifecycle::assign_to_agent(&state, &target).await;
+    } else if target.is_active && state.agent_registry.is_online(&target.agent_id) {
+        push_config_update(&state, &target).await;
+    }
+
+    Ok(Json(target))
+}
+
+/// Whether the target's running probe has to be replaced rather than
+/// reconfigured: config updates only carry timing and packet settings.
+fn needs_reassignment(before: &Target, after: &Target) -> bool {
+    before.address != after.address
+        || before.address_family != after.address_family
+        || before.flow_stable != after.flow_stable
+        || before.multipath_discovery != after.multipath_discovery
+        || before.pmtu_discovery != after.pmtu_discovery
+}
+
+/// Whether the agent can trace over IPv6, going by what it advertised when
+/// it last connected.
+async fn agent_supports_ipv6(state: &AppState, agent_id: Uuid) -> Result<bool, StatusCode> {
+    if state.agent_registry.is_online(&agent_id) {
+        return Ok(state.agent_registry.supports(&agent_id, capability::IPV6));
+    }
+    let agent = crate::db::agents::get_by_id(&state.pool, agent_id)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(agent.capabilities.iter().any(|c| c == capability::IPV6))
+}
+
+/// Send the target's probe settings to its agent so the running probe picks
+/// them up without a new session. The agent's AckResponse is matched back to
+/// the target through `pending_config_acks`. Agents that cannot apply
+/// config updates pick the new settings up when they next connect.
+async fn push_config_update(state: &AppState, target: &Target) {
+    let capabilities = state.agent_registry.capabilities(&target.agent_id);
+    if !capabilities.contains(capability::CONFIG_UPDATE) {
+        tracing::info!(target_id = %target.id, "Agent cannot apply config updates, settings apply on reconnect");
+        return;
+    }
+
+    state
+        .pending_config_acks
+        .retain(|_, (_, sent_at)| sent_at.elapsed() < CONFIG_ACK_TIMEOUT);
+
+    let envelope = WsEnvelope::new(WsPayload::ConfigUpdate(AgentConfigUpdate {
+        target_id: target.id,
+        interval_ms: Some(target.interval_ms as u32),
+        packet_size: Some(target.packet_size as u16),
+        probe_method: Some(assigned_probe_method(target, &capabilities)),
+        max_hops: Some(target.max_hops as u8),
+        probe_port: target.probe_port.map(|p| p as u16),
+    }));
+    let msg_id = envelope.msg_id;
+    state.pending_config_acks.insert(msg_id, (target.id, Instant::now()));
+
+    if let Err(e) = state.agent_registry.send_to_agent(&target.agent_id, envelope).await {
+        state.pending_config_acks.remove(&msg_id);
+        tracing::warn!(target_id = %target.id, error = %e, "Failed to push config update to agent");
+    }
+}
+
+/// Stop collecting for the target without ending its session.
+async fn pause_target(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<TraceSession>, StatusCode> {
+    let target = crate::db::targets::get_by_id(&state.pool, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    session_lifecycle::pause(&state, &target)
+        .await
         .map(Json)
-        .ok_or(StatusCode::NOT_FOUND)
+        .map_err(|e| {
+            tracing::error!(target_id = %id, error = %e, "Failed to pause target");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Continue collecting for a paused target in the same session.
+async fn resume_target(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<TraceSession>, StatusCode> {
+    let target = crate::db::targets::get_by_id(&state.pool, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    session_lifecycle::resume(&state, &target)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!(target_id = %id, error = %e, "Failed to resume target");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
 }
 
 async fn delete_target(
@@ -71,3 +207,7 @@ async fn delete_target(
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     Ok(StatusCode::NO_CONTENT)
 }
+
+fn is_valid_address_family(family: &str) -> bool {
+    matches!(family, "auto" | "ipv4" | "ipv6")
+}
@@ -2,22 +2,26 @@ use axum::{
     Json, Router,
     extract::{Path, Query, State},
     http::StatusCode,
-    routing::get,
+    routing::{get, post},
 };
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use uuid::Uuid;
 
-use nm_common::models::{Hop, TraceSession};
+use nm_common::models::{Hop, MultipathSnapshot, PathMtuResult, TraceSession};
 use crate::state::AppState;
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/targets/{target_id}/sessions", get(list_sessions))
-        .route("/sessions/{id}", get(get_session))
+        .route("/sessions/{id}", get(get_session).delete(delete_session))
+        .route("/sessions/{id}/archive", post(archive_session))
         .route("/sessions/{id}/hops", get(list_hops))
         .route("/sessions/{session_id}/hops/{hop_number}", get(get_hop))
         .route("/sessions/{id}/samples/timeseries", get(get_timeseries))
+        .route("/sessions/{id}/multipath", get(get_multipath))
+        .route("/sessions/{id}/path-mtu", get(get_path_mtu))
+        .route("/sessions/{id}/path-mtu/history", get(list_path_mtu))
 }
 
 async fn list_sessions(
@@ -41,6 +45,38 @@ async fn get_session(
         .ok_or(StatusCode::NOT_FOUND)
 }
 
+/// End a session and keep it for reporting. Archiving a target's open
+/// session moves its collection to a new one.
+async fn archive_session(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<TraceSession>, StatusCode> {
+    crate::engine::session_lifecycle::close(&state, id, crate::db::sessions::state::ARCHIVED)
+        .await
+        .map_err(|e| {
+            tracing::error!(session_id = %id, error = %e, "Failed to archive session");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Mark a session for deletion; it and its samples are purged in the
+/// background.
+async fn delete_session(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, StatusCode> {
+    crate::engine::session_lifecycle::close(&state, id, crate::db::sessions::state::WILL_DELETE)
+        .await
+        .map_err(|e| {
+            tracing::error!(session_id = %id, error = %e, "Failed to delete session");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(|_| StatusCode::ACCEPTED)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
 async fn list_hops(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
@@ -62,6 +98,50 @@ async fn get_hop(
         .ok_or(StatusCode::NOT_FOUND)
 }
 
+async fn get_multipath(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<MultipathSnapshot>, StatusCode> {
+    crate::db::multipath::latest_for_session(&state.pool, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_path_mtu(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PathMtuResult>, StatusCode> {
+    crate::db::path_mtu::latest_for_session(&state.pool, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    #[serde(default = "default_history_limit")]
+    limit: i64,
+}
+
+fn default_history_limit() -> i64 {
+    50
+}
+
+/// Past measurements, newest first; each carries the route snapshot it was taken on.
+async fn list_path_mtu(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<HistoryQuery>,
+) -> Result<Json<Vec<PathMtuResult>>, StatusCode> {
+    crate::db::path_mtu::list_for_session(&state.pool, id, params.limit)
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
 #[derive(Deserialize)]
 struct TimeseriesQuery {
     from: DateTime<Utc>,
@@ -95,6 +175,7 @@ async fn get_timeseries(
     let resolution_secs = parse_resolution(&params.resolution);
     crate::db::samples::get_timeseries(
         &state.pool,
+        &state.config,
         session_id,
         params.hop_id,
         params.from,
@@ -26,6 +26,52 @@ pub fn load() -> Result<ServerConfig> {
     if let Ok(v) = std::env::var("NM_STATIC_DIR") {
         config.static_dir = v;
     }
+    if let Ok(v) = std::env::var("NM_IP_INTEL_DIR") {
+        config.ip_intel_dir = v;
+    }
+    if let Ok(v) = std::env::var("NM_AGENT_TLS_LISTEN_ADDR") {
+        config.agent_tls_listen_addr = Some(v).filter(|v| !v.is_empty());
+    }
+    if let Ok(v) = std::env::var("NM_AGENT_CA_DIR") {
+        config.agent_ca_dir = v;
+    }
+    if let Ok(v) = std::env::var("NM_AGENT_TLS_HOSTNAMES") {
+        config.agent_tls_hostnames = v
+            .split(',')
+            .map(|h| h.trim().to_string())
+            .filter(|h| !h.is_empty())
+            .collect();
+    }
+    if let Ok(v) = std::env::var("NM_INGEST_QUEUE_ROUNDS") {
+        config.ingest_queue_rounds = v.parse().unwrap_or(5000);
+    }
+    if let Ok(v) = std::env::var("NM_INGEST_BATCH_ROWS") {
+        config.ingest_batch_rows = v.parse().unwrap_or(10_000);
+    }
+    if let Ok(v) = std::env::var("NM_INGEST_FLUSH_INTERVAL_MS") {
+        config.ingest_flush_interval_ms = v.parse().unwrap_or(250);
+    }
+    if let Ok(v) = std::env::var("NM_RAW_RETENTION_DAYS") {
+        config.raw_retention_days = v.parse().unwrap_or(7);
+    }
+    if let Ok(v) = std::env::var("NM_MINUTE_ROLLUP_RETENTION_DAYS") {
+        config.minute_rollup_retention_days = v.parse().unwrap_or(90);
+    }
+    if let Ok(v) = std::env::var("NM_HOURLY_ROLLUP_RETENTION_DAYS") {
+        config.hourly_rollup_retention_days = v.parse().unwrap_or(0);
+    }
+    if let Ok(v) = std::env::var("NM_AGENT_HEALTH_RETENTION_DAYS") {
+        config.agent_health_retention_days = v.parse().unwrap_or(30);
+    }
+    if let Ok(v) = std::env::var("NM_HOP_WINDOW_SAMPLES") {
+        config.hop_window_samples = v.parse().unwrap_or(100);
+    }
+    if let Ok(v) = std::env::var("NM_HOP_WINDOW_SECS") {
+        config.hop_window_secs = v.parse().unwrap_or(60);
+    }
+    if let Ok(v) = std::env::var("NM_HOP_STATS_IDLE_SECS") {
+        config.hop_stats_idle_secs = v.parse().unwrap_or(600);
+    }
 
     Ok(config)
 }
@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use nm_common::models::AgentHealthSample;
+use nm_common::protocol::AgentHeartbeat;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Record the metrics carried by one heartbeat.
+pub async fn insert(pool: &PgPool, agent_id: Uuid, hb: &AgentHeartbeat) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"INSERT INTO agent_health
+           (agent_id, cpu_usage_pct, memory_used_mb, memory_total_mb, load_avg_1m,
+            agent_rss_mb, active_targets, queued_rounds, skipped_rounds)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+    )
+    .bind(agent_id)
+    .bind(hb.cpu_usage_pct)
+    .bind(hb.memory_usage_mb as i32)
+    .bind(hb.memory_total_mb as i32)
+    .bind(hb.load_avg_1m)
+    .bind(hb.agent_rss_mb as i32)
+    .bind(hb.active_target_count as i32)
+    .bind(hb.queued_rounds as i32)
+    .bind(hb.skipped_rounds as i64)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Health samples of an agent in `[from, to]`, oldest first.
+pub async fn list_range(
+    pool: &PgPool,
+    agent_id: Uuid,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> anyhow::Result<Vec<AgentHealthSample>> {
+    let rows = sqlx::query_as::<_, AgentHealthSample>(
+        r#"SELECT recorded_at, cpu_usage_pct, memory_used_mb, memory_total_mb, load_avg_1m,
+                  agent_rss_mb, active_targets, queued_rounds, skipped_rounds
+           FROM agent_health
+           WHERE agent_id = $1 AND recorded_at BETWEEN $2 AND $3
+           ORDER BY recorded_at"#,
+    )
+    .bind(agent_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
@@ -6,7 +6,7 @@ use nm_common::models::{Agent, CreateAgent};
 pub async fn list(pool: &PgPool) -> anyhow::Result<Vec<Agent>> {
     let agents = sqlx::query_as::<_, Agent>(
         r#"SELECT id, name, hostname, os_info, version, ip_address,
-                  is_online, last_seen_at, created_at, updated_at
+                  is_online, last_seen_at, protocol_version, capabilities, has_certificate, created_at, updated_at
            FROM agents ORDER BY name"#,
     )
     .fetch_all(pool)
@@ -17,7 +17,7 @@ pub async fn list(pool: &PgPool) -> anyhow::Result<Vec<Agent>> {
 pub async fn get_by_id(pool: &PgPool, id: Uuid) -> anyhow::Result<Option<Agent>> {
     let agent = sqlx::query_as::<_, Agent>(
         r#"SELECT id, name, hostname, os_info, version, ip_address,
-                  is_online, last_seen_at, created_at, updated_at
+                  is_online, last_seen_at, protocol_version, capabilities, has_certificate, created_at, updated_at
            FROM agents WHERE id = $1"#,
     )
     .bind(id)
@@ -31,7 +31,7 @@ pub async fn create(pool: &PgPool, input: &CreateAgent, api_key_hash: &str) -> a
         r#"INSERT INTO agents (name, api_key_hash)
            VALUES ($1, $2)
            RETURNING id, name, hostname, os_info, version, ip_address,
-                     is_online, last_seen_at, created_at, updated_at"#,
+                     is_online, last_seen_at, protocol_version, capabilities, has_certificate, created_at, updated_at"#,
     )
     .bind(&input.name)
     .bind(api_key_hash)
@@ -63,3 +63,12 @@ pub async fn set_offline(pool: &PgPool, id: Uuid) -> anyhow::Result<()> {
         .await?;
     Ok(())
 }
+
+/// Record that `id` was issued a client certificate.
+pub async fn set_has_certificate(pool: &PgPool, id: Uuid) -> anyhow::Result<()> {
+    sqlx::query("UPDATE agents SET has_certificate = true, updated_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use nm_common::models::DiscoveredDevice;
+use nm_common::protocol::DiscoveredHost;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Insert or refresh the devices found by one scan. A name or vendor the
+/// scan did not learn keeps its previous value, unless another device (a
+/// different MAC address) now holds the IP address.
+pub async fn upsert(
+    pool: &PgPool,
+    agent_id: Uuid,
+    devices: &[(DiscoveredHost, Option<String>)],
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    for (host, vendor) in devices {
+        sqlx::query(
+            r#"INSERT INTO discovered_devices
+               (agent_id, ip_address, mac_address, hostname, vendor, latency_us)
+               VALUES ($1, $2, $3, $4, $5, $6)
+               ON CONFLICT (agent_id, ip_address) DO UPDATE SET
+                   hostname = CASE WHEN EXCLUDED.mac_address <> discovered_devices.mac_address
+                                   THEN EXCLUDED.hostname
+                                   ELSE COALESCE(EXCLUDED.hostname, discovered_devices.hostname) END,
+                   vendor = CASE WHEN EXCLUDED.mac_address <> discovered_devices.mac_address
+                                 THEN EXCLUDED.vendor
+                                 ELSE COALESCE(EXCLUDED.vendor, discovered_devices.vendor) END,
+                   mac_address = COALESCE(EXCLUDED.mac_address, discovered_devices.mac_address),
+                   latency_us = EXCLUDED.latency_us,
+                   last_seen_at = NOW()"#,
+        )
+        .bind(agent_id)
+        .bind(&host.ip_address)
+        .bind(&host.mac_address)
+        .bind(&host.hostname)
+        .bind(vendor)
+        .bind(host.latency_us.map(|us| us.min(i32::MAX as u32) as i32))
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Devices an agent has found, in address order.
+pub async fn list_for_agent(pool: &PgPool, agent_id: Uuid) -> anyhow::Result<Vec<DiscoveredDevice>> {
+    let devices = sqlx::query_as::<_, DiscoveredDevice>(
+        r#"SELECT id, agent_id, ip_address, mac_address, hostname, vendor, latency_us,
+                  description, discovered_at, last_seen_at
+           FROM discovered_devices
+           WHERE agent_id = $1
+           ORDER BY ip_address::inet"#,
+    )
+    .bind(agent_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(devices)
+}
+
+pub async fn get_by_id(pool: &PgPool, id: Uuid) -> anyhow::Result<Option<DiscoveredDevice>> {
+    let device = sqlx::query_as::<_, DiscoveredDevice>(
+        r#"SELECT id, agent_id, ip_address, mac_address, hostname, vendor, latency_us,
+                  description, discovered_at, last_seen_at
+           FROM discovered_devices
+           WHERE id = $1"#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(device)
+}
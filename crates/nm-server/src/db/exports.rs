@@ -11,6 +11,9 @@ pub struct CsvRow {
     pub rtt_us: Option<i32>,
     pub is_lost: bool,
     pub jitter_us: Option<i32>,
+    pub probe_method: String,
+    pub packet_size: i32,
+    pub ttl_sent: i16,
 }
 
 pub async fn get_session_csv_data(
@@ -21,7 +24,7 @@ pub async fn get_session_csv_data(
 ) -> anyhow::Result<Vec<CsvRow>> {
     let rows = sqlx::query_as::<_, CsvRow>(
         r#"SELECT s.sent_at, h.hop_number, h.ip_address, h.hostname,
-                  s.rtt_us, s.is_lost, s.jitter_us
+                  s.rtt_us, s.is_lost, s.jitter_us, s.probe_method, s.packet_size, s.ttl_sent
            FROM samples s
            JOIN hops h ON h.id = s.hop_id
            WHERE s.session_id = $1
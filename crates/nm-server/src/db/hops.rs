@@ -2,12 +2,13 @@ use sqlx::PgPool;
 use uuid::Uuid;
 
 use nm_common::models::Hop;
+use nm_common::protocol::HopMetadataUpdate;
 
 pub async fn list_for_session(pool: &PgPool, session_id: Uuid) -> anyhow::Result<Vec<Hop>> {
     let hops = sqlx::query_as::<_, Hop>(
         r#"SELECT id, session_id, hop_number, ip_address, hostname,
                   asn, as_name, geo_country, geo_city, geo_lat, geo_lon,
-                  whois_data, first_seen_at, last_seen_at
+                  whois_data, mpls_labels, first_seen_at, last_seen_at
            FROM hops WHERE session_id = $1 ORDER BY hop_number"#,
     )
     .bind(session_id)
@@ -24,7 +25,7 @@ pub async fn get_by_session_and_number(
     let hop = sqlx::query_as::<_, Hop>(
         r#"SELECT id, session_id, hop_number, ip_address, hostname,
                   asn, as_name, geo_country, geo_city, geo_lat, geo_lon,
-                  whois_data, first_seen_at, last_seen_at
+                  whois_data, mpls_labels, first_seen_at, last_seen_at
            FROM hops WHERE session_id = $1 AND hop_number = $2"#,
     )
     .bind(session_id)
@@ -33,3 +34,77 @@ pub async fn get_by_session_and_number(
     .await?;
     Ok(hop)
 }
+
+/// Store what the agent learned about a hop's address. Fields it could not
+/// determine keep their stored value.
+pub async fn update_metadata(pool: &PgPool, meta: &HopMetadataUpdate) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"UPDATE hops SET
+            hostname = COALESCE($1, hostname),
+            asn = COALESCE($2, asn),
+            as_name = COALESCE($3, as_name),
+            geo_country = COALESCE($4, geo_country),
+            geo_city = COALESCE($5, geo_city),
+            geo_lat = COALESCE($6, geo_lat),
+            geo_lon = COALESCE($7, geo_lon),
+            last_seen_at = NOW()
+        WHERE session_id = $8 AND hop_number = $9 AND ip_address = $10"#,
+    )
+    .bind(&meta.hostname)
+    .bind(meta.asn.map(|v| v as i32))
+    .bind(&meta.as_name)
+    .bind(&meta.geo_country)
+    .bind(&meta.geo_city)
+    .bind(meta.geo_lat)
+    .bind(meta.geo_lon)
+    .bind(meta.session_id)
+    .bind(meta.hop_number as i16)
+    .bind(&meta.ip_address)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `DATABASE_URL=... cargo test -p nm-server hop_rows_decode -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL database in DATABASE_URL"]
+    async fn hop_rows_decode() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+        let pool = sqlx::postgres::PgPoolOptions::new().max_connections(1).connect(&url).await.unwrap();
+        sqlx::migrate!("../../migrations").run(&pool).await.unwrap();
+
+        let agent_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO agents (name, api_key_hash) VALUES ('hop-decode', '') RETURNING id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let target_id: Uuid =
+            sqlx::query_scalar("INSERT INTO targets (agent_id, address) VALUES ($1, '192.0.2.1') RETURNING id")
+                .bind(agent_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let session_id = crate::db::sessions::open_or_create(&pool, target_id).await.unwrap().id;
+        let labels = serde_json::json!([{"label": 16004, "traffic_class": 0, "bottom_of_stack": true, "ttl": 1}]);
+        sqlx::query("INSERT INTO hops (session_id, hop_number, ip_address, mpls_labels) VALUES ($1, 3, '10.0.0.3', $2)")
+            .bind(session_id)
+            .bind(&labels)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let hops = list_for_session(&pool, session_id).await.unwrap();
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].mpls_labels.as_ref(), Some(&labels));
+        let hop = get_by_session_and_number(&pool, session_id, 3).await.unwrap().unwrap();
+        assert_eq!(hop.ip_address.as_deref(), Some("10.0.0.3"));
+        assert_eq!(hop.mpls_labels, Some(labels));
+
+        sqlx::query("DELETE FROM agents WHERE id = $1").bind(agent_id).execute(&pool).await.unwrap();
+    }
+}
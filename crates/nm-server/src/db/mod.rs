@@ -1,7 +1,11 @@
+pub mod agent_health;
 pub mod agents;
 pub mod alerts;
+pub mod devices;
 pub mod exports;
 pub mod hops;
+pub mod multipath;
+pub mod path_mtu;
 pub mod samples;
 pub mod sessions;
 pub mod share_tokens;
@@ -0,0 +1,36 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use nm_common::models::MultipathSnapshot;
+use nm_common::protocol::MultipathDiscoveryReport;
+
+pub async fn insert(pool: &PgPool, report: &MultipathDiscoveryReport) -> anyhow::Result<MultipathSnapshot> {
+    let max_width = report.hops.iter().map(|h| h.interfaces.len()).max().unwrap_or(0) as i16;
+    let snapshot = sqlx::query_as::<_, MultipathSnapshot>(
+        r#"INSERT INTO multipath_snapshots (session_id, captured_at, flows_probed, max_width,
+                                            hop_interfaces, links)
+           VALUES ($1, $2, $3, $4, $5, $6)
+           RETURNING id, session_id, captured_at, flows_probed, max_width, hop_interfaces, links"#,
+    )
+    .bind(report.session_id)
+    .bind(report.discovered_at)
+    .bind(report.flows_probed as i16)
+    .bind(max_width)
+    .bind(serde_json::to_value(&report.hops)?)
+    .bind(serde_json::to_value(&report.links)?)
+    .fetch_one(pool)
+    .await?;
+    Ok(snapshot)
+}
+
+pub async fn latest_for_session(pool: &PgPool, session_id: Uuid) -> anyhow::Result<Option<MultipathSnapshot>> {
+    let snapshot = sqlx::query_as::<_, MultipathSnapshot>(
+        r#"SELECT id, session_id, captured_at, flows_probed, max_width, hop_interfaces, links
+           FROM multipath_snapshots WHERE session_id = $1
+           ORDER BY captured_at DESC LIMIT 1"#,
+    )
+    .bind(session_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(snapshot)
+}
@@ -0,0 +1,104 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use nm_common::models::PathMtuResult;
+use nm_common::protocol::PathMtuReport;
+
+/// Store a measurement against the session's latest route snapshot.
+pub async fn insert(pool: &PgPool, report: &PathMtuReport) -> anyhow::Result<PathMtuResult> {
+    let black_hole_hops: Vec<i16> = report.black_hole_hops.iter().map(|h| *h as i16).collect();
+    let result = sqlx::query_as::<_, PathMtuResult>(
+        r#"INSERT INTO path_mtu_results (session_id, route_snapshot_id, captured_at, path_mtu,
+                                         reached_destination, mtu_drop_hop, black_hole_hops, hop_mtus)
+           VALUES ($1,
+                   (SELECT id FROM route_snapshots WHERE session_id = $1
+                    ORDER BY captured_at DESC LIMIT 1),
+                   $2, $3, $4, $5, $6, $7)
+           RETURNING id, session_id, route_snapshot_id, captured_at, path_mtu, reached_destination,
+                     mtu_drop_hop, black_hole_hops, hop_mtus"#,
+    )
+    .bind(report.session_id)
+    .bind(report.discovered_at)
+    .bind(i32::from(report.path_mtu))
+    .bind(report.reached_destination)
+    .bind(report.mtu_drop_hop.map(|h| h as i16))
+    .bind(&black_hole_hops)
+    .bind(serde_json::to_value(&report.hops)?)
+    .fetch_one(pool)
+    .await?;
+    Ok(result)
+}
+
+pub async fn latest_for_session(pool: &PgPool, session_id: Uuid) -> anyhow::Result<Option<PathMtuResult>> {
+    let result = sqlx::query_as::<_, PathMtuResult>(
+        r#"SELECT id, session_id, route_snapshot_id, captured_at, path_mtu, reached_destination,
+                  mtu_drop_hop, black_hole_hops, hop_mtus
+           FROM path_mtu_results WHERE session_id = $1
+           ORDER BY captured_at DESC LIMIT 1"#,
+    )
+    .bind(session_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(result)
+}
+
+pub async fn list_for_session(pool: &PgPool, session_id: Uuid, limit: i64) -> anyhow::Result<Vec<PathMtuResult>> {
+    let results = sqlx::query_as::<_, PathMtuResult>(
+        r#"SELECT id, session_id, route_snapshot_id, captured_at, path_mtu, reached_destination,
+                  mtu_drop_hop, black_hole_hops, hop_mtus
+           FROM path_mtu_results WHERE session_id = $1
+           ORDER BY captured_at DESC LIMIT $2"#,
+    )
+    .bind(session_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nm_common::protocol::HopMtu;
+
+    /// `DATABASE_URL=... cargo test -p nm-server path_mtu_keeps_loopback_mtu -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL database in DATABASE_URL"]
+    async fn path_mtu_keeps_loopback_mtu() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+        let pool = sqlx::postgres::PgPoolOptions::new().max_connections(1).connect(&url).await.unwrap();
+        sqlx::migrate!("../../migrations").run(&pool).await.unwrap();
+
+        let agent_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO agents (name, api_key_hash) VALUES ('pmtu-loopback', '') RETURNING id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let target_id: Uuid =
+            sqlx::query_scalar("INSERT INTO targets (agent_id, address) VALUES ($1, '127.0.0.1') RETURNING id")
+                .bind(agent_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let session_id = crate::db::sessions::open_or_create(&pool, target_id).await.unwrap().id;
+        let report = PathMtuReport {
+            target_id,
+            session_id,
+            discovered_at: chrono::Utc::now(),
+            path_mtu: 65535,
+            reached_destination: true,
+            mtu_drop_hop: None,
+            black_hole_hops: Vec::new(),
+            hops: vec![HopMtu { hop_number: 1, ip_address: Some("127.0.0.1".into()), mtu: 65535 }],
+        };
+
+        let stored = insert(&pool, &report).await.unwrap();
+        assert_eq!(stored.path_mtu, 65535);
+        let latest = latest_for_session(&pool, session_id).await.unwrap().unwrap();
+        assert_eq!(latest.path_mtu, 65535);
+        assert_eq!(latest.hop_mtus[0]["mtu"], 65535);
+
+        sqlx::query("DELETE FROM agents WHERE id = $1").bind(agent_id).execute(&pool).await.unwrap();
+    }
+}
@@ -1,10 +1,111 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use nm_common::config::ServerConfig;
 use nm_common::models::TimeSeriesDatapoint;
 
+/// Where a time series is read from: raw samples or one of the rollups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    Raw,
+    Minute,
+    Hour,
+}
+
+impl Tier {
+    fn granularity_seconds(self) -> i64 {
+        match self {
+            Tier::Raw => 1,
+            Tier::Minute => 60,
+            Tier::Hour => 3600,
+        }
+    }
+
+    fn retention_days(self, config: &ServerConfig) -> u32 {
+        match self {
+            Tier::Raw => config.raw_retention_days,
+            Tier::Minute => config.minute_rollup_retention_days,
+            Tier::Hour => config.hourly_rollup_retention_days,
+        }
+    }
+
+    /// Rollup table and its bucket column.
+    fn rollup_table(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Tier::Raw => None,
+            Tier::Minute => Some(("hop_stats_minutely", "minute")),
+            Tier::Hour => Some(("hop_stats_hourly", "hour")),
+        }
+    }
+}
+
+/// Picks the coarsest tier that still has data back to `from` and whose
+/// buckets divide the requested resolution. When none fits, the finest tier
+/// still holding `from` is used and its buckets come back as they are.
+pub fn choose_tier(
+    from: DateTime<Utc>,
+    now: DateTime<Utc>,
+    resolution_seconds: i32,
+    config: &ServerConfig,
+) -> Tier {
+    let covers = |tier: Tier| match tier.retention_days(config) {
+        0 => true,
+        days => from >= now - Duration::days(days as i64),
+    };
+    let fits = |tier: Tier| resolution_seconds as i64 % tier.granularity_seconds() == 0;
+
+    [Tier::Hour, Tier::Minute, Tier::Raw]
+        .into_iter()
+        .find(|&t| fits(t) && covers(t))
+        .or_else(|| [Tier::Raw, Tier::Minute, Tier::Hour].into_iter().find(|&t| covers(t)))
+        .unwrap_or(Tier::Hour)
+}
+
+/// Start of the buckets a rollup may not be complete for yet: the stats
+/// aggregator runs every `aggregation_interval_secs`, so newer data is read
+/// from raw samples. Aligned to the output buckets so none is split.
+pub fn rollup_cutoff(
+    tier: Tier,
+    now: DateTime<Utc>,
+    resolution_seconds: i32,
+    aggregation_interval_secs: u64,
+) -> DateTime<Utc> {
+    let align = (resolution_seconds as i64).max(tier.granularity_seconds());
+    let settled = now.timestamp() - aggregation_interval_secs as i64 - tier.granularity_seconds();
+    DateTime::from_timestamp(settled - settled.rem_euclid(align), 0).unwrap_or(now)
+}
+
 pub async fn get_timeseries(
+    pool: &PgPool,
+    config: &ServerConfig,
+    session_id: Uuid,
+    hop_id: Uuid,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    resolution_seconds: i32,
+) -> anyhow::Result<Vec<TimeSeriesDatapoint>> {
+    let now = Utc::now();
+    let tier = choose_tier(from, now, resolution_seconds, config);
+    if tier == Tier::Raw {
+        return raw_timeseries(pool, session_id, hop_id, from, to, resolution_seconds).await;
+    }
+
+    let cutoff = rollup_cutoff(tier, now, resolution_seconds, config.stats_aggregation_interval_secs);
+    let mut rows = Vec::new();
+    if from < cutoff {
+        rows = rollup_timeseries(pool, tier, session_id, hop_id, from, to.min(cutoff), resolution_seconds)
+            .await?;
+    }
+    if to > cutoff {
+        rows.extend(
+            raw_timeseries(pool, session_id, hop_id, from.max(cutoff), to, resolution_seconds).await?,
+        );
+    }
+    Ok(rows)
+}
+
+async fn raw_timeseries(
     pool: &PgPool,
     session_id: Uuid,
     hop_id: Uuid,
@@ -55,6 +156,96 @@ pub async fn get_timeseries(
     Ok(rows)
 }
 
+async fn rollup_timeseries(
+    pool: &PgPool,
+    tier: Tier,
+    session_id: Uuid,
+    hop_id: Uuid,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    resolution_seconds: i32,
+) -> anyhow::Result<Vec<TimeSeriesDatapoint>> {
+    let Some((table, bucket)) = tier.rollup_table() else {
+        return Ok(Vec::new());
+    };
+
+    // Averages are weighted by the replies behind each rollup row
+    let rows = sqlx::query_as::<_, TimeSeriesDatapoint>(&format!(
+        r#"SELECT
+            {bucket} - (EXTRACT(EPOCH FROM {bucket})::bigint % $5) * interval '1 second'
+                AS "timestamp",
+            (SUM(rtt_avg_us::bigint * (sample_count - loss_count))
+                / NULLIF(SUM(sample_count - loss_count) FILTER (WHERE rtt_avg_us IS NOT NULL), 0))::int
+                AS rtt_avg_us,
+            MIN(rtt_min_us) AS rtt_min_us,
+            MAX(rtt_max_us) AS rtt_max_us,
+            CASE WHEN SUM(sample_count) > 0
+                THEN SUM(loss_count)::float / SUM(sample_count)::float * 100.0
+                ELSE 0.0
+            END AS loss_pct,
+            (SUM(jitter_avg_us::bigint * (sample_count - loss_count))
+                / NULLIF(SUM(sample_count - loss_count) FILTER (WHERE jitter_avg_us IS NOT NULL), 0))::int
+                AS jitter_avg_us,
+            SUM(sample_count)::bigint AS sample_count
+        FROM {table}
+        WHERE session_id = $1
+            AND hop_id = $2
+            AND {bucket} >= $3
+            AND {bucket} < $4
+        GROUP BY 1
+        ORDER BY 1"#
+    ))
+    .bind(session_id)
+    .bind(hop_id)
+    .bind(from)
+    .bind(to)
+    .bind(resolution_seconds as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// A stored sample, as used to rebuild a hop's statistics window.
+#[derive(sqlx::FromRow)]
+pub struct RecentSample {
+    pub ttl_sent: i16,
+    pub sent_at: DateTime<Utc>,
+    pub rtt_us: Option<i32>,
+    pub is_lost: bool,
+    pub jitter_us: Option<i32>,
+}
+
+/// The last `per_hop` samples of each hop of a session sent since `since` in
+/// rounds before `before_round`, oldest first.
+pub async fn recent_for_session(
+    pool: &PgPool,
+    session_id: Uuid,
+    before_round: u64,
+    since: DateTime<Utc>,
+    per_hop: usize,
+) -> anyhow::Result<Vec<RecentSample>> {
+    let rows = sqlx::query_as::<_, RecentSample>(
+        r#"SELECT ttl_sent, sent_at, rtt_us, is_lost, jitter_us
+           FROM (
+               SELECT ttl_sent, sent_at, rtt_us, is_lost, jitter_us, round_number,
+                      ROW_NUMBER() OVER (PARTITION BY ttl_sent ORDER BY round_number DESC) AS rn
+               FROM samples
+               WHERE session_id = $1 AND round_number < $2 AND sent_at >= $3
+           ) recent
+           WHERE rn <= $4
+           ORDER BY round_number, ttl_sent"#,
+    )
+    .bind(session_id)
+    .bind(before_round as i64)
+    .bind(since)
+    .bind(per_hop as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
 pub async fn recent_loss_pct(pool: &PgPool, hop_id: Uuid, window_seconds: i32) -> f64 {
     let result = sqlx::query_scalar::<_, Option<f64>>(
         r#"SELECT CASE WHEN COUNT(*) > 0
@@ -71,3 +262,56 @@ pub async fn recent_loss_pct(pool: &PgPool, hop_id: Uuid, window_seconds: i32) -
 
     result.ok().flatten().unwrap_or(0.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 17, 12, 34, 56).unwrap()
+    }
+
+    #[test]
+    fn choose_tier_prefers_coarsest_matching_rollup() {
+        let config = ServerConfig::default();
+        let from = now() - Duration::hours(6);
+        assert_eq!(choose_tier(from, now(), 10, &config), Tier::Raw);
+        assert_eq!(choose_tier(from, now(), 60, &config), Tier::Minute);
+        assert_eq!(choose_tier(from, now(), 300, &config), Tier::Minute);
+        assert_eq!(choose_tier(from, now(), 3600, &config), Tier::Hour);
+    }
+
+    #[test]
+    fn choose_tier_falls_back_past_raw_retention() {
+        let config = ServerConfig::default();
+        // Raw samples are gone after 7 days, minute rollups after 90
+        let from = now() - Duration::days(30);
+        assert_eq!(choose_tier(from, now(), 10, &config), Tier::Minute);
+        assert_eq!(choose_tier(from, now(), 60, &config), Tier::Minute);
+        let from = now() - Duration::days(120);
+        assert_eq!(choose_tier(from, now(), 60, &config), Tier::Hour);
+    }
+
+    #[test]
+    fn choose_tier_uses_raw_when_kept_forever() {
+        let config = ServerConfig {
+            raw_retention_days: 0,
+            minute_rollup_retention_days: 1,
+            hourly_rollup_retention_days: 1,
+            ..Default::default()
+        };
+        let from = now() - Duration::days(30);
+        assert_eq!(choose_tier(from, now(), 3600, &config), Tier::Raw);
+    }
+
+    #[test]
+    fn rollup_cutoff_leaves_unsettled_buckets_to_raw() {
+        // 12:34:56 - 300s aggregation - 60s minute = 12:28:56, aligned to 5m
+        let cutoff = rollup_cutoff(Tier::Minute, now(), 300, 300);
+        assert_eq!(cutoff, Utc.with_ymd_and_hms(2026, 10, 17, 12, 25, 0).unwrap());
+        // Resolution finer than the tier aligns to the tier's buckets
+        let cutoff = rollup_cutoff(Tier::Hour, now(), 60, 300);
+        assert_eq!(cutoff, Utc.with_ymd_and_hms(2026, 10, 17, 11, 0, 0).unwrap());
+    }
+}
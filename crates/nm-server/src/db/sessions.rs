@@ -3,10 +3,30 @@ use uuid::Uuid;
 
 use nm_common::models::TraceSession;
 
+/// Values of `trace_sessions.state`.
+pub mod state {
+    /// Open and collecting.
+    pub const ACTIVE: &str = "active";
+    /// Open, but its agent has been told to stop probing the target.
+    pub const PAUSED: &str = "paused";
+    /// Ended; kept for reporting.
+    pub const ARCHIVED: &str = "archived";
+    /// Ended; purged with its samples by the session reaper.
+    pub const WILL_DELETE: &str = "will_delete";
+
+    pub fn is_open(state: &str) -> bool {
+        state == ACTIVE || state == PAUSED
+    }
+}
+
+/// Samples deleted per statement when purging a session.
+const PURGE_BATCH_ROWS: i64 = 10_000;
+
 pub async fn list_for_target(pool: &PgPool, target_id: Uuid) -> anyhow::Result<Vec<TraceSession>> {
     let sessions = sqlx::query_as::<_, TraceSession>(
-        r#"SELECT id, target_id, started_at, ended_at, sample_count
-           FROM trace_sessions WHERE target_id = $1 ORDER BY started_at DESC"#,
+        r#"SELECT id, target_id, started_at, ended_at, sample_count, state
+           FROM trace_sessions WHERE target_id = $1 AND state <> 'will_delete'
+           ORDER BY started_at DESC"#,
     )
     .bind(target_id)
     .fetch_all(pool)
@@ -16,7 +36,7 @@ pub async fn list_for_target(pool: &PgPool, target_id: Uuid) -> anyhow::Result<V
 
 pub async fn get_by_id(pool: &PgPool, id: Uuid) -> anyhow::Result<Option<TraceSession>> {
     let session = sqlx::query_as::<_, TraceSession>(
-        r#"SELECT id, target_id, started_at, ended_at, sample_count
+        r#"SELECT id, target_id, started_at, ended_at, sample_count, state
            FROM trace_sessions WHERE id = $1"#,
     )
     .bind(id)
@@ -25,22 +45,161 @@ pub async fn get_by_id(pool: &PgPool, id: Uuid) -> anyhow::Result<Option<TraceSe
     Ok(session)
 }
 
-pub async fn create(pool: &PgPool, target_id: Uuid) -> anyhow::Result<TraceSession> {
+/// The target's open (active or paused) session, if it has one.
+pub async fn open_for_target(pool: &PgPool, target_id: Uuid) -> anyhow::Result<Option<TraceSession>> {
     let session = sqlx::query_as::<_, TraceSession>(
+        r#"SELECT id, target_id, started_at, ended_at, sample_count, state
+           FROM trace_sessions WHERE target_id = $1 AND state IN ('active', 'paused')"#,
+    )
+    .bind(target_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(session)
+}
+
+/// The target's open session, starting a new active one if it has none.
+pub async fn open_or_create(pool: &PgPool, target_id: Uuid) -> anyhow::Result<TraceSession> {
+    let created = sqlx::query_as::<_, TraceSession>(
         r#"INSERT INTO trace_sessions (target_id)
            VALUES ($1)
-           RETURNING id, target_id, started_at, ended_at, sample_count"#,
+           ON CONFLICT (target_id) WHERE state IN ('active', 'paused') DO NOTHING
+           RETURNING id, target_id, started_at, ended_at, sample_count, state"#,
     )
     .bind(target_id)
-    .fetch_one(pool)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(session) = created {
+        return Ok(session);
+    }
+    open_for_target(pool, target_id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("open session of target {target_id} was closed concurrently"))
+}
+
+/// Switch the target's open session between active and paused.
+pub async fn set_open_state(
+    pool: &PgPool,
+    target_id: Uuid,
+    new_state: &str,
+) -> anyhow::Result<Option<TraceSession>> {
+    let session = sqlx::query_as::<_, TraceSession>(
+        r#"UPDATE trace_sessions SET state = $2
+           WHERE target_id = $1 AND state IN ('active', 'paused')
+           RETURNING id, target_id, started_at, ended_at, sample_count, state"#,
+    )
+    .bind(target_id)
+    .bind(new_state)
+    .fetch_optional(pool)
     .await?;
     Ok(session)
 }
 
-pub async fn end_session(pool: &PgPool, id: Uuid) -> anyhow::Result<()> {
-    sqlx::query("UPDATE trace_sessions SET ended_at = NOW() WHERE id = $1")
+/// End a session as `archived` or `will_delete`. Closing the target's open
+/// session starts its successor in the same state, so a paused target stays
+/// paused. Returns the closed session and the successor, if one was started.
+/// A session marked for deletion is never brought back to archived.
+pub async fn close(
+    pool: &PgPool,
+    id: Uuid,
+    new_state: &str,
+) -> anyhow::Result<Option<(TraceSession, Option<TraceSession>)>> {
+    let mut tx = pool.begin().await?;
+
+    let current = sqlx::query_as::<_, TraceSession>(
+        r#"SELECT id, target_id, started_at, ended_at, sample_count, state
+           FROM trace_sessions WHERE id = $1 FOR UPDATE"#,
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(current) = current else {
+        return Ok(None);
+    };
+    if current.state == state::WILL_DELETE || current.state == new_state {
+        return Ok(Some((current, None)));
+    }
+
+    let closed = sqlx::query_as::<_, TraceSession>(
+        r#"UPDATE trace_sessions SET state = $2, ended_at = COALESCE(ended_at, NOW())
+           WHERE id = $1
+           RETURNING id, target_id, started_at, ended_at, sample_count, state"#,
+    )
+    .bind(id)
+    .bind(new_state)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let successor = if state::is_open(&current.state) {
+        let session = sqlx::query_as::<_, TraceSession>(
+            r#"INSERT INTO trace_sessions (target_id, state)
+               VALUES ($1, $2)
+               RETURNING id, target_id, started_at, ended_at, sample_count, state"#,
+        )
+        .bind(current.target_id)
+        .bind(&current.state)
+        .fetch_one(&mut *tx)
+        .await?;
+        Some(session)
+    } else {
+        None
+    };
+
+    tx.commit().await?;
+    Ok(Some((closed, successor)))
+}
+
+/// Round number an agent continues a session from: one past the latest
+/// stored round. Samples are consulted as well as `ingested_rounds`, which
+/// is empty for sessions recorded before that table existed.
+pub async fn next_round(pool: &PgPool, id: Uuid) -> anyhow::Result<u64> {
+    let next = sqlx::query_scalar::<_, i64>(
+        r#"SELECT GREATEST(
+                      (SELECT COALESCE(MAX(round_number), 0) FROM ingested_rounds WHERE session_id = $1),
+                      (SELECT COALESCE(MAX(round_number), 0) FROM samples WHERE session_id = $1)
+                  ) + 1"#,
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await?;
+    Ok(next as u64)
+}
+
+pub async fn list_marked_for_deletion(pool: &PgPool) -> anyhow::Result<Vec<Uuid>> {
+    let ids = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM trace_sessions WHERE state = 'will_delete' ORDER BY ended_at",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(ids)
+}
+
+/// Delete a session and everything recorded in it. Samples go first, in
+/// batches, so no single statement holds locks on a large session for long.
+/// Returns the number of samples deleted.
+pub async fn purge(pool: &PgPool, id: Uuid) -> anyhow::Result<u64> {
+    let mut deleted = 0;
+    loop {
+        let rows = sqlx::query(
+            r#"DELETE FROM samples
+               WHERE (id, sent_at) IN (
+                   SELECT id, sent_at FROM samples WHERE session_id = $1 LIMIT $2
+               )"#,
+        )
+        .bind(id)
+        .bind(PURGE_BATCH_ROWS)
+        .execute(pool)
+        .await?
+        .rows_affected();
+        deleted += rows;
+        if rows == 0 {
+            break;
+        }
+    }
+
+    // Hops, rollups and the rest cascade from the session
+    sqlx::query("DELETE FROM trace_sessions WHERE id = $1 AND state = 'will_delete'")
         .bind(id)
         .execute(pool)
         .await?;
-    Ok(())
+    Ok(deleted)
 }
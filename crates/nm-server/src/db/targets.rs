@@ -7,7 +7,9 @@ pub async fn list_for_agent(pool: &PgPool, agent_id: Uuid) -> anyhow::Result<Vec
     let targets = sqlx::query_as::<_, Target>(
         r#"SELECT id, agent_id, address, resolved_ip, display_name,
                   probe_method, probe_port, packet_size, interval_ms,
-                  max_hops, is_active, created_at, updated_at
+                  max_hops, address_family, flow_stable, multipath_discovery, pmtu_discovery,
+                  config_id, is_active, config_applied_at,
+                  created_at, updated_at
            FROM targets WHERE agent_id = $1 ORDER BY created_at"#,
     )
     .bind(agent_id)
@@ -20,7 +22,9 @@ pub async fn get_by_id(pool: &PgPool, id: Uuid) -> anyhow::Result<Option<Target>
     let target = sqlx::query_as::<_, Target>(
         r#"SELECT id, agent_id, address, resolved_ip, display_name,
                   probe_method, probe_port, packet_size, interval_ms,
-                  max_hops, is_active, created_at, updated_at
+                  max_hops, address_family, flow_stable, multipath_discovery, pmtu_discovery,
+                  config_id, is_active, config_applied_at,
+                  created_at, updated_at
            FROM targets WHERE id = $1"#,
     )
     .bind(id)
@@ -32,11 +36,14 @@ pub async fn get_by_id(pool: &PgPool, id: Uuid) -> anyhow::Result<Option<Target>
 pub async fn create(pool: &PgPool, agent_id: Uuid, input: &CreateTarget) -> anyhow::Result<Target> {
     let target = sqlx::query_as::<_, Target>(
         r#"INSERT INTO targets (agent_id, address, display_name, probe_method, probe_port,
-                                packet_size, interval_ms, max_hops)
-           VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                                packet_size, interval_ms, max_hops, address_family,
+                                flow_stable, multipath_discovery, pmtu_discovery)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
            RETURNING id, agent_id, address, resolved_ip, display_name,
                      probe_method, probe_port, packet_size, interval_ms,
-                     max_hops, is_active, created_at, updated_at"#,
+                     max_hops, address_family, flow_stable, multipath_discovery, pmtu_discovery,
+                  config_id, is_active, config_applied_at,
+                  created_at, updated_at"#,
     )
     .bind(agent_id)
     .bind(&input.address)
@@ -46,6 +53,10 @@ pub async fn create(pool: &PgPool, agent_id: Uuid, input: &CreateTarget) -> anyh
     .bind(input.packet_size)
     .bind(input.interval_ms)
     .bind(input.max_hops)
+    .bind(&input.address_family)
+    .bind(input.flow_stable)
+    .bind(input.multipath_discovery)
+    .bind(input.pmtu_discovery)
     .fetch_one(pool)
     .await?;
     Ok(target)
@@ -62,11 +73,17 @@ pub async fn update(pool: &PgPool, id: Uuid, input: &UpdateTarget) -> anyhow::Re
             interval_ms = COALESCE($7, interval_ms),
             max_hops = COALESCE($8, max_hops),
             is_active = COALESCE($9, is_active),
+            address_family = COALESCE($10, address_family),
+            flow_stable = COALESCE($11, flow_stable),
+            multipath_discovery = COALESCE($12, multipath_discovery),
+            pmtu_discovery = COALESCE($13, pmtu_discovery),
             updated_at = NOW()
            WHERE id = $1
            RETURNING id, agent_id, address, resolved_ip, display_name,
                      probe_method, probe_port, packet_size, interval_ms,
-                     max_hops, is_active, created_at, updated_at"#,
+                     max_hops, address_family, flow_stable, multipath_discovery, pmtu_discovery,
+                  config_id, is_active, config_applied_at,
+                  created_at, updated_at"#,
     )
     .bind(id)
     .bind(&input.address)
@@ -77,6 +94,10 @@ pub async fn update(pool: &PgPool, id: Uuid, input: &UpdateTarget) -> anyhow::Re
     .bind(input.interval_ms)
     .bind(input.max_hops)
     .bind(input.is_active)
+    .bind(&input.address_family)
+    .bind(input.flow_stable)
+    .bind(input.multipath_discovery)
+    .bind(input.pmtu_discovery)
     .fetch_optional(pool)
     .await?;
     Ok(target)
@@ -89,3 +110,12 @@ pub async fn delete(pool: &PgPool, id: Uuid) -> anyhow::Result<()> {
         .await?;
     Ok(())
 }
+
+/// Record that the agent applied a live config update for this target.
+pub async fn mark_config_applied(pool: &PgPool, id: Uuid) -> anyhow::Result<()> {
+    sqlx::query("UPDATE targets SET config_applied_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -6,7 +6,7 @@ use nm_common::models::{CreateTraceProfile, TraceProfile, UpdateTraceProfile};
 pub async fn list_all(pool: &PgPool) -> anyhow::Result<Vec<TraceProfile>> {
     let profiles = sqlx::query_as::<_, TraceProfile>(
         r#"SELECT id, name, description, probe_method, probe_port,
-                  packet_size, interval_ms, max_hops, created_at, updated_at
+                  packet_size, interval_ms, max_hops, flow_stable, created_at, updated_at
            FROM trace_profiles ORDER BY name"#,
     )
     .fetch_all(pool)
@@ -17,7 +17,7 @@ pub async fn list_all(pool: &PgPool) -> anyhow::Result<Vec<TraceProfile>> {
 pub async fn get_by_id(pool: &PgPool, id: Uuid) -> anyhow::Result<Option<TraceProfile>> {
     let profile = sqlx::query_as::<_, TraceProfile>(
         r#"SELECT id, name, description, probe_method, probe_port,
-                  packet_size, interval_ms, max_hops, created_at, updated_at
+                  packet_size, interval_ms, max_hops, flow_stable, created_at, updated_at
            FROM trace_profiles WHERE id = $1"#,
     )
     .bind(id)
@@ -29,10 +29,10 @@ pub async fn get_by_id(pool: &PgPool, id: Uuid) -> anyhow::Result<Option<TracePr
 pub async fn create(pool: &PgPool, input: &CreateTraceProfile) -> anyhow::Result<TraceProfile> {
     let profile = sqlx::query_as::<_, TraceProfile>(
         r#"INSERT INTO trace_profiles (name, description, probe_method, probe_port,
-                                        packet_size, interval_ms, max_hops)
-           VALUES ($1, $2, $3, $4, $5, $6, $7)
+                                        packet_size, interval_ms, max_hops, flow_stable)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
            RETURNING id, name, description, probe_method, probe_port,
-                     packet_size, interval_ms, max_hops, created_at, updated_at"#,
+                     packet_size, interval_ms, max_hops, flow_stable, created_at, updated_at"#,
     )
     .bind(&input.name)
     .bind(&input.description)
@@ -41,6 +41,7 @@ pub async fn create(pool: &PgPool, input: &CreateTraceProfile) -> anyhow::Result
     .bind(input.packet_size)
     .bind(input.interval_ms)
     .bind(input.max_hops)
+    .bind(input.flow_stable)
     .fetch_one(pool)
     .await?;
     Ok(profile)
@@ -60,10 +61,11 @@ pub async fn update(
             packet_size = COALESCE($6, packet_size),
             interval_ms = COALESCE($7, interval_ms),
             max_hops = COALESCE($8, max_hops),
+            flow_stable = COALESCE($9, flow_stable),
             updated_at = NOW()
            WHERE id = $1
            RETURNING id, name, description, probe_method, probe_port,
-                     packet_size, interval_ms, max_hops, created_at, updated_at"#,
+                     packet_size, interval_ms, max_hops, flow_stable, created_at, updated_at"#,
     )
     .bind(id)
     .bind(&input.name)
@@ -73,6 +75,7 @@ pub async fn update(
     .bind(input.packet_size)
     .bind(input.interval_ms)
     .bind(input.max_hops)
+    .bind(input.flow_stable)
     .fetch_optional(pool)
     .await?;
     Ok(profile)
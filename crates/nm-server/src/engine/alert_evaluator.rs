@@ -3,8 +3,8 @@ use uuid::Uuid;
 
 use crate::state::AppState;
 
-/// Evaluate all enabled alert rules against the current round's running stats.
-/// Called inline after ingestion updates the running hop stats.
+/// Evaluate all enabled alert rules against the statistics of each hop's
+/// sliding window. Called inline after ingestion updates the windows.
 pub async fn evaluate_for_round(
     report: &TraceRoundReport,
     state: &AppState,
@@ -42,23 +42,28 @@ pub async fn evaluate_for_round(
         } else {
             report.hops.iter().map(|h| h.hop_number).collect()
         };
+        // The rule's window, within the hop window kept in memory
+        let window = (rule.window_seconds > 0).then(|| chrono::Duration::seconds(rule.window_seconds as i64));
 
         for &hop_number in &hops_to_check {
-            let key = (session_id, hop_number);
-            let Some(stats) = state.hop_stats.get(&key) else {
+            let Some(stats) = state
+                .hop_stats
+                .get(&session_id)
+                .and_then(|session| session.hops.get(&hop_number).map(|w| w.stats_within(window)))
+            else {
                 continue;
             };
+            if stats.sample_count == 0 {
+                continue;
+            }
 
-            // Compute the metric value from running stats
+            // Compute the metric value from the hop's window
             let metric_value = match rule.metric.as_str() {
-                "avg_rtt" => stats.avg_rtt_us() as f64 / 1000.0, // convert to ms
+                "avg_rtt" => stats.avg_rtt_us as f64 / 1000.0, // convert to ms
                 "max_rtt" => stats.max_rtt_us as f64 / 1000.0,
-                "min_rtt" => {
-                    if stats.min_rtt_us == u32::MAX { 0.0 }
-                    else { stats.min_rtt_us as f64 / 1000.0 }
-                }
-                "loss_pct" => stats.loss_pct(),
-                "jitter" => stats.avg_jitter_us() as f64 / 1000.0,
+                "min_rtt" => stats.min_rtt_us as f64 / 1000.0,
+                "loss_pct" => stats.loss_pct,
+                "jitter" => stats.jitter_avg_us as f64 / 1000.0,
                 _ => continue, // Unknown metric, skip
             };
 
@@ -155,7 +160,6 @@ struct RuleRow {
     metric: String,
     comparator: String,
     threshold: f64,
-    #[allow(dead_code)]
     window_seconds: i32,
     cooldown_seconds: i32,
     #[allow(dead_code)]
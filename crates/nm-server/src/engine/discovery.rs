@@ -0,0 +1,55 @@
+use std::net::IpAddr;
+
+use chrono::Utc;
+use nm_common::protocol::NetworkDiscoveryReport;
+use uuid::Uuid;
+
+use crate::state::AppState;
+
+/// Width of the `discovered_devices.hostname` column.
+const MAX_HOSTNAME_LEN: usize = 255;
+
+/// Store the devices an agent found, with vendors from the server's OUI
+/// lists, and mark the scan finished.
+pub async fn handle_discovery_report(agent_id: Uuid, report: NetworkDiscoveryReport, state: &AppState) {
+    let devices: Vec<_> = report
+        .devices
+        .into_iter()
+        .filter_map(|mut host| {
+            // Stored addresses are sorted as inet; keep out anything else
+            let ip: IpAddr = host.ip_address.parse().ok()?;
+            host.ip_address = ip.to_string();
+            host.mac_address = host.mac_address.filter(|mac| mac.len() == 17);
+            host.hostname = host.hostname.map(|name| name.chars().take(MAX_HOSTNAME_LEN).collect());
+            let vendor = host.mac_address.as_deref().and_then(|mac| state.ip_intel.vendor(mac));
+            Some((host, vendor))
+        })
+        .collect();
+
+    match &report.error {
+        Some(error) => tracing::warn!(agent_id = %agent_id, scan_id = %report.scan_id, %error, "Discovery scan failed"),
+        None => tracing::info!(
+            agent_id = %agent_id,
+            scan_id = %report.scan_id,
+            devices = devices.len(),
+            "Discovery scan finished"
+        ),
+    }
+
+    let mut error = report.error;
+    if let Err(e) = crate::db::devices::upsert(&state.pool, agent_id, &devices).await {
+        tracing::error!(agent_id = %agent_id, "Failed to store discovered devices: {e}");
+        error.get_or_insert_with(|| "failed to store discovered devices".to_string());
+    }
+
+    if let Some(mut scan) = state
+        .discovery_scans
+        .get_mut(&report.scan_id)
+        .filter(|scan| scan.agent_id == agent_id)
+    {
+        scan.finished_at = Some(Utc::now());
+        scan.subnets = report.subnets;
+        scan.device_count = Some(devices.len() as u32);
+        scan.error = error;
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use nm_common::config::ServerConfig;
+use nm_common::protocol::{HopRunningStats, TraceRoundReport};
+
+use crate::state::AppState;
+
+/// How often idle sessions are looked for.
+const EVICT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How far back a hop's window reaches: its last `max_samples` samples, no
+/// older than `max_age` before the newest one.
+#[derive(Debug, Clone, Copy)]
+pub struct WindowLimits {
+    pub max_samples: usize,
+    pub max_age: chrono::Duration,
+}
+
+impl WindowLimits {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            max_samples: config.hop_window_samples.max(1),
+            max_age: chrono::Duration::seconds(config.hop_window_secs.max(1) as i64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WindowSample {
+    pub sent_at: DateTime<Utc>,
+    pub rtt_us: Option<u32>,
+    pub is_lost: bool,
+    pub jitter_us: Option<u32>,
+}
+
+/// Recent samples of one hop in a ring buffer bounded by `WindowLimits`.
+#[derive(Debug, Clone, Default)]
+pub struct HopWindow {
+    samples: VecDeque<WindowSample>,
+    /// The hop's latest reply, even once it has left the window, for jitter.
+    pub last_rtt_us: Option<u32>,
+}
+
+impl HopWindow {
+    pub fn push(&mut self, sample: WindowSample, limits: &WindowLimits) {
+        if sample.rtt_us.is_some() {
+            self.last_rtt_us = sample.rtt_us;
+        }
+        self.samples.push_back(sample);
+
+        let oldest = sample.sent_at - limits.max_age;
+        while self.samples.len() > limits.max_samples
+            || self.samples.front().is_some_and(|s| s.sent_at < oldest)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Statistics over the whole window.
+    pub fn stats(&self) -> HopRunningStats {
+        self.stats_within(None)
+    }
+
+    /// Statistics over the part of the window no older than `max_age` before
+    /// its newest sample.
+    pub fn stats_within(&self, max_age: Option<chrono::Duration>) -> HopRunningStats {
+        let oldest = match (self.samples.back(), max_age) {
+            (Some(newest), Some(age)) => Some(newest.sent_at - age),
+            _ => None,
+        };
+
+        let mut min_rtt_us = u32::MAX;
+        let mut max_rtt_us = 0;
+        let (mut rtt_sum, mut rtt_count) = (0u64, 0u64);
+        let (mut jitter_sum, mut jitter_count) = (0u64, 0u64);
+        let (mut loss_count, mut sample_count) = (0u64, 0u64);
+        for sample in self.samples.iter().filter(|s| oldest.is_none_or(|t| s.sent_at >= t)) {
+            sample_count += 1;
+            if sample.is_lost {
+                loss_count += 1;
+            }
+            if let Some(rtt) = sample.rtt_us {
+                min_rtt_us = min_rtt_us.min(rtt);
+                max_rtt_us = max_rtt_us.max(rtt);
+                rtt_sum += rtt as u64;
+                rtt_count += 1;
+            }
+            if let Some(jitter) = sample.jitter_us {
+                jitter_sum += jitter as u64;
+                jitter_count += 1;
+            }
+        }
+
+        HopRunningStats {
+            min_rtt_us: if rtt_count > 0 { min_rtt_us } else { 0 },
+            avg_rtt_us: rtt_sum.checked_div(rtt_count).unwrap_or(0) as u32,
+            max_rtt_us,
+            loss_pct: if sample_count > 0 {
+                loss_count as f64 / sample_count as f64 * 100.0
+            } else {
+                0.0
+            },
+            jitter_avg_us: jitter_sum.checked_div(jitter_count).unwrap_or(0) as u32,
+            sample_count,
+        }
+    }
+}
+
+/// Windows of every hop of one session.
+pub struct SessionWindows {
+    pub hops: HashMap<u8, HopWindow>,
+    /// When the session last had a live round, for eviction.
+    pub last_seen: Instant,
+}
+
+impl SessionWindows {
+    pub fn new() -> Self {
+        Self { hops: HashMap::new(), last_seen: Instant::now() }
+    }
+}
+
+/// Make sure the session of `report` has windows, rebuilding them from the
+/// samples stored before this round when the server restarted or evicted
+/// the session since its last round.
+pub async fn ensure_session(state: &AppState, report: &TraceRoundReport) {
+    if state.hop_stats.contains_key(&report.session_id) {
+        return;
+    }
+
+    let limits = WindowLimits::from_config(&state.config);
+    let mut windows = SessionWindows::new();
+    match crate::db::samples::recent_for_session(
+        &state.pool,
+        report.session_id,
+        report.round_number,
+        report.sent_at - limits.max_age,
+        limits.max_samples,
+    )
+    .await
+    {
+        Ok(rows) => {
+            for row in rows {
+                let sample = WindowSample {
+                    sent_at: row.sent_at,
+                    rtt_us: row.rtt_us.map(|v| v as u32),
+                    is_lost: row.is_lost,
+                    jitter_us: row.jitter_us.map(|v| v as u32),
+                };
+                windows.hops.entry(row.ttl_sent as u8).or_default().push(sample, &limits);
+            }
+        }
+        Err(e) => {
+            tracing::warn!(session_id = %report.session_id, error = %e, "Failed to rebuild hop windows");
+        }
+    }
+    state.hop_stats.entry(report.session_id).or_insert(windows);
+}
+
+/// Background task that drops the in-memory state of sessions without a live
+/// round for `hop_stats_idle_secs`. It is rebuilt from the database if the
+/// session comes back.
+pub async fn run_evictor(state: AppState) {
+    let idle = Duration::from_secs(state.config.hop_stats_idle_secs);
+    let mut interval = tokio::time::interval(EVICT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        let before = state.hop_stats.len();
+        state.hop_stats.retain(|_, session| session.last_seen.elapsed() < idle);
+        state.route_cache.retain(|id, _| state.hop_stats.contains_key(id));
+        state.ecmp_sets.retain(|id, _| state.hop_stats.contains_key(id));
+
+        let evicted = before.saturating_sub(state.hop_stats.len());
+        if evicted > 0 {
+            tracing::debug!(evicted, "Evicted idle sessions from hop stats");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sample(secs: i64, rtt_us: Option<u32>) -> WindowSample {
+        WindowSample {
+            sent_at: Utc.with_ymd_and_hms(2026, 10, 17, 12, 0, 0).unwrap() + chrono::Duration::seconds(secs),
+            rtt_us,
+            is_lost: rtt_us.is_none(),
+            jitter_us: rtt_us.map(|_| 100),
+        }
+    }
+
+    #[test]
+    fn window_keeps_the_last_samples() {
+        let limits = WindowLimits { max_samples: 3, max_age: chrono::Duration::seconds(600) };
+        let mut window = HopWindow::default();
+        for (i, rtt) in [9000, 1000, 2000, 3000].into_iter().enumerate() {
+            window.push(sample(i as i64, Some(rtt)), &limits);
+        }
+        let stats = window.stats();
+        assert_eq!(stats.sample_count, 3);
+        assert_eq!(stats.min_rtt_us, 1000);
+        assert_eq!(stats.max_rtt_us, 3000);
+        assert_eq!(stats.avg_rtt_us, 2000);
+        assert_eq!(stats.jitter_avg_us, 100);
+    }
+
+    #[test]
+    fn window_drops_samples_older_than_max_age() {
+        let limits = WindowLimits { max_samples: 100, max_age: chrono::Duration::seconds(60) };
+        let mut window = HopWindow::default();
+        window.push(sample(0, None), &limits);
+        window.push(sample(30, Some(1000)), &limits);
+        assert_eq!(window.stats().loss_pct, 50.0);
+
+        // The loss at t=0 is more than 60 s older than the newest sample
+        window.push(sample(61, Some(3000)), &limits);
+        let stats = window.stats();
+        assert_eq!(stats.sample_count, 2);
+        assert_eq!(stats.loss_pct, 0.0);
+        assert_eq!(stats.avg_rtt_us, 2000);
+    }
+
+    #[test]
+    fn stats_within_narrows_the_window() {
+        let limits = WindowLimits { max_samples: 100, max_age: chrono::Duration::seconds(600) };
+        let mut window = HopWindow::default();
+        window.push(sample(0, None), &limits);
+        window.push(sample(100, Some(1000)), &limits);
+        window.push(sample(110, Some(2000)), &limits);
+
+        assert_eq!(window.stats().sample_count, 3);
+        let recent = window.stats_within(Some(chrono::Duration::seconds(30)));
+        assert_eq!(recent.sample_count, 2);
+        assert_eq!(recent.loss_pct, 0.0);
+        assert_eq!(recent.min_rtt_us, 1000);
+    }
+
+    #[test]
+    fn lost_samples_keep_the_last_reply_for_jitter() {
+        let limits = WindowLimits { max_samples: 1, max_age: chrono::Duration::seconds(60) };
+        let mut window = HopWindow::default();
+        window.push(sample(0, Some(1500)), &limits);
+        window.push(sample(1, None), &limits);
+        assert_eq!(window.last_rtt_us, Some(1500));
+        let stats = window.stats();
+        assert_eq!(stats.min_rtt_us, 0);
+        assert_eq!(stats.loss_pct, 100.0);
+    }
+}
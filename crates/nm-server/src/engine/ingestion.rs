@@ -1,73 +1,93 @@
-use nm_common::protocol::{HopRunningStats, LiveHopData, LiveTraceUpdate, TraceRoundReport};
+use std::sync::Arc;
+use std::time::Instant;
+
+use nm_common::protocol::{HopSample, LiveHopData, LiveTraceUpdate, TraceRoundReport};
 use uuid::Uuid;
 
-use crate::state::{AppState, RunningHopStats};
+use crate::engine::hop_window::{self, SessionWindows, WindowLimits, WindowSample};
+use crate::engine::sample_writer::RoundWrite;
+use crate::state::AppState;
 
 /// Ingest a complete trace round from an agent.
-/// This is the hottest code path in the server.
+/// This is the hottest code path in the server: the round is queued for the
+/// sample writer and everything else here is in memory, except route
+/// change and alert bookkeeping.
 pub async fn ingest_trace_round(report: TraceRoundReport, agent_id: Uuid, state: &AppState) {
-    let session_id = report.session_id;
-    let target_id = report.target_id;
+    let report = Arc::new(report);
+    state.sample_writer.submit(report.clone()).await;
+    process(&report, agent_id, state, true).await;
+}
 
-    // Upsert hops and collect hop_ids
-    let mut hop_ids: Vec<(u8, Uuid)> = Vec::with_capacity(report.hops.len());
-    for hop in &report.hops {
-        let hop_id = upsert_hop(&state.pool, session_id, hop).await;
-        if let Some(id) = hop_id {
-            hop_ids.push((hop.hop_number, id));
+/// Ingest a round the agent spooled while it was disconnected. It is stored
+/// and feeds route detection like any other round, but is too old to be
+/// pushed to live views, counted in the hop windows or evaluated against
+/// alert rules.
+///
+/// Waits until the round is committed; returns false if it could not be stored.
+pub async fn ingest_replayed_round(report: TraceRoundReport, agent_id: Uuid, state: &AppState) -> bool {
+    let report = Arc::new(report);
+    match state.sample_writer.write(report.clone()).await {
+        RoundWrite::Stored => {
+            process(&report, agent_id, state, false).await;
+            true
         }
+        // A round the agent sent twice (a replayed batch whose ack was lost) stops here
+        RoundWrite::Duplicate => true,
+        RoundWrite::Failed => false,
     }
+}
 
-    // Batch insert samples using a single query
-    batch_insert_samples(&state.pool, &report, &hop_ids).await;
+async fn process(report: &TraceRoundReport, agent_id: Uuid, state: &AppState, live: bool) {
+    let session_id = report.session_id;
+    let target_id = report.target_id;
 
-    // Update session sample count
-    let _ = sqlx::query(
-        "UPDATE trace_sessions SET sample_count = sample_count + $1 WHERE id = $2",
-    )
-    .bind(report.hops.len() as i64)
-    .bind(session_id)
-    .execute(&state.pool)
-    .await;
+    // Inline route change detection from probe data
+    detect_route_change_from_round(report, session_id, state).await;
 
-    // Update running stats and build live update
-    let live_hops: Vec<LiveHopData> = report
-        .hops
-        .iter()
-        .map(|hop| {
-            let key = (session_id, hop.hop_number);
-            let mut entry = state.hop_stats.entry(key).or_insert_with(RunningHopStats::new);
-
-            // Compute jitter before updating stats
-            let jitter_us = if let (Some(rtt), Some(prev)) = (hop.rtt_us, entry.last_rtt_us) {
-                Some((rtt as i64 - prev as i64).unsigned_abs() as u32)
-            } else {
-                None
-            };
-
-            entry.update(hop.rtt_us, hop.is_lost);
-
-            LiveHopData {
-                hop_number: hop.hop_number,
-                ip_address: hop.ip_address.clone(),
-                hostname: None,
-                rtt_us: hop.rtt_us,
-                is_lost: hop.is_lost,
-                jitter_us,
-                stats: HopRunningStats {
-                    min_rtt_us: if entry.min_rtt_us == u32::MAX { 0 } else { entry.min_rtt_us },
-                    avg_rtt_us: entry.avg_rtt_us(),
-                    max_rtt_us: entry.max_rtt_us,
-                    loss_pct: entry.loss_pct(),
-                    jitter_avg_us: entry.avg_jitter_us(),
-                    sample_count: entry.total_count,
-                },
-            }
-        })
-        .collect();
+    if !live {
+        return;
+    }
 
-    // Inline route change detection from probe data
-    detect_route_change_from_round(&report, session_id, state).await;
+    // Update the hop windows and build the live update
+    hop_window::ensure_session(state, report).await;
+    let limits = WindowLimits::from_config(&state.config);
+    let live_hops: Vec<LiveHopData> = {
+        let mut session = state.hop_stats.entry(session_id).or_insert_with(SessionWindows::new);
+        session.last_seen = Instant::now();
+
+        report
+            .hops
+            .iter()
+            .map(|hop| {
+                let window = session.hops.entry(hop.hop_number).or_default();
+
+                // Agents report jitter themselves; compute it for those that don't
+                let jitter_us = hop.jitter_us.or_else(|| match (hop.rtt_us, window.last_rtt_us) {
+                    (Some(rtt), Some(prev)) => Some((rtt as i64 - prev as i64).unsigned_abs() as u32),
+                    _ => None,
+                });
+
+                let sample = WindowSample {
+                    sent_at: report.sent_at,
+                    rtt_us: hop.rtt_us,
+                    is_lost: hop.is_lost,
+                    jitter_us,
+                };
+                window.push(sample, &limits);
+
+                LiveHopData {
+                    hop_number: hop.hop_number,
+                    ip_address: hop.ip_address.clone(),
+                    hostname: hop_hostname(state, session_id, hop),
+                    rtt_us: hop.rtt_us,
+                    is_lost: hop.is_lost,
+                    jitter_us,
+                    stats: window.stats(),
+                    tcp_reply: hop.tcp_reply,
+                }
+            })
+            .collect()
+    };
 
     // Broadcast live update to frontend subscribers
     let live_update = LiveTraceUpdate {
@@ -81,8 +101,8 @@ pub async fn ingest_trace_round(report: TraceRoundReport, agent_id: Uuid, state:
 
     let _ = state.live_tx.send(live_update);
 
-    // Evaluate alert rules against updated running stats
-    crate::engine::alert_evaluator::evaluate_for_round(&report, state).await;
+    // Evaluate alert rules against the updated hop windows
+    crate::engine::alert_evaluator::evaluate_for_round(report, state).await;
 }
 
 /// Detect route changes by comparing hop IPs from the current round against cached route.
@@ -98,12 +118,28 @@ async fn detect_route_change_from_round(
         .map(|h| h.ip_address.clone())
         .collect();
 
-    // Check against cached route
-    let route_changed = if let Some(cached) = state.route_cache.get(&session_id) {
-        *cached != current_route
-    } else {
+    // Check against cached route; moving between paths of a known ECMP set is not a change.
+    // After a restart or eviction the cache is refilled from the latest snapshot
+    let mut cached = state.route_cache.get(&session_id).map(|r| r.clone());
+    if cached.is_none() {
+        cached = crate::engine::route_detector::latest_route(session_id, state).await;
+        if let Some(route) = &cached {
+            state.route_cache.insert(session_id, route.clone());
+        }
+    }
+    let route_changed = match cached {
+        Some(cached) => {
+            cached != current_route
+                && !crate::engine::route_detector::within_ecmp_set(
+                    session_id,
+                    &cached,
+                    &current_route,
+                    state,
+                )
+                .await
+        }
         // First round - store initial route, create initial snapshot
-        true
+        None => true,
     };
 
     if route_changed {
@@ -130,75 +166,15 @@ async fn detect_route_change_from_round(
     }
 }
 
-async fn upsert_hop(
-    pool: &sqlx::PgPool,
-    session_id: Uuid,
-    hop: &nm_common::protocol::HopSample,
-) -> Option<Uuid> {
-    let ip_str = hop.ip_address.as_deref();
-
-    let result = sqlx::query_scalar::<_, Uuid>(
-        r#"INSERT INTO hops (session_id, hop_number, ip_address)
-           VALUES ($1, $2, $3)
-           ON CONFLICT (session_id, hop_number, ip_address) DO UPDATE
-           SET last_seen_at = NOW()
-           RETURNING id"#,
-    )
-    .bind(session_id)
-    .bind(hop.hop_number as i16)
-    .bind(ip_str)
-    .fetch_one(pool)
-    .await;
-
-    match result {
-        Ok(id) => Some(id),
-        Err(e) => {
-            tracing::error!("Failed to upsert hop: {}", e);
-            None
-        }
-    }
-}
-
-/// Batch insert samples using a dynamically-built multi-row INSERT.
-async fn batch_insert_samples(
-    pool: &sqlx::PgPool,
-    report: &TraceRoundReport,
-    hop_ids: &[(u8, Uuid)],
-) {
-    use std::collections::HashMap;
-    let hop_map: HashMap<u8, Uuid> = hop_ids.iter().copied().collect();
-
-    // Use a transaction so all inserts are committed atomically
-    let mut tx = match pool.begin().await {
-        Ok(tx) => tx,
-        Err(e) => {
-            tracing::error!("Failed to begin transaction: {}", e);
-            return;
-        }
-    };
-
-    for hop in &report.hops {
-        let Some(&hop_id) = hop_map.get(&hop.hop_number) else {
-            continue;
-        };
-
-        let _ = sqlx::query(
-            r#"INSERT INTO samples (session_id, hop_id, round_number, sent_at, rtt_us, is_lost, probe_method, packet_size, ttl_sent, ttl_received)
-               VALUES ($1, $2, $3, $4, $5, $6, 'icmp', 64, $7, $8)"#,
-        )
-        .bind(report.session_id)
-        .bind(hop_id)
-        .bind(report.round_number as i64)
-        .bind(report.sent_at)
-        .bind(hop.rtt_us.map(|v| v as i32))
-        .bind(hop.is_lost)
-        .bind(hop.hop_number as i16)
-        .bind(hop.ttl_received.map(|v| v as i16))
-        .execute(&mut *tx)
-        .await;
-    }
-
-    if let Err(e) = tx.commit().await {
-        tracing::error!("Failed to commit sample batch: {}", e);
-    }
+/// The hop's stored hostname, or the server's cached reverse DNS name for
+/// a hop the writer has not stored yet.
+fn hop_hostname(state: &AppState, session_id: Uuid, hop: &HopSample) -> Option<String> {
+    let ip = hop.ip_address.as_deref();
+    state
+        .sample_writer
+        .hostname(session_id, hop.hop_number, ip)
+        .or_else(|| {
+            let addr = ip?.parse::<std::net::IpAddr>().ok()?;
+            state.ip_intel.cached_hostname(addr.to_canonical())
+        })
 }
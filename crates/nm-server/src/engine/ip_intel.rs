@@ -0,0 +1,528 @@
+//! Server-side IP intelligence: ASN and geolocation from `.mmdb` databases,
+//! country fallback from RIR delegation files, cached reverse DNS, and MAC
+//! vendors for discovered devices.
+//!
+//! Everything is loaded from one directory (`NM_IP_INTEL_DIR`). Files ending
+//! in `.mmdb` are MaxMind/IPinfo databases; files named `delegated-*` are RIR
+//! statistics exchange files (`registry|cc|type|start|value|date|status`).
+//! IEEE registry exports (`oui.csv`, `mam.csv`, `oui36.csv`, `oui.txt`) and
+//! Wireshark's `manuf` map MAC prefixes to vendors.
+//! Lookups never block on the network: a hostname missing from the DNS cache
+//! is resolved in the background and shows up on a later call.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use dashmap::DashMap;
+use maxminddb::{MaxMindDBError, Reader};
+use nm_common::ip_info::{is_public, IpInfo};
+use serde::Serialize;
+use tokio::sync::Semaphore;
+
+/// How long a reverse DNS answer (or its absence) is reused.
+const DNS_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// Entries kept before expired ones are pruned.
+const DNS_CACHE_CAPACITY: usize = 100_000;
+
+/// Reverse lookups in flight at once.
+const MAX_CONCURRENT_DNS: usize = 16;
+
+pub struct IpIntel {
+    dir: PathBuf,
+    sources: RwLock<Arc<Sources>>,
+    /// Reverse DNS answers by address, with when they were resolved.
+    dns_cache: Arc<DashMap<IpAddr, (Option<String>, Instant)>>,
+    dns_slots: Arc<Semaphore>,
+}
+
+#[derive(Default)]
+struct Sources {
+    mmdbs: Vec<(String, Reader<Vec<u8>>)>,
+    rir: RirTable,
+    oui: OuiTable,
+    files: Vec<SourceFile>,
+}
+
+/// A database file as reported by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceFile {
+    pub name: String,
+    /// "mmdb", "rir" or "oui"
+    pub kind: &'static str,
+    /// mmdb database type, e.g. "GeoLite2-City", the RIR registry names, or
+    /// the MAC prefix list format.
+    pub description: String,
+    pub size_bytes: u64,
+    /// Address ranges in an RIR file, MAC prefixes in an OUI file.
+    pub ranges: Option<usize>,
+}
+
+impl IpIntel {
+    /// Load whatever is in `dir`. Unreadable files are logged and skipped.
+    pub fn load(dir: PathBuf) -> Self {
+        let sources = Sources::load(&dir).unwrap_or_else(|e| {
+            tracing::warn!(dir = %dir.display(), "Cannot read IP intelligence directory: {e:#}");
+            Sources::default()
+        });
+        Self {
+            dir,
+            sources: RwLock::new(Arc::new(sources)),
+            dns_cache: Arc::new(DashMap::new()),
+            dns_slots: Arc::new(Semaphore::new(MAX_CONCURRENT_DNS)),
+        }
+    }
+
+    /// Re-read the directory and swap the new databases in.
+    pub fn reload(&self) -> anyhow::Result<Vec<SourceFile>> {
+        let sources = Sources::load(&self.dir)?;
+        let files = sources.files.clone();
+        *self.sources.write().unwrap() = Arc::new(sources);
+        Ok(files)
+    }
+
+    pub fn files(&self) -> Vec<SourceFile> {
+        self.sources().files.clone()
+    }
+
+    /// Validate `data` as a database named `name` and store it in the
+    /// directory, replacing a file of the same name. Takes effect on `reload`.
+    pub async fn store(&self, name: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            bail!("invalid file name");
+        }
+        match source_kind(name) {
+            Some("mmdb") => {
+                Reader::from_source(data.as_slice()).map_err(|e| anyhow::anyhow!("not a valid .mmdb file: {e}"))?;
+            }
+            Some("oui") => {
+                let text = std::str::from_utf8(&data).context("OUI file is not text")?;
+                if parse_oui(text).is_empty() {
+                    bail!("no MAC prefixes in OUI file");
+                }
+            }
+            Some(_) => {
+                let text = std::str::from_utf8(&data).context("RIR file is not text")?;
+                if parse_delegation(text).is_empty() {
+                    bail!("no allocated address ranges in RIR file");
+                }
+            }
+            None => bail!("expected a .mmdb file, an RIR delegated-* file or an OUI list"),
+        }
+
+        // Write beside the target and rename, so a reload never sees half a file.
+        let path = self.dir.join(name);
+        let tmp = self.dir.join(format!(".{name}.upload"));
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(&tmp, &data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    /// ASN and location of a public address; empty for private ones.
+    pub fn lookup(&self, ip: IpAddr) -> IpInfo {
+        let mut info = IpInfo::default();
+        if !is_public(ip) {
+            return info;
+        }
+        let ip = ip.to_canonical();
+        let sources = self.sources();
+        for (name, reader) in &sources.mmdbs {
+            match reader.lookup::<serde_json::Value>(ip) {
+                Ok(record) => info.merge_mmdb_record(&record),
+                Err(MaxMindDBError::AddressNotFoundError(_)) => {}
+                Err(e) => tracing::debug!(file = %name, ip = %ip, error = %e, "IP database lookup failed"),
+            }
+        }
+        info.set_country(sources.rir.country(ip));
+        info
+    }
+
+    /// Cached reverse DNS name of a public address. On a miss the lookup is
+    /// started in the background and None is returned for now.
+    pub fn cached_hostname(&self, ip: IpAddr) -> Option<String> {
+        if !is_public(ip) {
+            return None;
+        }
+        let ip = ip.to_canonical();
+        let stale = match self.dns_cache.get(&ip) {
+            Some(entry) if entry.1.elapsed() < DNS_CACHE_TTL => return entry.0.clone(),
+            Some(entry) => entry.0.clone(),
+            None => None,
+        };
+
+        // Too many lookups in flight; a later call will try again.
+        let Ok(slot) = self.dns_slots.clone().try_acquire_owned() else {
+            return stale;
+        };
+        if self.dns_cache.len() >= DNS_CACHE_CAPACITY {
+            self.dns_cache.retain(|_, (_, resolved_at)| resolved_at.elapsed() < DNS_CACHE_TTL);
+        }
+        // Refresh the entry so concurrent callers do not start the same lookup.
+        self.dns_cache.insert(ip, (stale.clone(), Instant::now()));
+        let cache = self.dns_cache.clone();
+        tokio::spawn(async move {
+            let hostname = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip).ok())
+                .await
+                .ok()
+                .flatten()
+                .filter(|name| name.parse::<IpAddr>().is_err());
+            drop(slot);
+            cache.insert(ip, (hostname, Instant::now()));
+        });
+        stale
+    }
+
+    /// Vendor registered for the prefix of a MAC address (aa:bb:cc:dd:ee:ff).
+    /// None for locally administered addresses, which phones and laptops
+    /// randomize.
+    pub fn vendor(&self, mac: &str) -> Option<String> {
+        self.sources().oui.vendor(mac).map(str::to_string)
+    }
+
+    fn sources(&self) -> Arc<Sources> {
+        self.sources.read().unwrap().clone()
+    }
+}
+
+impl Sources {
+    fn load(dir: &Path) -> anyhow::Result<Self> {
+        let mut sources = Sources::default();
+        if !dir.exists() {
+            return Ok(sources);
+        }
+
+        let mut entries: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .collect();
+        // Databases are merged first-wins; make that order predictable.
+        entries.sort();
+
+        for path in entries {
+            let Some(name) = path.file_name().and_then(|n| n.to_str()).map(str::to_string) else {
+                continue;
+            };
+            let size_bytes = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            match source_kind(&name) {
+                Some("mmdb") => match Reader::open_readfile(&path) {
+                    Ok(reader) => {
+                        sources.files.push(SourceFile {
+                            name: name.clone(),
+                            kind: "mmdb",
+                            description: reader.metadata.database_type.clone(),
+                            size_bytes,
+                            ranges: None,
+                        });
+                        sources.mmdbs.push((name, reader));
+                    }
+                    Err(e) => tracing::warn!(file = %name, error = %e, "Skipping unreadable .mmdb file"),
+                },
+                Some("oui") => match std::fs::read_to_string(&path) {
+                    Ok(text) => {
+                        let entries = parse_oui(&text);
+                        sources.files.push(SourceFile {
+                            description: if name == "manuf" { "Wireshark manuf" } else { "IEEE registry" }.to_string(),
+                            name,
+                            kind: "oui",
+                            size_bytes,
+                            ranges: Some(entries.len()),
+                        });
+                        sources.oui.extend(entries);
+                    }
+                    Err(e) => tracing::warn!(file = %name, error = %e, "Skipping unreadable OUI file"),
+                },
+                Some(_) => match std::fs::read_to_string(&path) {
+                    Ok(text) => {
+                        let ranges = parse_delegation(&text);
+                        sources.files.push(SourceFile {
+                            name,
+                            kind: "rir",
+                            description: registries(&text),
+                            size_bytes,
+                            ranges: Some(ranges.len()),
+                        });
+                        sources.rir.extend(ranges);
+                    }
+                    Err(e) => tracing::warn!(file = %name, error = %e, "Skipping unreadable RIR file"),
+                },
+                None => {}
+            }
+        }
+        sources.rir.finish();
+
+        tracing::info!(
+            dir = %dir.display(),
+            mmdb_files = sources.mmdbs.len(),
+            rir_ranges = sources.rir.len(),
+            mac_prefixes = sources.oui.len(),
+            "IP intelligence databases loaded"
+        );
+        Ok(sources)
+    }
+}
+
+fn source_kind(name: &str) -> Option<&'static str> {
+    if name.ends_with(".mmdb") {
+        Some("mmdb")
+    } else if name.starts_with("delegated-") {
+        Some("rir")
+    } else if name == "manuf"
+        || (["oui", "mam"].iter().any(|p| name.starts_with(p)) && (name.ends_with(".csv") || name.ends_with(".txt")))
+    {
+        Some("oui")
+    } else {
+        None
+    }
+}
+
+/// One allocated block from an RIR file. IPv4 and IPv6 are kept apart; both
+/// use u128 bounds so they share the search code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RirRange {
+    v6: bool,
+    start: u128,
+    end: u128,
+    country: [u8; 2],
+}
+
+/// Allocated address blocks by country, sorted for binary search.
+#[derive(Default)]
+struct RirTable {
+    ranges: Vec<RirRange>,
+}
+
+impl RirTable {
+    fn extend(&mut self, ranges: Vec<RirRange>) {
+        self.ranges.extend(ranges);
+    }
+
+    fn finish(&mut self) {
+        self.ranges.sort_by_key(|r| (r.v6, r.start));
+    }
+
+    fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    fn country(&self, ip: IpAddr) -> Option<&str> {
+        let (v6, addr) = match ip {
+            IpAddr::V4(v4) => (false, u32::from(v4) as u128),
+            IpAddr::V6(v6) => (true, u128::from(v6)),
+        };
+        // Last range starting at or before the address
+        let idx = self.ranges.partition_point(|r| (r.v6, r.start) <= (v6, addr));
+        let range = self.ranges[..idx].last()?;
+        (range.v6 == v6 && addr <= range.end)
+            .then(|| std::str::from_utf8(&range.country).ok())
+            .flatten()
+    }
+}
+
+/// Parse the allocated and assigned IPv4/IPv6 blocks of an RIR statistics
+/// exchange file. Header, summary, ASN and unallocated lines are skipped.
+fn parse_delegation(text: &str) -> Vec<RirRange> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('|').collect();
+            if fields.len() < 7 || !matches!(fields[6], "allocated" | "assigned") {
+                return None;
+            }
+            let country: [u8; 2] = fields[1].as_bytes().try_into().ok()?;
+            if !country.iter().all(u8::is_ascii_uppercase) {
+                return None;
+            }
+            match fields[2] {
+                "ipv4" => {
+                    let start = u32::from(fields[3].parse::<std::net::Ipv4Addr>().ok()?) as u128;
+                    let count: u128 = fields[4].parse().ok()?;
+                    (count > 0).then_some(RirRange { v6: false, start, end: start + count - 1, country })
+                }
+                "ipv6" => {
+                    let start = u128::from(fields[3].parse::<std::net::Ipv6Addr>().ok()?);
+                    let prefix_len: u32 = fields[4].parse().ok()?;
+                    let host_bits = 128u32.checked_sub(prefix_len)?;
+                    let size = 1u128.checked_shl(host_bits).map_or(u128::MAX, |s| s - 1);
+                    Some(RirRange { v6: true, start, end: start.saturating_add(size), country })
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// A MAC prefix of `bits` bits (24 for MA-L, 28 for MA-M, 36 for MA-S).
+#[derive(Debug, Clone, PartialEq)]
+struct OuiEntry {
+    bits: u8,
+    prefix: u64,
+    vendor: String,
+}
+
+/// Vendors by MAC prefix; the longest matching prefix wins.
+#[derive(Default)]
+struct OuiTable {
+    vendors: HashMap<(u8, u64), String>,
+}
+
+impl OuiTable {
+    fn extend(&mut self, entries: Vec<OuiEntry>) {
+        for entry in entries {
+            self.vendors.entry((entry.bits, entry.prefix)).or_insert(entry.vendor);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.vendors.len()
+    }
+
+    fn vendor(&self, mac: &str) -> Option<&str> {
+        let bytes: Vec<u8> = mac
+            .split([':', '-'])
+            .map(|b| u8::from_str_radix(b, 16).ok())
+            .collect::<Option<_>>()?;
+        let bytes: [u8; 6] = bytes.try_into().ok()?;
+        // Locally administered: randomized or assigned by software
+        if bytes[0] & 0x02 != 0 {
+            return None;
+        }
+        let mac = bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        [36u8, 28, 24]
+            .iter()
+            .find_map(|&bits| self.vendors.get(&(bits, mac >> (48 - bits))))
+            .map(String::as_str)
+    }
+}
+
+/// Parse MAC prefixes from IEEE registry CSV (`MA-L,00000C,Cisco Systems, Inc,...`),
+/// IEEE `oui.txt` (`00-00-0C   (hex)\t\tCisco Systems, Inc`) or Wireshark
+/// `manuf` (`00:00:0C\tCisco\tCisco Systems, Inc`, `00:1B:C5:00:00:00/36\t...`).
+fn parse_oui(text: &str) -> Vec<OuiEntry> {
+    let entry = |hex: &str, bits: Option<u8>, vendor: &str| {
+        let hex: String = hex.chars().filter(char::is_ascii_hexdigit).collect();
+        let bits = bits.unwrap_or(hex.len() as u8 * 4);
+        let vendor = vendor.trim();
+        if !matches!(bits, 24 | 28 | 36) || hex.len() * 4 < bits as usize || vendor.is_empty() {
+            return None;
+        }
+        let value = u64::from_str_radix(&hex[..bits as usize / 4], 16).ok()?;
+        Some(OuiEntry { bits, prefix: value, vendor: vendor.to_string() })
+    };
+
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            if line.starts_with("MA-") {
+                let fields = csv_fields(line);
+                return entry(fields.get(1)?, None, fields.get(2)?);
+            }
+            if let Some((prefix, vendor)) = line.split_once("(hex)") {
+                return entry(prefix, None, vendor);
+            }
+            let mut fields = line.split('\t').filter(|f| !f.trim().is_empty());
+            let prefix = fields.next()?.trim();
+            if !prefix.contains(':') {
+                return None;
+            }
+            let short = fields.next()?;
+            let vendor = fields.next().unwrap_or(short);
+            match prefix.split_once('/') {
+                Some((addr, bits)) => entry(addr, Some(bits.parse().ok()?), vendor),
+                None => entry(prefix, None, vendor),
+            }
+        })
+        .collect()
+}
+
+/// Fields of one CSV line, honoring double quotes.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Registry names in a file's version line, e.g. "ripencc".
+fn registries(text: &str) -> String {
+    text.lines()
+        .find(|line| !line.starts_with('#'))
+        .and_then(|line| line.split('|').nth(1))
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rir_ranges_give_countries() {
+        let text = "\
+2|ripencc|1700000000|3|19830705|20240101|+0100
+ripencc|*|ipv4|*|2|summary
+ripencc|FR|ipv4|2.0.0.0|1048576|20100712|allocated
+ripencc|DE|ipv6|2001:db8::|32|20050101|allocated|a1
+ripencc||ipv4|5.0.0.0|256||available
+ripencc|NL|asn|1101|1|19930901|allocated
+";
+        let mut table = RirTable::default();
+        table.extend(parse_delegation(text));
+        table.finish();
+        assert_eq!(table.len(), 2);
+
+        let country = |ip: &str| table.country(ip.parse().unwrap()).map(str::to_string);
+        assert_eq!(country("2.0.0.1").as_deref(), Some("FR"));
+        assert_eq!(country("2.15.255.255").as_deref(), Some("FR"));
+        assert_eq!(country("2.16.0.0"), None);
+        assert_eq!(country("5.0.0.1"), None);
+        assert_eq!(country("2001:db8:ffff::1").as_deref(), Some("DE"));
+        assert_eq!(country("2001:db9::1"), None);
+        assert_eq!(registries(text), "ripencc");
+    }
+
+    #[test]
+    fn oui_files_give_vendors() {
+        let csv = "\
+Registry,Assignment,Organization Name,Organization Address
+MA-L,00000C,\"Cisco Systems, Inc\",170 WEST TASMAN DRIVE SAN JOSE CA US 95134
+MA-M,70B3D5F,Example Sensors,Somewhere
+";
+        let txt = "00-1B-21   (hex)\t\tIntel Corporate\n001B21     (base 16)\t\tIntel Corporate\n";
+        let manuf = "# Wireshark\n00:03:93\tApple\tApple, Inc.\n00:1B:C5:00:00:00/36\tConverg\tConverging Systems Inc.\nB8:27:EB\tRaspberr\n";
+
+        let mut table = OuiTable::default();
+        for text in [csv, txt, manuf] {
+            table.extend(parse_oui(text));
+        }
+        assert_eq!(table.len(), 6);
+
+        assert_eq!(table.vendor("00:00:0c:12:34:56"), Some("Cisco Systems, Inc"));
+        assert_eq!(table.vendor("00-1B-21-0A-0B-0C"), Some("Intel Corporate"));
+        assert_eq!(table.vendor("00:03:93:aa:bb:cc"), Some("Apple, Inc."));
+        assert_eq!(table.vendor("b8:27:eb:00:00:01"), Some("Raspberr"));
+        // Longer prefixes win over the MA-L block they sit in
+        assert_eq!(table.vendor("70:b3:d5:f1:23:45"), Some("Example Sensors"));
+        assert_eq!(table.vendor("00:1b:c5:00:00:42"), Some("Converging Systems Inc."));
+        assert_eq!(table.vendor("00:1b:c5:00:10:42"), None);
+        // Randomized (locally administered) and malformed addresses
+        assert_eq!(table.vendor("02:00:0c:12:34:56"), None);
+        assert_eq!(table.vendor("not a mac"), None);
+    }
+}
@@ -1,6 +1,12 @@
 pub mod alert_evaluator;
+pub mod discovery;
+pub mod hop_window;
 pub mod ingestion;
+pub mod ip_intel;
+pub mod retention;
 pub mod route_detector;
+pub mod sample_writer;
+pub mod session_lifecycle;
 pub mod stats_aggregator;
 pub mod traffic;
 pub mod update_watcher;
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use chrono::{NaiveDate, Utc};
+use sqlx::PgPool;
+
+use crate::state::AppState;
+
+/// Daily partitions created ahead of today, so a missed run never leaves
+/// samples for a new day in the default partition.
+const PARTITIONS_AHEAD: i64 = 7;
+
+/// Background task that keeps the daily partitions of `samples` and
+/// `hop_stats_minutely` ahead of time and drops those past their tier's
+/// retention. Hourly rollups and agent health are not partitioned and are
+/// deleted by age.
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(3600));
+
+    loop {
+        interval.tick().await;
+        let tables = [
+            ("samples", "sent_at", state.config.raw_retention_days),
+            ("hop_stats_minutely", "minute", state.config.minute_rollup_retention_days),
+        ];
+        for (parent, key, retention_days) in tables {
+            if let Err(e) = maintain_partitions(&state.pool, parent, key, retention_days).await {
+                tracing::error!(table = parent, "Partition maintenance failed: {}", e);
+            }
+        }
+        if let Err(e) = purge_rows(&state).await {
+            tracing::error!("Retention cleanup failed: {}", e);
+        }
+    }
+}
+
+async fn maintain_partitions(
+    pool: &PgPool,
+    parent: &str,
+    key: &str,
+    retention_days: u32,
+) -> anyhow::Result<()> {
+    let today = Utc::now().date_naive();
+    for offset in -1..=PARTITIONS_AHEAD {
+        sqlx::query("SELECT create_daily_partition($1, $2)")
+            .bind(parent)
+            .bind(today + chrono::Duration::days(offset))
+            .execute(pool)
+            .await?;
+    }
+
+    if retention_days == 0 {
+        return Ok(());
+    }
+    let cutoff = today - chrono::Duration::days(retention_days as i64);
+
+    let partitions = sqlx::query_scalar::<_, String>(
+        r#"SELECT c.relname::text
+           FROM pg_inherits i
+           JOIN pg_class c ON c.oid = i.inhrelid
+           WHERE i.inhparent = $1::regclass"#,
+    )
+    .bind(parent)
+    .fetch_all(pool)
+    .await?;
+
+    for name in partitions {
+        let Some(day) = partition_day(parent, &name) else { continue };
+        if day >= cutoff {
+            continue;
+        }
+        sqlx::query(&format!("DROP TABLE \"{name}\"")).execute(pool).await?;
+        tracing::info!(partition = %name, "Dropped expired partition");
+    }
+
+    // Rows that landed in the default partition age out row by row
+    let deleted = sqlx::query(&format!(
+        "DELETE FROM \"{parent}_default\" WHERE {key} < $1"
+    ))
+    .bind(cutoff.and_hms_opt(0, 0, 0).unwrap().and_utc())
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if deleted > 0 {
+        tracing::info!(table = parent, deleted, "Purged expired rows from default partition");
+    }
+
+    Ok(())
+}
+
+async fn purge_rows(state: &AppState) -> anyhow::Result<()> {
+    let hourly_days = state.config.hourly_rollup_retention_days;
+    if hourly_days > 0 {
+        sqlx::query("DELETE FROM hop_stats_hourly WHERE hour < NOW() - make_interval(days => $1)")
+            .bind(hourly_days as i32)
+            .execute(&state.pool)
+            .await?;
+    }
+
+    let health_days = state.config.agent_health_retention_days;
+    if health_days > 0 {
+        sqlx::query("DELETE FROM agent_health WHERE recorded_at < NOW() - make_interval(days => $1)")
+            .bind(health_days as i32)
+            .execute(&state.pool)
+            .await?;
+    }
+
+    // Round numbers only deduplicate replays of samples still stored
+    let raw_days = state.config.raw_retention_days;
+    if raw_days > 0 {
+        sqlx::query("DELETE FROM ingested_rounds WHERE ingested_at < NOW() - make_interval(days => $1)")
+            .bind(raw_days as i32)
+            .execute(&state.pool)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The UTC day held by a daily partition named `<parent>_YYYYMMDD`.
+fn partition_day(parent: &str, name: &str) -> Option<NaiveDate> {
+    let suffix = name.strip_prefix(parent)?.strip_prefix('_')?;
+    if suffix.len() != 8 {
+        return None;
+    }
+    NaiveDate::parse_from_str(suffix, "%Y%m%d").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_day_parses_daily_partitions_only() {
+        assert_eq!(
+            partition_day("samples", "samples_20261017"),
+            NaiveDate::from_ymd_opt(2026, 10, 17)
+        );
+        assert_eq!(partition_day("samples", "samples_default"), None);
+        assert_eq!(partition_day("samples", "samples_2026101"), None);
+        assert_eq!(partition_day("samples", "hop_stats_minutely_20261017"), None);
+        assert_eq!(
+            partition_day("hop_stats_minutely", "hop_stats_minutely_20260101"),
+            NaiveDate::from_ymd_opt(2026, 1, 1)
+        );
+    }
+}
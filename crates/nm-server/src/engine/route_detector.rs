@@ -1,5 +1,5 @@
 use nm_common::crypto::route_hash;
-use nm_common::protocol::RouteDiscoveryReport;
+use nm_common::protocol::{MultipathDiscoveryReport, MultipathHop, PathMtuReport, RouteDiscoveryReport};
 use uuid::Uuid;
 
 use crate::state::AppState;
@@ -18,8 +18,8 @@ pub async fn check_route_change(report: RouteDiscoveryReport, state: &AppState)
     let hop_count = hop_ips.len() as i16;
 
     // Get previous snapshot
-    let previous = sqlx::query_as::<_, (Uuid, String, i16)>(
-        "SELECT id, route_hash, hop_count FROM route_snapshots WHERE session_id = $1 ORDER BY captured_at DESC LIMIT 1",
+    let previous = sqlx::query_as::<_, (Uuid, String, i16, Vec<Option<String>>)>(
+        "SELECT id, route_hash, hop_count, hop_sequence FROM route_snapshots WHERE session_id = $1 ORDER BY captured_at DESC LIMIT 1",
     )
     .bind(session_id)
     .fetch_optional(&state.pool)
@@ -42,8 +42,8 @@ pub async fn check_route_change(report: RouteDiscoveryReport, state: &AppState)
     .await;
 
     // Detect route change
-    if let Some((prev_id, prev_hash, prev_hop_count)) = previous {
-        if prev_hash != hash {
+    if let Some((prev_id, prev_hash, prev_hop_count, prev_hops)) = previous {
+        if prev_hash != hash && !within_ecmp_set(session_id, &prev_hops, &hop_ips, state).await {
             // Count how many hops actually changed
             let hops_changed = count_changed_hops(&state.pool, session_id, prev_id, new_snapshot_id).await;
 
@@ -85,6 +85,99 @@ pub async fn check_route_change(report: RouteDiscoveryReport, state: &AppState)
     }
 }
 
+/// Store a multipath discovery next to the route snapshots and refresh the
+/// session's known ECMP sets.
+pub async fn store_multipath(report: MultipathDiscoveryReport, state: &AppState) {
+    match crate::db::multipath::insert(&state.pool, &report).await {
+        Ok(snapshot) => {
+            tracing::info!(
+                session_id = %report.session_id,
+                flows = report.flows_probed,
+                max_width = snapshot.max_width,
+                "Multipath snapshot stored"
+            );
+        }
+        Err(e) => {
+            tracing::error!("Failed to store multipath snapshot: {}", e);
+            return;
+        }
+    }
+
+    state.ecmp_sets.insert(report.session_id, ecmp_sets_from_hops(&report.hops));
+}
+
+/// Store a path MTU measurement against the session's current route snapshot.
+pub async fn store_path_mtu(report: PathMtuReport, state: &AppState) {
+    match crate::db::path_mtu::insert(&state.pool, &report).await {
+        Ok(result) => {
+            tracing::info!(
+                session_id = %report.session_id,
+                path_mtu = report.path_mtu,
+                drop_hop = ?report.mtu_drop_hop,
+                black_holes = report.black_hole_hops.len(),
+                route_snapshot_id = ?result.route_snapshot_id,
+                "Path MTU stored"
+            );
+        }
+        Err(e) => tracing::error!("Failed to store path MTU result: {}", e),
+    }
+}
+
+/// True when every hop that differs between two routes only moved to another
+/// interface of the same ECMP set, i.e. the probes took another known
+/// load-balanced path rather than a new route.
+pub async fn within_ecmp_set(
+    session_id: Uuid,
+    previous: &[Option<String>],
+    current: &[Option<String>],
+    state: &AppState,
+) -> bool {
+    if !state.ecmp_sets.contains_key(&session_id) {
+        // Cache the latest discovery (or the lack of one) for this session
+        let sets = match crate::db::multipath::latest_for_session(&state.pool, session_id).await {
+            Ok(Some(snapshot)) => serde_json::from_value::<Vec<MultipathHop>>(snapshot.hop_interfaces)
+                .map(|hops| ecmp_sets_from_hops(&hops))
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        state.ecmp_sets.insert(session_id, sets);
+    }
+
+    let Some(sets) = state.ecmp_sets.get(&session_id) else {
+        return false;
+    };
+    if sets.is_empty() || previous.len() != current.len() {
+        return false;
+    }
+
+    previous.iter().zip(current).enumerate().all(|(i, (prev, cur))| match (prev, cur) {
+        _ if prev == cur => true,
+        (Some(prev), Some(cur)) => sets.get(i).is_some_and(|set| set.contains(prev) && set.contains(cur)),
+        _ => false,
+    })
+}
+
+fn ecmp_sets_from_hops(hops: &[MultipathHop]) -> Vec<Vec<String>> {
+    let len = hops.iter().map(|h| h.hop_number as usize).max().unwrap_or(0);
+    let mut sets = vec![Vec::new(); len];
+    for hop in hops.iter().filter(|h| h.hop_number > 0) {
+        sets[hop.hop_number as usize - 1] = hop.interfaces.clone();
+    }
+    sets
+}
+
+/// The session's route as of its latest snapshot, if it has one.
+pub async fn latest_route(session_id: Uuid, state: &AppState) -> Option<Vec<Option<String>>> {
+    sqlx::query_scalar::<_, Vec<Option<String>>>(
+        "SELECT hop_sequence FROM route_snapshots WHERE session_id = $1 ORDER BY captured_at DESC LIMIT 1",
+    )
+    .bind(session_id)
+    .fetch_optional(&state.pool)
+    .await
+    .ok()
+    .flatten()
+}
+
 /// Create the initial route snapshot for a new session (first round of probes).
 pub async fn create_initial_snapshot(
     session_id: Uuid,
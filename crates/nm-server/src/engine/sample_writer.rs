@@ -0,0 +1,706 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use nm_common::config::ServerConfig;
+use nm_common::protocol::TraceRoundReport;
+use sqlx::PgPool;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::engine::ip_intel::IpIntel;
+
+/// Cached hops not seen for this long are dropped from memory.
+const HOP_CACHE_IDLE: Duration = Duration::from_secs(3600);
+
+/// What happened to a round handed to the writer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundWrite {
+    Stored,
+    /// The round was ingested before (e.g. a replayed batch whose ack was lost).
+    Duplicate,
+    Failed,
+}
+
+/// Handle to the background task that stores trace rounds. Rounds from all
+/// agents are buffered and written in one transaction per flush: a single
+/// `COPY` for the samples, `UNNEST` batches for everything else.
+///
+/// The queue is bounded; when the writer falls behind, `submit` waits, which
+/// stops the agent's socket from being read until the writer catches up.
+#[derive(Clone)]
+pub struct SampleWriter {
+    tx: mpsc::Sender<PendingRound>,
+    hop_cache: Arc<HopCache>,
+    backpressure_waits: Arc<AtomicU64>,
+}
+
+struct PendingRound {
+    report: Arc<TraceRoundReport>,
+    /// Set when the submitter waits for the result; the round is flushed
+    /// without waiting for the batch to fill.
+    done: Option<oneshot::Sender<RoundWrite>>,
+}
+
+impl SampleWriter {
+    /// Start the writer task.
+    pub fn spawn(pool: PgPool, ip_intel: Arc<IpIntel>, config: &ServerConfig) -> Self {
+        let (tx, rx) = mpsc::channel(config.ingest_queue_rounds.max(1));
+        let writer = Self {
+            tx,
+            hop_cache: Arc::new(HopCache::default()),
+            backpressure_waits: Arc::new(AtomicU64::new(0)),
+        };
+        let task = WriterTask {
+            pool,
+            ip_intel,
+            hop_cache: writer.hop_cache.clone(),
+            backpressure_waits: writer.backpressure_waits.clone(),
+            batch_rows: config.ingest_batch_rows.max(1),
+            flush_interval: Duration::from_millis(config.ingest_flush_interval_ms.max(1)),
+        };
+        tokio::spawn(task.run(rx));
+        writer
+    }
+
+    /// Queue a round for storage without waiting for it to be written.
+    pub async fn submit(&self, report: Arc<TraceRoundReport>) {
+        let round = PendingRound { report, done: None };
+        let round = match self.tx.try_send(round) {
+            Ok(()) => return,
+            Err(mpsc::error::TrySendError::Full(round)) => {
+                self.backpressure_waits.fetch_add(1, Ordering::Relaxed);
+                round
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                tracing::error!("Sample writer stopped, round dropped");
+                return;
+            }
+        };
+        if self.tx.send(round).await.is_err() {
+            tracing::error!("Sample writer stopped, round dropped");
+        }
+    }
+
+    /// Store a round and wait until it is committed.
+    pub async fn write(&self, report: Arc<TraceRoundReport>) -> RoundWrite {
+        let (done, result) = oneshot::channel();
+        let round = PendingRound { report, done: Some(done) };
+        if self.tx.send(round).await.is_err() {
+            tracing::error!("Sample writer stopped, round dropped");
+            return RoundWrite::Failed;
+        }
+        result.await.unwrap_or(RoundWrite::Failed)
+    }
+
+    /// Hostname stored for a hop, if the hop has been written before.
+    pub fn hostname(&self, session_id: Uuid, hop_number: u8, ip: Option<&str>) -> Option<String> {
+        let key = (session_id, hop_number, ip.map(canonical_ip));
+        self.hop_cache.inner.get(&key).and_then(|hop| hop.hostname.clone())
+    }
+
+    /// Keep the cached hostname in step with metadata an agent reported.
+    pub fn set_hostname(&self, session_id: Uuid, hop_number: u8, ip: &str, hostname: String) {
+        let key = (session_id, hop_number, Some(canonical_ip(ip)));
+        if let Some(mut hop) = self.hop_cache.inner.get_mut(&key) {
+            hop.hostname = Some(hostname);
+        }
+    }
+}
+
+/// (session_id, hop_number, canonical IP)
+type HopKey = (Uuid, u8, Option<String>);
+
+/// Hop rows already in the database, so only new hops need an upsert.
+#[derive(Default)]
+struct HopCache {
+    inner: DashMap<HopKey, CachedHop>,
+}
+
+struct CachedHop {
+    id: Uuid,
+    hostname: Option<String>,
+    last_used: Instant,
+}
+
+/// A hop row to insert or update, with the enrichment the server has for it.
+struct HopUpsert {
+    session_id: Uuid,
+    hop_number: i16,
+    ip: Option<String>,
+    mpls_labels: Option<serde_json::Value>,
+    hostname: Option<String>,
+    asn: Option<i32>,
+    as_name: Option<String>,
+    country: Option<String>,
+    city: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+struct WriterTask {
+    pool: PgPool,
+    ip_intel: Arc<IpIntel>,
+    hop_cache: Arc<HopCache>,
+    backpressure_waits: Arc<AtomicU64>,
+    batch_rows: usize,
+    flush_interval: Duration,
+}
+
+impl WriterTask {
+    async fn run(self, mut rx: mpsc::Receiver<PendingRound>) {
+        let mut ticker = tokio::time::interval(self.flush_interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut last_prune = Instant::now();
+        let mut batch: Vec<PendingRound> = Vec::new();
+        let mut rows = 0;
+
+        loop {
+            let mut urgent = false;
+            tokio::select! {
+                round = rx.recv() => {
+                    let Some(round) = round else {
+                        self.flush(std::mem::take(&mut batch)).await;
+                        return;
+                    };
+                    urgent |= round.done.is_some();
+                    rows += round.report.hops.len();
+                    batch.push(round);
+                    // Take everything already queued before deciding to flush
+                    while rows < self.batch_rows {
+                        let Ok(round) = rx.try_recv() else { break };
+                        urgent |= round.done.is_some();
+                        rows += round.report.hops.len();
+                        batch.push(round);
+                    }
+                    if !urgent && rows < self.batch_rows {
+                        continue;
+                    }
+                }
+                _ = ticker.tick() => {
+                    if batch.is_empty() {
+                        continue;
+                    }
+                }
+            }
+
+            self.flush(std::mem::take(&mut batch)).await;
+            rows = 0;
+
+            if last_prune.elapsed() > Duration::from_secs(60) {
+                self.hop_cache.inner.retain(|_, hop| hop.last_used.elapsed() < HOP_CACHE_IDLE);
+                last_prune = Instant::now();
+            }
+        }
+    }
+
+    async fn flush(&self, batch: Vec<PendingRound>) {
+        if batch.is_empty() {
+            return;
+        }
+        let started = Instant::now();
+        let result = self.write_batch(&batch).await;
+
+        let waits = self.backpressure_waits.swap(0, Ordering::Relaxed);
+        if waits > 0 {
+            tracing::warn!(
+                waits = waits,
+                rounds = batch.len(),
+                elapsed_ms = started.elapsed().as_millis() as u64,
+                "Ingestion queue full, agents held back until the writer caught up"
+            );
+        }
+
+        let mut stored = match result {
+            Ok(stored) => stored,
+            Err(e) => {
+                tracing::error!(rounds = batch.len(), "Failed to store trace rounds: {e:#}");
+                for round in batch {
+                    if let Some(done) = round.done {
+                        let _ = done.send(RoundWrite::Failed);
+                    }
+                }
+                return;
+            }
+        };
+        for round in batch {
+            let key = (round.report.session_id, round.report.round_number as i64);
+            let outcome = if stored.remove(&key) { RoundWrite::Stored } else { RoundWrite::Duplicate };
+            if outcome == RoundWrite::Duplicate {
+                tracing::debug!(
+                    session_id = %round.report.session_id,
+                    round = round.report.round_number,
+                    "Round already ingested, skipping"
+                );
+            }
+            if let Some(done) = round.done {
+                let _ = done.send(outcome);
+            }
+        }
+    }
+
+    /// Write a batch in one transaction. Returns the (session_id, round_number)
+    /// of every round that was stored.
+    async fn write_batch(&self, batch: &[PendingRound]) -> anyhow::Result<HashSet<(Uuid, i64)>> {
+        let mut tx = self.pool.begin().await?;
+
+        // Claim rounds so one sent twice is only stored once. Rounds of
+        // sessions deleted since they were probed are dropped here too.
+        let (session_ids, round_numbers): (Vec<Uuid>, Vec<i64>) = batch
+            .iter()
+            .map(|r| (r.report.session_id, r.report.round_number as i64))
+            .unzip();
+        let claimed: HashSet<(Uuid, i64)> = sqlx::query_as::<_, (Uuid, i64)>(
+            r#"INSERT INTO ingested_rounds (session_id, round_number)
+               SELECT r.session_id, r.round_number
+               FROM UNNEST($1::uuid[], $2::bigint[]) AS r(session_id, round_number)
+               WHERE EXISTS (SELECT 1 FROM trace_sessions s WHERE s.id = r.session_id)
+               ON CONFLICT DO NOTHING
+               RETURNING session_id, round_number"#,
+        )
+        .bind(&session_ids)
+        .bind(&round_numbers)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+
+        // The first copy of a round in the batch is the one stored
+        let mut unclaimed = claimed.clone();
+        let rounds: Vec<&TraceRoundReport> = batch
+            .iter()
+            .map(|r| r.report.as_ref())
+            .filter(|r| unclaimed.remove(&(r.session_id, r.round_number as i64)))
+            .collect();
+
+        // Resolve hop ids, touching hops already known and upserting new ones
+        let mut hop_ids: HashMap<HopKey, Uuid> = HashMap::new();
+        let mut touched: HashMap<Uuid, Option<serde_json::Value>> = HashMap::new();
+        let mut misses: HashMap<HopKey, HopUpsert> = HashMap::new();
+        for report in &rounds {
+            for hop in &report.hops {
+                let ip = hop.ip_address.as_deref().map(canonical_ip);
+                let key = (report.session_id, hop.hop_number, ip);
+                // Only an answering hop tells us its current label stack (possibly empty).
+                let mpls_labels = (!hop.is_lost)
+                    .then(|| serde_json::to_value(&hop.mpls_labels).ok())
+                    .flatten();
+                if let Some(mut cached) = self.hop_cache.inner.get_mut(&key) {
+                    cached.last_used = Instant::now();
+                    hop_ids.insert(key, cached.id);
+                    let labels = touched.entry(cached.id).or_default();
+                    if mpls_labels.is_some() {
+                        *labels = mpls_labels;
+                    }
+                    continue;
+                }
+                match misses.get_mut(&key) {
+                    Some(miss) => {
+                        if mpls_labels.is_some() {
+                            miss.mpls_labels = mpls_labels;
+                        }
+                    }
+                    None => {
+                        let miss = self.hop_upsert(&key, mpls_labels);
+                        misses.insert(key, miss);
+                    }
+                }
+            }
+        }
+
+        if !touched.is_empty() {
+            let (ids, labels): (Vec<Uuid>, Vec<Option<serde_json::Value>>) = touched.into_iter().unzip();
+            sqlx::query(
+                r#"UPDATE hops SET
+                       last_seen_at = NOW(),
+                       mpls_labels = COALESCE(t.mpls_labels, hops.mpls_labels)
+                   FROM UNNEST($1::uuid[], $2::jsonb[]) AS t(id, mpls_labels)
+                   WHERE hops.id = t.id"#,
+            )
+            .bind(&ids)
+            .bind(&labels)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let mut new_hops: Vec<(HopKey, CachedHop)> = Vec::with_capacity(misses.len());
+        if !misses.is_empty() {
+            for (key, row) in upsert_hops(&mut tx, misses.into_values().collect()).await? {
+                hop_ids.insert(key.clone(), row.id);
+                new_hops.push((key, row));
+            }
+        }
+
+        // Agents that predate per-round probe settings get their target's
+        // current method and packet size, read once per session in the batch
+        let legacy_sessions: HashSet<Uuid> = rounds
+            .iter()
+            .filter(|r| r.probe_method.is_none() || r.packet_size.is_none())
+            .map(|r| r.session_id)
+            .collect();
+        let target_settings: HashMap<Uuid, (String, i32)> = if legacy_sessions.is_empty() {
+            HashMap::new()
+        } else {
+            let ids: Vec<Uuid> = legacy_sessions.into_iter().collect();
+            sqlx::query_as::<_, (Uuid, String, i32)>(
+                r#"SELECT s.id, t.probe_method, t.packet_size
+                   FROM trace_sessions s JOIN targets t ON t.id = s.target_id
+                   WHERE s.id = ANY($1)"#,
+            )
+            .bind(&ids)
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(|(id, method, size)| (id, (method, size)))
+            .collect()
+        };
+
+        // Samples, all in one COPY
+        let mut copy_data = String::new();
+        let mut session_counts: HashMap<Uuid, i64> = HashMap::new();
+        for report in &rounds {
+            let fallback = target_settings.get(&report.session_id);
+            let probe_method = match (report.probe_method, fallback) {
+                (Some(method), _) => method.to_string(),
+                (None, Some((method, _))) => method.clone(),
+                (None, None) => "icmp".to_string(),
+            };
+            let packet_size = report
+                .packet_size
+                .map(i32::from)
+                .or(fallback.map(|(_, size)| *size))
+                .unwrap_or(64);
+            for hop in &report.hops {
+                let ip = hop.ip_address.as_deref().map(canonical_ip);
+                let Some(&hop_id) = hop_ids.get(&(report.session_id, hop.hop_number, ip)) else {
+                    continue;
+                };
+                push_sample_row(&mut copy_data, report, hop_id, hop, &probe_method, packet_size);
+            }
+            *session_counts.entry(report.session_id).or_default() += report.hops.len() as i64;
+        }
+        if !copy_data.is_empty() {
+            let mut copy = tx
+                .copy_in_raw(
+                    "COPY samples (session_id, hop_id, round_number, sent_at, rtt_us, is_lost, ttl_sent, ttl_received, \
+                     jitter_us, probe_method, packet_size) FROM STDIN",
+                )
+                .await?;
+            copy.send(copy_data.into_bytes()).await?;
+            copy.finish().await?;
+        }
+
+        if !session_counts.is_empty() {
+            let (ids, counts): (Vec<Uuid>, Vec<i64>) = session_counts.into_iter().unzip();
+            sqlx::query(
+                r#"UPDATE trace_sessions SET sample_count = sample_count + c.count
+                   FROM UNNEST($1::uuid[], $2::bigint[]) AS c(id, count)
+                   WHERE trace_sessions.id = c.id"#,
+            )
+            .bind(&ids)
+            .bind(&counts)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        // Only cache hops once their rows are committed
+        for (key, hop) in new_hops {
+            self.hop_cache.inner.insert(key, hop);
+        }
+        Ok(claimed)
+    }
+
+    /// A new hop row, enriched from the server's own IP intelligence.
+    fn hop_upsert(&self, key: &HopKey, mpls_labels: Option<serde_json::Value>) -> HopUpsert {
+        let (session_id, hop_number, ip) = key;
+        let addr = ip.as_deref().and_then(|ip| ip.parse::<std::net::IpAddr>().ok());
+        let info = addr.map(|addr| self.ip_intel.lookup(addr)).unwrap_or_default();
+        HopUpsert {
+            session_id: *session_id,
+            hop_number: *hop_number as i16,
+            ip: ip.clone(),
+            mpls_labels,
+            hostname: addr.and_then(|addr| self.ip_intel.cached_hostname(addr)),
+            asn: info.asn.map(|v| v as i32),
+            as_name: info.as_name,
+            country: info.country,
+            city: info.city,
+            lat: info.lat,
+            lon: info.lon,
+        }
+    }
+}
+
+/// Insert or touch hop rows. The server's own IP intelligence fills in
+/// whatever a row does not have yet; values the agent reported win.
+async fn upsert_hops(
+    tx: &mut sqlx::PgConnection,
+    hops: Vec<HopUpsert>,
+) -> anyhow::Result<Vec<(HopKey, CachedHop)>> {
+    let mut session_ids = Vec::with_capacity(hops.len());
+    let mut hop_numbers = Vec::with_capacity(hops.len());
+    let mut ips = Vec::with_capacity(hops.len());
+    let mut mpls_labels = Vec::with_capacity(hops.len());
+    let mut hostnames = Vec::with_capacity(hops.len());
+    let mut asns = Vec::with_capacity(hops.len());
+    let mut as_names = Vec::with_capacity(hops.len());
+    let mut countries = Vec::with_capacity(hops.len());
+    let mut cities = Vec::with_capacity(hops.len());
+    let mut lats = Vec::with_capacity(hops.len());
+    let mut lons = Vec::with_capacity(hops.len());
+    for hop in hops {
+        session_ids.push(hop.session_id);
+        hop_numbers.push(hop.hop_number);
+        ips.push(hop.ip);
+        mpls_labels.push(hop.mpls_labels);
+        hostnames.push(hop.hostname);
+        asns.push(hop.asn);
+        as_names.push(hop.as_name);
+        countries.push(hop.country);
+        cities.push(hop.city);
+        lats.push(hop.lat);
+        lons.push(hop.lon);
+    }
+
+    let rows = sqlx::query_as::<_, (Uuid, Uuid, i16, Option<String>, Option<String>)>(
+        r#"INSERT INTO hops (session_id, hop_number, ip_address, mpls_labels,
+                             hostname, asn, as_name, geo_country, geo_city, geo_lat, geo_lon)
+           SELECT * FROM UNNEST($1::uuid[], $2::int2[], $3::varchar[], $4::jsonb[],
+                                $5::varchar[], $6::int4[], $7::varchar[], $8::varchar[],
+                                $9::varchar[], $10::float8[], $11::float8[])
+           ON CONFLICT (session_id, hop_number, ip_address) DO UPDATE
+           SET last_seen_at = NOW(),
+               mpls_labels = COALESCE(EXCLUDED.mpls_labels, hops.mpls_labels),
+               hostname = COALESCE(hops.hostname, EXCLUDED.hostname),
+               asn = COALESCE(hops.asn, EXCLUDED.asn),
+               as_name = COALESCE(hops.as_name, EXCLUDED.as_name),
+               geo_country = COALESCE(hops.geo_country, EXCLUDED.geo_country),
+               geo_city = COALESCE(hops.geo_city, EXCLUDED.geo_city),
+               geo_lat = COALESCE(hops.geo_lat, EXCLUDED.geo_lat),
+               geo_lon = COALESCE(hops.geo_lon, EXCLUDED.geo_lon)
+           RETURNING id, session_id, hop_number, ip_address, hostname"#,
+    )
+    .bind(&session_ids)
+    .bind(&hop_numbers)
+    .bind(&ips)
+    .bind(&mpls_labels)
+    .bind(&hostnames)
+    .bind(&asns)
+    .bind(&as_names)
+    .bind(&countries)
+    .bind(&cities)
+    .bind(&lats)
+    .bind(&lons)
+    .fetch_all(tx)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, session_id, hop_number, ip, hostname)| {
+            let hop = CachedHop { id, hostname, last_used: Instant::now() };
+            ((session_id, hop_number as u8, ip), hop)
+        })
+        .collect())
+}
+
+/// Append one sample in `COPY ... FROM STDIN` text format. The hop number
+/// is the TTL the probe was sent with.
+fn push_sample_row(
+    out: &mut String,
+    report: &TraceRoundReport,
+    hop_id: Uuid,
+    hop: &nm_common::protocol::HopSample,
+    probe_method: &str,
+    packet_size: i32,
+) {
+    let _ = write!(
+        out,
+        "{}\t{}\t{}\t{}\t",
+        report.session_id,
+        hop_id,
+        report.round_number as i64,
+        report.sent_at.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+    );
+    match hop.rtt_us {
+        Some(rtt) => {
+            let _ = write!(out, "{}", rtt as i32);
+        }
+        None => out.push_str("\\N"),
+    }
+    let _ = write!(out, "\t{}\t{}\t", if hop.is_lost { 't' } else { 'f' }, hop.hop_number);
+    match hop.ttl_received {
+        Some(ttl) => {
+            let _ = write!(out, "{ttl}");
+        }
+        None => out.push_str("\\N"),
+    }
+    out.push('\t');
+    match hop.jitter_us {
+        Some(jitter) => {
+            let _ = write!(out, "{}", jitter as i32);
+        }
+        None => out.push_str("\\N"),
+    }
+    out.push('\t');
+    push_copy_text(out, probe_method);
+    let _ = writeln!(out, "\t{packet_size}");
+}
+
+/// Append a text value with COPY's backslash escapes.
+fn push_copy_text(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Normalize an IP literal so each address has exactly one spelling in `hops`
+/// (compressed lowercase IPv6, IPv4-mapped IPv6 folded back to IPv4).
+pub fn canonical_ip(ip: &str) -> String {
+    match ip.parse::<std::net::IpAddr>() {
+        Ok(addr) => addr.to_canonical().to_string(),
+        Err(_) => ip.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use nm_common::protocol::HopSample;
+
+    fn hop(hop_number: u8, rtt_us: Option<u32>) -> HopSample {
+        HopSample {
+            hop_number,
+            ip_address: rtt_us.map(|_| format!("10.0.0.{hop_number}")),
+            rtt_us,
+            is_lost: rtt_us.is_none(),
+            ttl_received: rtt_us.map(|_| 64 - hop_number),
+            tcp_reply: None,
+            mpls_labels: vec![],
+            jitter_us: rtt_us.map(|rtt| rtt / 10),
+        }
+    }
+
+    fn round(session_id: Uuid, round_number: u64, hops: Vec<HopSample>) -> TraceRoundReport {
+        TraceRoundReport {
+            target_id: Uuid::nil(),
+            session_id,
+            round_number,
+            sent_at: chrono::Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap(),
+            hops,
+            probe_method: Some(nm_common::protocol::ProbeMethod::Tcp),
+            packet_size: Some(60),
+        }
+    }
+
+    #[test]
+    fn sample_rows_use_copy_text_format() {
+        let session_id = Uuid::from_u128(1);
+        let hop_id = Uuid::from_u128(2);
+        let report = round(session_id, 7, vec![]);
+        let mut out = String::new();
+        push_sample_row(&mut out, &report, hop_id, &hop(3, Some(1500)), "tcp", 60);
+        push_sample_row(&mut out, &report, hop_id, &hop(4, None), "odd\tname", 64);
+        assert_eq!(
+            out,
+            format!(
+                "{session_id}\t{hop_id}\t7\t2026-03-01T12:00:00.000000Z\t1500\tf\t3\t61\t150\ttcp\t60\n\
+                 {session_id}\t{hop_id}\t7\t2026-03-01T12:00:00.000000Z\t\\N\tt\t4\t\\N\t\\N\todd\\tname\t64\n"
+            )
+        );
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_share_a_cache_key() {
+        assert_eq!(canonical_ip("::ffff:192.0.2.1"), "192.0.2.1");
+        assert_eq!(canonical_ip("2001:DB8::0:1"), "2001:db8::1");
+        assert_eq!(canonical_ip("not-an-ip"), "not-an-ip");
+    }
+
+    /// Sustained ingestion rate against a real database:
+    /// `DATABASE_URL=... cargo test --release -p nm-server ingest_throughput -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL database in DATABASE_URL"]
+    async fn ingest_throughput() {
+        const SESSIONS: usize = 50;
+        const ROUNDS_PER_SESSION: u64 = 200;
+        const HOPS: u8 = 15;
+
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+        let pool = sqlx::postgres::PgPoolOptions::new().max_connections(4).connect(&url).await.unwrap();
+        sqlx::migrate!("../../migrations").run(&pool).await.unwrap();
+
+        let agent_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO agents (name, api_key_hash) VALUES ('ingest-bench', '') RETURNING id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let mut sessions = Vec::with_capacity(SESSIONS);
+        for i in 0..SESSIONS {
+            let target_id: Uuid = sqlx::query_scalar(
+                "INSERT INTO targets (agent_id, address) VALUES ($1, $2) RETURNING id",
+            )
+            .bind(agent_id)
+            .bind(format!("198.51.100.{i}"))
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            sessions.push(crate::db::sessions::open_or_create(&pool, target_id).await.unwrap().id);
+        }
+
+        let config = ServerConfig::default();
+        let writer = SampleWriter::spawn(pool.clone(), Arc::new(IpIntel::load(config.ip_intel_dir.clone().into())), &config);
+        let hops = |round: u64| -> Vec<HopSample> {
+            (1..=HOPS)
+                .map(|n| hop(n, (round % 10 != n as u64 % 10).then_some(1000 * n as u32)))
+                .collect()
+        };
+
+        let started = Instant::now();
+        for round_number in 0..ROUNDS_PER_SESSION {
+            for &session_id in &sessions {
+                writer.submit(Arc::new(round(session_id, round_number, hops(round_number)))).await;
+            }
+        }
+        // Queued rounds are written in order, so this one finishes last
+        let last = writer.write(Arc::new(round(sessions[0], ROUNDS_PER_SESSION, hops(0)))).await;
+        let elapsed = started.elapsed();
+        assert_eq!(last, RoundWrite::Stored);
+
+        let rounds = SESSIONS as u64 * ROUNDS_PER_SESSION + 1;
+        println!(
+            "{rounds} rounds ({} samples) in {elapsed:.2?}: {:.0} rounds/s, {:.0} samples/s",
+            rounds * HOPS as u64,
+            rounds as f64 / elapsed.as_secs_f64(),
+            (rounds * HOPS as u64) as f64 / elapsed.as_secs_f64(),
+        );
+
+        let stored: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM samples s JOIN trace_sessions t ON t.id = s.session_id
+             JOIN targets g ON g.id = t.target_id
+             WHERE g.agent_id = $1 AND s.probe_method = 'tcp' AND s.packet_size = 60",
+        )
+        .bind(agent_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(stored, (rounds * HOPS as u64) as i64);
+
+        let replayed = writer.write(Arc::new(round(sessions[0], 0, hops(0)))).await;
+        assert_eq!(replayed, RoundWrite::Duplicate);
+
+        sqlx::query("DELETE FROM agents WHERE id = $1").bind(agent_id).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use nm_common::models::{Target, TraceSession};
+use nm_common::protocol::{capability, TargetAssignment, TargetRemoval, WsEnvelope, WsPayload};
+use uuid::Uuid;
+
+use crate::db::sessions;
+use crate::state::AppState;
+use crate::ws::agent_handler::{agent_can_trace, target_config};
+
+/// How often sessions marked for deletion are purged.
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The session an agent with `capabilities` should trace `target` in: the
+/// target's open session, kept across reconnects. `None` while the target is
+/// paused. Agents that cannot resume a session restart their round numbers
+/// on every assignment, which the server would drop as replays, so they get
+/// a fresh session each time instead.
+pub async fn session_for_assignment(
+    state: &AppState,
+    target: &Target,
+    capabilities: &HashSet<String>,
+) -> anyhow::Result<Option<TraceSession>> {
+    let session = sessions::open_or_create(&state.pool, target.id).await?;
+    if session.state == sessions::state::PAUSED {
+        return Ok(None);
+    }
+    if capabilities.contains(capability::SESSION_RESUME) || session.sample_count == 0 {
+        return Ok(Some(session));
+    }
+
+    let successor = sessions::close(&state.pool, session.id, sessions::state::ARCHIVED)
+        .await?
+        .and_then(|(_, successor)| successor);
+    match successor {
+        Some(session) => Ok(Some(session)),
+        None => sessions::open_or_create(&state.pool, target.id).await.map(Some),
+    }
+}
+
+/// Stop collecting for `target`: its open session is paused and its agent
+/// told to drop the target. It stays paused across agent reconnects.
+pub async fn pause(state: &AppState, target: &Target) -> anyhow::Result<TraceSession> {
+    sessions::open_or_create(&state.pool, target.id).await?;
+    let session = sessions::set_open_state(&state.pool, target.id, sessions::state::PAUSED)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("target {} has no open session", target.id))?;
+
+    remove_from_agent(state, target).await;
+    tracing::info!(target_id = %target.id, session_id = %session.id, "Collection paused");
+    Ok(session)
+}
+
+/// Continue collecting for a paused `target` in the same session.
+pub async fn resume(state: &AppState, target: &Target) -> anyhow::Result<TraceSession> {
+    sessions::open_or_create(&state.pool, target.id).await?;
+    let session = sessions::set_open_state(&state.pool, target.id, sessions::state::ACTIVE)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("target {} has no open session", target.id))?;
+
+    assign_to_agent(state, target).await;
+    tracing::info!(target_id = %target.id, session_id = %session.id, "Collection resumed");
+    Ok(session)
+}
+
+/// End a session as `archived` or `will_delete`. If it was the target's open
+/// session, collection carries on in a new session, which the agent is
+/// switched to straight away unless the target is paused.
+pub async fn close(
+    state: &AppState,
+    session_id: Uuid,
+    new_state: &str,
+) -> anyhow::Result<Option<TraceSession>> {
+    let Some((closed, successor)) = sessions::close(&state.pool, session_id, new_state).await? else {
+        return Ok(None);
+    };
+
+    if let Some(successor) = successor {
+        tracing::info!(
+            session_id = %closed.id,
+            successor_id = %successor.id,
+            state = new_state,
+            "Open session closed, collection continues in a new session"
+        );
+        if successor.state == sessions::state::ACTIVE {
+            if let Some(target) = crate::db::targets::get_by_id(&state.pool, closed.target_id).await? {
+                assign_to_agent(state, &target).await;
+            }
+        }
+    }
+    Ok(Some(closed))
+}
+
+/// Start or continue tracing `target` in its open session right away if its
+/// agent is online; otherwise the agent picks it up when it next connects.
+pub async fn assign_to_agent(state: &AppState, target: &Target) {
+    if !target.is_active || !state.agent_registry.is_online(&target.agent_id) {
+        return;
+    }
+    let capabilities = state.agent_registry.capabilities(&target.agent_id);
+    if !agent_can_trace(target, &capabilities) {
+        return;
+    }
+    let session = match session_for_assignment(state, target, &capabilities).await {
+        Ok(Some(s)) => s,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::error!("Failed to open session for target {}: {}", target.id, e);
+            return;
+        }
+    };
+
+    let protocol_version = state.agent_registry.protocol_version(&target.agent_id);
+    let config = target_config(state, target.clone(), session.id, protocol_version, &capabilities).await;
+    let envelope = WsEnvelope::new(WsPayload::TargetAssignment(TargetAssignment { targets: vec![config] }));
+    if let Err(e) = state.agent_registry.send_to_agent(&target.agent_id, envelope).await {
+        tracing::warn!(target_id = %target.id, error = %e, "Failed to push target to agent");
+    }
+}
+
+/// Tell the target's agent, if online, to stop probing it.
+pub async fn remove_from_agent(state: &AppState, target: &Target) {
+    if !state.agent_registry.is_online(&target.agent_id) {
+        return;
+    }
+    let envelope = WsEnvelope::new(WsPayload::TargetRemoval(TargetRemoval { target_ids: vec![target.id] }));
+    if let Err(e) = state.agent_registry.send_to_agent(&target.agent_id, envelope).await {
+        tracing::warn!(target_id = %target.id, error = %e, "Failed to remove target from agent");
+    }
+}
+
+/// Background task that purges sessions marked `will_delete`, with their
+/// samples and everything else recorded in them.
+pub async fn run_reaper(state: AppState) {
+    let mut interval = tokio::time::interval(REAP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        let ids = match sessions::list_marked_for_deletion(&state.pool).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                tracing::error!("Failed to list sessions to purge: {}", e);
+                continue;
+            }
+        };
+        for id in ids {
+            match sessions::purge(&state.pool, id).await {
+                Ok(samples) => {
+                    forget_session(&state, id);
+                    tracing::info!(session_id = %id, samples, "Purged session");
+                }
+                Err(e) => tracing::error!(session_id = %id, "Failed to purge session: {}", e),
+            }
+        }
+    }
+}
+
+/// Drop what is held in memory for a purged session.
+fn forget_session(state: &AppState, session_id: Uuid) {
+    state.hop_stats.remove(&session_id);
+    state.route_cache.remove(&session_id);
+    state.ecmp_sets.remove(&session_id);
+}
@@ -26,6 +26,10 @@ pub async fn handle_traffic_report(report: ProcessTrafficReport, state: &AppStat
                     protocol: c.protocol,
                     bytes_in_per_sec: c.bytes_in as f64 / interval_secs,
                     bytes_out_per_sec: c.bytes_out as f64 / interval_secs,
+                    hostname: None,
+                    asn: None,
+                    as_name: None,
+                    country: None,
                 })
                 .collect();
 
@@ -38,6 +42,17 @@ pub async fn handle_traffic_report(report: ProcessTrafficReport, state: &AppStat
             });
             endpoints.truncate(10);
 
+            for endpoint in &mut endpoints {
+                let Ok(addr) = endpoint.remote_addr.parse() else {
+                    continue;
+                };
+                let info = state.ip_intel.lookup(addr);
+                endpoint.hostname = state.ip_intel.cached_hostname(addr);
+                endpoint.asn = info.asn;
+                endpoint.as_name = info.as_name;
+                endpoint.country = info.country;
+            }
+
             ProcessTrafficSummary {
                 pid: p.pid,
                 process_name: p.process_name.clone(),
@@ -15,6 +15,7 @@ pub mod auth;
 mod config;
 mod db;
 mod engine;
+mod pki;
 mod state;
 mod ws;
 
@@ -63,6 +64,24 @@ async fn main() -> Result<()> {
     tokio::fs::create_dir_all(&update_dir).await?;
     tracing::info!("Update directory ready at {:?}", update_dir);
 
+    let ip_intel = Arc::new(engine::ip_intel::IpIntel::load(std::path::PathBuf::from(&config.ip_intel_dir)));
+    let sample_writer = engine::sample_writer::SampleWriter::spawn(pool.clone(), ip_intel.clone(), &config);
+
+    // Optional mTLS listener for agents, with certificates from the built-in CA
+    let agent_tls = match &config.agent_tls_listen_addr {
+        Some(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            let ca = pki::AgentCa::load_or_create(
+                std::path::Path::new(&config.agent_ca_dir),
+                &config.agent_tls_hostnames,
+                listener.local_addr()?.port(),
+            )?;
+            tracing::info!(fingerprint = %ca.server_fingerprint(), "Agent CA ready");
+            Some((listener, Arc::new(ca)))
+        }
+        None => None,
+    };
+
     // Build app state
     let state = AppState {
         pool,
@@ -75,7 +94,13 @@ async fn main() -> Result<()> {
         config: Arc::new(config.clone()),
         hop_stats: Arc::new(dashmap::DashMap::new()),
         route_cache: Arc::new(dashmap::DashMap::new()),
+        ecmp_sets: Arc::new(dashmap::DashMap::new()),
+        pending_config_acks: Arc::new(dashmap::DashMap::new()),
+        discovery_scans: Arc::new(dashmap::DashMap::new()),
         update_dir,
+        ip_intel,
+        agent_ca: agent_tls.as_ref().map(|(_, ca)| ca.clone()),
+        sample_writer,
     };
 
     // Spawn background tasks
@@ -84,11 +109,42 @@ async fn main() -> Result<()> {
         engine::stats_aggregator::run(state_clone).await;
     });
 
+    let state_clone = state.clone();
+    tokio::spawn(async move {
+        engine::retention::run(state_clone).await;
+    });
+
+    let state_clone = state.clone();
+    tokio::spawn(async move {
+        engine::session_lifecycle::run_reaper(state_clone).await;
+    });
+
+    let state_clone = state.clone();
+    tokio::spawn(async move {
+        engine::hop_window::run_evictor(state_clone).await;
+    });
+
     let state_clone = state.clone();
     tokio::spawn(async move {
         engine::update_watcher::run(state_clone).await;
     });
 
+    if let Some((listener, ca)) = agent_tls {
+        let addr = listener.local_addr()?;
+        let listener = ws::agent_tls::AgentTlsListener::new(listener, ca.tls_config())?;
+        let app = Router::new()
+            .route("/ws/agent", get(ws::agent_handler::handle_mtls))
+            .layer(TraceLayer::new_for_http())
+            .with_state(state.clone());
+        tracing::info!("Agent mTLS listening on {}", addr);
+        tokio::spawn(async move {
+            let app = app.into_make_service_with_connect_info::<ws::agent_tls::AgentPeer>();
+            if let Err(e) = axum::serve(listener, app).await {
+                tracing::error!(error = %e, "Agent mTLS listener failed");
+            }
+        });
+    }
+
     // SPA static file fallback (serves frontend, returns index.html for client-side routes)
     let spa_fallback = ServeDir::new(&config.static_dir)
         .not_found_service(ServeFile::new(format!("{}/index.html", &config.static_dir)));
@@ -0,0 +1,229 @@
+//! Built-in certificate authority for agent mutual TLS.
+//!
+//! The CA and the TLS listener's certificate are created on first start and
+//! kept in `agent_ca_dir`, so the fingerprint agents pin stays the same across
+//! restarts. Agents send a certificate signing request for a key they keep;
+//! the client certificate issued for it has the agent id as subject CN.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, Days, Utc};
+use rcgen::{
+    BasicConstraints, CertificateParams, CertificateSigningRequestParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    Issuer, KeyPair, KeyUsagePurpose, SerialNumber,
+};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use uuid::Uuid;
+
+use nm_common::models::AgentCertificate;
+
+const CA_CERT: &str = "ca.pem";
+const CA_KEY: &str = "ca-key.pem";
+const SERVER_CERT: &str = "server.pem";
+const SERVER_KEY: &str = "server-key.pem";
+
+const CA_VALIDITY_YEARS: i32 = 30;
+const CERT_VALIDITY_YEARS: i32 = 10;
+
+pub struct AgentCa {
+    issuer: Issuer<'static, KeyPair>,
+    ca_cert_pem: String,
+    server_fingerprint: String,
+    tls_port: u16,
+    tls_config: Arc<rustls::ServerConfig>,
+}
+
+impl AgentCa {
+    /// Load the CA and the listener certificate from `dir`, creating
+    /// whichever is missing. `hostnames` only matter for a new listener
+    /// certificate; delete `server.pem` to issue one for other names.
+    pub fn load_or_create(dir: &Path, hostnames: &[String], tls_port: u16) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let (ca_cert_pem, ca_key) = match read_pair(dir, CA_CERT, CA_KEY)? {
+            Some((cert, key)) => (cert, KeyPair::from_pem(&key).context("Invalid CA key")?),
+            None => {
+                let key = KeyPair::generate()?;
+                let cert = ca_params().self_signed(&key)?.pem();
+                write_pair(dir, CA_CERT, &cert, CA_KEY, &key.serialize_pem())?;
+                tracing::info!(dir = %dir.display(), "Created agent CA");
+                (cert, key)
+            }
+        };
+        let issuer = Issuer::from_ca_cert_pem(&ca_cert_pem, ca_key).context("Invalid CA certificate")?;
+
+        let (server_cert_pem, server_key_pem) = match read_pair(dir, SERVER_CERT, SERVER_KEY)? {
+            Some(pair) => pair,
+            None => {
+                let key = KeyPair::generate()?;
+                let cert = server_params(hostnames)?.signed_by(&key, &issuer)?.pem();
+                write_pair(dir, SERVER_CERT, &cert, SERVER_KEY, &key.serialize_pem())?;
+                tracing::info!(hostnames = ?hostnames, "Issued agent TLS listener certificate");
+                (cert, key.serialize_pem())
+            }
+        };
+        let server_cert =
+            CertificateDer::from_pem_slice(server_cert_pem.as_bytes()).context("Invalid listener certificate")?;
+        let server_key = PrivateKeyDer::from_pem_slice(server_key_pem.as_bytes()).context("Invalid listener key")?;
+        let server_fingerprint = nm_common::crypto::sha256_hex(&server_cert);
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_slice(ca_cert_pem.as_bytes())?)?;
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone()).build()?;
+        let tls_config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(vec![server_cert], server_key)?;
+
+        Ok(Self {
+            issuer,
+            ca_cert_pem,
+            server_fingerprint,
+            tls_port,
+            tls_config: Arc::new(tls_config),
+        })
+    }
+
+    /// Server side of the mTLS listener: client certificates are required
+    /// and must chain to this CA.
+    pub fn tls_config(&self) -> Arc<rustls::ServerConfig> {
+        self.tls_config.clone()
+    }
+
+    pub fn server_fingerprint(&self) -> &str {
+        &self.server_fingerprint
+    }
+
+    /// Issue a new client certificate for `agent_id` and the key in the PEM
+    /// signing request `csr_pem`. Only the key is taken from the request.
+    /// Earlier certificates stay valid.
+    pub fn sign(&self, agent_id: Uuid, csr_pem: &str) -> Result<AgentCertificate> {
+        let mut csr = CertificateSigningRequestParams::from_pem(csr_pem).context("Invalid certificate signing request")?;
+        let mut params = CertificateParams::default();
+        params.distinguished_name.remove(DnType::CommonName);
+        params.distinguished_name.push(DnType::CommonName, agent_id.to_string());
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.use_authority_key_identifier_extension = true;
+        params.serial_number = Some(random_serial());
+        set_validity(&mut params, CERT_VALIDITY_YEARS);
+        csr.params = params;
+
+        Ok(AgentCertificate {
+            cert_pem: csr.signed_by(&self.issuer)?.pem(),
+            ca_cert_pem: self.ca_cert_pem.clone(),
+            server_fingerprint: self.server_fingerprint.clone(),
+            tls_port: self.tls_port,
+        })
+    }
+}
+
+/// Subject CN of a client certificate that passed verification.
+pub fn client_identity(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+    let cn = cert.subject().iter_common_name().next()?.as_str().ok()?;
+    Some(cn.to_string())
+}
+
+fn ca_params() -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name.remove(DnType::CommonName);
+    params.distinguished_name.push(DnType::CommonName, "Network Master Agent CA");
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    params.serial_number = Some(random_serial());
+    set_validity(&mut params, CA_VALIDITY_YEARS);
+    params
+}
+
+fn server_params(hostnames: &[String]) -> Result<CertificateParams> {
+    let mut params = CertificateParams::new(hostnames.to_vec()).context("Invalid agent TLS hostname")?;
+    params.distinguished_name.remove(DnType::CommonName);
+    params.distinguished_name.push(DnType::CommonName, "Network Master");
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.use_authority_key_identifier_extension = true;
+    params.serial_number = Some(random_serial());
+    set_validity(&mut params, CERT_VALIDITY_YEARS);
+    Ok(params)
+}
+
+/// Valid from yesterday, to allow for agents whose clock is behind.
+fn set_validity(params: &mut CertificateParams, years: i32) {
+    let from = Utc::now().date_naive() - Days::new(1);
+    params.not_before = rcgen::date_time_ymd(from.year(), from.month() as u8, from.day() as u8);
+    params.not_after = rcgen::date_time_ymd(from.year() + years, from.month() as u8, from.day().min(28) as u8);
+}
+
+fn random_serial() -> SerialNumber {
+    let mut bytes = *Uuid::new_v4().as_bytes();
+    // Serial numbers are positive integers
+    bytes[0] &= 0x7f;
+    SerialNumber::from_slice(&bytes)
+}
+
+fn read_pair(dir: &Path, cert: &str, key: &str) -> Result<Option<(String, String)>> {
+    let (cert, key) = (dir.join(cert), dir.join(key));
+    match (cert.exists(), key.exists()) {
+        (true, true) => Ok(Some((
+            fs::read_to_string(&cert).with_context(|| format!("Failed to read {}", cert.display()))?,
+            fs::read_to_string(&key).with_context(|| format!("Failed to read {}", key.display()))?,
+        ))),
+        (false, false) => Ok(None),
+        (true, false) => bail!("{} exists without {}", cert.display(), key.display()),
+        (false, true) => bail!("{} exists without {}", key.display(), cert.display()),
+    }
+}
+
+fn write_pair(dir: &Path, cert_name: &str, cert: &str, key_name: &str, key: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let path = dir.join(key_name);
+    options
+        .open(&path)
+        .and_then(|mut file| file.write_all(key.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    let path = dir.join(cert_name);
+    fs::write(&path, cert).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::PublicKeyData;
+
+    #[test]
+    fn issued_certificate_names_the_agent() {
+        let dir = std::env::temp_dir().join(format!("nm-ca-test-{}", Uuid::new_v4()));
+        let ca = AgentCa::load_or_create(&dir, &["localhost".to_string()], 8443).unwrap();
+        let agent_id = Uuid::new_v4();
+        let key = KeyPair::generate().unwrap();
+        let mut requested = CertificateParams::new(vec!["ca.example".to_string()]).unwrap();
+        requested.distinguished_name.push(DnType::CommonName, "someone-else");
+        let csr = requested.serialize_request(&key).unwrap().pem().unwrap();
+        let issued = ca.sign(agent_id, &csr).unwrap();
+        let cert = CertificateDer::from_pem_slice(issued.cert_pem.as_bytes()).unwrap();
+        assert_eq!(client_identity(&cert), Some(agent_id.to_string()));
+
+        // The certificate is for the requested key, with none of its names
+        let (_, parsed) = x509_parser::parse_x509_certificate(&cert).unwrap();
+        assert_eq!(parsed.public_key().raw, key.subject_public_key_info().as_slice());
+        assert!(parsed.subject_alternative_name().unwrap().is_none());
+        assert!(ca.sign(agent_id, "not a request").is_err());
+
+        // Reloading keeps the listener certificate agents pinned
+        let reloaded = AgentCa::load_or_create(&dir, &[], 8443).unwrap();
+        assert_eq!(reloaded.server_fingerprint(), ca.server_fingerprint());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,10 @@
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 
 use dashmap::DashMap;
 use nm_common::config::ServerConfig;
+use nm_common::models::DiscoveryScan;
 use nm_common::protocol::{
     AgentOnlineStatusChange, AlertFiredNotification, LiveProcessTrafficUpdate, LiveTraceUpdate,
     UpdateProgressReport,
@@ -11,6 +13,10 @@ use sqlx::PgPool;
 use tokio::sync::broadcast;
 use uuid::Uuid;
 
+use crate::engine::hop_window::SessionWindows;
+use crate::engine::ip_intel::IpIntel;
+use crate::engine::sample_writer::SampleWriter;
+use crate::pki::AgentCa;
 use crate::ws::connection_mgr::AgentRegistry;
 
 #[derive(Clone)]
@@ -23,84 +29,25 @@ pub struct AppState {
     pub agent_status_tx: broadcast::Sender<AgentOnlineStatusChange>,
     pub agent_registry: AgentRegistry,
     pub config: Arc<ServerConfig>,
-    /// In-memory running stats per hop: key = (session_id, hop_number)
-    pub hop_stats: Arc<DashMap<(Uuid, u8), RunningHopStats>>,
+    /// Sliding-window stats of each hop of sessions with recent live rounds:
+    /// key = session_id. Idle sessions are evicted
+    pub hop_stats: Arc<DashMap<Uuid, SessionWindows>>,
     /// Last known route per session: key = session_id, value = vec of hop IPs
     pub route_cache: Arc<DashMap<Uuid, Vec<Option<String>>>>,
+    /// Known ECMP interface sets per session from the latest multipath discovery:
+    /// key = session_id, value = interfaces seen at each hop (index = hop_number - 1)
+    pub ecmp_sets: Arc<DashMap<Uuid, Vec<Vec<String>>>>,
+    /// Config updates pushed to agents and not yet acknowledged:
+    /// key = ConfigUpdate msg_id, value = (target_id, sent at)
+    pub pending_config_acks: Arc<DashMap<Uuid, (Uuid, Instant)>>,
+    /// Network discovery scans sent to agents: key = scan_id
+    pub discovery_scans: Arc<DashMap<Uuid, DiscoveryScan>>,
     /// Directory for storing update binaries
     pub update_dir: PathBuf,
-}
-
-/// In-memory running statistics for a single hop within a session.
-pub struct RunningHopStats {
-    pub min_rtt_us: u32,
-    pub max_rtt_us: u32,
-    pub sum_rtt_us: u64,
-    pub rtt_count: u64,
-    pub loss_count: u64,
-    pub total_count: u64,
-    pub last_rtt_us: Option<u32>,
-    pub sum_jitter_us: u64,
-    pub jitter_count: u64,
-}
-
-impl RunningHopStats {
-    pub fn new() -> Self {
-        Self {
-            min_rtt_us: u32::MAX,
-            max_rtt_us: 0,
-            sum_rtt_us: 0,
-            rtt_count: 0,
-            loss_count: 0,
-            total_count: 0,
-            last_rtt_us: None,
-            sum_jitter_us: 0,
-            jitter_count: 0,
-        }
-    }
-
-    pub fn update(&mut self, rtt_us: Option<u32>, is_lost: bool) {
-        self.total_count += 1;
-        if is_lost {
-            self.loss_count += 1;
-        }
-        if let Some(rtt) = rtt_us {
-            self.min_rtt_us = self.min_rtt_us.min(rtt);
-            self.max_rtt_us = self.max_rtt_us.max(rtt);
-            self.sum_rtt_us += rtt as u64;
-            self.rtt_count += 1;
-
-            // Compute jitter as |current - previous|
-            if let Some(prev) = self.last_rtt_us {
-                let jitter = (rtt as i64 - prev as i64).unsigned_abs() as u64;
-                self.sum_jitter_us += jitter;
-                self.jitter_count += 1;
-            }
-            self.last_rtt_us = Some(rtt);
-        }
-    }
-
-    pub fn avg_rtt_us(&self) -> u32 {
-        if self.rtt_count > 0 {
-            (self.sum_rtt_us / self.rtt_count) as u32
-        } else {
-            0
-        }
-    }
-
-    pub fn loss_pct(&self) -> f64 {
-        if self.total_count > 0 {
-            (self.loss_count as f64 / self.total_count as f64) * 100.0
-        } else {
-            0.0
-        }
-    }
-
-    pub fn avg_jitter_us(&self) -> u32 {
-        if self.jitter_count > 0 {
-            (self.sum_jitter_us / self.jitter_count) as u32
-        } else {
-            0
-        }
-    }
+    /// ASN, geolocation and reverse DNS for hop and endpoint addresses
+    pub ip_intel: Arc<IpIntel>,
+    /// Issues agent client certificates when the mTLS listener is enabled
+    pub agent_ca: Option<Arc<AgentCa>>,
+    /// Batches trace rounds from all agents into bulk writes
+    pub sample_writer: SampleWriter,
 }
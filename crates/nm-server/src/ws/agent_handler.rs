@@ -1,26 +1,43 @@
 use std::collections::HashSet;
 
 use axum::{
-    extract::{State, WebSocketUpgrade, ws::{Message, WebSocket}},
+    extract::{ConnectInfo, State, WebSocketUpgrade, ws::{Message, WebSocket}},
     response::IntoResponse,
 };
 use chrono::Utc;
 use futures_util::{SinkExt, StreamExt};
-use nm_common::protocol::{ProbeMethod, TargetConfig, WsEnvelope, WsPayload};
+use nm_common::protocol::{
+    capability, AckResponse, AddressFamily, AgentStatus, AssignedTarget, ProbeMethod, SpooledBatch, TargetConfig, WsEnvelope,
+    WsPayload, PROTOCOL_VERSION,
+};
+use nm_common::models::Target;
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
+use crate::engine::session_lifecycle;
 use crate::state::AppState;
+use crate::ws::agent_tls::AgentPeer;
 use crate::ws::connection_mgr::ConnectedAgent;
 
 pub async fn handle(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_agent_socket(socket, state))
+    ws.on_upgrade(move |socket| handle_agent_socket(socket, state, None))
+}
+
+/// `/ws/agent` on the mTLS listener: the client certificate must name the
+/// agent that authenticates.
+pub async fn handle_mtls(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<AgentPeer>,
+) -> impl IntoResponse {
+    tracing::debug!(peer = %peer.addr, certificate = %peer.identity, "Agent mTLS connection");
+    ws.on_upgrade(move |socket| handle_agent_socket(socket, state, Some(peer.identity)))
 }
 
-async fn handle_agent_socket(socket: WebSocket, state: AppState) {
+async fn handle_agent_socket(socket: WebSocket, state: AppState, cert_identity: Option<String>) {
     let (mut ws_tx, mut ws_rx) = socket.split();
     let (cmd_tx, mut cmd_rx) = mpsc::channel::<WsEnvelope>(256);
 
@@ -31,13 +48,13 @@ async fn handle_agent_socket(socket: WebSocket, state: AppState) {
     )
     .await;
 
-    let (agent_id, agent_name) = match auth_result {
+    let agent = match auth_result {
         Ok(Some(Ok(Message::Binary(data)))) => {
             match rmp_serde::from_slice::<WsEnvelope>(&data) {
                 Ok(envelope) => {
                     if let WsPayload::AuthRequest(ref auth) = envelope.payload {
-                        match validate_and_respond(&state, auth, &mut ws_tx).await {
-                            Some((id, name)) => (id, name),
+                        match validate_and_respond(&state, auth, cert_identity.as_deref(), &mut ws_tx).await {
+                            Some(agent) => agent,
                             None => return,
                         }
                     } else {
@@ -55,8 +72,8 @@ async fn handle_agent_socket(socket: WebSocket, state: AppState) {
             match serde_json::from_str::<WsEnvelope>(&text) {
                 Ok(envelope) => {
                     if let WsPayload::AuthRequest(ref auth) = envelope.payload {
-                        match validate_and_respond(&state, auth, &mut ws_tx).await {
-                            Some((id, name)) => (id, name),
+                        match validate_and_respond(&state, auth, cert_identity.as_deref(), &mut ws_tx).await {
+                            Some(agent) => agent,
                             None => return,
                         }
                     } else {
@@ -72,6 +89,9 @@ async fn handle_agent_socket(socket: WebSocket, state: AppState) {
         }
     };
 
+    let agent_id = agent.agent_id;
+    let agent_name = agent.name;
+
     // Register agent
     state.agent_registry.register(
         agent_id,
@@ -81,6 +101,8 @@ async fn handle_agent_socket(socket: WebSocket, state: AppState) {
             connected_at: Utc::now(),
             tx: cmd_tx,
             active_targets: HashSet::new(),
+            protocol_version: agent.protocol_version,
+            capabilities: agent.capabilities,
         },
     );
 
@@ -163,25 +185,41 @@ async fn handle_agent_socket(socket: WebSocket, state: AppState) {
     writer.abort();
 }
 
+/// An agent that passed the handshake.
+struct AuthenticatedAgent {
+    agent_id: Uuid,
+    name: String,
+    protocol_version: u16,
+    capabilities: HashSet<String>,
+}
+
 /// Validate the agent's API key against the DB and send an AuthResponse.
-/// Returns (agent_id, agent_name) on success, None on failure.
+/// `cert_identity` is the client certificate CN on the mTLS listener.
+/// Returns None on failure.
 async fn validate_and_respond(
     state: &AppState,
     auth: &nm_common::protocol::AuthRequest,
+    cert_identity: Option<&str>,
     ws_tx: &mut futures_util::stream::SplitSink<WebSocket, Message>,
-) -> Option<(Uuid, String)> {
+) -> Option<AuthenticatedAgent> {
     let agent_id = auth.agent_id;
 
+    if let Some(identity) = cert_identity.filter(|identity| *identity != agent_id.to_string()) {
+        tracing::warn!(agent_id = %agent_id, certificate = %identity, "Client certificate does not match agent");
+        send_auth_failure(ws_tx, "Client certificate does not match agent").await;
+        return None;
+    }
+
     // Look up agent in DB and get api_key_hash
-    let row = sqlx::query_as::<_, (String,)>(
-        "SELECT api_key_hash FROM agents WHERE id = $1",
+    let row = sqlx::query_as::<_, (String, bool)>(
+        "SELECT api_key_hash, has_certificate FROM agents WHERE id = $1",
     )
     .bind(agent_id)
     .fetch_optional(&state.pool)
     .await;
 
-    let api_key_hash = match row {
-        Ok(Some((hash,))) => hash,
+    let (api_key_hash, has_certificate) = match row {
+        Ok(Some(row)) => row,
         Ok(None) => {
             tracing::warn!(agent_id = %agent_id, "Agent not found in DB");
             send_auth_failure(ws_tx, "Agent not found").await;
@@ -194,6 +232,14 @@ async fn validate_and_respond(
         }
     };
 
+    // An agent with a certificate never needs the plain listener, so its API
+    // key alone is not enough there
+    if has_certificate && cert_identity.is_none() {
+        tracing::warn!(agent_id = %agent_id, "Agent with a client certificate tried the plain listener");
+        send_auth_failure(ws_tx, "Agent must connect to the mTLS listener").await;
+        return None;
+    }
+
     // Verify API key against bcrypt hash
     match bcrypt::verify(&auth.api_key, &api_key_hash) {
         Ok(true) => { /* valid */ }
@@ -215,6 +261,8 @@ async fn validate_and_respond(
             hostname = $2,
             os_info = $3,
             version = $4,
+            protocol_version = $5,
+            capabilities = $6,
             last_seen_at = NOW()
         WHERE id = $1"#,
     )
@@ -222,19 +270,31 @@ async fn validate_and_respond(
     .bind(&auth.hostname)
     .bind(&auth.os_info)
     .bind(&auth.agent_version)
+    .bind(auth.protocol_version as i32)
+    .bind(&auth.capabilities)
     .execute(&state.pool)
     .await;
 
+    let capabilities: HashSet<String> = auth.capabilities.iter().cloned().collect();
+
     // Load assigned targets for this agent
-    let assigned_targets = load_agent_targets(state, agent_id).await;
+    let assigned_targets = load_agent_targets(state, agent_id, auth.protocol_version, &capabilities).await;
 
     tracing::info!(
         agent_id = %agent_id,
         hostname = %auth.hostname,
+        protocol_version = auth.protocol_version,
         targets = assigned_targets.len(),
         "Agent authenticated successfully"
     );
 
+    // Agents that predate negotiation get the response shape they can decode
+    let (protocol_version, server_capabilities) = if auth.protocol_version == 0 {
+        (0, vec![])
+    } else {
+        (PROTOCOL_VERSION, vec![capability::SPOOL.to_string()])
+    };
+
     // Send AuthResponse
     let response = WsEnvelope::new(WsPayload::AuthResponse(
         nm_common::protocol::AuthResponse {
@@ -242,6 +302,8 @@ async fn validate_and_respond(
             error: None,
             session_token: None,
             assigned_targets,
+            protocol_version,
+            capabilities: server_capabilities,
         },
     ));
     let response_bytes = rmp_serde::to_vec(&response).unwrap();
@@ -249,11 +311,22 @@ async fn validate_and_respond(
         return None;
     }
 
-    Some((agent_id, auth.hostname.clone()))
+    Some(AuthenticatedAgent {
+        agent_id,
+        name: auth.hostname.clone(),
+        protocol_version: auth.protocol_version,
+        capabilities,
+    })
 }
 
-/// Load active targets for an agent and create trace sessions for each.
-async fn load_agent_targets(state: &AppState, agent_id: Uuid) -> Vec<TargetConfig> {
+/// Load active targets for an agent with the session each is traced in.
+/// Paused targets are left out.
+async fn load_agent_targets(
+    state: &AppState,
+    agent_id: Uuid,
+    protocol_version: u16,
+    capabilities: &HashSet<String>,
+) -> Vec<AssignedTarget> {
     let targets = match crate::db::targets::list_for_agent(&state.pool, agent_id).await {
         Ok(t) => t,
         Err(e) => {
@@ -264,40 +337,175 @@ async fn load_agent_targets(state: &AppState, agent_id: Uuid) -> Vec<TargetConfi
 
     let mut configs = Vec::with_capacity(targets.len());
     for target in targets {
-        if !target.is_active {
+        if !target.is_active || !agent_can_trace(&target, capabilities) {
             continue;
         }
 
-        // Create a new trace session for this target
-        let session = match crate::db::sessions::create(&state.pool, target.id).await {
-            Ok(s) => s,
+        let session = match session_lifecycle::session_for_assignment(state, &target, capabilities).await {
+            Ok(Some(s)) => s,
+            Ok(None) => continue,
             Err(e) => {
-                tracing::error!("Failed to create session for target {}: {}", target.id, e);
+                tracing::error!("Failed to open session for target {}: {}", target.id, e);
                 continue;
             }
         };
 
-        let probe_method = match target.probe_method.as_str() {
-            "tcp" => ProbeMethod::Tcp,
-            "udp" => ProbeMethod::Udp,
-            _ => ProbeMethod::Icmp,
-        };
-
-        configs.push(TargetConfig {
-            target_id: target.id,
-            session_id: session.id,
-            address: target.address,
-            probe_method,
-            probe_port: target.probe_port.map(|p| p as u16),
-            packet_size: target.packet_size as u16,
-            interval_ms: target.interval_ms as u32,
-            max_hops: target.max_hops as u8,
-        });
+        configs.push(target_config(state, target, session.id, protocol_version, capabilities).await);
     }
 
     configs
 }
 
+/// Whether an agent with `capabilities` can trace `target` at all. IPv6
+/// targets are held back from agents that cannot trace over IPv6.
+pub fn agent_can_trace(target: &Target, capabilities: &HashSet<String>) -> bool {
+    if needs_ipv6(&target.address, &target.address_family) && !capabilities.contains(capability::IPV6) {
+        tracing::warn!(target_id = %target.id, agent_id = %target.agent_id, "Agent cannot trace IPv6, target not assigned");
+        return false;
+    }
+    true
+}
+
+/// Whether a target at `address` must be traced over IPv6.
+pub fn needs_ipv6(address: &str, address_family: &str) -> bool {
+    address_family == "ipv6" || address.parse::<std::net::Ipv6Addr>().is_ok()
+}
+
+/// The probe settings an agent with `capabilities` needs to trace `target`
+/// in `session_id`, continuing its round numbers if the session is resumed.
+/// Agents at protocol version 0 get only the settings they can decode.
+pub async fn target_config(
+    state: &AppState,
+    target: Target,
+    session_id: Uuid,
+    protocol_version: u16,
+    capabilities: &HashSet<String>,
+) -> AssignedTarget {
+    let probe_method = assigned_probe_method(&target, capabilities);
+
+    let address_family = match target.address_family.as_str() {
+        "ipv4" => AddressFamily::Ipv4,
+        "ipv6" => AddressFamily::Ipv6,
+        _ => AddressFamily::Auto,
+    };
+
+    // Flow-stable probing can be switched on for the target itself or
+    // through the trace profile it was configured from.
+    let mut flow_stable = target.flow_stable;
+    if !flow_stable {
+        if let Some(config_id) = target.config_id {
+            if let Ok(Some(profile)) =
+                crate::db::trace_profiles::get_by_id(&state.pool, config_id).await
+            {
+                flow_stable = profile.flow_stable;
+            }
+        }
+    }
+
+    let mut first_round = 0;
+    if capabilities.contains(capability::SESSION_RESUME) {
+        match crate::db::sessions::next_round(&state.pool, session_id).await {
+            Ok(round) => first_round = round,
+            Err(e) => tracing::warn!(session_id = %session_id, error = %e, "Failed to look up last round of session"),
+        }
+    }
+
+    let config = TargetConfig {
+        target_id: target.id,
+        session_id,
+        address: target.address,
+        probe_method,
+        probe_port: target.probe_port.map(|p| p as u16),
+        packet_size: target.packet_size as u16,
+        interval_ms: target.interval_ms as u32,
+        max_hops: target.max_hops as u8,
+        address_family,
+        flow_stable,
+        multipath_discovery: target.multipath_discovery,
+        pmtu_discovery: target.pmtu_discovery,
+        first_round,
+    };
+    AssignedTarget::for_version(config, protocol_version)
+}
+
+/// Replay data an agent buffered while disconnected, in order, then ack the
+/// batch so the agent can drop it from its spool. Trace rounds that could
+/// not be stored fail the ack, so the agent sends the batch again.
+async fn handle_spooled_batch(agent_id: Uuid, msg_id: Uuid, batch: SpooledBatch, state: &AppState) {
+    let count = batch.messages.len();
+    let mut failed_rounds = 0;
+    for message in batch.messages {
+        match message.payload {
+            WsPayload::TraceRound(report) => {
+                if !crate::engine::ingestion::ingest_replayed_round(report, agent_id, state).await {
+                    failed_rounds += 1;
+                }
+            }
+            WsPayload::RouteDiscovery(report) => {
+                crate::engine::route_detector::check_route_change(report, state).await;
+            }
+            WsPayload::MultipathDiscovery(report) => {
+                crate::engine::route_detector::store_multipath(report, state).await;
+            }
+            WsPayload::PathMtuDiscovery(report) => {
+                crate::engine::route_detector::store_path_mtu(report, state).await;
+            }
+            WsPayload::HopMetadata(meta) => store_hop_metadata(meta, state).await,
+            WsPayload::NetworkDiscovery(report) => {
+                crate::engine::discovery::handle_discovery_report(agent_id, report, state).await;
+            }
+            _ => tracing::debug!("Ignoring unexpected message in spooled batch"),
+        }
+    }
+    if failed_rounds > 0 {
+        tracing::warn!(agent_id = %agent_id, messages = count, failed_rounds, "Spooled batch not fully stored");
+    } else {
+        tracing::info!(agent_id = %agent_id, messages = count, "Spooled batch ingested");
+    }
+
+    let ack = WsEnvelope::new(WsPayload::AckResponse(AckResponse {
+        ack_msg_id: msg_id,
+        success: failed_rounds == 0,
+        error: (failed_rounds > 0).then(|| format!("{failed_rounds} trace rounds could not be stored")),
+    }));
+    if let Err(e) = state.agent_registry.send_to_agent(&agent_id, ack).await {
+        tracing::warn!(agent_id = %agent_id, error = %e, "Failed to ack spooled batch");
+    }
+}
+
+/// The probe method an agent with `capabilities` is told to use for `target`.
+pub fn assigned_probe_method(target: &Target, capabilities: &HashSet<String>) -> ProbeMethod {
+    let method = parse_probe_method(&target.probe_method);
+    // Older agents guess TCP hops from connect() errors; their results don't
+    // carry the SYN replies the server reads for TCP targets
+    if method == ProbeMethod::Tcp && !capabilities.contains(capability::TCP_SYN) {
+        tracing::warn!(target_id = %target.id, "Agent cannot send TCP SYN probes, tracing with ICMP");
+        return ProbeMethod::Icmp;
+    }
+    method
+}
+
+async fn store_hop_metadata(meta: nm_common::protocol::HopMetadataUpdate, state: &AppState) {
+    if let Err(e) = crate::db::hops::update_metadata(&state.pool, &meta).await {
+        tracing::error!("Failed to store hop metadata: {e}");
+        return;
+    }
+    if let Some(hostname) = meta.hostname {
+        state
+            .sample_writer
+            .set_hostname(meta.session_id, meta.hop_number, &meta.ip_address, hostname);
+    }
+}
+
+/// Map a target's stored probe method to the protocol enum (ICMP by default).
+pub fn parse_probe_method(method: &str) -> ProbeMethod {
+    match method {
+        "tcp" => ProbeMethod::Tcp,
+        "udp" => ProbeMethod::Udp,
+        _ => ProbeMethod::Icmp,
+    }
+}
+
 async fn send_auth_failure(
     ws_tx: &mut futures_util::stream::SplitSink<WebSocket, Message>,
     error: &str,
@@ -308,6 +516,8 @@ async fn send_auth_failure(
             error: Some(error.to_string()),
             session_token: None,
             assigned_targets: vec![],
+            protocol_version: 0,
+            capabilities: vec![],
         },
     ));
     let bytes = rmp_serde::to_vec(&response).unwrap();
@@ -322,41 +532,38 @@ async fn handle_agent_message(agent_id: Uuid, envelope: WsEnvelope, state: &AppS
         WsPayload::RouteDiscovery(report) => {
             crate::engine::route_detector::check_route_change(report, state).await;
         }
+        WsPayload::MultipathDiscovery(report) => {
+            crate::engine::route_detector::store_multipath(report, state).await;
+        }
+        WsPayload::PathMtuDiscovery(report) => {
+            crate::engine::route_detector::store_path_mtu(report, state).await;
+        }
         WsPayload::Heartbeat(hb) => {
             let _ = sqlx::query("UPDATE agents SET last_seen_at = NOW() WHERE id = $1")
                 .bind(hb.agent_id)
                 .execute(&state.pool)
                 .await;
+            if hb.skipped_rounds > 0 {
+                tracing::debug!(
+                    agent_id = %agent_id,
+                    skipped_rounds = hb.skipped_rounds,
+                    cpu_usage_pct = hb.cpu_usage_pct,
+                    "Agent skipped probe rounds"
+                );
+            }
+            if let Err(e) = crate::db::agent_health::insert(&state.pool, agent_id, &hb).await {
+                tracing::error!(agent_id = %agent_id, "Failed to store agent health: {e}");
+            }
         }
-        WsPayload::HopMetadata(meta) => {
-            let _ = sqlx::query(
-                r#"UPDATE hops SET
-                    hostname = COALESCE($1, hostname),
-                    asn = COALESCE($2, asn),
-                    as_name = COALESCE($3, as_name),
-                    geo_country = COALESCE($4, geo_country),
-                    geo_city = COALESCE($5, geo_city),
-                    geo_lat = COALESCE($6, geo_lat),
-                    geo_lon = COALESCE($7, geo_lon),
-                    last_seen_at = NOW()
-                WHERE session_id = $8 AND hop_number = $9 AND ip_address = $10"#,
-            )
-            .bind(&meta.hostname)
-            .bind(meta.asn.map(|v| v as i32))
-            .bind(&meta.as_name)
-            .bind(&meta.geo_country)
-            .bind(&meta.geo_city)
-            .bind(meta.geo_lat)
-            .bind(meta.geo_lon)
-            .bind(meta.session_id)
-            .bind(meta.hop_number as i16)
-            .bind(&meta.ip_address)
-            .execute(&state.pool)
-            .await;
-        }
-        WsPayload::AgentStatus(status) => {
-            tracing::info!(agent_id = %agent_id, status = ?status.status, "Agent status update");
-        }
+        WsPayload::HopMetadata(meta) => store_hop_metadata(meta, state).await,
+        WsPayload::AgentStatus(status) => match status.status {
+            AgentStatus::Degraded => {
+                tracing::warn!(agent_id = %agent_id, message = ?status.message, "Agent degraded");
+            }
+            _ => {
+                tracing::info!(agent_id = %agent_id, status = ?status.status, "Agent status update");
+            }
+        },
         WsPayload::UpdateProgress(report) => {
             tracing::info!(
                 agent_id = %agent_id,
@@ -369,6 +576,31 @@ async fn handle_agent_message(agent_id: Uuid, envelope: WsEnvelope, state: &AppS
         WsPayload::ProcessTraffic(report) => {
             crate::engine::traffic::handle_traffic_report(report, state).await;
         }
+        WsPayload::NetworkDiscovery(report) => {
+            crate::engine::discovery::handle_discovery_report(agent_id, report, state).await;
+        }
+        WsPayload::SpooledBatch(batch) => {
+            handle_spooled_batch(agent_id, envelope.msg_id, batch, state).await;
+        }
+        WsPayload::AckResponse(ack) => {
+            let Some((_, (target_id, _))) = state.pending_config_acks.remove(&ack.ack_msg_id) else {
+                tracing::debug!(agent_id = %agent_id, msg_id = %ack.ack_msg_id, "Ack for unknown message");
+                return;
+            };
+            if ack.success {
+                tracing::info!(agent_id = %agent_id, target_id = %target_id, "Agent applied config update");
+                if let Err(e) = crate::db::targets::mark_config_applied(&state.pool, target_id).await {
+                    tracing::error!("Failed to record config ack for target {}: {}", target_id, e);
+                }
+            } else {
+                tracing::warn!(
+                    agent_id = %agent_id,
+                    target_id = %target_id,
+                    error = ?ack.error,
+                    "Agent rejected config update"
+                );
+            }
+        }
         _ => {
             tracing::debug!("Unhandled agent message type");
         }
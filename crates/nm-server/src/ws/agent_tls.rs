@@ -0,0 +1,103 @@
+//! mTLS listener for `/ws/agent`. Only clients with a certificate from the
+//! built-in CA complete the handshake; the certificate's CN travels with the
+//! connection so the agent handler can check it against the agent id.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A verified agent connection.
+#[derive(Debug, Clone)]
+pub struct AgentPeer {
+    pub addr: SocketAddr,
+    /// Subject CN of the client certificate.
+    pub identity: String,
+}
+
+/// Handshakes run in their own tasks, so a slow client cannot hold up others.
+pub struct AgentTlsListener {
+    local_addr: SocketAddr,
+    accepted_rx: mpsc::Receiver<(TlsStream<TcpStream>, AgentPeer)>,
+}
+
+impl AgentTlsListener {
+    pub fn new(listener: TcpListener, config: Arc<rustls::ServerConfig>) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let acceptor = TlsAcceptor::from(config);
+        let (accepted_tx, accepted_rx) = mpsc::channel(64);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Agent TLS accept failed");
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let accepted_tx = accepted_tx.clone();
+                tokio::spawn(async move {
+                    let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => {
+                            tracing::warn!(peer = %addr, error = %e, "Agent TLS handshake failed");
+                            return;
+                        }
+                        Err(_) => {
+                            tracing::warn!(peer = %addr, "Agent TLS handshake timed out");
+                            return;
+                        }
+                    };
+                    let identity = stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .and_then(|certs| certs.first())
+                        .and_then(crate::pki::client_identity);
+                    let Some(identity) = identity else {
+                        tracing::warn!(peer = %addr, "Agent client certificate has no subject CN");
+                        return;
+                    };
+                    let _ = accepted_tx.send((stream, AgentPeer { addr, identity })).await;
+                });
+            }
+        });
+
+        Ok(Self { local_addr, accepted_rx })
+    }
+}
+
+impl Listener for AgentTlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = AgentPeer;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.accepted_rx.recv().await {
+            Some(conn) => conn,
+            // The accept task never ends while the receiver exists
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(AgentPeer { addr: self.local_addr, identity: String::new() })
+    }
+}
+
+impl Connected<IncomingStream<'_, AgentTlsListener>> for AgentPeer {
+    fn connect_info(stream: IncomingStream<'_, AgentTlsListener>) -> Self {
+        stream.remote_addr().clone()
+    }
+}
@@ -18,6 +18,9 @@ pub struct ConnectedAgent {
     pub connected_at: DateTime<Utc>,
     pub tx: mpsc::Sender<WsEnvelope>,
     pub active_targets: HashSet<Uuid>,
+    pub protocol_version: u16,
+    /// Capabilities the agent advertised in its AuthRequest
+    pub capabilities: HashSet<String>,
 }
 
 impl AgentRegistry {
@@ -55,6 +58,24 @@ impl AgentRegistry {
         self.inner.contains_key(agent_id)
     }
 
+    /// Whether the connected agent advertised `capability`; false when offline.
+    pub fn supports(&self, agent_id: &Uuid, capability: &str) -> bool {
+        self.inner
+            .get(agent_id)
+            .is_some_and(|agent| agent.capabilities.contains(capability))
+    }
+
+    pub fn capabilities(&self, agent_id: &Uuid) -> HashSet<String> {
+        self.inner
+            .get(agent_id)
+            .map(|agent| agent.capabilities.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn protocol_version(&self, agent_id: &Uuid) -> u16 {
+        self.inner.get(agent_id).map_or(0, |agent| agent.protocol_version)
+    }
+
     pub fn online_count(&self) -> usize {
         self.inner.len()
     }
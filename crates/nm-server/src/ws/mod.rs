@@ -1,3 +1,4 @@
 pub mod agent_handler;
+pub mod agent_tls;
 pub mod connection_mgr;
 pub mod frontend_handler;
@@ -8,6 +8,9 @@ export interface Agent {
   ip_address: string | null;
   is_online: boolean;
   last_seen_at: string | null;
+  protocol_version: number;
+  capabilities: string[];
+  has_certificate: boolean;
   created_at: string;
   updated_at: string;
 }
@@ -15,6 +18,14 @@ export interface Agent {
 export interface AgentRegistration {
   agent: Agent;
   api_key: string;
+  certificate?: AgentCertificate;
+}
+
+export interface AgentCertificate {
+  cert_pem: string;
+  ca_cert_pem: string;
+  server_fingerprint: string;
+  tls_port: number;
 }
 
 // ─── Target ────────────────────────────────────────────
@@ -51,6 +62,7 @@ export interface TraceSession {
   started_at: string;
   ended_at: string | null;
   sample_count: number;
+  state: 'active' | 'paused' | 'archived' | 'will_delete';
 }
 
 // ─── Hop ───────────────────────────────────────────────
@@ -207,6 +219,34 @@ export interface RemoteEndpoint {
   protocol: 'tcp' | 'udp';
   bytes_in_per_sec: number;
   bytes_out_per_sec: number;
+  hostname?: string | null;
+  asn?: number | null;
+  as_name?: string | null;
+  country?: string | null;
+}
+
+// ─── Network Discovery ─────────────────────────────────
+export interface DiscoveredDevice {
+  id: string;
+  agent_id: string;
+  ip_address: string;
+  mac_address: string | null;
+  hostname: string | null;
+  vendor: string | null;
+  latency_us: number | null;
+  description: string | null;
+  discovered_at: string;
+  last_seen_at: string;
+}
+
+export interface DiscoveryScan {
+  scan_id: string;
+  agent_id: string;
+  requested_at: string;
+  finished_at: string | null;
+  subnets: string[];
+  device_count: number | null;
+  error: string | null;
 }
 
 // ─── Real-time Hop Data (Store) ────────────────────────
@@ -0,0 +1,68 @@
+-- migrations/008_ipv6_targets.sql
+
+-- Per-target address family: 'auto' uses whatever the resolver returns first,
+-- 'ipv4' / 'ipv6' pin the trace to one family on dual-stack targets.
+ALTER TABLE targets ADD COLUMN address_family VARCHAR(4) NOT NULL DEFAULT 'auto'
+    CHECK (address_family IN ('auto', 'ipv4', 'ipv6'));
+
+-- Store hop addresses in canonical form so the same IPv6 router is not
+-- recorded twice under different spellings. This matches what the sample
+-- writer stores: IPv4-mapped addresses fold to IPv4, and anything that does
+-- not parse as a bare address is left as it is.
+CREATE FUNCTION pg_temp.canonical_ip(addr TEXT) RETURNS TEXT AS $$
+DECLARE
+    parsed INET;
+BEGIN
+    IF addr NOT LIKE '%:%' OR addr LIKE '%/%' THEN
+        RETURN addr;
+    END IF;
+    parsed := addr::inet;
+    IF parsed <<= '::ffff:0.0.0.0/96'::inet THEN
+        RETURN host('0.0.0.0'::inet + (parsed - '::ffff:0.0.0.0'::inet));
+    END IF;
+    RETURN host(parsed);
+EXCEPTION WHEN invalid_text_representation THEN
+    RETURN addr;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TEMP TABLE hop_spellings ON COMMIT DROP AS
+SELECT id, session_id, hop_number, ip_address, pg_temp.canonical_ip(ip_address) AS canonical,
+       first_seen_at, last_seen_at
+FROM hops WHERE ip_address IS NOT NULL;
+
+-- Spellings of one address at one hop collapse onto a single row, preferring
+-- the one already stored canonically, then the oldest.
+CREATE TEMP TABLE hop_merges ON COMMIT DROP AS
+SELECT id AS old_id,
+       FIRST_VALUE(id) OVER (PARTITION BY session_id, hop_number, canonical
+                             ORDER BY ip_address = canonical DESC, first_seen_at, id) AS new_id
+FROM hop_spellings;
+DELETE FROM hop_merges WHERE old_id = new_id;
+
+UPDATE samples SET hop_id = m.new_id FROM hop_merges m WHERE samples.hop_id = m.old_id;
+UPDATE alert_events SET hop_id = m.new_id FROM hop_merges m WHERE alert_events.hop_id = m.old_id;
+
+-- Hourly rollups are unique per hop and hour; keep the surviving hop's row
+-- for an hour and drop the merged ones' rows for it.
+DELETE FROM hop_stats_hourly WHERE id IN (
+    SELECT id FROM (
+        SELECT h.id, ROW_NUMBER() OVER (PARTITION BY COALESCE(m.new_id, h.hop_id), h.hour
+                                        ORDER BY m.old_id IS NULL DESC, h.sample_count DESC) AS rank
+        FROM hop_stats_hourly h LEFT JOIN hop_merges m ON m.old_id = h.hop_id
+    ) ranked WHERE rank > 1
+);
+UPDATE hop_stats_hourly SET hop_id = m.new_id FROM hop_merges m WHERE hop_stats_hourly.hop_id = m.old_id;
+
+UPDATE hops SET first_seen_at = seen.first_seen_at, last_seen_at = seen.last_seen_at
+FROM (
+    SELECT m.new_id, MIN(s.first_seen_at) AS first_seen_at, MAX(s.last_seen_at) AS last_seen_at
+    FROM hop_spellings s JOIN hop_merges m ON s.id IN (m.old_id, m.new_id)
+    GROUP BY m.new_id
+) seen
+WHERE hops.id = seen.new_id;
+DELETE FROM hops USING hop_merges m WHERE hops.id = m.old_id;
+
+UPDATE hops SET ip_address = s.canonical
+FROM hop_spellings s
+WHERE hops.id = s.id AND s.ip_address <> s.canonical;
@@ -0,0 +1,12 @@
+-- migrations/009_flow_stable_probing.sql
+
+-- Paris-traceroute style probing: keep the flow identifier (ports for UDP/TCP,
+-- checksum for ICMP) constant across TTLs so ECMP load balancers send every
+-- probe of a round down the same path. Enabled per target or per profile.
+ALTER TABLE targets ADD COLUMN flow_stable BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE trace_profiles ADD COLUMN flow_stable BOOLEAN NOT NULL DEFAULT FALSE;
+
+INSERT INTO trace_profiles (name, description, probe_method, packet_size, interval_ms, max_hops, flow_stable) VALUES
+    ('Paris ICMP', 'Flow-stable ICMP trace for load-balanced paths', 'icmp', 64, 2500, 30, TRUE),
+    ('Paris UDP', 'Flow-stable UDP trace for load-balanced paths', 'udp', 64, 2500, 30, TRUE)
+ON CONFLICT (name) DO NOTHING;
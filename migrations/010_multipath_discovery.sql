@@ -0,0 +1,18 @@
+-- migrations/010_multipath_discovery.sql
+
+-- Opt-in MDA multipath discovery per target.
+ALTER TABLE targets ADD COLUMN multipath_discovery BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Load-balanced hop graph discovered by the agent, stored next to route_snapshots.
+-- hop_interfaces: [{"hop_number": 3, "interfaces": ["10.0.0.1", "10.0.0.5"]}, ...]
+-- links:          [{"hop_number": 3, "from_ip": "10.0.0.1", "to_ip": "10.0.1.1"}, ...]
+CREATE TABLE multipath_snapshots (
+    id              UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    session_id      UUID NOT NULL REFERENCES trace_sessions(id) ON DELETE CASCADE,
+    captured_at     TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    flows_probed    SMALLINT NOT NULL,
+    max_width       SMALLINT NOT NULL,
+    hop_interfaces  JSONB NOT NULL,
+    links           JSONB NOT NULL
+);
+CREATE INDEX idx_multipath_snapshots_session ON multipath_snapshots(session_id, captured_at);
@@ -0,0 +1,21 @@
+-- migrations/011_path_mtu.sql
+
+-- Opt-in path MTU discovery per target.
+ALTER TABLE targets ADD COLUMN pmtu_discovery BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- One row per measurement, linked to the route snapshot that was current when
+-- it arrived so MTU changes can be read alongside route changes.
+-- hop_mtus: [{"hop_number": 3, "ip_address": "10.0.0.1", "mtu": 1420}, ...]
+CREATE TABLE path_mtu_results (
+    id                  UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    session_id          UUID NOT NULL REFERENCES trace_sessions(id) ON DELETE CASCADE,
+    route_snapshot_id   UUID REFERENCES route_snapshots(id) ON DELETE SET NULL,
+    captured_at         TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    path_mtu            SMALLINT NOT NULL,
+    reached_destination BOOLEAN NOT NULL,
+    mtu_drop_hop        SMALLINT,
+    black_hole_hops     SMALLINT[] NOT NULL DEFAULT '{}',
+    hop_mtus            JSONB NOT NULL
+);
+CREATE INDEX idx_path_mtu_results_session ON path_mtu_results(session_id, captured_at);
+CREATE INDEX idx_path_mtu_results_snapshot ON path_mtu_results(route_snapshot_id);
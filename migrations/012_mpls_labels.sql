@@ -0,0 +1,6 @@
+-- migrations/012_mpls_labels.sql
+
+-- Latest MPLS label stack (RFC 4950) quoted by each hop's ICMP replies.
+-- [{"label": 16004, "traffic_class": 0, "bottom_of_stack": true, "ttl": 1}, ...]
+-- NULL until the hop has answered; [] when it answers without labels.
+ALTER TABLE hops ADD COLUMN mpls_labels JSONB;
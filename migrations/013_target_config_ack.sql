@@ -0,0 +1,5 @@
+-- migrations/013_target_config_ack.sql
+
+-- When the agent last acknowledged a live config update for the target.
+-- NULL until an update has been pushed and applied without a restart.
+ALTER TABLE targets ADD COLUMN config_applied_at TIMESTAMPTZ;
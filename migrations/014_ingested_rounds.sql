@@ -0,0 +1,10 @@
+-- migrations/014_ingested_rounds.sql
+
+-- One row per trace round stored, so a round an agent sends twice (e.g. a
+-- spooled batch replayed after its ack was lost) is only ingested once.
+CREATE TABLE ingested_rounds (
+    session_id      UUID NOT NULL REFERENCES trace_sessions(id) ON DELETE CASCADE,
+    round_number    BIGINT NOT NULL,
+    ingested_at     TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (session_id, round_number)
+);
@@ -0,0 +1,19 @@
+-- migrations/015_agent_health.sql
+
+-- Host metrics from agent heartbeats, one row per heartbeat. Lets RTT
+-- anomalies be checked against a busy or memory-starved agent host.
+CREATE TABLE agent_health (
+    agent_id        UUID NOT NULL REFERENCES agents(id) ON DELETE CASCADE,
+    recorded_at     TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    cpu_usage_pct   REAL NOT NULL,
+    memory_used_mb  INTEGER NOT NULL,
+    memory_total_mb INTEGER NOT NULL,
+    load_avg_1m     REAL,
+    agent_rss_mb    INTEGER NOT NULL,
+    active_targets  INTEGER NOT NULL,
+    queued_rounds   INTEGER NOT NULL,
+    -- Probe rounds skipped since the previous heartbeat
+    skipped_rounds  BIGINT NOT NULL
+);
+
+CREATE INDEX idx_agent_health_agent_time ON agent_health (agent_id, recorded_at DESC);
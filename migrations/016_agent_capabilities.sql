@@ -0,0 +1,8 @@
+-- migrations/016_agent_capabilities.sql
+
+-- Protocol version and capability flags from the agent's last handshake, so a
+-- mixed-version fleet can be seen during a rolling upgrade. Agents that
+-- predate negotiation report version 0 and no capabilities.
+ALTER TABLE agents
+    ADD COLUMN protocol_version INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN capabilities TEXT[] NOT NULL DEFAULT '{}';
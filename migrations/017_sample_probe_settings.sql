@@ -0,0 +1,30 @@
+-- migrations/017_sample_probe_settings.sql
+
+-- Samples were stored as 'icmp' / 64 bytes with no jitter whatever the target
+-- was probed with. New samples carry the real values; repair old ones where
+-- they can be derived.
+
+-- A target not edited since its session started was probed with its current
+-- settings for the whole session.
+UPDATE samples s
+SET probe_method = t.probe_method,
+    packet_size = t.packet_size
+FROM trace_sessions ts
+JOIN targets t ON t.id = ts.target_id
+WHERE s.session_id = ts.id
+  AND t.updated_at <= ts.started_at
+  AND (s.probe_method, s.packet_size) IS DISTINCT FROM (t.probe_method, t.packet_size);
+
+-- Jitter: difference from the same hop's previous reply in the session, as
+-- the agent now computes it.
+UPDATE samples s
+SET jitter_us = j.jitter_us
+FROM (
+    SELECT id,
+           ABS(rtt_us - LAG(rtt_us) OVER (PARTITION BY session_id, ttl_sent ORDER BY round_number)) AS jitter_us
+    FROM samples
+    WHERE rtt_us IS NOT NULL
+) j
+WHERE s.id = j.id
+  AND s.jitter_us IS NULL
+  AND j.jitter_us IS NOT NULL;
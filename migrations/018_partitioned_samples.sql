@@ -0,0 +1,119 @@
+-- migrations/018_partitioned_samples.sql
+
+-- Samples are partitioned by UTC day so expired data is dropped a whole
+-- partition at a time instead of deleted row by row. Minute rollups are kept
+-- the same way; hourly rollups stay in hop_stats_hourly.
+
+-- Creates <parent>_YYYYMMDD for one UTC day of a table partitioned by range
+-- on a timestamp. Rows for that day already in <parent>_default are moved in.
+CREATE FUNCTION create_daily_partition(parent TEXT, day DATE) RETURNS VOID AS $$
+DECLARE
+    part TEXT := parent || '_' || to_char(day, 'YYYYMMDD');
+    lo TIMESTAMPTZ := day::timestamp AT TIME ZONE 'UTC';
+    hi TIMESTAMPTZ := (day + 1)::timestamp AT TIME ZONE 'UTC';
+    key TEXT;
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext(part));
+    IF to_regclass(part) IS NOT NULL THEN
+        RETURN;
+    END IF;
+
+    SELECT a.attname INTO key
+    FROM pg_partitioned_table p
+    JOIN pg_attribute a ON a.attrelid = p.partrelid AND a.attnum = p.partattrs[0]
+    WHERE p.partrelid = parent::regclass;
+
+    EXECUTE format('CREATE TABLE %I (LIKE %I INCLUDING DEFAULTS)', part, parent);
+    IF to_regclass(parent || '_default') IS NOT NULL THEN
+        EXECUTE format('WITH moved AS (DELETE FROM %I WHERE %I >= $1 AND %I < $2 RETURNING *)
+                        INSERT INTO %I SELECT * FROM moved',
+                       parent || '_default', key, key, part)
+        USING lo, hi;
+    END IF;
+    EXECUTE format('ALTER TABLE %I ATTACH PARTITION %I FOR VALUES FROM (%L) TO (%L)',
+                   parent, part, lo, hi);
+END;
+$$ LANGUAGE plpgsql;
+
+-- ============================================================
+-- SAMPLES
+-- ============================================================
+ALTER TABLE samples RENAME TO samples_unpartitioned;
+ALTER TABLE samples_unpartitioned RENAME CONSTRAINT samples_pkey TO samples_unpartitioned_pkey;
+DROP INDEX idx_samples_session_time;
+DROP INDEX idx_samples_hop_time;
+ALTER SEQUENCE samples_id_seq OWNED BY NONE;
+
+CREATE TABLE samples (
+    id              BIGINT NOT NULL DEFAULT nextval('samples_id_seq'),
+    session_id      UUID NOT NULL REFERENCES trace_sessions(id) ON DELETE CASCADE,
+    hop_id          UUID NOT NULL REFERENCES hops(id) ON DELETE CASCADE,
+    round_number    BIGINT NOT NULL,
+    sent_at         TIMESTAMPTZ NOT NULL,
+    rtt_us          INTEGER,
+    is_lost         BOOLEAN NOT NULL DEFAULT FALSE,
+    jitter_us       INTEGER,
+    probe_method    VARCHAR(10) NOT NULL DEFAULT 'icmp',
+    packet_size     INTEGER NOT NULL DEFAULT 64,
+    ttl_sent        SMALLINT NOT NULL,
+    ttl_received    SMALLINT,
+    PRIMARY KEY (id, sent_at)
+) PARTITION BY RANGE (sent_at);
+CREATE INDEX idx_samples_session_time ON samples(session_id, sent_at);
+CREATE INDEX idx_samples_hop_time ON samples(hop_id, sent_at);
+ALTER SEQUENCE samples_id_seq OWNED BY samples.id;
+
+-- Catches samples outside every daily partition, e.g. a spooled round older
+-- than the partitions kept.
+CREATE TABLE samples_default PARTITION OF samples DEFAULT;
+
+SELECT create_daily_partition('samples', d)
+FROM (
+    SELECT DISTINCT (sent_at AT TIME ZONE 'UTC')::date AS d FROM samples_unpartitioned
+    UNION
+    SELECT (NOW() AT TIME ZONE 'UTC')::date + n FROM generate_series(0, 3) n
+) days;
+
+INSERT INTO samples (id, session_id, hop_id, round_number, sent_at, rtt_us, is_lost,
+    jitter_us, probe_method, packet_size, ttl_sent, ttl_received)
+SELECT id, session_id, hop_id, round_number, sent_at, rtt_us, is_lost,
+    jitter_us, probe_method, packet_size, ttl_sent, ttl_received
+FROM samples_unpartitioned;
+
+DROP TABLE samples_unpartitioned;
+
+-- ============================================================
+-- HOP STATS (minute rollups)
+-- ============================================================
+CREATE TABLE hop_stats_minutely (
+    hop_id          UUID NOT NULL REFERENCES hops(id) ON DELETE CASCADE,
+    session_id      UUID NOT NULL REFERENCES trace_sessions(id) ON DELETE CASCADE,
+    minute          TIMESTAMPTZ NOT NULL,
+    sample_count    INTEGER NOT NULL,
+    loss_count      INTEGER NOT NULL,
+    rtt_min_us      INTEGER,
+    rtt_avg_us      INTEGER,
+    rtt_max_us      INTEGER,
+    jitter_avg_us   INTEGER,
+    jitter_max_us   INTEGER,
+    PRIMARY KEY (hop_id, minute)
+) PARTITION BY RANGE (minute);
+CREATE INDEX idx_hop_stats_minutely_session ON hop_stats_minutely(session_id, minute);
+CREATE TABLE hop_stats_minutely_default PARTITION OF hop_stats_minutely DEFAULT;
+
+SELECT create_daily_partition('hop_stats_minutely', d)
+FROM (
+    SELECT DISTINCT (sent_at AT TIME ZONE 'UTC')::date AS d FROM samples
+    UNION
+    SELECT (NOW() AT TIME ZONE 'UTC')::date + n FROM generate_series(0, 3) n
+) days;
+
+-- Existing samples are rolled up now, before older ones age out.
+INSERT INTO hop_stats_minutely (hop_id, session_id, minute, sample_count, loss_count,
+    rtt_min_us, rtt_avg_us, rtt_max_us, jitter_avg_us, jitter_max_us)
+SELECT hop_id, session_id, date_trunc('minute', sent_at),
+    COUNT(*), COUNT(*) FILTER (WHERE is_lost),
+    MIN(rtt_us), AVG(rtt_us)::int, MAX(rtt_us),
+    AVG(jitter_us)::int, MAX(jitter_us)
+FROM samples
+GROUP BY hop_id, session_id, date_trunc('minute', sent_at);
@@ -0,0 +1,28 @@
+-- migrations/019_session_lifecycle.sql
+
+-- A target has at most one open (active or paused) session, reused when its
+-- agent reconnects. Until now every reconnect opened a new session and none
+-- was ever ended: close all but the latest, each ending where the next began.
+UPDATE trace_sessions s
+SET state = 'archived',
+    ended_at = COALESCE(s.ended_at, o.next_started_at, NOW())
+FROM (
+    SELECT id,
+           LEAD(started_at) OVER (PARTITION BY target_id ORDER BY started_at) AS next_started_at
+    FROM trace_sessions
+    WHERE state IN ('active', 'paused')
+) o
+WHERE s.id = o.id
+  AND (o.next_started_at IS NOT NULL OR s.ended_at IS NOT NULL);
+
+CREATE UNIQUE INDEX idx_sessions_open_per_target ON trace_sessions(target_id)
+    WHERE state IN ('active', 'paused');
+
+-- Purging a session deletes its hops; alert history outlives both.
+ALTER TABLE alert_events
+    DROP CONSTRAINT alert_events_session_id_fkey,
+    ADD CONSTRAINT alert_events_session_id_fkey
+        FOREIGN KEY (session_id) REFERENCES trace_sessions(id) ON DELETE SET NULL,
+    DROP CONSTRAINT alert_events_hop_id_fkey,
+    ADD CONSTRAINT alert_events_hop_id_fkey
+        FOREIGN KEY (hop_id) REFERENCES hops(id) ON DELETE SET NULL;
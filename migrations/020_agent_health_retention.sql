@@ -0,0 +1,5 @@
+-- migrations/020_agent_health_retention.sql
+
+-- Heartbeat metrics older than NM_AGENT_HEALTH_RETENTION_DAYS are deleted
+-- across all agents, which the per-agent index cannot serve.
+CREATE INDEX idx_agent_health_recorded_at ON agent_health (recorded_at);
@@ -0,0 +1,7 @@
+-- migrations/021_agent_certificates.sql
+
+-- Agents issued a client certificate may only log in on the mTLS listener,
+-- so a leaked API key alone does not get past the plain /ws/agent. Agents
+-- certified before this column existed are not known; issue them a new
+-- certificate to set it.
+ALTER TABLE agents ADD COLUMN has_certificate BOOLEAN NOT NULL DEFAULT FALSE;
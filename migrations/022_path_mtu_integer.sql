@@ -0,0 +1,6 @@
+-- migrations/022_path_mtu_integer.sql
+
+-- MTUs are u16 on the wire; loopback and jumbo interfaces report values above
+-- 32767 that wrapped negative in SMALLINT. Per-hop MTUs in hop_mtus are JSON
+-- numbers and already hold the full range.
+ALTER TABLE path_mtu_results ALTER COLUMN path_mtu TYPE INTEGER;